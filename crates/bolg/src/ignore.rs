@@ -0,0 +1,192 @@
+use std::{
+    borrow::Cow,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{components, is_separator, lexical_absolute, matches_components, matches_ex};
+
+/// One line of an ignore file.
+#[derive(Clone, Debug)]
+struct Rule {
+    /// The directory the rule applies below, absolute.
+    base: PathBuf,
+    /// The components of an anchored rule, or the single name any
+    /// component may match.
+    pattern: Vec<Vec<char>>,
+    anchored: bool,
+    /// `!pattern`: a match is not ignored after all.
+    negated: bool,
+    /// `pattern/`: only directories match.
+    dir_only: bool,
+}
+
+impl Rule {
+    /// The rule a line of an ignore file holds, if it is not blank or a comment.
+    fn parse(base: &Path, line: &str) -> Option<Self> {
+        let line = trim_unescaped_spaces(line);
+        let line = line.as_ref();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (
+                false,
+                line.strip_prefix('\\')
+                    .filter(|rest| rest.starts_with(['#', '!']))
+                    .unwrap_or(line),
+            ),
+        };
+        let dir_only = line.ends_with(is_separator);
+        let line = line.trim_end_matches(is_separator);
+        if line.is_empty() {
+            return None;
+        }
+
+        let anchored = line.contains(is_separator);
+        let pattern = match anchored {
+            true => line
+                .split(is_separator)
+                .filter(|component| !component.is_empty())
+                .map(|component| component.chars().collect())
+                .collect(),
+            false => vec![line.chars().collect()],
+        };
+        Some(Self {
+            base: base.to_path_buf(),
+            pattern,
+            anchored,
+            negated,
+            dir_only,
+        })
+    }
+
+    /// Whether the rule matches `path`, absolute, which is a directory if `is_dir`.
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Ok(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        let relative = components(relative);
+        match self.anchored {
+            true => matches_components(&self.pattern, &relative).unwrap_or(false),
+            false => relative
+                .last()
+                .is_some_and(|name| matches_ex(&self.pattern[0], 0, &mut 0, name).unwrap_or(false)),
+        }
+    }
+}
+
+/// `line` without trailing spaces; `\ ` keeps one, without the backslash.
+fn trim_unescaped_spaces(line: &str) -> Cow<'_, str> {
+    let trimmed = line.trim_end_matches(' ');
+    match trimmed.strip_suffix('\\').filter(|_| trimmed.len() < line.len()) {
+        Some(kept) => Cow::Owned(format!("{kept} ")),
+        None => Cow::Borrowed(trimmed),
+    }
+}
+
+/// Rules in gitignore syntax that leave paths out of a walk; see
+/// [`Paths::ignore`](crate::Paths::ignore).
+///
+/// A rule applies below the directory of the file it comes from, as in git.
+/// One with a separator in it, other than at the end, is anchored there and
+/// matched a component at a time, with `**` for any number of directories;
+/// any other matches the name of a file or directory at any depth. A trailing
+/// `/` matches directories only, and `!` re-includes what an earlier rule
+/// left out. The last rule that matches a path decides; a directory that is
+/// left out is not walked into, so nothing in it can be re-included, as in
+/// git. `#` starts a comment, and `\#` and `\!` are the literal characters.
+#[derive(Clone, Debug, Default)]
+pub struct Ignore {
+    rules: Vec<Rule>,
+}
+
+impl Ignore {
+    /// Adds the rules of the ignore file at `path`, after those added before.
+    pub fn add_file(&mut self, path: &Path) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+        let base = lexical_absolute(path)?;
+        self.add_rules(base.parent().unwrap_or(&base), &text);
+        Ok(())
+    }
+
+    /// Adds the rules in `text`, one per line, applying below `dir`.
+    pub fn add_rules(&mut self, dir: &Path, text: &str) {
+        let base = lexical_absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+        self.rules.extend(text.lines().filter_map(|line| Rule::parse(&base, line)));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether the rules leave out `path`, which is a directory if `is_dir`.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let Ok(path) = lexical_absolute(path) else {
+            return false;
+        };
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(&path, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignore(text: &str) -> Ignore {
+        let mut ignore = Ignore::default();
+        ignore.add_rules(Path::new("/proj"), text);
+        ignore
+    }
+
+    #[test]
+    fn names_match_at_any_depth_and_anchored_rules_below_the_base() {
+        let ignore = ignore("*.log\n/build\ndocs/*.md\n");
+
+        assert!(ignore.is_ignored(Path::new("/proj/a.log"), false));
+        assert!(ignore.is_ignored(Path::new("/proj/src/deep/a.log"), false));
+        assert!(ignore.is_ignored(Path::new("/proj/build"), true));
+        assert!(!ignore.is_ignored(Path::new("/proj/src/build"), true));
+        assert!(ignore.is_ignored(Path::new("/proj/docs/a.md"), false));
+        assert!(!ignore.is_ignored(Path::new("/proj/docs/api/a.md"), false));
+        assert!(!ignore.is_ignored(Path::new("/other/a.log"), false));
+    }
+
+    #[test]
+    fn the_last_matching_rule_decides() {
+        let ignore = ignore("*.log\n!keep.log\n!/src/*.log\nsrc/noisy.log\n");
+
+        assert!(ignore.is_ignored(Path::new("/proj/a.log"), false));
+        assert!(!ignore.is_ignored(Path::new("/proj/x/keep.log"), false));
+        assert!(!ignore.is_ignored(Path::new("/proj/src/a.log"), false));
+        assert!(ignore.is_ignored(Path::new("/proj/src/noisy.log"), false));
+    }
+
+    #[test]
+    fn comments_escapes_and_directory_rules() {
+        let ignore =
+            ignore("# a comment\n\n\\#hash\n\\!bang\ntarget/\ntrailing\\ \nspaces   \na/**/z\n");
+
+        assert!(ignore.is_ignored(Path::new("/proj/#hash"), false));
+        assert!(ignore.is_ignored(Path::new("/proj/!bang"), false));
+        assert!(ignore.is_ignored(Path::new("/proj/target"), true));
+        assert!(!ignore.is_ignored(Path::new("/proj/target"), false));
+        assert!(ignore.is_ignored(Path::new("/proj/trailing "), false));
+        assert!(!ignore.is_ignored(Path::new("/proj/trailing"), false));
+        assert!(ignore.is_ignored(Path::new("/proj/spaces"), false));
+        assert!(ignore.is_ignored(Path::new("/proj/a/z"), false));
+        assert!(ignore.is_ignored(Path::new("/proj/a/b/c/z"), false));
+        assert!(!ignore.is_ignored(Path::new("/proj/# a comment"), false));
+    }
+}
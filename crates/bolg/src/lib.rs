@@ -1,157 +1,398 @@
 use std::{
     collections::VecDeque,
-    fs::{self, ReadDir},
-    path::{Iter, Path, PathBuf, Component},
+    ffi::OsString,
+    fmt,
+    env,
+    fs::{self, Metadata},
+    io,
+    path::{Component, Path, PathBuf, Prefix},
+    vec,
 };
 
+mod ignore;
+
+pub use ignore::Ignore;
+
 #[derive(Debug)]
 pub struct GlobError {
     pub msg: String,
 }
 
+impl fmt::Display for GlobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl std::error::Error for GlobError {}
+
 #[derive(Debug)]
 pub enum PathEntry {
     File(PathBuf),
-    Dir(ReadDir),
+    /// The remaining entries of a directory, and how many levels below the
+    /// root that directory is.
+    Dir(vec::IntoIter<PathBuf>, usize),
 }
 
+/// Walks a tree iteratively, keeping one sorted directory listing per level
+/// on an explicit stack. Directories that cannot be read are skipped and
+/// symlinks are never followed, so cycles cannot occur.
 #[derive(Debug)]
-pub struct Paths<'a> {
+pub struct Paths {
     pattern_chars: Vec<char>,
-    components: Vec<&'a str>,
-    path: &'a PathBuf,
-    is_wildcard: bool,
+    /// The components of an anchored pattern; see [`glob`].
+    anchored: Option<Vec<Vec<char>>>,
     entries_to_process: VecDeque<PathEntry>,
+    max_depth: Option<usize>,
+    root: PathBuf,
+    exclude_dirs: Vec<Vec<char>>,
+    /// The device of the root, when the walk stays on its file system.
+    root_device: Option<u64>,
+    device: fn(&Path, &Metadata) -> Option<u64>,
+    filters: Filters,
+    /// The absolute root, when paths are yielded relative to it.
+    relative_to: Option<PathBuf>,
 }
 
-impl<'a> Paths<'a> {
-    pub fn matches(&self, path: &PathBuf) -> Result<bool, GlobError> {
-        if !path.is_file() {
-            panic!("Paths to dir are not yet supported");
-        }
+/// What a [`Paths::with_filter`] predicate decides about a path found by the walk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterDecision {
+    /// Walk on as if there were no filter.
+    Keep,
+    /// Leave the file out; a directory is still walked into.
+    SkipFile,
+    /// Do not walk into the directory; a file is left out.
+    PruneDir,
+}
 
-        let canon = path;
+type Filter = Box<dyn FnMut(&Path, &Metadata) -> FilterDecision + Send>;
 
-        let path_chars: Vec<char> = canon.to_str().unwrap().chars().collect();
+/// The predicates added with [`Paths::with_filter`], in order.
+#[derive(Default)]
+struct Filters(Vec<Filter>);
 
-        self.matches_ex(0, &mut 0, &path_chars)
+impl fmt::Debug for Filters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Filters({})", self.0.len())
     }
+}
 
-    fn matches_ex(
-        &self,
-        mut pattern_idx: usize,
-        text_idx: &mut usize,
-        text: &Vec<char>,
-    ) -> Result<bool, GlobError> {
-
-        while pattern_idx < self.pattern_chars.len() && *text_idx < text.len() {
+impl Filters {
+    /// The first decision other than `Keep`, or `Keep`.
+    fn decide(&mut self, path: &Path, meta: &Metadata) -> FilterDecision {
+        self.0
+            .iter_mut()
+            .map(|filter| filter(path, meta))
+            .find(|decision| *decision != FilterDecision::Keep)
+            .unwrap_or(FilterDecision::Keep)
+    }
+}
 
-        if pattern_idx == self.pattern_chars.len()-1 && self.pattern_chars[pattern_idx] == '*' {
-            return Ok(true);
-        }
+/// Whether [`Paths::one_file_system`] can tell file systems apart on this
+/// platform; where it cannot, the option does nothing.
+pub const ONE_FILE_SYSTEM_SUPPORTED: bool = cfg!(unix);
 
-            match self.pattern_chars[pattern_idx] {
-                '*' => {
-                    if self
-                        .matches_ex(pattern_idx + 1, text_idx, text)
-                        .is_ok_and(|x| x)
-                    {
-                        return Ok(true);
-                    }
-                    *text_idx += 1;
-                }
-                '[' => {
-                    pattern_idx += 1;
-                    let mut matched = false;
-                    while pattern_idx < self.pattern_chars.len()
-                        && *text_idx < text.len()
-                        && self.pattern_chars[pattern_idx] != ']'
-                    {
-                        if self.pattern_chars[pattern_idx] == text[*text_idx] {
-                            matched = true;
-                            *text_idx += 1;
-                        }
-                        pattern_idx += 1;
-                    }
+/// The id of the device holding the file, `st_dev` on Unix.
+#[cfg(unix)]
+fn device(_: &Path, meta: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(meta.dev())
+}
 
-                    if !matched {
-                        return Ok(false);
-                    }
+#[cfg(not(unix))]
+fn device(_: &Path, _: &Metadata) -> Option<u64> {
+    None
+}
 
-                    while self.pattern_chars[pattern_idx] != ']' {
-                        pattern_idx += 1;
-                    }
+/// Reads the entries of `path` sorted by name, so files are walked, and so
+/// listed, in the same order on every platform and every run, whatever
+/// order `read_dir` gives.
+fn read_dir_sorted(path: &Path) -> Result<vec::IntoIter<PathBuf>, GlobError> {
+    let entries = fs::read_dir(path).map_err(|err| GlobError {
+        msg: format!("Failed to read directory: '{}': {}", path.display(), err),
+    })?;
 
-                    pattern_idx += 1;
-                }
-                ']' => {
-                    //TODO: return err
-                    panic!("Standalone ']' is not allowed!");
-                }
-                '?' => {
-                    pattern_idx += 1;
-                    *text_idx += 1;
-                }
-                _ => {
-                    if self.pattern_chars[pattern_idx] != text[*text_idx] {
-                        return Ok(false);
-                    }
-                    pattern_idx += 1;
-                    *text_idx += 1;
-                }
-            }
-        }
+    let mut paths: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    paths.sort();
 
-        let have_pattern_left = pattern_idx < self.pattern_chars.len();
-        let have_text_left = *text_idx < text.len();
+    Ok(paths.into_iter())
+}
 
-        if !have_pattern_left && !have_text_left {
-            return Ok(true);
+impl Paths {
+    pub fn matches(&self, path: &Path) -> Result<bool, GlobError> {
+        if !path.is_file() {
+            return Err(GlobError {
+                msg: format!("Paths to dir are not yet supported: '{}'", path.display()),
+            });
         }
 
-        if have_text_left {
-            if pattern_idx < self.pattern_chars.len() {
-                while self.pattern_chars[pattern_idx] == '*' {
-                    pattern_idx += 1;
-                }
-                if pattern_idx >= self.pattern_chars.len() {
+        self.matches_path(path)
+    }
+
+    /// Whether `path` matches, without looking at the file system.
+    fn matches_path(&self, path: &Path) -> Result<bool, GlobError> {
+        // Patterns are written without the verbatim prefix.
+        let path = &strip_verbatim(path);
+        if let Some(pattern) = &self.anchored {
+            let below_root = path
+                .strip_prefix(&self.root)
+                .ok()
+                .filter(|relative| !relative.as_os_str().is_empty());
+            if let Some(relative) = below_root {
+                if matches_components(pattern, &components(relative))? {
                     return Ok(true);
                 }
             }
+            return matches_components(pattern, &components(path));
         }
 
-        Ok(false)
+        let path_chars: Vec<char> = path.to_string_lossy().chars().collect();
+
+        matches_ex(&self.pattern_chars, 0, &mut 0, &path_chars)
+    }
+
+    /// Whether files below the directory at `dir` may match an anchored
+    /// pattern, tested the way [`Paths::matches_path`] tests files. A walk
+    /// for `src/parser/*.rs` never reads `tests` or `src/lexer`.
+    fn may_match_below(&self, dir: &Path) -> bool {
+        let Some(pattern) = &self.anchored else {
+            return true;
+        };
+        let dir = &strip_verbatim(dir);
+        let below_root = dir.strip_prefix(&self.root).ok();
+        below_root.is_some_and(|relative| may_match_below(pattern, &components(relative)))
+            || may_match_below(pattern, &components(dir))
     }
 
-    pub fn new(pattern: &'a str, path: &'a PathBuf) -> Self {
-        let is_wildcard = pattern.contains('*') || pattern.contains('?') || pattern.contains('[');
-        let components: Vec<&str> = pattern.split('/').collect();
+    /// Whether the walk leaves out the directory at `dir`: its name or its
+    /// path below the root, with `/` separators, matches an excluded pattern.
+    fn is_excluded(&self, dir: &Path) -> bool {
+        if self.exclude_dirs.is_empty() {
+            return false;
+        }
+        let name: Vec<char> = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().chars().collect())
+            .unwrap_or_default();
+        let dir = strip_verbatim(dir);
+        let relative: Vec<char> = dir
+            .strip_prefix(&self.root)
+            .unwrap_or(&dir)
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+            .chars()
+            .collect();
+
+        self.exclude_dirs.iter().any(|pattern| {
+            matches_ex(pattern, 0, &mut 0, &name).unwrap_or(false)
+                || matches_ex(pattern, 0, &mut 0, &relative).unwrap_or(false)
+        })
+    }
 
+    pub fn new(pattern: &str, path: &Path) -> Result<Self, GlobError> {
         let mut queque: VecDeque<PathEntry> = VecDeque::new();
 
         if path.is_file() {
-            queque.push_back(PathEntry::File(path.clone()));
+            queque.push_back(PathEntry::File(path.to_path_buf()));
         }
 
         if path.is_dir() {
-            let iter = fs::read_dir(path).expect(&format!(
-                "Failed to read directory: '{}'",
-                path.to_str().unwrap()
-            ));
-            queque.push_back(PathEntry::Dir(iter));
+            queque.push_back(PathEntry::Dir(read_dir_sorted(path)?, 0));
         }
 
+        Ok(Self {
+            entries_to_process: queque,
+            ..Self::unwalked(pattern, path)
+        })
+    }
+
+    /// A walk of `root` that has nothing left to visit.
+    fn unwalked(pattern: &str, root: &Path) -> Self {
         Self {
             pattern_chars: pattern.chars().collect(),
-            is_wildcard,
-            components,
-            path,
-            entries_to_process: queque,
+            anchored: anchored(pattern),
+            entries_to_process: VecDeque::new(),
+            max_depth: None,
+            root: strip_verbatim(root),
+            exclude_dirs: vec![],
+            root_device: None,
+            device,
+            filters: Filters::default(),
+            relative_to: None,
+        }
+    }
+
+    /// The directory or file the walk starts from: the one it was given, or
+    /// the literal start of an absolute pattern.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Only yields files at most `max_depth` levels below the root: 1 means
+    /// the files directly in it. `None` walks the whole tree.
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Does not descend into directories whose name or path below the root
+    /// matches one of `patterns`, so their contents are never read. The root
+    /// itself is always walked.
+    pub fn exclude_dirs<S: AsRef<str>>(mut self, patterns: &[S]) -> Self {
+        self.exclude_dirs = patterns
+            .iter()
+            .map(|pattern| pattern.as_ref().trim_end_matches('/').chars().collect())
+            .collect();
+        self
+    }
+
+    /// Does not descend into directories on another file system than the
+    /// root, such as `/proc` or network mounts below `/`. Does nothing where
+    /// [`ONE_FILE_SYSTEM_SUPPORTED`] is false.
+    pub fn one_file_system(mut self, one_file_system: bool) -> Self {
+        self.root_device = match one_file_system {
+            true => fs::metadata(&self.root)
+                .ok()
+                .and_then(|meta| (self.device)(&self.root, &meta)),
+            false => None,
+        };
+        self
+    }
+
+    /// Yields paths relative to the root, such as `src/main.rs` rather than
+    /// `../proj/src/main.rs`. Both are made absolute with
+    /// [`lexical_absolute`] first, so a root given as `.` or with `..` in it
+    /// is cut off too. A root that is itself a file is yielded as given.
+    pub fn relative(mut self, relative: bool) -> Self {
+        self.relative_to = match relative {
+            true => lexical_absolute(&self.root).ok(),
+            false => None,
+        };
+        self
+    }
+
+    /// `path` as it is yielded; see [`Paths::relative`].
+    fn yielded(&self, path: &Path) -> PathBuf {
+        let relative = self.relative_to.as_ref().and_then(|root| {
+            let path = lexical_absolute(path).ok()?;
+            let relative = path.strip_prefix(root).ok()?;
+            (!relative.as_os_str().is_empty()).then(|| relative.to_path_buf())
+        });
+        relative.unwrap_or_else(|| path.to_path_buf())
+    }
+
+    /// Asks `filter` about every file and directory the walk finds, before a
+    /// file is matched against the pattern or a directory is read. The root
+    /// given to the walk is not filtered. Filters added earlier are asked
+    /// first, and the first decision other than `Keep` stands.
+    pub fn with_filter(
+        mut self,
+        filter: impl FnMut(&Path, &Metadata) -> FilterDecision + Send + 'static,
+    ) -> Self {
+        self.filters.0.push(Box::new(filter));
+        self
+    }
+
+    /// Leaves out the files and directories `ignore` matches; an ignored
+    /// directory is not walked into. Added as a filter, see
+    /// [`Paths::with_filter`].
+    pub fn ignore(self, ignore: Ignore) -> Self {
+        if ignore.is_empty() {
+            return self;
+        }
+        self.with_filter(move |path, meta| match ignore.is_ignored(path, meta.is_dir()) {
+            true => FilterDecision::PruneDir,
+            false => FilterDecision::Keep,
+        })
+    }
+
+    /// Whether a directory found by the walk is on the root's file system,
+    /// or the walk may leave it.
+    fn on_root_device(&self, dir: &Path, meta: &Metadata) -> bool {
+        self.root_device
+            .map_or(true, |root| (self.device)(dir, meta).map_or(true, |dev| dev == root))
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c == '/' || c == std::path::MAIN_SEPARATOR
+}
+
+fn matches_ex(
+    pattern_chars: &[char],
+    mut pattern_idx: usize,
+    text_idx: &mut usize,
+    text: &[char],
+) -> Result<bool, GlobError> {
+    while pattern_idx < pattern_chars.len() && *text_idx < text.len() {
+
+    if pattern_idx == pattern_chars.len()-1 && pattern_chars[pattern_idx] == '*' {
+        return Ok(true);
+    }
+
+        match pattern_chars[pattern_idx] {
+            '*' => {
+                // Each try at the rest starts where the star stopped.
+                let mut rest_idx = *text_idx;
+                if matches_ex(pattern_chars, pattern_idx + 1, &mut rest_idx, text)
+                    .is_ok_and(|x| x)
+                {
+                    return Ok(true);
+                }
+                *text_idx += 1;
+            }
+            '[' => {
+                pattern_idx += 1;
+                let mut matched = false;
+                while pattern_idx < pattern_chars.len() && pattern_chars[pattern_idx] != ']' {
+                    matched |= pattern_chars[pattern_idx] == text[*text_idx];
+                    pattern_idx += 1;
+                }
+
+                if !matched {
+                    return Ok(false);
+                }
+
+                *text_idx += 1;
+                pattern_idx += 1;
+            }
+            ']' => {
+                return Err(GlobError {
+                    msg: "Standalone ']' is not allowed!".to_string(),
+                });
+            }
+            // One character of a name, never a separator.
+            '?' if is_separator(text[*text_idx]) => return Ok(false),
+            '?' => {
+                pattern_idx += 1;
+                *text_idx += 1;
+            }
+            // `/` and `\` are the same separator on Windows.
+            c if is_separator(c) && is_separator(text[*text_idx]) => {
+                pattern_idx += 1;
+                *text_idx += 1;
+            }
+            _ => {
+                if pattern_chars[pattern_idx] != text[*text_idx] {
+                    return Ok(false);
+                }
+                pattern_idx += 1;
+                *text_idx += 1;
+            }
         }
     }
+
+    // Trailing stars match the rest of the text, or nothing.
+    let stars = pattern_chars[pattern_idx..].iter().take_while(|c| **c == '*').count();
+    let have_pattern_left = pattern_idx + stars < pattern_chars.len();
+    let have_text_left = *text_idx < text.len();
+
+    Ok(!have_pattern_left && (!have_text_left || stars > 0))
 }
 
-impl<'a> Iterator for Paths<'a> {
+impl Iterator for Paths {
     type Item = PathBuf;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -162,7 +403,7 @@ impl<'a> Iterator for Paths<'a> {
                 PathEntry::File(file_path) => match self.matches(file_path) {
                     Ok(matched) => {
                         if matched {
-                            return Some(file_path.clone());
+                            return Some(self.yielded(file_path));
                         }
                     }
                     Err(err) => {
@@ -170,25 +411,33 @@ impl<'a> Iterator for Paths<'a> {
                         return None;
                     }
                 },
-                PathEntry::Dir(dir_iter) => match dir_iter.next() {
-                    Some(entry) => {
+                PathEntry::Dir(dir_iter, depth) => {
+                    let depth = *depth + 1;
+                    if let Some(entry) = dir_iter.next() {
                         to_append.push_back(current_entry);
-                        if let Ok(x) = entry {
-                            let meta = x.metadata().expect("Cannot read metadata of: '{}'");
+                        // Entries that vanished or cannot be read are skipped rather
+                        // than aborting the whole traversal. Links are not followed,
+                        // as one to a parent directory would be walked forever.
+                        if let Ok(meta) = fs::symlink_metadata(&entry) {
+                            let decision = self.filters.decide(&entry, &meta);
                             if meta.is_file() {
-                                to_append.push_back(PathEntry::File(x.path()));
-                            }
-                            if meta.is_dir() {
-                                let iter = fs::read_dir(x.path()).expect(&format!(
-                                    "Failed to read directory: '{}'",
-                                    x.path().to_str().unwrap()
-                                ));
-                                to_append.push_back(PathEntry::Dir(iter));
+                                if decision == FilterDecision::Keep {
+                                    to_append.push_back(PathEntry::File(entry));
+                                }
+                            } else if meta.is_dir()
+                                && decision != FilterDecision::PruneDir
+                                && self.max_depth.map_or(true, |max| depth < max)
+                                && !self.is_excluded(&entry)
+                                && self.may_match_below(&entry)
+                                && self.on_root_device(&entry, &meta)
+                            {
+                                if let Ok(iter) = read_dir_sorted(&entry) {
+                                    to_append.push_back(PathEntry::Dir(iter, depth));
+                                }
                             }
                         }
                     }
-                    None => {}
-                },
+                }
             }
             self.entries_to_process.append(&mut to_append);
         }
@@ -206,130 +455,907 @@ impl<'a> Iterator for Paths<'a> {
  *
  */
 
-pub fn glob<'a>(pattern: &'a str, path: &'a PathBuf) -> Result<Paths<'a>, GlobError> {
+/// Whether the file at `path` matches `pattern`, tested the way a walk tests
+/// the files it finds.
+pub fn matches(pattern: &str, path: &Path) -> Result<bool, GlobError> {
+    Paths::unwalked(pattern, Path::new("")).matches(path)
+}
+
+/// `path` with a verbatim Windows prefix (`\\?\C:`, `\\?\UNC\server\share`)
+/// written the usual way (`C:`, `\\server\share`), so a path from
+/// `fs::canonicalize` matches and compares like the one a user types. Other
+/// paths are returned as they are.
+pub fn strip_verbatim(path: &Path) -> PathBuf {
+    let mut components = path.components();
+    let prefix = match components.next() {
+        Some(Component::Prefix(prefix)) => prefix,
+        _ => return path.to_path_buf(),
+    };
+    let mut stripped = match prefix.kind() {
+        Prefix::VerbatimDisk(drive) => PathBuf::from(format!("{}:", drive as char)),
+        Prefix::VerbatimUNC(server, share) => {
+            let mut unc = OsString::from(r"\\");
+            unc.push(server);
+            unc.push(r"\");
+            unc.push(share);
+            PathBuf::from(unc)
+        }
+        _ => return path.to_path_buf(),
+    };
+    stripped.extend(components);
+    stripped
+}
+
+/// `path` made absolute against the working directory, with `.` and `..`
+/// resolved by name only: symlinks are not followed and the file need not
+/// exist. Verbatim prefixes are written the usual way; see [`strip_verbatim`].
+pub fn lexical_absolute(path: &Path) -> io::Result<PathBuf> {
+    let path = strip_verbatim(path);
+    let mut absolute = match path.is_absolute() {
+        true => PathBuf::new(),
+        // `C:file` is relative to the working directory of drive C, which
+        // only the platform knows.
+        false if matches!(path.components().next(), Some(Component::Prefix(_))) => {
+            return std::path::absolute(&path);
+        }
+        false => env::current_dir()?,
+    };
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                absolute.pop();
+            }
+            component => absolute.push(component),
+        }
+    }
+    Ok(absolute)
+}
+
+/// The components of `pattern` if it is anchored: relative, with a
+/// separator in it. `.` components are dropped.
+fn anchored(pattern: &str) -> Option<Vec<Vec<char>>> {
+    if !pattern.contains(is_separator) || absolute_prefix(pattern, cfg!(windows)).is_some() {
+        return None;
+    }
+    let components = pattern
+        .split(is_separator)
+        .filter(|component| !component.is_empty() && *component != ".")
+        .map(|component| component.chars().collect())
+        .collect();
+    Some(components)
+}
+
+/// The names `path` is made of, without `.` components.
+fn components(path: &Path) -> Vec<Vec<char>> {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .map(|component| component.as_os_str().to_string_lossy().chars().collect())
+        .collect()
+}
+
+/// Matches a pattern against a path one component at a time: a `**`
+/// component matches any number of path components, any other exactly one.
+fn matches_components(pattern: &[Vec<char>], path: &[Vec<char>]) -> Result<bool, GlobError> {
+    match (pattern.split_first(), path.split_first()) {
+        (None, _) => Ok(path.is_empty()),
+        (Some((first, rest)), _) if first[..] == ['*', '*'] => {
+            for skipped in 0..=path.len() {
+                if matches_components(rest, &path[skipped..])? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        (Some(_), None) => Ok(false),
+        (Some((first, rest)), Some((component, path_rest))) => {
+            Ok(matches_ex(first, 0, &mut 0, component)? && matches_components(rest, path_rest)?)
+        }
+    }
+}
+
+/// Whether a path below the directory `dir` may match `pattern`, both split
+/// into components: `dir` matches the pattern's leading components, or runs
+/// into a `**`. Errs on the side of walking a directory.
+fn may_match_below(pattern: &[Vec<char>], dir: &[Vec<char>]) -> bool {
+    match (pattern.split_first(), dir.split_first()) {
+        (Some((first, _)), _) if first[..] == ['*', '*'] => true,
+        // The pattern is spent, so only `dir` itself could match.
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some((first, rest)), Some((component, dir_rest))) => {
+            matches_ex(first, 0, &mut 0, component).unwrap_or(true)
+                && may_match_below(rest, dir_rest)
+        }
+    }
+}
+
+/// The directories an absolute pattern starts with: everything up to the
+/// last separator before the first wildcard, such as `/var/log` for
+/// `/var/log/*/*.log`. `None` for a relative pattern. With `windows`, `\` is
+/// a separator too and drive letters make a pattern absolute (`C:\logs` for
+/// `C:\logs\*.txt`).
+fn absolute_prefix(pattern: &str, windows: bool) -> Option<&str> {
+    let is_separator = |c: char| c == '/' || (windows && c == '\\');
+    let root_len = match pattern.as_bytes() {
+        [first, ..] if is_separator(*first as char) => 1,
+        [drive, b':', separator, ..]
+            if windows && drive.is_ascii_alphabetic() && is_separator(*separator as char) =>
+        {
+            3
+        }
+        _ => return None,
+    };
+
+    let literal = pattern.find(['*', '?', '[']).unwrap_or(pattern.len());
+    let end = pattern[..literal].rfind(is_separator).unwrap_or(0);
+    // A bare root keeps its separator: `/`, `C:\`.
+    Some(&pattern[..end.max(root_len)])
+}
+
+/// A path with wildcards in it split for [`glob`]: the literal directories it
+/// starts with, to walk, and a pattern for the rest, as `src` and `**/*.rs`
+/// for `src/**/*.rs`. The pattern stays anchored below those directories, so
+/// `src/*.rs` gives `./*.rs` and only matches files directly in `src`, as a
+/// shell would. `None` for a path without `*`, `?` or `[`.
+pub fn split_glob(path: &str) -> Option<(&str, String)> {
+    let wildcard = path.find(['*', '?', '['])?;
+    let dir = match absolute_prefix(path, cfg!(windows)) {
+        Some(prefix) => prefix,
+        None => path[..wildcard].rfind(is_separator).map_or(".", |end| &path[..end]),
+    };
+    let rest = match dir {
+        "." => path,
+        dir => path[dir.len()..].trim_start_matches(is_separator),
+    };
+    let pattern = match rest.contains(is_separator) {
+        true => rest.to_string(),
+        false => format!("./{rest}"),
+    };
+    Some((dir, pattern))
+}
+
+/// Walks `path` for files matching `pattern`.
+///
+/// A relative pattern with a separator is anchored, as in gitignore: it is
+/// matched one component at a time against the path below the root, or the
+/// whole path as walked, so `src/*.rs` matches `src/main.rs` but neither
+/// `vendor/src/lib.rs` nor `src/bin/main.rs`. A `**` component matches any
+/// number of directories: `src/**/*.rs` matches all three. Directories no
+/// match can be below, such as `vendor` for `src/*.rs`, are not read. Other
+/// patterns are matched against the whole path as walked.
+///
+/// An absolute pattern picks its own root: the walk starts at the literal
+/// directories it begins with and `path` is not used, so `/var/log/*.log`
+/// never walks outside `/var/log`.
+///
+/// The walk keeps its own copy of what it needs, so it borrows neither
+/// `pattern` nor `path`.
+pub fn glob(pattern: &str, path: &Path) -> Result<Paths, GlobError> {
+    let path = absolute_prefix(pattern, cfg!(windows)).map_or(path, Path::new);
     if !path.exists() {
         return Err(GlobError {
-            msg: format!("Path: '{}' does not exist!", path.to_str().unwrap()),
+            msg: format!("Path: '{}' does not exist!", path.display()),
         });
     }
 
     let mut chars = pattern.chars();
     while let Some(c) = chars.next() {
         match c {
-            '[' => {
-                if chars.find(|v| *v == ']').is_none() {
-                    return Err(GlobError {
-                        msg: format!("Invalid pattern, '[' needs a matching brace"),
-                    });
-                }
+            '[' if !chars.any(|v| v == ']') => {
+                return Err(GlobError {
+                    msg: "Invalid pattern, '[' needs a matching brace".to_string(),
+                });
+            }
+            ']' => {
+                return Err(GlobError {
+                    msg: "Invalid pattern, standalone ']' is not allowed".to_string(),
+                });
             }
             _ => {}
         }
     }
 
-    let paths = Paths::new(pattern, path);
-
-    Ok(paths)
+    Paths::new(pattern, path)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Test paths are written with '/' and converted to the platform separator.
+    fn os(path: &str) -> String {
+        path.replace('/', std::path::MAIN_SEPARATOR_STR)
+    }
+
+    /// What a row of [`CONFORMANCE`] follows.
+    #[derive(Clone, Copy, PartialEq)]
+    enum Rule {
+        /// The POSIX rules, which the `glob` crate follows too.
+        Posix,
+        /// A rule of bolg's own, as in ripgrep and gitignore, or a POSIX
+        /// feature bolg does not support yet.
+        Bolg,
+    }
+
+    /// How bolg matches: (pattern, path, expected, rule). A change to what a
+    /// pattern matches comes with its rows here.
+    const CONFORMANCE: &[(&str, &str, bool, Rule)] = &[
+        // Literals match themselves, case-sensitively.
+        ("abc", "abc", true, Rule::Posix),
+        ("abc", "abd", false, Rule::Posix),
+        ("abc", "abcd", false, Rule::Posix),
+        ("abc", "ab", false, Rule::Posix),
+        ("*.RS", "main.rs", false, Rule::Posix),
+        // `*` matches any run of characters, including none.
+        ("*", "abc", true, Rule::Posix),
+        ("a*", "a", true, Rule::Posix),
+        ("a*c", "abbc", true, Rule::Posix),
+        ("a*c", "abcd", false, Rule::Posix),
+        ("*ab", "aab", true, Rule::Posix),
+        ("*a*b*", "xaxbx", true, Rule::Posix),
+        ("*.rs", "main.rs", true, Rule::Posix),
+        ("*.rs", "main.rsx", false, Rule::Posix),
+        // `?` matches one character, which may take several bytes.
+        ("?", "a", true, Rule::Posix),
+        ("??", "a", false, Rule::Posix),
+        ("a?c", "abc", true, Rule::Posix),
+        ("a?c", "ac", false, Rule::Posix),
+        ("ś?", "śż", true, Rule::Posix),
+        // A class matches one of its characters.
+        ("[ab]", "a", true, Rule::Posix),
+        ("[ab]", "c", false, Rule::Posix),
+        ("[ab]", "ab", false, Rule::Posix),
+        ("[ab]x", "bx", true, Rule::Posix),
+        ("*.[ch]", "x.c", true, Rule::Posix),
+        // Ranges and negated classes are not supported yet: `-` and `!` are
+        // members like any other character.
+        ("[a-c]", "b", false, Rule::Bolg),
+        ("[a-c]", "-", true, Rule::Bolg),
+        ("[!a]", "b", false, Rule::Bolg),
+        ("[!a]", "!", true, Rule::Bolg),
+        // Wildcards match a leading dot, as in gitignore.
+        ("*", ".hidden", true, Rule::Posix),
+        ("*.rs", ".main.rs", true, Rule::Posix),
+        ("?hidden", ".hidden", true, Rule::Posix),
+        // A pattern without a separator is matched against the whole path,
+        // and its `*` crosses separators; its `?` never does.
+        ("*.rs", "src/main.rs", true, Rule::Posix),
+        ("src*", "src/bin/main.rs", true, Rule::Posix),
+        ("a?b", "a/b", false, Rule::Bolg),
+        // A pattern with a separator is anchored and matched a component at
+        // a time; `**` matches any number of components.
+        ("src/*.rs", "src/main.rs", true, Rule::Posix),
+        ("src/*.rs", "src/bin/main.rs", false, Rule::Posix),
+        ("src/*.rs", "vendor/src/lib.rs", false, Rule::Posix),
+        ("src/**/*.rs", "src/main.rs", true, Rule::Posix),
+        ("src/**/*.rs", "src/bin/main.rs", true, Rule::Posix),
+        ("**/*.rs", "a/b/c.rs", true, Rule::Posix),
+        ("src/**", "src/a/b", true, Rule::Posix),
+        ("s?c/*.rs", "src/main.rs", true, Rule::Posix),
+        ("./src/*.rs", "src/main.rs", true, Rule::Bolg),
+        ("src//*.rs", "src/main.rs", true, Rule::Bolg),
+    ];
+
+    fn conforms(pattern: &str, path: &str) -> bool {
+        let path = PathBuf::from(os(path));
+        Paths::unwalked(&os(pattern), Path::new("")).matches_path(&path).unwrap()
+    }
+
+    #[test]
+    fn conformance() {
+        for (pattern, path, expected, _) in CONFORMANCE {
+            assert_eq!(conforms(pattern, path), *expected, "'{pattern}' on '{path}'");
+        }
+    }
+
+    /// The `glob` crate agrees on the POSIX rows, with `*` crossing
+    /// separators unless the pattern is anchored.
+    #[test]
+    fn conformance_agrees_with_the_glob_crate() {
+        for (pattern, path, _, rule) in CONFORMANCE {
+            if *rule != Rule::Posix {
+                continue;
+            }
+            let options = ::glob::MatchOptions {
+                case_sensitive: true,
+                require_literal_separator: pattern.contains('/'),
+                require_literal_leading_dot: false,
+            };
+            let expected = ::glob::Pattern::new(pattern).unwrap().matches_with(path, options);
+            assert_eq!(conforms(pattern, path), expected, "'{pattern}' on '{path}'");
+        }
+    }
+
+    #[test]
+    fn lexical_absolute_resolves_dots_by_name() {
+        let cwd = env::current_dir().unwrap();
+
+        assert_eq!(lexical_absolute(Path::new("a/./b/../c")).unwrap(), cwd.join("a/c"));
+        assert_eq!(lexical_absolute(Path::new("../x")).unwrap(), cwd.parent().unwrap().join("x"));
+        assert_eq!(lexical_absolute(Path::new("/a/../b")).unwrap(), PathBuf::from("/b"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn lexical_absolute_keeps_windows_prefixes() {
+        let tests = vec![
+            (r"C:\proj\..\src/main.rs", r"C:\src\main.rs"),
+            (r"C:\..\..\x", r"C:\x"),
+            (r"\\?\C:\proj\.\src", r"C:\proj\src"),
+            (r"\\server\share\logs\..\a.txt", r"\\server\share\a.txt"),
+            (r"\\?\UNC\server\share\logs", r"\\server\share\logs"),
+        ];
+
+        for (path, expected) in tests {
+            assert_eq!(
+                lexical_absolute(Path::new(path)).unwrap(),
+                PathBuf::from(expected),
+                "{path}"
+            );
+        }
+        assert!(lexical_absolute(Path::new("C:file")).unwrap().is_absolute());
+    }
+
+    #[test]
+    fn relative_paths_drop_the_root() {
+        let walk = |pattern: &str, root: &Path| -> Vec<PathBuf> {
+            glob(pattern, root).unwrap().relative(true).collect()
+        };
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("sub")).unwrap();
+        fs::write(root.path().join("a.txt"), "").unwrap();
+        fs::write(root.path().join("sub").join("b.txt"), "").unwrap();
+        let expected = vec![PathBuf::from("a.txt"), PathBuf::from(os("sub/b.txt"))];
+
+        assert_eq!(walk("*.txt", root.path()), expected);
+        assert_eq!(walk("*.txt", &root.path().join("sub").join("..")), expected);
+        assert_eq!(walk("*.toml", Path::new(".")), vec![PathBuf::from("Cargo.toml")]);
+        assert_eq!(
+            walk("*/Cargo.toml", Path::new("..")),
+            vec![PathBuf::from(os("bolg/Cargo.toml")), PathBuf::from(os("perg/Cargo.toml"))]
+        );
+    }
+
+    #[test]
+    fn relative_root_file_is_yielded_as_given() {
+        let file = PathBuf::from(os("../../test_files/ext/file.a"));
+
+        let found: Vec<PathBuf> = glob("*", &file).unwrap().relative(true).collect();
+
+        assert_eq!(found, vec![file]);
+    }
+
     #[test]
     fn glob_returns_error_on_invalid_pattern() {
-        let x = PathBuf::from("..\\..\\test_files");
+        let x = PathBuf::from(os("../../test_files"));
         let result = glob("*.[abc", &x);
 
         assert!(result.is_err());
     }
 
+    #[test]
+    fn glob_returns_error_on_standalone_closing_brace() {
+        let x = PathBuf::from(os("../../test_files"));
+        let result = glob("*.abc]", &x);
+
+        assert!(result.is_err_and(|err| err.msg.contains("standalone ']'")));
+    }
+
+    #[test]
+    fn matches_tests_a_single_file() {
+        let file = PathBuf::from(os("../../test_files/ext/file.a"));
+
+        assert!(matches("*.[ab]", &file).unwrap());
+        assert!(matches("*ext*", &file).unwrap());
+        assert!(!matches("*.c", &file).unwrap());
+    }
+
+    #[test]
+    fn glob_returns_error_on_missing_path() {
+        let x = PathBuf::from(os("../../test_files/does_not_exist"));
+        let result = glob("*", &x);
+
+        assert!(result.is_err_and(|err| err.msg.contains("does not exist")));
+    }
+
+    #[test]
+    fn absolute_prefix_is_the_literal_start_of_the_pattern() {
+        let tests = vec![
+            ("/var/log/**/*.log", false, Some("/var/log")),
+            ("/var/log/syslog", false, Some("/var/log")),
+            ("/*.log", false, Some("/")),
+            ("/var/l?g/*.log", false, Some("/var")),
+            ("*.log", false, None),
+            ("logs/*.log", false, None),
+            ("C:\\logs\\**\\*.txt", true, Some("C:\\logs")),
+            ("C:/logs/app/[ab].txt", true, Some("C:/logs/app")),
+            ("C:\\*.txt", true, Some("C:\\")),
+            ("\\logs\\*.txt", true, Some("\\logs")),
+            ("C:\\logs\\*.txt", false, None),
+            ("logs\\*.txt", true, None),
+        ];
+
+        for (pattern, windows, expected) in tests {
+            assert_eq!(absolute_prefix(pattern, windows), expected, "{pattern}");
+        }
+    }
+
+    #[test]
+    fn absolute_pattern_walks_from_its_own_prefix() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["logs/app", "other"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+            fs::write(root.path().join(dir).join("f.log"), "").unwrap();
+        }
+        let logs = root.path().join("logs");
+        let pattern = format!("{}{}*.log", logs.display(), std::path::MAIN_SEPARATOR);
+
+        let found: Vec<PathBuf> = glob(&pattern, &root.path().join("other")).unwrap().collect();
+
+        assert_eq!(found, vec![logs.join("app").join("f.log")]);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn strip_verbatim_writes_prefixes_the_usual_way() {
+        let tests = vec![
+            (r"\\?\C:\proj\src", r"C:\proj\src"),
+            (r"\\?\UNC\server\share\logs", r"\\server\share\logs"),
+            (r"C:\proj", r"C:\proj"),
+            (r"\\server\share\logs", r"\\server\share\logs"),
+            (r"proj\src", r"proj\src"),
+        ];
+
+        for (path, expected) in tests {
+            assert_eq!(strip_verbatim(Path::new(path)), PathBuf::from(expected), "{path}");
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn drive_roots_match_with_either_separator() {
+        let pattern: Vec<char> = "C:/Users/*/proj/*.rs".chars().collect();
+        let file: Vec<char> = r"C:\Users\me\proj\main.rs".chars().collect();
+        assert!(matches_ex(&pattern, 0, &mut 0, &file).unwrap());
+
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("main.rs"), "").unwrap();
+        let mixed = format!("{}/*.rs", root.path().display());
+        assert_eq!(glob(&mixed, Path::new(".")).unwrap().count(), 1);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn verbatim_roots_are_walked_and_matched_like_plain_ones() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("src")).unwrap();
+        fs::write(root.path().join("src").join("main.rs"), "").unwrap();
+        let verbatim = fs::canonicalize(root.path()).unwrap();
+        assert!(verbatim.to_string_lossy().starts_with(r"\\?\"));
+
+        let found: Vec<PathBuf> = glob("src/*.rs", &verbatim).unwrap().collect();
+        assert_eq!(found.len(), 1);
+        let plain = strip_verbatim(&verbatim);
+        let pattern = format!(r"{}\src\*.rs", plain.display());
+        assert_eq!(glob(&pattern, &verbatim).unwrap().count(), 1);
+        let excluded = glob("*.rs", &verbatim).unwrap().exclude_dirs(&["src"]).count();
+        assert_eq!(excluded, 0);
+    }
+
+    #[test]
+    fn split_glob_walks_the_literal_directories() {
+        let tests = vec![
+            ("src/**/*.rs", Some(("src", "**/*.rs"))),
+            ("src/*.rs", Some(("src", "./*.rs"))),
+            ("crates/*/src/*.rs", Some(("crates", "*/src/*.rs"))),
+            ("*.rs", Some((".", "./*.rs"))),
+            ("s?c/a.rs", Some((".", "s?c/a.rs"))),
+            ("./src/[ab].rs", Some(("./src", "./[ab].rs"))),
+            ("src/main.rs", None),
+            ("src", None),
+        ];
+
+        for (path, expected) in tests {
+            let split = split_glob(path);
+            assert_eq!(
+                split
+                    .as_ref()
+                    .map(|(dir, pattern)| (*dir, pattern.as_str())),
+                expected,
+                "{path}"
+            );
+        }
+        #[cfg(unix)]
+        assert_eq!(split_glob("/var/log/*.log"), Some(("/var/log", "./*.log".to_string())));
+        #[cfg(unix)]
+        assert_eq!(split_glob("/*.log"), Some(("/", "./*.log".to_string())));
+    }
+
+    #[test]
+    fn split_globs_match_like_the_path() {
+        let (dir, pattern) = split_glob("test_files/*").unwrap();
+
+        let found: Vec<PathBuf> = glob(&pattern, &Path::new("../..").join(dir)).unwrap().collect();
+        assert!(!found.is_empty());
+        // Not the files in `test_files/nested`.
+        assert!(
+            found
+                .iter()
+                .all(|path| path.parent() == Some(&Path::new("../..").join(dir))),
+            "{found:?}"
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn unc_patterns_walk_from_the_share() {
+        let pattern = r"\\server\share\logs\*.txt";
+
+        assert_eq!(absolute_prefix(pattern, true), Some(r"\\server\share\logs"));
+        assert!(Path::new(absolute_prefix(pattern, true).unwrap()).is_absolute());
+    }
+
     #[test]
     fn glob_matches_folder() {
-        let result: Vec<PathBuf> = glob("*\\nested\\*", &PathBuf::from("..\\..\\test_files"))
+        let result: Vec<PathBuf> = glob(&os("nested/*"), &PathBuf::from(os("../../test_files")))
             .unwrap()
-            .into_iter()
             .collect();
 
-        let result_string: Vec<&str> = result.iter().map(|p| p.to_str().unwrap()).collect();
+        let result_string: Vec<String> = result
+            .iter()
+            .map(|p| p.to_str().unwrap().to_string())
+            .collect();
 
         assert_eq!(
             result_string,
             vec![
-                "..\\..\\test_files\\nested\\c.w3c",
-                "..\\..\\test_files\\nested\\d.cpp",
-                "..\\..\\test_files\\nested\\f.cpp",
-                "..\\..\\test_files\\nested\\f.h",
+                os("../../test_files/nested/c.w3c"),
+                os("../../test_files/nested/d.cpp"),
+                os("../../test_files/nested/f.cpp"),
+                os("../../test_files/nested/f.h"),
             ]
         );
     }
 
     #[test]
     fn glob_matches_given_extentions() {
-        let result: Vec<PathBuf> = glob("*.[abc]", &PathBuf::from("..\\..\\test_files"))
+        let result: Vec<PathBuf> = glob(&os("*.[abc]"), &PathBuf::from(os("../../test_files")))
             .unwrap()
-            .into_iter()
             .collect();
 
-        let result_string: Vec<&str> = result.iter().map(|p| p.to_str().unwrap()).collect();
+        let result_string: Vec<String> = result
+            .iter()
+            .map(|p| p.to_str().unwrap().to_string())
+            .collect();
 
         assert_eq!(
             result_string,
             vec![
-                "..\\..\\test_files\\ext\\file.a",
-                "..\\..\\test_files\\ext\\file.b",
-                "..\\..\\test_files\\ext\\file.c"
+                os("../../test_files/ext/file.a"),
+                os("../../test_files/ext/file.b"),
+                os("../../test_files/ext/file.c")
             ]
         );
     }
 
+    #[test]
+    fn patterns_with_a_separator_are_anchored_to_the_root() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["src/deep", "vendor/src", "src/src"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+            fs::write(root.path().join(dir).join("f.rs"), "").unwrap();
+        }
+        fs::write(root.path().join("src").join("main.rs"), "").unwrap();
+        let found = |pattern: &str| -> Vec<PathBuf> {
+            glob(&os(pattern), root.path())
+                .unwrap()
+                .map(|path| path.strip_prefix(root.path()).unwrap().to_path_buf())
+                .collect()
+        };
+
+        assert_eq!(found("src/*.rs"), [os("src/main.rs")].map(PathBuf::from));
+        assert_eq!(found("./src/*.rs"), [os("src/main.rs")].map(PathBuf::from));
+        assert_eq!(
+            found("*/src/*.rs"),
+            [os("src/src/f.rs"), os("vendor/src/f.rs")].map(PathBuf::from)
+        );
+        assert_eq!(
+            found("src/**/*.rs"),
+            [os("src/deep/f.rs"), os("src/main.rs"), os("src/src/f.rs")].map(PathBuf::from)
+        );
+        assert_eq!(found("*.rs").len(), 4);
+    }
+
+    #[test]
+    fn anchored_patterns_never_read_unrelated_directories() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["src/parser/deep", "src/lexer", "tests", "target/src/parser"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+            fs::write(root.path().join(dir).join("f.rs"), "").unwrap();
+        }
+        fs::write(root.path().join("src/parser/f.rs"), "").unwrap();
+        let visited = |pattern: &str| -> (Vec<PathBuf>, Vec<PathBuf>) {
+            let asked = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+            let (seen, base) = (asked.clone(), root.path().to_path_buf());
+            let found = glob(&os(pattern), root.path())
+                .unwrap()
+                .with_filter(move |path, _| {
+                    seen.lock().unwrap().push(path.strip_prefix(&base).unwrap().to_path_buf());
+                    FilterDecision::Keep
+                })
+                .map(|path| path.strip_prefix(root.path()).unwrap().to_path_buf())
+                .collect();
+            let asked = asked.lock().unwrap().clone();
+            (found, asked)
+        };
+        // Whether some entry of the directory `dir` was asked about, so it was read.
+        let read = |asked: &[PathBuf], dir: &str| {
+            asked
+                .iter()
+                .any(|path| path.parent() == Some(Path::new(&os(dir))))
+        };
+
+        let (found, asked) = visited("src/parser/*.rs");
+        assert_eq!(found, [os("src/parser/f.rs")].map(PathBuf::from));
+        assert!(read(&asked, "src/parser"));
+        for dir in ["src/lexer", "tests", "target", "src/parser/deep"] {
+            assert!(!read(&asked, dir), "{dir} was read");
+        }
+
+        let (found, asked) = visited("src/**/*.rs");
+        assert_eq!(found.len(), 3);
+        assert!(read(&asked, "src/parser/deep"));
+        assert!(!read(&asked, "target") && !read(&asked, "tests"));
+
+        let (found, asked) = visited("*/parser/*.rs");
+        assert_eq!(found, [os("src/parser/f.rs")].map(PathBuf::from));
+        assert!(read(&asked, "target") && !read(&asked, "target/src"));
+        assert!(!read(&asked, "src/lexer"));
+    }
+
     #[test]
     fn glob_exact_match() {
-        let result: Vec<PathBuf> = glob("..\\..\\test_files\\nested\\f.h", &PathBuf::from("..\\..\\test_files"))
-            .unwrap()
-            .into_iter()
-            .collect();
+        let result: Vec<PathBuf> = glob(
+            &os("../../test_files/nested/f.h"),
+            &PathBuf::from(os("../../test_files")),
+        )
+        .unwrap()
+        .collect();
 
-        let result_string: Vec<&str> = result.iter().map(|p| p.to_str().unwrap()).collect();
+        let result_string: Vec<String> = result
+            .iter()
+            .map(|p| p.to_str().unwrap().to_string())
+            .collect();
 
-        assert_eq!(result_string, vec!["..\\..\\test_files\\nested\\f.h"]);
+        assert_eq!(result_string, vec![os("../../test_files/nested/f.h")]);
     }
 
     #[test]
     fn glob_question_mark_skipes_two_chars() {
-        let result: Vec<PathBuf> = glob("..\\..\\test_files\\a??a", &PathBuf::from("..\\..\\test_files"))
-            .unwrap()
-            .into_iter()
-            .collect();
+        let result: Vec<PathBuf> = glob(
+            &os("../../test_files/a??a"),
+            &PathBuf::from(os("../../test_files")),
+        )
+        .unwrap()
+        .collect();
 
-        let result_string: Vec<&str> = result.iter().map(|p| p.to_str().unwrap()).collect();
+        let result_string: Vec<String> = result
+            .iter()
+            .map(|p| p.to_str().unwrap().to_string())
+            .collect();
 
         assert_eq!(
             result_string,
-            vec!["..\\..\\test_files\\abba", "..\\..\\test_files\\acca"]
+            vec![os("../../test_files/abba"), os("../../test_files/acca")]
         );
     }
 
+    #[test]
+    fn question_mark_never_matches_a_separator() {
+        let matches = |pattern: &str, text: &str| {
+            let pattern: Vec<char> = pattern.chars().collect();
+            let text: Vec<char> = text.chars().collect();
+            matches_ex(&pattern, 0, &mut 0, &text).unwrap()
+        };
+
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", &os("a/c")));
+        assert!(matches("??", "ab"));
+        assert!(!matches("??", &os("a/")));
+        assert!(!matches("a??", &os("a/b")));
+        assert!(matches("*a?c", &os("x/abc")));
+        assert!(!matches("*a?c", &os("x/a/c")));
+    }
+
+    #[test]
+    fn glob_question_mark_stays_within_a_name() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir(root.path().join("a")).unwrap();
+        fs::write(root.path().join("a").join("c"), "").unwrap();
+        fs::write(root.path().join("abc"), "").unwrap();
+
+        let found: Vec<PathBuf> = glob("*a?c", root.path()).unwrap().collect();
+
+        assert_eq!(found, vec![root.path().join("abc")]);
+    }
+
     #[test]
     fn glob_question_mark_skipes_one_chars() {
-        let result: Vec<PathBuf> = glob("*a????", &PathBuf::from("..\\..\\test_files"))
+        let result: Vec<PathBuf> = glob(&os("*a????"), &PathBuf::from(os("../../test_files")))
             .unwrap()
-            .into_iter()
             .collect();
 
-        let result_string: Vec<&str> = result.iter().map(|p| p.to_str().unwrap()).collect();
+        let result_string: Vec<String> = result
+            .iter()
+            .map(|p| p.to_str().unwrap().to_string())
+            .collect();
+
+        assert_eq!(result_string, vec![os("../../test_files/a.txt")]);
+    }
+
+    /// Creates `depth` nested directories under `root`, each holding `f.txt`.
+    fn deep_tree(root: &Path, depth: usize) {
+        let mut dir = root.to_path_buf();
+        for level in 0..depth {
+            dir.push(format!("d{level}"));
+            fs::create_dir(&dir).unwrap();
+            fs::write(dir.join("f.txt"), "").unwrap();
+        }
+    }
+
+    #[test]
+    fn glob_walks_a_deep_tree() {
+        let root = tempfile::tempdir().unwrap();
+        deep_tree(root.path(), 200);
+
+        assert_eq!(glob("*.txt", root.path()).unwrap().count(), 200);
+    }
+
+    #[test]
+    fn glob_stops_at_max_depth() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("top.txt"), "").unwrap();
+        deep_tree(root.path(), 5);
+
+        let depth_of = |max| glob("*.txt", root.path()).unwrap().max_depth(max).count();
+        assert_eq!(depth_of(Some(1)), 1);
+        assert_eq!(depth_of(Some(3)), 3);
+        assert_eq!(depth_of(None), 6);
+    }
+
+    #[test]
+    fn glob_prunes_excluded_directories() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["src", "target/debug", "crates/a/target", "crates/a/src"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+            fs::write(root.path().join(dir).join("f.txt"), "").unwrap();
+        }
+
+        let found = |excluded: &[&str]| -> Vec<PathBuf> {
+            glob("*.txt", root.path())
+                .unwrap()
+                .exclude_dirs(excluded)
+                .map(|path| path.strip_prefix(root.path()).unwrap().to_path_buf())
+                .collect()
+        };
+
+        let by_name = found(&["target"]);
+        assert_eq!(by_name, [os("crates/a/src/f.txt"), os("src/f.txt")].map(PathBuf::from));
+        let by_path = found(&["crates/*/target/"]);
+        assert_eq!(by_path.len(), 3);
+        assert!(!by_path.contains(&PathBuf::from(os("crates/a/target/f.txt"))));
+    }
+
+    #[test]
+    fn glob_walks_an_excluded_root() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("target");
+        fs::create_dir(&target).unwrap();
+        fs::write(target.join("f.txt"), "").unwrap();
+
+        assert_eq!(glob("*.txt", &target).unwrap().exclude_dirs(&["target"]).count(), 1);
+    }
+
+    #[test]
+    fn with_filter_keeps_skips_and_prunes() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["src", "vendor/lib"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+            fs::write(root.path().join(dir).join("small.txt"), "").unwrap();
+            fs::write(root.path().join(dir).join("large.txt"), "0123456789").unwrap();
+        }
+        let names = |paths: Paths| -> Vec<PathBuf> {
+            paths
+                .map(|path| path.strip_prefix(root.path()).unwrap().to_path_buf())
+                .collect()
+        };
+
+        let kept = glob("*.txt", root.path())
+            .unwrap()
+            .with_filter(|_, _| FilterDecision::Keep);
+        assert_eq!(names(kept).len(), 4);
+
+        let small =
+            glob("*.txt", root.path())
+                .unwrap()
+                .with_filter(|_, meta| match meta.len() > 5 {
+                    true => FilterDecision::SkipFile,
+                    false => FilterDecision::Keep,
+                });
+        assert_eq!(
+            names(small),
+            [os("src/small.txt"), os("vendor/lib/small.txt")].map(PathBuf::from)
+        );
 
-        assert_eq!(result_string, vec!["..\\..\\test_files\\a.txt"]);
+        let pruned = glob("*.txt", root.path()).unwrap().with_filter(|path, _| {
+            // The pruned directory is never read, so nothing in it is asked about.
+            assert!(!path.to_string_lossy().contains("lib"));
+            match path.ends_with("vendor") {
+                true => FilterDecision::PruneDir,
+                false => FilterDecision::Keep,
+            }
+        });
+        assert_eq!(
+            names(pruned),
+            [os("src/large.txt"), os("src/small.txt")].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn glob_stays_on_the_root_file_system() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["local", "mnt/remote"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+            fs::write(root.path().join(dir).join("f.txt"), "").unwrap();
+        }
+        // Pretends `mnt` is a mount point; the root is device 1.
+        fn mounted(path: &Path, _: &Metadata) -> Option<u64> {
+            Some(if path.ends_with("mnt") { 2 } else { 1 })
+        }
+
+        let walk = |one_file_system| {
+            let mut paths = glob("*.txt", root.path()).unwrap();
+            paths.device = mounted;
+            paths.one_file_system(one_file_system).count()
+        };
+        assert_eq!(walk(false), 2);
+        assert_eq!(walk(true), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn glob_skips_unreadable_directories_and_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let root = tempfile::tempdir().unwrap();
+        deep_tree(root.path(), 2);
+        let locked = root.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::write(locked.join("secret.txt"), "").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // A cycle, were symlinks followed.
+        symlink(root.path(), root.path().join("d0").join("loop")).unwrap();
+
+        // Permissions do not stop root from reading it.
+        let readable = fs::read_dir(&locked).is_ok();
+        let found: Vec<PathBuf> = glob("*.txt", root.path()).unwrap().collect();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+        assert!(found.iter().all(|path| !path.to_string_lossy().contains("loop")));
+        assert_eq!(found.len(), if readable { 3 } else { 2 });
     }
 
     #[test]
     fn glob_print_only_h_files() {
-        let result: Vec<PathBuf> = glob("*.h", &PathBuf::from("..\\..\\test_files"))
+        let result: Vec<PathBuf> = glob(&os("*.h"), &PathBuf::from(os("../../test_files")))
             .unwrap()
-            .into_iter()
             .collect();
 
-        let result_string: Vec<&str> = result.iter().map(|p| p.to_str().unwrap()).collect();
-        assert_eq!(result_string, vec!["..\\..\\test_files\\nested\\f.h"]);
+        let result_string: Vec<String> = result
+            .iter()
+            .map(|p| p.to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(result_string, vec![os("../../test_files/nested/f.h")]);
     }
 }
@@ -0,0 +1,130 @@
+#ifndef PERG_H
+#define PERG_H
+
+/* Generated by cbindgen from src/capi.rs; do not edit. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Matches ignoring case.
+ */
+#define PERG_IGNORE_CASE 1
+
+/**
+ * Matches only at the start of each line.
+ */
+#define PERG_ANCHORED (1 << 1)
+
+/**
+ * What a call returns when it fails. Calls that answer yes or no return 1
+ * or 0 instead of `Ok`.
+ */
+typedef enum perg_status {
+  PERG_STATUS_OK = 0,
+  /**
+   * A pointer that must be valid was NULL.
+   */
+  PERG_STATUS_NULL_POINTER = -1,
+  /**
+   * The pattern or haystack is not valid UTF-8.
+   */
+  PERG_STATUS_INVALID_UTF8 = -2,
+  /**
+   * The pattern does not compile.
+   */
+  PERG_STATUS_INVALID_PATTERN = -3,
+  /**
+   * perg panicked; the call had no effect.
+   */
+  PERG_STATUS_PANIC = -4,
+} perg_status;
+
+/**
+ * A compiled pattern, shared by any number of threads. Free it with
+ * [`perg_regex_free`].
+ */
+typedef struct perg_regex perg_regex;
+
+/**
+ * The byte span `from..to` of a match within line `line`, counted from 0.
+ */
+typedef struct perg_match {
+  size_t line;
+  size_t from;
+  size_t to;
+} perg_match;
+
+/**
+ * Compiles `pattern` with `flags`, a combination of `PERG_IGNORE_CASE` and
+ * `PERG_ANCHORED`. Returns NULL on failure and, unless `error_out` is NULL,
+ * stores a message there for [`perg_error_free`].
+ *
+ * # Safety
+ *
+ * `pattern` must be NULL or a NUL-terminated string, and `error_out` NULL
+ * or valid for a write.
+ */
+struct perg_regex *perg_regex_compile(const char *pattern, uint32_t flags, char **error_out);
+
+/**
+ * 1 if `re` matches anywhere in the `len` bytes at `haystack`, 0 if not,
+ * or a negative status on failure.
+ *
+ * # Safety
+ *
+ * `re` must be NULL or come from [`perg_regex_compile`] and not be freed,
+ * and `haystack` point to `len` readable bytes.
+ */
+int32_t perg_regex_is_match(const struct perg_regex *re, const uint8_t *haystack, size_t len);
+
+/**
+ * Finds every match of `re` in the `len` bytes at `haystack`, with lines
+ * separated by `\n`. Stores an array of them in `out_matches`, to free with
+ * [`perg_matches_free`], and their number in `out_count`; with no match the
+ * array is NULL.
+ *
+ * # Safety
+ *
+ * `re` must be NULL or come from [`perg_regex_compile`] and not be freed,
+ * `haystack` point to `len` readable bytes, and `out_matches` and
+ * `out_count` be NULL or valid for a write.
+ */
+enum perg_status perg_regex_find_matches(const struct perg_regex *re,
+                                         const uint8_t *haystack,
+                                         size_t len,
+                                         struct perg_match **out_matches,
+                                         size_t *out_count);
+
+/**
+ * Frees a pattern from [`perg_regex_compile`]; NULL is ignored.
+ *
+ * # Safety
+ *
+ * `re` must be NULL or come from [`perg_regex_compile`], and not be used
+ * again.
+ */
+void perg_regex_free(struct perg_regex *re);
+
+/**
+ * Frees the `count` matches [`perg_regex_find_matches`] stored in
+ * `matches`; NULL is ignored.
+ *
+ * # Safety
+ *
+ * `matches` and `count` must be what one call stored, and the matches not
+ * be used again.
+ */
+void perg_matches_free(struct perg_match *matches, size_t count);
+
+/**
+ * Frees a message [`perg_regex_compile`] stored; NULL is ignored.
+ *
+ * # Safety
+ *
+ * `message` must be NULL or come from [`perg_regex_compile`], and not be
+ * used again.
+ */
+void perg_error_free(char *message);
+
+#endif  /* PERG_H */
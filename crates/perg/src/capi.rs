@@ -0,0 +1,243 @@
+//! A C API for perg's matcher, for editor plugins and other native callers.
+//! Built with the `capi` feature; `include/perg.h` declares it. A shared
+//! library comes from
+//!
+//! ```text
+//! cargo rustc -p perg --lib --release --features capi --crate-type cdylib
+//! ```
+//!
+//! Patterns and haystacks are UTF-8: the pattern NUL-terminated, the
+//! haystack a pointer and a length in bytes. Offsets in matches are bytes
+//! within their line. No panic crosses into C; it is reported as
+//! [`PergStatus::Panic`] instead.
+
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::{ptr, slice, str};
+
+use crate::matcher::{build_matcher, Engine, Match, Matcher};
+use crate::nfa::{Anchor, NfaOptions};
+
+/// Matches ignoring case.
+pub const PERG_IGNORE_CASE: u32 = 1;
+/// Matches only at the start of each line.
+pub const PERG_ANCHORED: u32 = 1 << 1;
+
+/// What a call returns when it fails. Calls that answer yes or no return 1
+/// or 0 instead of `Ok`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PergStatus {
+    Ok = 0,
+    /// A pointer that must be valid was NULL.
+    NullPointer = -1,
+    /// The pattern or haystack is not valid UTF-8.
+    InvalidUtf8 = -2,
+    /// The pattern does not compile.
+    InvalidPattern = -3,
+    /// perg panicked; the call had no effect.
+    Panic = -4,
+}
+
+/// A compiled pattern, shared by any number of threads. Free it with
+/// [`perg_regex_free`].
+pub struct PergRegex {
+    matcher: Arc<dyn Matcher>,
+}
+
+/// The byte span `from..to` of a match within line `line`, counted from 0.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PergMatch {
+    pub line: usize,
+    pub from: usize,
+    pub to: usize,
+}
+
+impl From<Match> for PergMatch {
+    fn from(m: Match) -> Self {
+        Self {
+            line: m.line,
+            from: m.from,
+            to: m.to,
+        }
+    }
+}
+
+/// Runs `f`, turning a panic into [`PergStatus::Panic`].
+fn guard<T>(f: impl FnOnce() -> Result<T, PergStatus>) -> Result<T, PergStatus> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(Err(PergStatus::Panic))
+}
+
+/// The `len` bytes at `haystack` as text.
+///
+/// # Safety
+///
+/// `haystack` must point to `len` readable bytes, or be NULL with `len` 0.
+unsafe fn haystack<'a>(haystack: *const u8, len: usize) -> Result<&'a str, PergStatus> {
+    if haystack.is_null() {
+        return match len {
+            0 => Ok(""),
+            _ => Err(PergStatus::NullPointer),
+        };
+    }
+    str::from_utf8(slice::from_raw_parts(haystack, len)).map_err(|_| PergStatus::InvalidUtf8)
+}
+
+/// Compiles `pattern` with `flags`, a combination of `PERG_IGNORE_CASE` and
+/// `PERG_ANCHORED`. Returns NULL on failure and, unless `error_out` is NULL,
+/// stores a message there for [`perg_error_free`].
+///
+/// # Safety
+///
+/// `pattern` must be NULL or a NUL-terminated string, and `error_out` NULL
+/// or valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn perg_regex_compile(
+    pattern: *const c_char,
+    flags: u32,
+    error_out: *mut *mut c_char,
+) -> *mut PergRegex {
+    let mut message = None;
+    let compiled = guard(|| {
+        if pattern.is_null() {
+            return Err(PergStatus::NullPointer);
+        }
+        let pattern = CStr::from_ptr(pattern).to_str().map_err(|_| PergStatus::InvalidUtf8)?;
+        let options = NfaOptions {
+            ignore_case: flags & PERG_IGNORE_CASE != 0,
+            anchored: match flags & PERG_ANCHORED {
+                0 => Anchor::None,
+                _ => Anchor::Start,
+            },
+            ..Default::default()
+        };
+        build_matcher(pattern, &options, Engine::Auto).map_err(|err| {
+            message = Some(err.error.to_string());
+            PergStatus::InvalidPattern
+        })
+    });
+
+    match compiled {
+        Ok(matcher) => Box::into_raw(Box::new(PergRegex { matcher })),
+        Err(status) => {
+            if !error_out.is_null() {
+                let message = message.unwrap_or_else(|| match status {
+                    PergStatus::NullPointer => "the pattern is NULL".to_string(),
+                    PergStatus::InvalidUtf8 => "the pattern is not valid UTF-8".to_string(),
+                    _ => "perg panicked".to_string(),
+                });
+                // A message holding NUL is cut there.
+                let message = CString::new(message).unwrap_or_else(|err| {
+                    let end = err.nul_position();
+                    CString::new(&err.into_vec()[..end]).unwrap_or_default()
+                });
+                *error_out = message.into_raw();
+            }
+            ptr::null_mut()
+        }
+    }
+}
+
+/// 1 if `re` matches anywhere in the `len` bytes at `haystack`, 0 if not,
+/// or a negative status on failure.
+///
+/// # Safety
+///
+/// `re` must be NULL or come from [`perg_regex_compile`] and not be freed,
+/// and `haystack` point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn perg_regex_is_match(
+    re: *const PergRegex,
+    haystack: *const u8,
+    len: usize,
+) -> i32 {
+    let found = guard(|| {
+        let re = re.as_ref().ok_or(PergStatus::NullPointer)?;
+        Ok(re.matcher.is_match(self::haystack(haystack, len)?))
+    });
+    match found {
+        Ok(found) => i32::from(found),
+        Err(status) => status as i32,
+    }
+}
+
+/// Finds every match of `re` in the `len` bytes at `haystack`, with lines
+/// separated by `\n`. Stores an array of them in `out_matches`, to free with
+/// [`perg_matches_free`], and their number in `out_count`; with no match the
+/// array is NULL.
+///
+/// # Safety
+///
+/// `re` must be NULL or come from [`perg_regex_compile`] and not be freed,
+/// `haystack` point to `len` readable bytes, and `out_matches` and
+/// `out_count` be NULL or valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn perg_regex_find_matches(
+    re: *const PergRegex,
+    haystack: *const u8,
+    len: usize,
+    out_matches: *mut *mut PergMatch,
+    out_count: *mut usize,
+) -> PergStatus {
+    let found = guard(|| {
+        let re = re.as_ref().ok_or(PergStatus::NullPointer)?;
+        if out_matches.is_null() || out_count.is_null() {
+            return Err(PergStatus::NullPointer);
+        }
+        let matches = re.matcher.find_matches(self::haystack(haystack, len)?);
+        Ok(matches.into_iter().map(PergMatch::from).collect::<Box<[_]>>())
+    });
+    match found {
+        Ok(matches) => {
+            *out_count = matches.len();
+            *out_matches = match matches.is_empty() {
+                true => ptr::null_mut(),
+                false => Box::into_raw(matches).cast(),
+            };
+            PergStatus::Ok
+        }
+        Err(status) => status,
+    }
+}
+
+/// Frees a pattern from [`perg_regex_compile`]; NULL is ignored.
+///
+/// # Safety
+///
+/// `re` must be NULL or come from [`perg_regex_compile`], and not be used
+/// again.
+#[no_mangle]
+pub unsafe extern "C" fn perg_regex_free(re: *mut PergRegex) {
+    if !re.is_null() {
+        drop(Box::from_raw(re));
+    }
+}
+
+/// Frees the `count` matches [`perg_regex_find_matches`] stored in
+/// `matches`; NULL is ignored.
+///
+/// # Safety
+///
+/// `matches` and `count` must be what one call stored, and the matches not
+/// be used again.
+#[no_mangle]
+pub unsafe extern "C" fn perg_matches_free(matches: *mut PergMatch, count: usize) {
+    if !matches.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(matches, count)));
+    }
+}
+
+/// Frees a message [`perg_regex_compile`] stored; NULL is ignored.
+///
+/// # Safety
+///
+/// `message` must be NULL or come from [`perg_regex_compile`], and not be
+/// used again.
+#[no_mangle]
+pub unsafe extern "C" fn perg_error_free(message: *mut c_char) {
+    if !message.is_null() {
+        drop(CString::from_raw(message));
+    }
+}
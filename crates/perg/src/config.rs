@@ -0,0 +1,192 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::Command;
+use thiserror::Error;
+use toml::Value;
+
+/// Names the config file to read instead of the one in the user's config
+/// directory.
+pub const CONFIG_PATH_VAR: &str = "PERG_CONFIG_PATH";
+
+/// Skips the config file.
+pub const NO_CONFIG: &str = "--no-config";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("config file {}: {source}", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("config file {}: {message}", path.display())]
+    Parse { path: PathBuf, message: String },
+    #[error("config file {}: unknown option '{key}'", path.display())]
+    UnknownOption { path: PathBuf, key: String },
+    #[error("config file {}: '{key}' {expected}", path.display())]
+    InvalidValue {
+        path: PathBuf,
+        key: String,
+        expected: &'static str,
+    },
+}
+
+/// `args` with the options of the config file put in front of the ones
+/// given, so those on the command line win: a later value replaces an
+/// earlier one, and a list such as `--exclude-dir` grows. The file is
+/// `$PERG_CONFIG_PATH`, or `perg/config.toml` in the user's config directory
+/// if it exists. Nothing is read with `--no-config`, or for a subcommand such
+/// as `perg match`, whose options are its own.
+///
+/// Every key is the long name of an option of `command`, with `_` or `-`
+/// between words: `true` turns a flag on, and an option takes a string, a
+/// number or a list of them, like `exclude_dir = ["target", ".git"]`.
+pub fn with_config(args: Vec<OsString>, command: &Command) -> Result<Vec<OsString>, ConfigError> {
+    let skipped = args.iter().skip(1).take_while(|arg| *arg != "--").any(|arg| arg == NO_CONFIG)
+        || args
+            .get(1)
+            .and_then(|arg| arg.to_str())
+            .is_some_and(|arg| command.find_subcommand(arg).is_some());
+    if skipped {
+        return Ok(args);
+    }
+    let (path, required) = match env::var_os(CONFIG_PATH_VAR).filter(|path| !path.is_empty()) {
+        Some(path) => (PathBuf::from(path), true),
+        None => match default_config_path() {
+            Some(path) => (path, false),
+            None => return Ok(args),
+        },
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if !required && err.kind() == io::ErrorKind::NotFound => return Ok(args),
+        Err(source) => return Err(ConfigError::Read { path, source }),
+    };
+
+    let mut args = args.into_iter();
+    let program = args.next();
+    Ok(program.into_iter().chain(config_args(&path, &text, command)?).chain(args).collect())
+}
+
+/// `perg/config.toml` in `$XDG_CONFIG_HOME` or `~/.config`, or in
+/// `%APPDATA%` on Windows.
+fn default_config_path() -> Option<PathBuf> {
+    let non_empty = |var| env::var_os(var).filter(|value| !value.is_empty());
+    let dir = match cfg!(windows) {
+        true => non_empty("APPDATA").map(PathBuf::from),
+        false => non_empty("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| non_empty("HOME").map(|home| Path::new(&home).join(".config"))),
+    };
+    Some(dir?.join("perg").join("config.toml"))
+}
+
+/// The command line arguments the config file at `path`, holding `text`,
+/// stands for.
+fn config_args(path: &Path, text: &str, command: &Command) -> Result<Vec<OsString>, ConfigError> {
+    let table: toml::Table = text.parse().map_err(|err: toml::de::Error| ConfigError::Parse {
+        path: path.to_path_buf(),
+        message: err.to_string().trim_end().to_string(),
+    })?;
+
+    let mut args = vec![];
+    for (key, value) in table {
+        let long = key.replace('_', "-");
+        let invalid = |expected| ConfigError::InvalidValue {
+            path: path.to_path_buf(),
+            key: key.clone(),
+            expected,
+        };
+        let arg = command
+            .get_arguments()
+            .filter(|arg| !matches!(arg.get_id().as_str(), "help" | "version" | "no_config"))
+            .find(|arg| arg.get_long() == Some(long.as_str()))
+            .ok_or_else(|| ConfigError::UnknownOption {
+                path: path.to_path_buf(),
+                key: key.clone(),
+            })?;
+
+        if !arg.get_action().takes_values() {
+            match value {
+                Value::Boolean(true) => args.push(format!("--{long}").into()),
+                Value::Boolean(false) => {}
+                _ => return Err(invalid("must be true or false")),
+            }
+            continue;
+        }
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::String(value) => value,
+                Value::Integer(value) => value.to_string(),
+                Value::Float(value) => value.to_string(),
+                _ => return Err(invalid("must be a string, a number or a list of them")),
+            };
+            args.push(format!("--{long}={value}").into());
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction};
+
+    fn command() -> Command {
+        Command::new("perg")
+            .arg(Arg::new("trim").long("trim").action(ArgAction::SetTrue))
+            .arg(Arg::new("context").short('C').long("context"))
+            .arg(Arg::new("exclude_dir").long("exclude-dir").action(ArgAction::Append))
+            .arg(Arg::new("no_config").long("no-config").action(ArgAction::SetTrue))
+    }
+
+    fn args(text: &str) -> Result<Vec<OsString>, ConfigError> {
+        config_args(Path::new("config.toml"), text, &command())
+    }
+
+    #[test]
+    fn keys_become_long_options() {
+        let text = "trim = true\ncontext = 2\nexclude-dir = [\"target\", \"-x\"]\n";
+
+        assert_eq!(
+            args(text).unwrap(),
+            vec!["--context=2", "--exclude-dir=target", "--exclude-dir=-x", "--trim"]
+        );
+        assert_eq!(args("trim = false\nexclude_dir = []").unwrap(), Vec::<OsString>::new());
+    }
+
+    #[test]
+    fn invalid_options_name_the_file_and_key() {
+        let tests = vec![
+            ("smart_case = true", "config file config.toml: unknown option 'smart_case'"),
+            ("no_config = true", "config file config.toml: unknown option 'no_config'"),
+            ("trim = 1", "config file config.toml: 'trim' must be true or false"),
+            (
+                "context = { lines = 2 }",
+                "config file config.toml: 'context' must be a string, a number or a list of them",
+            ),
+        ];
+
+        for (text, expected) in tests {
+            assert_eq!(args(text).unwrap_err().to_string(), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn parse_errors_give_the_location() {
+        let err = args("trim = true\ncontext = \n").unwrap_err().to_string();
+
+        assert!(
+            err.starts_with("config file config.toml: TOML parse error at line 2, column 11"),
+            "{err}"
+        );
+    }
+}
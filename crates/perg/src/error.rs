@@ -0,0 +1,70 @@
+use std::{io, path::PathBuf};
+
+use bolg::GlobError;
+use thiserror::Error;
+
+use crate::config::ConfigError;
+use crate::re::PatternError;
+
+/// Every failure the search pipeline can report to the user.
+#[derive(Debug, Error)]
+pub enum PergError {
+    #[error("{0}")]
+    Config(#[from] ConfigError),
+    #[error("invalid pattern: {0}")]
+    Pattern(#[from] PatternError),
+    #[error("glob search failed: {0}")]
+    Glob(#[from] GlobError),
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{}: file is not valid UTF-8", path.display())]
+    Encoding { path: PathBuf },
+    /// The file looks binary and binary files are skipped; see
+    /// [`BinaryFiles`](crate::search::BinaryFiles).
+    #[error("{}: binary file", path.display())]
+    Binary { path: PathBuf },
+    #[error("{}: --pre failed: {reason}", path.display())]
+    Preprocessor { path: PathBuf, reason: String },
+    #[error("worker pool failure: {0}")]
+    Worker(String),
+    #[error("failed to write output: {0}")]
+    Output(#[source] io::Error),
+    /// The walk found nothing to search: the globs matched no files, or the
+    /// filters left none of them. `globbed` tells whether any globs were given.
+    #[error(
+        "no {} under {}",
+        if *globbed { "files matched the provided globs" } else { "searchable files" },
+        path.display()
+    )]
+    NoFiles { path: PathBuf, globbed: bool },
+}
+
+impl PergError {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Self::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// Exit code reported by the binary, following grep: 2 means "an error
+    /// occurred". Finding no files to search is 1, as nothing could match.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::NoFiles { .. } => 1,
+            Self::Config(_)
+            | Self::Pattern(_)
+            | Self::Glob(_)
+            | Self::Io { .. }
+            | Self::Encoding { .. }
+            | Self::Binary { .. }
+            | Self::Preprocessor { .. }
+            | Self::Worker(_)
+            | Self::Output(_) => 2,
+        }
+    }
+}
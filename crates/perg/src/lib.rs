@@ -0,0 +1,17 @@
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod config;
+pub mod error;
+pub mod log;
+pub mod matcher;
+pub mod misc;
+pub mod nfa;
+pub mod printer;
+pub mod progress;
+pub mod re;
+pub mod search;
+pub mod searcher;
+pub mod template;
+pub mod watch;
+#[cfg(feature = "wasm")]
+pub mod wasm;
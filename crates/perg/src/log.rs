@@ -0,0 +1,109 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Sets the level when `-v` is not given: `off`, `info` or `debug`.
+pub const LOG_VAR: &str = "PERG_LOG";
+
+/// How much perg says about what it is doing. Every level above `Off` also
+/// shows the ones below it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    #[default]
+    Off,
+    /// A summary: how the patterns were compiled and how many workers ran.
+    Info,
+    /// Everything: files left out and why, and what each worker did.
+    Debug,
+}
+
+impl Level {
+    /// The level `-v` given `count` times asks for.
+    pub fn from_verbosity(count: u8) -> Self {
+        match count {
+            0 => Level::Off,
+            1 => Level::Info,
+            _ => Level::Debug,
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level.trim().to_lowercase().as_str() {
+            "" | "off" => Ok(Level::Off),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(format!("unknown log level '{level}'; expected off, info or debug")),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Level::Off => "off",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        })
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Off as u8);
+
+/// Shows messages up to `level` from now on, in every thread.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether messages at `level` are shown.
+pub fn enabled(level: Level) -> bool {
+    level != Level::Off && level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Writes `message` to stderr if `level` is enabled. Log lines never go to
+/// stdout, which stays clean for pipes and `--json`. Use
+/// [`info!`](crate::info!) and [`debug!`](crate::debug!) instead.
+#[doc(hidden)]
+pub fn write(level: Level, message: fmt::Arguments) {
+    if enabled(level) {
+        let _ = writeln!(io::stderr().lock(), "perg: {level}: {message}");
+    }
+}
+
+/// Logs a message at [`Level::Info`], formatted like `format!`.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Info, format_args!($($arg)*))
+    };
+}
+
+/// Logs a message at [`Level::Debug`], formatted like `format!`.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log::write($crate::log::Level::Debug, format_args!($($arg)*))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_come_from_verbosity_or_a_name() {
+        assert_eq!(Level::from_verbosity(0), Level::Off);
+        assert_eq!(Level::from_verbosity(1), Level::Info);
+        assert_eq!(Level::from_verbosity(3), Level::Debug);
+        assert_eq!(" Debug ".parse(), Ok(Level::Debug));
+        assert_eq!("".parse(), Ok(Level::Off));
+        assert_eq!(
+            "trace".parse::<Level>(),
+            Err("unknown log level 'trace'; expected off, info or debug".to_string())
+        );
+    }
+}
@@ -1,135 +1,780 @@
-use bolg::glob;
-use clap::{command, Parser};
-use futures::executor::{block_on, ThreadPool};
-use futures::future::join_all;
-use futures::task::SpawnExt;
-use lazy_static::lazy_static;
-use nfa::{FileMatch, NfaOptions, NFA};
-use re::regex_to_nfa;
-use std::{collections::HashSet, fs, path::PathBuf};
-
-mod misc;
-mod nfa;
-mod re;
-
-macro_rules! debug_println {
-    ($($arg:tt)*) => (if ::std::cfg!(debug_assertions) { ::std::println!($($arg)*); })
-}
-
-//TODO: determin if file is a text file by checking its contants
-lazy_static! {
-    pub static ref ALLOWED_EXT: HashSet<String> = {
-        let mut m = HashSet::new();
-        for ext in ["txt", "rs", "cpp", "hpp", "h", "json", "xml", "java", "py"] {
-            m.insert(ext.to_string());
-        }
-        m
-    };
-}
+use clap::error::ErrorKind;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand};
+use colored::Color;
+use perg::config::with_config;
+use perg::error::PergError;
+use perg::log::{self, Level, LOG_VAR};
+use perg::matcher::{build_matcher, Engine};
+use perg::nfa::{Anchor, NfaOptions};
+use perg::printer::{format_json, format_span, OutputMode, Printer};
+use perg::progress::{Progress, REDRAW_INTERVAL};
+use perg::re::regex_to_nfa;
+use perg::search::{
+    file_list, search_files_with, search_with, BinaryFiles, MatchRecord, MmapMode, PathDisplay,
+    SearchOptions, Sort, DEFAULT_EXTENSIONS, STDIN_PATH,
+};
+use perg::searcher::{LongLines, MAX_LINE_LEN};
+use perg::template::Template;
+use perg::watch::{clock, wait_for_change, Snapshot, DEBOUNCE, POLL_INTERVAL};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use std::{collections::HashSet, process};
 
 #[derive(Clone, Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Args {
     #[arg(short = 'i', long)]
     ignore_case: bool,
 
+    /// Ignore case unless the pattern has an uppercase letter
+    #[arg(short = 'S', long)]
+    smart_case: bool,
+
     #[arg(short, long, default_value_t = false)]
     recursive: bool,
 
     #[arg(short, long, default_value_t = false)]
     count: bool,
 
-    #[arg(short = 'p')]
-    pattern: String,
+    /// Only print the paths of files with at least one match
+    #[arg(short = 'l', long, conflicts_with = "count")]
+    files_with_matches: bool,
+
+    /// Stop searching a file after this many matches
+    #[arg(short = 'm', long, value_name = "NUM")]
+    max_count: Option<usize>,
+
+    /// Stop the whole search once NUM results are printed: matches, or lines with --count and -l
+    #[arg(long, value_name = "NUM")]
+    max_results: Option<usize>,
+
+    #[arg(short = 'p', required_unless_present = "regexp")]
+    pattern: Option<String>,
+
+    /// Another pattern to search for; may be repeated. Lines matching any pattern are reported
+    #[arg(short = 'e', long, value_name = "PATTERN")]
+    regexp: Vec<String>,
+
+    /// Only report lines that every pattern matches
+    #[arg(long)]
+    all_match: bool,
+
+    /// Highlight the matches of the pattern with this 1-based index, -p first, in COLOR, e.g.
+    /// 2:blue or 3:bright-red; may be repeated
+    #[arg(long, value_name = "INDEX:COLOR", value_parser = parse_pattern_color)]
+    colors: Vec<(usize, Color)>,
 
     #[arg(short = 'C', long, default_value_t = 1)]
     context: u32,
 
+    /// Only search files whose path matches one of these globs; without any, every file under the
+    /// path
     #[arg(short = 'g', long, default_values_t = Vec::<String>::new(), num_args=0..)]
     glob: Vec<String>,
 
-    #[arg()]
-    path: String,
+    /// Also search files with this extension when walking directories; may be repeated
+    #[arg(long, value_name = "EXT")]
+    ext: Vec<String>,
+
+    /// Search files of every extension when walking directories
+    #[arg(long, conflicts_with = "ext")]
+    no_ext_filter: bool,
+
+    /// Do not walk into directories whose name, or path below the searched path, matches GLOB;
+    /// may be repeated
+    #[arg(long, value_name = "GLOB")]
+    exclude_dir: Vec<String>,
+
+    /// Leave out files and directories matched by the gitignore-style rules in FILE, relative to
+    /// its directory; may be repeated, and later files win
+    #[arg(long, value_name = "FILE")]
+    ignore_file: Vec<PathBuf>,
+
+    /// Do not walk into directories on another file system than the searched path, e.g. /proc
+    #[arg(long)]
+    one_file_system: bool,
+
+    /// Descend at most NUM directories below the path; 1 searches only the files directly in it
+    #[arg(long, value_name = "NUM")]
+    max_depth: Option<usize>,
+
+    /// Matching engine; `auto` uses perg's engine and, when built with the
+    /// `fallback-regex` feature, the regex crate for unsupported syntax.
+    #[arg(long, value_enum, default_value_t = Engine::Auto)]
+    engine: Engine,
+
+    /// Also match text within K inserted, deleted or substituted characters of the pattern
+    #[arg(long, visible_alias = "max-errors", value_name = "K", default_value_t = 0)]
+    fuzzy: usize,
+
+    /// Let . and negated sets like [^a] match a newline
+    #[arg(long)]
+    multiline_dotall: bool,
+
+    /// Print every match as `path:line:column:text`
+    #[arg(long, conflicts_with_all = ["count", "files_with_matches", "json"])]
+    vimgrep: bool,
+
+    /// Print every match as a JSON object, one per line
+    #[arg(long, conflicts_with_all = ["count", "files_with_matches"])]
+    json: bool,
+
+    /// Print every match on its own line as TEMPLATE, with {path}, {line}, {column}, {offset},
+    /// {text} or {match} replaced; \t and \n are escapes. Not deduplicated: {path} prints once per
+    /// match
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        conflicts_with_all = ["count", "files_with_matches", "json", "vimgrep"]
+    )]
+    format: Option<Template>,
+
+    /// Search inside gzip-compressed (`.gz`) files
+    #[arg(short = 'z', long)]
+    search_zip: bool,
+
+    /// Search files that are not valid UTF-8, replacing the invalid bytes, instead of skipping
+    /// them, and print the matched lines of binary files instead of `Binary file PATH matches`
+    #[arg(short = 'a', long)]
+    text: bool,
+
+    /// Skip binary files, those with a NUL byte, instead of reporting that they match
+    #[arg(short = 'I', long, conflicts_with = "text")]
+    skip_binary: bool,
+
+    /// Search the standard output of COMMAND run on each file, e.g. a converter from PDF to text
+    #[arg(long, value_name = "COMMAND")]
+    pre: Option<PathBuf>,
+
+    /// Only run --pre on files matching this glob; may be repeated
+    #[arg(long, value_name = "GLOB", requires = "pre")]
+    pre_glob: Vec<String>,
+
+    /// Treat input as NUL-separated records instead of lines
+    #[arg(long)]
+    null_data: bool,
+
+    /// When to memory-map files instead of reading them; needs the `mmap` feature
+    #[arg(long, value_enum, default_value_t = MmapMode::Auto)]
+    mmap: MmapMode,
+
+    /// Lines longer than this many bytes are skipped or truncated while matching; 0 means no limit
+    #[arg(long, value_name = "BYTES", default_value_t = MAX_LINE_LEN)]
+    max_line_len: usize,
+
+    /// What to do with lines longer than --max-line-len
+    #[arg(long, value_enum, default_value_t = LongLines::Skip)]
+    long_lines: LongLines,
+
+    /// Stop searching a file after this long, e.g. 500ms, 2s or 1m; a bare number is seconds
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    timeout: Option<Duration>,
+
+    /// Only match lines START to END, counted from 1 and inclusive, e.g. 10:20, :50 or 100:; may be
+    /// repeated
+    #[arg(long, value_name = "START:END", value_parser = parse_line_range)]
+    line_range: Vec<Range<usize>>,
+
+    /// Do not print warnings about single files, such as those skipped or timed out
+    #[arg(short = 's', long)]
+    no_messages: bool,
+
+    /// Do not print lines longer than this many bytes, only a note
+    #[arg(long, value_name = "NUM")]
+    max_columns: Option<usize>,
+
+    /// Strip leading spaces and tabs from printed lines
+    #[arg(long)]
+    trim: bool,
+
+    /// How to print the paths of files: as given, relative to the working directory, or absolute
+    #[arg(long, value_enum, default_value_t = PathDisplay::Relative)]
+    path_display: PathDisplay,
+
+    /// Order of the results; defaults to `path` when output is not a terminal
+    #[arg(long, value_enum)]
+    sort: Option<Sort>,
+
+    /// Flush output after every line, e.g. when following a pipe
+    #[arg(long)]
+    line_buffered: bool,
+
+    /// Show a status line on stderr while searching, if it is a terminal
+    #[arg(long)]
+    progress: bool,
+
+    /// Print totals after the results
+    #[arg(long)]
+    stats: bool,
+
+    /// Log what perg is doing to stderr; -vv logs more. Defaults to $PERG_LOG: off, info or debug
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Keep running and search again whenever the searched files change; stdout must be a terminal
+    #[arg(long, conflicts_with_all = ["files_from", "files_from0"])]
+    watch: bool,
+
+    /// Search exactly the files listed in FILE, one per line; `-` reads the list from standard
+    /// input
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["files_from0", "glob", "path"])]
+    files_from: Option<PathBuf>,
+
+    /// Like --files-from, but the paths are separated by NUL bytes
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["glob", "path"])]
+    files_from0: Option<PathBuf>,
+
+    /// Do not read the config file, $PERG_CONFIG_PATH or perg/config.toml in the user's config
+    /// directory, whose options otherwise come before those given here
+    #[arg(long)]
+    no_config: bool,
+
+    /// File or directory to search; `-` reads standard input. With *, ? or [ in it, a glob:
+    /// "src/**/*.rs" searches the Rust files below src, and any -g globs are searched for there too
+    #[arg(required_unless_present_any = ["files_from", "files_from0"])]
+    path: Option<String>,
+
+    /// Take the path as it is, even with *, ? or [ in it
+    #[arg(long)]
+    literal_paths: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-async fn find_matches_in_files(chunk: Vec<PathBuf>, args: Args, options: NfaOptions) -> Vec<FileMatch> {
-    let nfa = regex_to_nfa(&args.pattern, &options);
-    let mut output: Vec<FileMatch> = vec![];
-    for file_path in chunk {
-        if let Ok(m) = fs::metadata(&file_path) {
-            if m.is_dir() {
-                continue;
-            }
-            let input = fs::read_to_string(&file_path).expect(&format!(
-                "Failed to read input file: '{}'",
-                file_path.to_str().unwrap()
-            ));
-            let matches = nfa.find_matches(&input);
-            let file_match = FileMatch {
-                file_path: Some(PathBuf::from(file_path)),
-                matches,
-            };
-            output.push(file_match);
+#[derive(Clone, Subcommand, Debug)]
+enum Command {
+    /// Match a pattern against TEXT instead of searching files, and print where it matched
+    Match(MatchArgs),
+}
+
+#[derive(Clone, clap::Args, Debug)]
+struct MatchArgs {
+    #[arg(short = 'p')]
+    pattern: String,
+
+    #[arg(short = 'i', long)]
+    ignore_case: bool,
+
+    /// Ignore case unless the pattern has an uppercase letter, as for a search
+    #[arg(short = 'S', long)]
+    smart_case: bool,
+
+    /// Matching engine, as for a search
+    #[arg(long, value_enum, default_value_t = Engine::Auto)]
+    engine: Engine,
+
+    /// Also match text within K edits of the pattern, as for a search
+    #[arg(long, visible_alias = "max-errors", value_name = "K", default_value_t = 0)]
+    fuzzy: usize,
+
+    /// Print every match as a JSON object, one per line
+    #[arg(long)]
+    json: bool,
+
+    /// Print the automaton the pattern compiles to before the matches
+    #[arg(long, conflicts_with = "json")]
+    debug: bool,
+
+    /// The text to match; standard input when left out
+    text: Option<String>,
+}
+
+impl From<&Args> for NfaOptions {
+    fn from(value: &Args) -> Self {
+        Self {
+            ignore_case: value.ignore_case,
+            smart_case: value.smart_case,
+            count: value.count,
+            context: value.context,
+            anchored: Anchor::None,
+            max_errors: value.fuzzy,
+            literal_escapes: false,
+            dot_matches_newline: value.multiline_dotall,
         }
     }
-    output
 }
 
-fn main() {
-    let executor = ThreadPool::new().unwrap();
-    let args = Args::parse();
+impl From<&Args> for OutputMode {
+    fn from(value: &Args) -> Self {
+        if value.format.is_some() {
+            OutputMode::Template
+        } else if value.vimgrep {
+            OutputMode::Vimgrep
+        } else if value.json {
+            OutputMode::Json
+        } else if value.count {
+            OutputMode::Count
+        } else if value.files_with_matches {
+            OutputMode::FilesWithMatches
+        } else {
+            OutputMode::Matches
+        }
+    }
+}
 
-    let path = PathBuf::from(&args.path);
+/// A duration such as `500ms`, `2s`, `1.5s` or `1m`; a bare number is seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let scale = match unit {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        _ => return Err(format!("unknown unit '{unit}'; expected ms, s or m")),
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .and_then(|number| Duration::try_from_secs_f64(number * scale).ok())
+        .ok_or_else(|| format!("'{value}' is not a duration"))
+}
 
-    let options = NfaOptions::from(&args);
+/// `START:END`, 1-based and inclusive with either bound left out, as the
+/// 0-based range of line numbers it covers.
+fn parse_line_range(value: &str) -> Result<Range<usize>, String> {
+    let (start, end) = value
+        .split_once(':')
+        .ok_or_else(|| format!("'{value}' is not a line range; expected START:END"))?;
+    let bound = |bound: &str, missing: usize| match bound {
+        "" => Ok(missing),
+        _ => bound
+            .parse::<usize>()
+            .ok()
+            .filter(|&line| line > 0)
+            .ok_or_else(|| format!("'{bound}' is not a line number; lines count from 1")),
+    };
+    let (start, end) = (bound(start, 1)?, bound(end, usize::MAX)?);
+    match start <= end {
+        true => Ok(start - 1..end),
+        false => Err(format!("line range '{value}' ends before it starts")),
+    }
+}
 
-    let number_of_available_threads =
-        std::thread::available_parallelism().expect("Cannot determin number of CPU cores");
+/// `INDEX:COLOR`, a 1-based pattern index and a color name, as the 0-based
+/// index and the color.
+fn parse_pattern_color(value: &str) -> Result<(usize, Color), String> {
+    let (index, color) = value
+        .split_once(':')
+        .ok_or_else(|| format!("'{value}' is not a pattern color; expected INDEX:COLOR"))?;
+    let index = index
+        .parse::<usize>()
+        .ok()
+        .filter(|&index| index > 0)
+        .ok_or_else(|| format!("'{index}' is not a pattern index; patterns count from 1"))?;
+    let color = color
+        .replace(['-', '_'], " ")
+        .parse::<Color>()
+        .map_err(|()| {
+            format!("'{color}' is not a color; expected a name such as red or bright-blue")
+        })?;
+    Ok((index - 1, color))
+}
+
+/// The list of files given to `--files-from` or `--files-from0`, and its separator.
+fn file_list_source(args: &Args) -> Option<(&Path, u8)> {
+    match (&args.files_from, &args.files_from0) {
+        (Some(list), _) => Some((list, b'\n')),
+        (None, Some(list)) => Some((list, b'\0')),
+        (None, None) => None,
+    }
+}
 
-    let mut files = vec![];
-    for pattern in &args.glob {
-        let mut matched_files = glob(pattern, &path)
-            .expect("Cannot perform glob search")
-            .collect::<Vec<_>>();
-        files.append(&mut matched_files);
+fn open_file_list(path: &Path) -> Result<Box<dyn BufRead + Send>, PergError> {
+    if path == Path::new("-") {
+        return Ok(Box::new(BufReader::new(io::stdin())));
     }
+    let file = File::open(path).map_err(|err| PergError::io(path, err))?;
+    Ok(Box::new(BufReader::new(file)))
+}
 
-    let mut chunk_size = files.len() / number_of_available_threads;
+/// Exit code after Ctrl-C, as shells report a process killed by SIGINT.
+const INTERRUPTED: i32 = 130;
 
-    if files.len() < number_of_available_threads.get() {
-        chunk_size = files.len();
+/// Makes Ctrl-C stop the search through `cancel` and sets `interrupted`, so
+/// what was found so far still gets printed. A second Ctrl-C exits at once.
+#[cfg(not(target_family = "wasm"))]
+fn handle_interrupt(cancel: Arc<AtomicBool>, interrupted: Arc<AtomicBool>) {
+    let handler = move || {
+        if interrupted.swap(true, Ordering::SeqCst) {
+            process::exit(INTERRUPTED);
+        }
+        cancel.store(true, Ordering::SeqCst);
+    };
+    // Without a handler Ctrl-C still stops perg, just without the summary.
+    if let Err(err) = ctrlc::set_handler(handler) {
+        eprintln!("perg: cannot handle Ctrl-C: {err}");
     }
+}
+
+/// There is no Ctrl-C to handle on wasm32.
+#[cfg(target_family = "wasm")]
+fn handle_interrupt(_cancel: Arc<AtomicBool>, _interrupted: Arc<AtomicBool>) {}
 
-    if chunk_size == 0 {
+/// Lets a Windows console interpret the color escapes. Legacy consoles that
+/// cannot would print them as text, so colors are turned off there instead.
+#[cfg(windows)]
+fn enable_colors() {
+    use windows_sys::Win32::System::Console::{
+        GetConsoleMode, GetStdHandle, SetConsoleMode, ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+        STD_OUTPUT_HANDLE,
+    };
+
+    // Redirected output is no console; the environment decides about colors.
+    if !io::stdout().is_terminal() {
         return;
     }
+    // SAFETY: the handle is only passed back to the console API, and the mode
+    // is written to a local.
+    let enabled = unsafe {
+        let handle = GetStdHandle(STD_OUTPUT_HANDLE);
+        let mut mode = 0;
+        GetConsoleMode(handle, &mut mode) != 0
+            && (mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+                || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0)
+    };
+    if !enabled {
+        colored::control::set_override(false);
+    }
+}
 
-    debug_println!(
-        "Threads: {}, Files matched: {}, Chunk size: {}",
-        number_of_available_threads,
-        files.len(),
-        chunk_size
-    );
+#[cfg(not(windows))]
+fn enable_colors() {}
 
-    let mut handles = vec![];
-    for chunk in files.chunks(chunk_size) {
-        let chunk = chunk.to_vec();
-        let fut = find_matches_in_files(chunk, args.clone(), options.clone());
-        let handle = executor.spawn_with_handle(fut).expect("Failed to spawn thread");
-        handles.push(handle);
+/// The extensions a walk searches: the defaults plus `--ext`, or all of them
+/// with `--no-ext-filter`.
+fn extensions(args: &Args) -> Option<HashSet<String>> {
+    if args.no_ext_filter {
+        return None;
     }
+    let defaults = DEFAULT_EXTENSIONS.iter().map(|ext| ext.to_string());
+    let added = args.ext.iter().map(|ext| ext.trim_start_matches('.').to_lowercase());
+    Some(defaults.chain(added).collect())
+}
 
-    let results = block_on(join_all(handles));
+/// Sets the log level from `-v`, or from `$PERG_LOG` without it.
+fn init_log(args: &Args) {
+    let level = match (args.verbose, std::env::var(LOG_VAR)) {
+        (0, Ok(level)) => level.parse().unwrap_or_else(|err| {
+            eprintln!("perg: ${LOG_VAR}: {err}");
+            Level::Off
+        }),
+        (verbose, _) => Level::from_verbosity(verbose),
+    };
+    log::set_level(level);
+}
 
-    for matches in results {
-        if args.count {
-            for m in matches {
-                m.print_count();
-            }
-        } else {
-            for m in matches {
-                m.print_matches(&options);
+fn run(args: Args) -> Result<i32, PergError> {
+    enable_colors();
+    init_log(&args);
+    let cancel = Arc::new(AtomicBool::new(false));
+    let interrupted = Arc::new(AtomicBool::new(false));
+    handle_interrupt(cancel.clone(), interrupted.clone());
+    let progress = match args.progress && io::stderr().is_terminal() {
+        true => Some(Arc::new(Progress::default())),
+        false => None,
+    };
+
+    if args.one_file_system && !bolg::ONE_FILE_SYSTEM_SUPPORTED {
+        eprintln!("perg: --one-file-system is not supported on this platform and is ignored");
+    }
+
+    let mut ignore = bolg::Ignore::default();
+    for path in &args.ignore_file {
+        ignore.add_file(path).map_err(|err| PergError::io(path, err))?;
+    }
+
+    let mut patterns = args.pattern.iter().chain(&args.regexp).cloned();
+    let pattern = patterns.next().expect("clap requires -p or -e");
+    let options = SearchOptions {
+        pattern: NfaOptions::from(&args),
+        engine: args.engine,
+        patterns: patterns.collect(),
+        all_match: args.all_match,
+        globs: args.glob.clone(),
+        literal_paths: args.literal_paths,
+        max_depth: args.max_depth,
+        exclude_dirs: args.exclude_dir.clone(),
+        one_file_system: args.one_file_system,
+        ignore,
+        extensions: extensions(&args),
+        null_data: args.null_data,
+        search_zip: args.search_zip,
+        text: args.text,
+        binary: match (args.text, args.skip_binary) {
+            (true, _) => BinaryFiles::Text,
+            (false, true) => BinaryFiles::Skip,
+            (false, false) => BinaryFiles::Summary,
+        },
+        mmap: args.mmap,
+        pre: args.pre.clone(),
+        pre_globs: args.pre_glob.clone(),
+        // One match is enough to list a file.
+        max_count: match args.files_with_matches {
+            true => Some(1),
+            false => args.max_count,
+        },
+        max_line_len: match args.max_line_len {
+            0 => Some(usize::MAX),
+            max => Some(max),
+        },
+        long_lines: args.long_lines,
+        timeout: args.timeout,
+        line_ranges: args.line_range.clone(),
+        // Piped output is kept stable so runs can be diffed.
+        sort: args.sort.unwrap_or(match io::stdout().is_terminal() {
+            true => Sort::None,
+            false => Sort::Path,
+        }),
+        path_display: args.path_display,
+        cancel: Some(cancel),
+        progress: progress.clone(),
+        ..Default::default()
+    };
+
+    if args.watch {
+        return watch(&args, &pattern, &options, progress, &interrupted);
+    }
+    search(&args, &pattern, &options, progress, &interrupted)
+}
+
+/// What the text given to `perg match` is called in its JSON records.
+const TEXT_PATH: &str = "<text>";
+
+/// Matches the pattern of `perg match` against its text and prints every
+/// match, or `no match`; returns the exit code, 1 without a match.
+fn match_text(args: &MatchArgs) -> Result<i32, PergError> {
+    enable_colors();
+    let options = NfaOptions {
+        ignore_case: args.ignore_case,
+        smart_case: args.smart_case,
+        max_errors: args.fuzzy,
+        ..Default::default()
+    };
+    let matcher = build_matcher(&args.pattern, &options, args.engine)?;
+    let (path, text) = match &args.text {
+        Some(text) => (TEXT_PATH, text.clone()),
+        None => {
+            let mut text = String::new();
+            io::stdin()
+                .read_to_string(&mut text)
+                .map_err(|err| PergError::io(STDIN_PATH, err))?;
+            (STDIN_PATH, text)
+        }
+    };
+    // The newline that ends the last line does not start another one.
+    let text = text.strip_suffix('\n').unwrap_or(&text);
+    let lines: Vec<&str> = text.split('\n').collect();
+    let line_start = |line: usize| lines[..line].iter().map(|line| line.len() + 1).sum::<usize>();
+    let matches = matcher.find_matches(text);
+
+    let mut rendered = String::new();
+    if args.debug {
+        let _ = match regex_to_nfa(&args.pattern, &options) {
+            Ok(nfa) if args.engine != Engine::Regex => writeln!(rendered, "{nfa}"),
+            _ => writeln!(
+                rendered,
+                "The regex crate compiles this pattern; it has no automaton to show.\n"
+            ),
+        };
+    }
+    for m in &matches {
+        let line = lines.get(m.line).copied().unwrap_or_default();
+        let _ = match args.json {
+            true => writeln!(
+                rendered,
+                "{}",
+                format_json(&MatchRecord {
+                    path: PathBuf::from(path),
+                    line_number: m.line + 1,
+                    column: m.from + 1,
+                    span: (m.from, m.to),
+                    offset: line_start(m.line) + m.from,
+                    line: line.to_string(),
+                    pattern: m.pattern,
+                })
+            ),
+            false => writeln!(rendered, "{}", format_span(m, line)),
+        };
+    }
+    if matches.is_empty() && !args.json {
+        rendered.push_str("no match\n");
+    }
+    match io::stdout().write_all(rendered.as_bytes()) {
+        // Nobody is left to read the rest.
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+        written => written.map_err(PergError::Output)?,
+    }
+    Ok(match matches.is_empty() {
+        true => 1,
+        false => 0,
+    })
+}
+
+/// Searches once and prints the results; returns the exit code. Output
+/// piped into a reader that exits early, like `head`, ends the search
+/// quietly with the exit code so far: 0 unless an error was reported, as
+/// for a finished search, rather than the 141 of a process killed by SIGPIPE.
+fn search(
+    args: &Args,
+    pattern: &str,
+    options: &SearchOptions,
+    progress: Option<Arc<Progress>>,
+    interrupted: &AtomicBool,
+) -> Result<i32, PergError> {
+    let mut printer = Printer::new(OutputMode::from(args))
+        .patterns(
+            std::iter::once(pattern.to_string())
+                .chain(options.patterns.iter().cloned())
+                .collect(),
+        )
+        .pattern_colors(args.colors.iter().copied())
+        .max_columns(args.max_columns)
+        .trim(args.trim)
+        .template(args.format.clone())
+        .count_lines(args.all_match)
+        .long_lines(args.long_lines)
+        .no_messages(args.no_messages)
+        .line_buffered(args.line_buffered)
+        .max_results(args.max_results)
+        .progress(progress.clone());
+    let drawer = progress.as_ref().map(|progress| progress.draw_every(REDRAW_INTERVAL));
+    // The search stops early on Ctrl-C, when the printer finds stdout closed,
+    // which needs no report, or at --max-results; either way the printer has
+    // what it needs, so only errors are kept.
+    let sink = |event| printer.handle(event);
+    let searched = match (file_list_source(args), &args.path) {
+        (Some((list, separator)), _) => open_file_list(list).and_then(|reader| {
+            search_files_with(pattern, file_list(list, reader, separator), options, sink)
+        }),
+        (None, Some(path)) => search_with(pattern, &[path], options, sink),
+        (None, None) => unreachable!("clap requires a path without --files-from"),
+    }
+    .map(drop);
+    if let (Some(progress), Some(drawer)) = (progress, drawer) {
+        progress.stop(drawer);
+    }
+    searched?;
+
+    if args.stats {
+        printer.print_stats()?;
+    }
+    printer.finish()?;
+    if printer.limit_reached() {
+        eprintln!("-- result limit reached --");
+    }
+
+    if interrupted.load(Ordering::SeqCst) {
+        return Ok(INTERRUPTED);
+    }
+    // Tell an empty walk apart from files that were searched without a match.
+    let stats = printer.stats();
+    if let (None, Some(path)) = (file_list_source(args), &args.path) {
+        // The search may have stopped before the first file was done.
+        if stats.files_searched + stats.skipped == 0
+            && printer.exit_code() == 0
+            && !printer.closed()
+        {
+            return Err(PergError::NoFiles {
+                path: path.into(),
+                globbed: !options.globs.is_empty(),
+            });
+        }
+    }
+    Ok(printer.exit_code())
+}
+
+/// Searches, then again whenever the searched files change, each time on a
+/// cleared screen under the time of the search. Runs until Ctrl-C.
+fn watch(
+    args: &Args,
+    pattern: &str,
+    options: &SearchOptions,
+    progress: Option<Arc<Progress>>,
+    interrupted: &AtomicBool,
+) -> Result<i32, PergError> {
+    let roots = [args.path.as_deref().expect("clap requires a path with --watch")];
+    let mut snapshot = Snapshot::take(&roots, options);
+    loop {
+        let mut out = io::stdout().lock();
+        write!(out, "\x1b[2J\x1b[H")
+            .and_then(|_| {
+                writeln!(out, "perg --watch: searched at {} UTC\n", clock(SystemTime::now()))
+            })
+            .and_then(|_| out.flush())
+            .map_err(PergError::Output)?;
+        drop(out);
+
+        // The status line counts one search at a time.
+        let progress = progress.as_ref().map(|_| Arc::new(Progress::default()));
+        let options = SearchOptions {
+            progress: progress.clone(),
+            ..options.clone()
+        };
+        match search(args, pattern, &options, progress, interrupted) {
+            // The files may show up later.
+            Err(err @ PergError::NoFiles { .. }) => eprintln!("perg: {err}"),
+            Err(err) => return Err(err),
+            Ok(INTERRUPTED) => return Ok(INTERRUPTED),
+            Ok(_) => {}
+        }
+
+        let cancel = options.cancel.as_deref().expect("run sets a cancel flag");
+        // Only --max-results can have cancelled a search that was not interrupted.
+        cancel.store(false, Ordering::SeqCst);
+        snapshot =
+            match wait_for_change(&roots, &options, &snapshot, POLL_INTERVAL, DEBOUNCE, cancel) {
+                Some(snapshot) => snapshot,
+                None => return Ok(INTERRUPTED),
+            };
+    }
+}
+
+fn main() {
+    let args = match with_config(std::env::args_os().collect(), &Args::command()) {
+        Ok(args) => args,
+        Err(err) => {
+            let err = PergError::from(err);
+            eprintln!("perg: {err}");
+            process::exit(err.exit_code());
+        }
+    };
+    // An option given again, as on the command line after the config file,
+    // replaces the earlier value; lists are added to.
+    let matches = Args::command().args_override_self(true).get_matches_from(args);
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    if let Some(Command::Match(args)) = &args.command {
+        match match_text(args) {
+            Ok(exit_code) => process::exit(exit_code),
+            Err(err) => {
+                eprintln!("perg: {err}");
+                process::exit(err.exit_code());
             }
         }
     }
+    if args.watch && !io::stdout().is_terminal() {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, "--watch needs stdout to be a terminal")
+            .exit();
+    }
+    if args.watch && args.path.as_deref() == Some("-") {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, "--watch cannot search standard input")
+            .exit();
+    }
+
+    match run(args) {
+        Ok(exit_code) => process::exit(exit_code),
+        Err(err) => {
+            eprintln!("perg: {err}");
+            process::exit(err.exit_code());
+        }
+    }
 }
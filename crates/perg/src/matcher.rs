@@ -0,0 +1,609 @@
+use std::cmp::Reverse;
+use std::sync::Arc;
+
+#[cfg(feature = "fallback-regex")]
+use crate::nfa::Anchor;
+use crate::nfa::{NfaOptions, NFA};
+use crate::re::{regex_to_nfa, regexes_to_nfa, PatternError, RegexError};
+
+/// A single match inside a haystack: the byte span `from..to` within line `line`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub from: usize,
+    pub to: usize,
+    pub line: usize,
+    /// Edits between the matched text and the pattern, for fuzzy matching;
+    /// see [`NFA::fuzzy`]. 0 otherwise.
+    pub errors: usize,
+    /// Which of the patterns searched together matched, by its 0-based index
+    /// as given to [`build_matchers`]; 0 for a single pattern.
+    pub pattern: usize,
+}
+
+/// A matching engine the search and printing layers can work with without
+/// knowing how patterns are compiled. Implementations are immutable once built,
+/// so a single instance can be shared between worker threads behind an `Arc`.
+pub trait Matcher: Send + Sync {
+    /// Every match in `haystack`, with line numbers counted from the start of it.
+    fn find_matches(&self, haystack: &str) -> Vec<Match>;
+
+    fn is_match(&self, haystack: &str) -> bool;
+
+    /// Like [`Matcher::find_matches`], but asks `go_on` now and then while it
+    /// works and gives up with `None` once the answer is no. Matchers that
+    /// take time linear in the haystack, like the regex crate's, never ask.
+    fn find_matches_while(
+        &self,
+        haystack: &str,
+        go_on: &mut dyn FnMut() -> bool,
+    ) -> Option<Vec<Match>> {
+        let _ = go_on;
+        Some(self.find_matches(haystack))
+    }
+
+    /// Lazily yields the matches of `haystack` one line at a time, so callers can
+    /// stop early without the remaining lines being searched.
+    fn find_iter<'a>(&'a self, haystack: &'a str) -> Box<dyn Iterator<Item = Match> + 'a> {
+        Box::new(
+            haystack
+                .split('\n')
+                .enumerate()
+                .flat_map(move |(line_number, line)| {
+                    self.find_matches(line).into_iter().map(move |m| Match {
+                        line: line_number,
+                        ..m
+                    })
+                }),
+        )
+    }
+}
+
+/// Matches a fixed string. Like the other engines, it reports occurrences
+/// that do not overlap, each search resuming where the last match ended.
+#[derive(Clone, Debug)]
+pub struct LiteralMatcher {
+    needle: String,
+}
+
+impl LiteralMatcher {
+    pub fn new<S: Into<String>>(needle: S) -> Self {
+        Self {
+            needle: needle.into(),
+        }
+    }
+}
+
+impl Matcher for LiteralMatcher {
+    fn find_matches(&self, haystack: &str) -> Vec<Match> {
+        if self.needle.is_empty() {
+            return vec![];
+        }
+
+        let mut matches = vec![];
+        for (line_number, line) in haystack.split('\n').enumerate() {
+            for (k, _) in line.match_indices(self.needle.as_str()) {
+                matches.push(Match {
+                    from: k,
+                    to: k + self.needle.len(),
+                    line: line_number,
+                    errors: 0,
+                    pattern: 0,
+                });
+            }
+        }
+        matches
+    }
+
+    fn is_match(&self, haystack: &str) -> bool {
+        haystack.contains(&self.needle)
+    }
+}
+
+/// Which engine compiles the pattern.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Engine {
+    /// perg's own engine, falling back to the regex crate (when built with the
+    /// `fallback-regex` feature) for constructs perg does not support yet.
+    #[default]
+    Auto,
+    Nfa,
+    Regex,
+}
+
+/// Why the regex crate cannot search with [`NfaOptions::max_errors`].
+const FUZZY_NEEDS_NFA: &str = "fuzzy matching needs perg's engine";
+
+pub fn build_matcher(
+    pattern: &str,
+    options: &NfaOptions,
+    engine: Engine,
+) -> Result<Arc<dyn Matcher>, PatternError> {
+    let nfa = |nfa: NFA| -> Arc<dyn Matcher> {
+        crate::debug!("'{pattern}': compiled by perg's engine to {} states", nfa.states.len());
+        Arc::new(nfa)
+    };
+    let matcher = match engine {
+        Engine::Nfa => regex_to_nfa(pattern, options).map(nfa),
+        Engine::Regex if options.max_errors > 0 => {
+            Err(RegexError::Backend(FUZZY_NEEDS_NFA.to_string()))
+        }
+        Engine::Regex => {
+            crate::debug!("'{pattern}': compiled by the regex crate");
+            regex_matcher(pattern, options)
+        }
+        Engine::Auto => match regex_to_nfa(pattern, options) {
+            Ok(compiled) => Ok(nfa(compiled)),
+            Err(RegexError::Unsupported { construct, .. })
+                if cfg!(feature = "fallback-regex") && options.max_errors == 0 =>
+            {
+                crate::debug!(
+                    "'{pattern}': '{construct}' is not supported by perg's engine, \
+                     using the regex crate"
+                );
+                regex_matcher(pattern, options)
+            }
+            Err(err) => Err(err),
+        },
+    };
+    matcher.map_err(|err| PatternError::new(pattern, err))
+}
+
+/// Compiles every pattern on its own and searches them as one; see
+/// [`MultiMatcher`]. A single pattern gets its plain matcher. Patterns that
+/// perg's engine compiles become one automaton by [`regexes_to_nfa`]
+/// instead, which finds the same leftmost-longest matches in a single pass,
+/// unless a line must match all of them, one is lazy and so follows
+/// priorities, or they are fuzzy and each counts its own edits. Either way a
+/// match tells which pattern it is of.
+pub fn build_matchers(
+    patterns: &[&str],
+    options: &NfaOptions,
+    engine: Engine,
+    all_match: bool,
+) -> Result<Arc<dyn Matcher>, PatternError> {
+    if patterns.len() > 1 && !all_match && engine != Engine::Regex && options.max_errors == 0 {
+        // Failures are reported, or handed to the regex crate, below.
+        if let Some(union) = regexes_to_nfa(patterns, options)
+            .ok()
+            .filter(|union| !union.lazy)
+        {
+            crate::debug!(
+                "{} patterns: compiled by perg's engine to {} states",
+                patterns.len(),
+                union.states.len()
+            );
+            return Ok(Arc::new(union));
+        }
+    }
+    let mut matchers = patterns
+        .iter()
+        .map(|pattern| build_matcher(pattern, options, engine))
+        .collect::<Result<Vec<_>, _>>()?;
+    match matchers.len() {
+        0 => Err(PatternError::new("", RegexError::EmptyPattern)),
+        1 => Ok(matchers.remove(0)),
+        _ => Ok(Arc::new(MultiMatcher::new(matchers, all_match))),
+    }
+}
+
+/// Several patterns searched together, each by its own matcher. A line
+/// matches when any pattern matches it or, with `all_match`, only when every
+/// one does. Either way each of its matches is reported: leftmost first,
+/// longest at the same start, and dropping those overlapping an earlier one.
+pub struct MultiMatcher {
+    matchers: Vec<Arc<dyn Matcher>>,
+    all_match: bool,
+}
+
+impl MultiMatcher {
+    pub fn new(matchers: Vec<Arc<dyn Matcher>>, all_match: bool) -> Self {
+        Self {
+            matchers,
+            all_match,
+        }
+    }
+}
+
+impl Matcher for MultiMatcher {
+    fn find_matches(&self, haystack: &str) -> Vec<Match> {
+        self.find_matches_while(haystack, &mut || true).unwrap_or_default()
+    }
+
+    fn find_matches_while(
+        &self,
+        haystack: &str,
+        go_on: &mut dyn FnMut() -> bool,
+    ) -> Option<Vec<Match>> {
+        let mut matches: Vec<Match> = vec![];
+        for (line_number, line) in haystack.split('\n').enumerate() {
+            let per_pattern: Vec<Vec<Match>> = self
+                .matchers
+                .iter()
+                .map(|matcher| matcher.find_matches_while(line, go_on))
+                .collect::<Option<_>>()?;
+            if self.all_match && per_pattern.iter().any(Vec::is_empty) {
+                continue;
+            }
+
+            let mut found: Vec<Match> = per_pattern
+                .into_iter()
+                .enumerate()
+                .flat_map(|(pattern, matches)| {
+                    matches.into_iter().map(move |m| Match { pattern, ..m })
+                })
+                .collect();
+            // The sort is stable, so the same span found by several patterns
+            // is reported for the first of them.
+            found.sort_by_key(|m| (m.from, Reverse(m.to)));
+            let mut last: Option<Match> = None;
+            for m in found {
+                if last
+                    .as_ref()
+                    .is_some_and(|last| m.from < last.to || (m.from, m.to) == (last.from, last.to))
+                {
+                    continue;
+                }
+                last = Some(m.clone());
+                matches.push(Match {
+                    line: line_number,
+                    ..m
+                });
+            }
+        }
+        Some(matches)
+    }
+
+    fn is_match(&self, haystack: &str) -> bool {
+        match self.all_match {
+            true => !self.find_matches(haystack).is_empty(),
+            false => self.matchers.iter().any(|matcher| matcher.is_match(haystack)),
+        }
+    }
+}
+
+#[cfg(feature = "fallback-regex")]
+fn regex_matcher(pattern: &str, options: &NfaOptions) -> Result<Arc<dyn Matcher>, RegexError> {
+    Ok(Arc::new(RegexMatcher::new(pattern, options)?))
+}
+
+#[cfg(not(feature = "fallback-regex"))]
+fn regex_matcher(_pattern: &str, _options: &NfaOptions) -> Result<Arc<dyn Matcher>, RegexError> {
+    Err(RegexError::Backend(
+        "perg was built without the 'fallback-regex' feature".to_string(),
+    ))
+}
+
+/// Matches with the `regex` crate, using its syntax for the whole pattern.
+#[cfg(feature = "fallback-regex")]
+#[derive(Clone, Debug)]
+pub struct RegexMatcher {
+    regex: regex::Regex,
+}
+
+#[cfg(feature = "fallback-regex")]
+impl RegexMatcher {
+    pub fn new(pattern: &str, options: &NfaOptions) -> Result<Self, RegexError> {
+        let ignore_case = options.ignores_case(pattern);
+        let pattern = match options.anchored {
+            Anchor::None => pattern.to_string(),
+            Anchor::Start => format!("\\A(?:{pattern})"),
+            Anchor::Both => format!("\\A(?:{pattern})\\z"),
+        };
+        let regex = regex::RegexBuilder::new(&pattern)
+            .case_insensitive(ignore_case)
+            .dot_matches_new_line(options.dot_matches_newline)
+            .build()
+            .map_err(|err| RegexError::Backend(err.to_string()))?;
+
+        Ok(Self { regex })
+    }
+}
+
+#[cfg(feature = "fallback-regex")]
+impl Matcher for RegexMatcher {
+    fn find_matches(&self, haystack: &str) -> Vec<Match> {
+        let mut matches = vec![];
+        for (line_number, line) in haystack.split('\n').enumerate() {
+            for m in self.regex.find_iter(line) {
+                matches.push(Match {
+                    from: m.start(),
+                    to: m.end(),
+                    line: line_number,
+                    errors: 0,
+                    pattern: 0,
+                });
+            }
+        }
+        matches
+    }
+
+    fn is_match(&self, haystack: &str) -> bool {
+        haystack.split('\n').any(|line| self.regex.is_match(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    /// Every engine is expected to behave the same for plain literal needles.
+    fn engines(needle: &str) -> Vec<(&'static str, Arc<dyn Matcher>)> {
+        let nfa = regex_to_nfa(needle, &NfaOptions::default()).unwrap();
+        vec![
+            ("nfa", Arc::new(nfa)),
+            ("literal", Arc::new(LiteralMatcher::new(needle))),
+            #[cfg(feature = "fallback-regex")]
+            (
+                "regex",
+                Arc::new(RegexMatcher::new(needle, &NfaOptions::default()).unwrap()),
+            ),
+        ]
+    }
+
+    fn m(from: usize, to: usize, line: usize) -> Match {
+        Match {
+            from,
+            to,
+            line,
+            errors: 0,
+            pattern: 0,
+        }
+    }
+
+    /// `m`, found by the pattern with index `pattern`.
+    fn of(pattern: usize, m: Match) -> Match {
+        Match { pattern, ..m }
+    }
+
+    #[test]
+    fn conformance_find_matches() {
+        let tests = vec![
+            ("ab", "xab", vec![m(1, 3, 0)]),
+            ("ab", "abx", vec![m(0, 2, 0)]),
+            ("ab", "abab x", vec![m(0, 2, 0), m(2, 4, 0)]),
+            ("aa", "aaaa", vec![m(0, 2, 0), m(2, 4, 0)]),
+            ("abc", "abc", vec![m(0, 3, 0)]),
+            ("ab", "ab\nxx\nab", vec![m(0, 2, 0), m(0, 2, 2)]),
+            ("ść", "źść", vec![m(2, 6, 0)]),
+            ("ab", "ba", vec![]),
+            ("ab", "", vec![]),
+        ];
+
+        for (needle, haystack, expected) in tests {
+            for (name, matcher) in engines(needle) {
+                println!("{name}: '{needle}' in '{haystack}'");
+                assert_eq!(matcher.find_matches(haystack), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn conformance_is_match() {
+        let tests = vec![
+            ("ab", "xab", true),
+            ("ab", "a b", false),
+            ("abc", "ab\nc", false),
+            ("ść", "źść", true),
+            ("a", "", false),
+        ];
+
+        for (needle, haystack, expected) in tests {
+            for (name, matcher) in engines(needle) {
+                println!("{name}: '{needle}' in '{haystack}'");
+                assert_eq!(matcher.is_match(haystack), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn conformance_find_iter() {
+        for (name, matcher) in engines("ab") {
+            println!("{name}");
+            let haystack = "xab\nab ab\nb";
+            let streamed: Vec<Match> = matcher.find_iter(haystack).collect();
+            assert_eq!(streamed, matcher.find_matches(haystack));
+        }
+    }
+
+    #[test]
+    fn ignore_case_spans_are_offsets_into_the_original_line() {
+        let options = NfaOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let tests = vec![
+            ("stra", "straße STRAẞE", vec![m(0, 4, 0), m(8, 12, 0)]),
+            ("ẞe", "STRAẞE straße", vec![m(4, 8, 0), m(13, 16, 0)]),
+            ("ﬁle", "ﬁle FILE", vec![m(0, 5, 0)]),
+            ("i", "İi", vec![m(2, 3, 0)]),
+        ];
+
+        for (needle, haystack, expected) in tests {
+            let mut engines = vec![("nfa", build_matcher(needle, &options, Engine::Nfa).unwrap())];
+            if cfg!(feature = "fallback-regex") {
+                engines.push(("regex", build_matcher(needle, &options, Engine::Regex).unwrap()));
+            }
+            for (name, matcher) in engines {
+                println!("{name}: '{needle}' in '{haystack}'");
+                assert_eq!(matcher.find_matches(haystack), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn multi_matcher_reports_lines_matching_any_pattern() {
+        let matcher = build_matchers(
+            &["user", "error"],
+            &NfaOptions::default(),
+            Engine::Nfa,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            matcher.find_matches("user ok\nerror\nneither\nerror: user"),
+            vec![
+                m(0, 4, 0),
+                of(1, m(0, 5, 1)),
+                of(1, m(0, 5, 3)),
+                m(7, 11, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn multi_matcher_with_all_match_needs_every_pattern() {
+        let matcher = build_matchers(
+            &["user", "error"],
+            &NfaOptions::default(),
+            Engine::Nfa,
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(
+            matcher.find_matches("user ok\nerror\nerror: user"),
+            vec![of(1, m(0, 5, 2)), m(7, 11, 2)]
+        );
+        assert!(matcher.is_match("x\nuser error"));
+        assert!(!matcher.is_match("user\nerror"));
+    }
+
+    #[test]
+    fn multi_matcher_drops_overlapping_matches() {
+        let matcher = build_matchers(
+            &["ab", "abc", "bcd", "ab"],
+            &NfaOptions::default(),
+            Engine::Nfa,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(
+            matcher.find_matches("abcd ab"),
+            vec![of(1, m(0, 3, 0)), m(5, 7, 0)]
+        );
+    }
+
+    #[test]
+    fn a_union_tells_patterns_apart_like_separate_matchers() {
+        let patterns = ["ab", "a(b|c)", "[xy]*z", "ab", "^x", "z$"];
+        let nfas = patterns
+            .iter()
+            .map(|pattern| regex_to_nfa(pattern, &NfaOptions::default()).unwrap());
+        let union = regexes_to_nfa(&patterns, &NfaOptions::default()).unwrap();
+        let separate = MultiMatcher::new(
+            nfas.map(|nfa| Arc::new(nfa) as Arc<dyn Matcher>).collect(),
+            false,
+        );
+
+        for text in ["ab ac xyz", "z abz\nxab", "yy", "xy z\r\nz x"] {
+            assert_eq!(union.find_matches(text), separate.find_matches(text), "{text}");
+        }
+        assert_eq!(
+            union.find_matches("ac ab xz"),
+            vec![of(1, m(0, 2, 0)), m(3, 5, 0), of(2, m(6, 8, 0))]
+        );
+    }
+
+    #[test]
+    fn patterns_keep_their_own_anchors() {
+        let mixed =
+            build_matchers(&["^ab", "cd"], &NfaOptions::default(), Engine::Nfa, false).unwrap();
+        let anchored =
+            build_matchers(&["^ab", "^cd"], &NfaOptions::default(), Engine::Nfa, false).unwrap();
+
+        assert_eq!(
+            mixed.find_matches("ab cd ab\ncd"),
+            vec![m(0, 2, 0), of(1, m(3, 5, 0)), of(1, m(0, 2, 1))]
+        );
+        assert_eq!(
+            anchored.find_matches("ab cd\ncd ab"),
+            vec![m(0, 2, 0), of(1, m(0, 2, 1))]
+        );
+
+        let ends =
+            build_matchers(&["ab$", "cd"], &NfaOptions::default(), Engine::Nfa, false).unwrap();
+        assert_eq!(
+            ends.find_matches("ab cd ab\nab"),
+            vec![of(1, m(3, 5, 0)), m(6, 8, 0), m(0, 2, 1)]
+        );
+    }
+
+    #[test]
+    fn build_matcher_reports_unsupported_without_fallback() {
+        let result = build_matcher("a\\pZb", &NfaOptions::default(), Engine::Nfa);
+        assert!(matches!(result, Err(PatternError { error: RegexError::Unsupported { .. }, .. })));
+    }
+
+    #[cfg(not(feature = "fallback-regex"))]
+    #[test]
+    fn build_matcher_regex_engine_requires_feature() {
+        let result = build_matcher("ab", &NfaOptions::default(), Engine::Regex);
+        assert!(matches!(result, Err(PatternError { error: RegexError::Backend(_), .. })));
+    }
+
+    #[cfg(feature = "fallback-regex")]
+    #[test]
+    fn build_matcher_auto_falls_back_to_regex() {
+        let matcher = build_matcher("a\\pZb", &NfaOptions::default(), Engine::Auto).unwrap();
+        assert_eq!(matcher.find_matches("ab a b"), vec![m(3, 6, 0)]);
+    }
+
+    #[cfg(feature = "fallback-regex")]
+    #[test]
+    fn nfa_and_regex_agree_on_empty_matches() {
+        for (pattern, haystack) in [
+            ("a*", "bbb"),
+            ("a*", ""),
+            ("a*", "baab\nab"),
+            ("(ab)*", "xabab"),
+        ] {
+            let nfa = regex_to_nfa(pattern, &NfaOptions::default()).unwrap();
+            let regex = RegexMatcher::new(pattern, &NfaOptions::default()).unwrap();
+            println!("'{pattern}' in '{haystack}'");
+            assert_eq!(nfa.find_matches(haystack), regex.find_matches(haystack));
+        }
+    }
+
+    #[cfg(feature = "fallback-regex")]
+    #[test]
+    fn nfa_and_regex_agree_on_anchored_matches() {
+        for anchored in [Anchor::Start, Anchor::Both] {
+            let options = NfaOptions {
+                anchored,
+                ..Default::default()
+            };
+            for (pattern, haystack) in
+                [("ab", "xab\nabab\nab"), ("a*", "baa\naa"), ("a|b", "ba\nb")]
+            {
+                let nfa = regex_to_nfa(pattern, &options).unwrap();
+                let regex = RegexMatcher::new(pattern, &options).unwrap();
+                assert_eq!(
+                    nfa.find_matches(haystack),
+                    regex.find_matches(haystack),
+                    "'{pattern}' in '{haystack}'"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn conformance_shared_between_threads() {
+        for (name, matcher) in engines("ab") {
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    let matcher = Arc::clone(&matcher);
+                    thread::spawn(move || matcher.find_matches("xxab"))
+                })
+                .collect();
+
+            for handle in handles {
+                println!("{name}");
+                assert_eq!(handle.join().unwrap(), vec![m(2, 4, 0)]);
+            }
+        }
+    }
+}
@@ -5,5 +5,5 @@ pub fn clamp<T: PartialOrd>(value: T, min: T, max: T) -> T {
     if value > max {
         return max;
     }
-    return value;
+    value
 }
@@ -1,93 +1,351 @@
-use colored::*;
-use lazy_static::lazy_static;
-use std::collections::{BTreeMap, HashSet};
-use std::cell::RefCell;
-use std::fs::File;
-use std::io::BufRead;
-use std::path::PathBuf;
-use std::rc::Rc;
-use std::{fmt, fs, io};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Range;
 
-use crate::{misc, Args};
+use crate::matcher::{Match, Matcher};
 
-type RcMut<T> = Rc<RefCell<T>>;
+/// Index of a state inside [`NFA::states`].
+pub type StateId = usize;
 
 pub const EPLISON: char = 'ε';
-pub const CONCAT: char = '?';
-pub const UNION: char = '+';
+pub const UNION: char = '|';
+pub const PLUS: char = '+';
+pub const OPTIONAL: char = '?';
 pub const KLEEN: char = '*';
-pub const ANY_DIGIT: char = '#';
-pub const ANY_ALPHANUMERIC: char = '=';
-pub const ANY_OTHER_CHAR: char = '&';
 pub const SLASH: char = '\\';
 pub const CHAR_SET_START: char = '[';
 pub const CHAR_SET_END: char = ']';
 pub const GROUP_START: char = '(';
 pub const GROUP_END: char = ')';
 
-lazy_static! {
-    pub static ref RESERVED_CHARS: HashSet<char> = {
-        let mut m = HashSet::new();
-        m.insert(EPLISON);
-        m.insert(CONCAT);
-        m.insert(UNION);
-        m.insert(KLEEN);
-        m.insert(ANY_DIGIT);
-        m.insert(ANY_ALPHANUMERIC);
-        m.insert(ANY_OTHER_CHAR);
-        m.insert(SLASH);
-        m.insert(GROUP_START);
-        m.insert(GROUP_END);
-        m.insert(CHAR_SET_END);
-        m.insert(CHAR_SET_START);
-        m
-    };
-    pub static ref CANNOT_CONCAT_PREV_CHAR: HashSet<char> = {
-        let mut m = HashSet::new();
-        m.insert(GROUP_START);
-        m.insert(UNION);
-        m.insert(CHAR_SET_START);
-        m.insert(SLASH);
-        m
-    };
-    pub static ref CANNOT_CONCAT_CURRENT_CHAR: HashSet<char> = {
-        let mut m = HashSet::new();
-        m.insert(CONCAT);
-        m.insert(UNION);
-        m.insert(KLEEN);
-        m.insert(GROUP_END);
-        m.insert(CHAR_SET_END);
-        m
-    };
+/// How many positions [`NFA::find_matches_while`] tries a match at between
+/// two questions to its caller.
+pub const POLL_EVERY: usize = 256;
+
+/// A shorthand class written inside brackets, like the `\d` of `[\d_]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shorthand {
+    /// `\d`: any character [`char::is_numeric`] accepts.
+    Digit,
+    /// `\D`
+    NotDigit,
+    /// `\w`: `_` or any character [`char::is_alphanumeric`] accepts.
+    Word,
+    /// `\W`
+    NotWord,
+    /// `\s`: any character [`char::is_whitespace`] accepts.
+    Space,
+    /// `\S`
+    NotSpace,
+}
+
+impl Shorthand {
+    /// The shorthand `\` followed by `c` spells, if any.
+    pub fn from_escape(c: char) -> Option<Self> {
+        match c {
+            'd' => Some(Shorthand::Digit),
+            'D' => Some(Shorthand::NotDigit),
+            'w' => Some(Shorthand::Word),
+            'W' => Some(Shorthand::NotWord),
+            's' => Some(Shorthand::Space),
+            'S' => Some(Shorthand::NotSpace),
+            _ => None,
+        }
+    }
+
+    pub fn accepts(self, c: char) -> bool {
+        match self {
+            Shorthand::Digit => c.is_numeric(),
+            Shorthand::NotDigit => !c.is_numeric(),
+            Shorthand::Word => c.is_alphanumeric() || c == '_',
+            Shorthand::NotWord => !Shorthand::Word.accepts(c),
+            Shorthand::Space => c.is_whitespace(),
+            Shorthand::NotSpace => !c.is_whitespace(),
+        }
+    }
+}
+
+impl fmt::Display for Shorthand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Shorthand::Digit => "\\d",
+            Shorthand::NotDigit => "\\D",
+            Shorthand::Word => "\\w",
+            Shorthand::NotWord => "\\W",
+            Shorthand::Space => "\\s",
+            Shorthand::NotSpace => "\\S",
+        })
+    }
+}
+
+/// A set of characters: inclusive ranges, kept sorted and apart from each
+/// other, and shorthands like `\d`, or every character outside them when
+/// negated. A set spelled out character by character collapses into a few
+/// ranges, and a character is looked up with a binary search. A folded
+/// class ignores case: its ranges hold folded characters, and a character
+/// is folded before it is looked up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CharClass {
+    ranges: Vec<(char, char)>,
+    shorthands: Vec<Shorthand>,
+    negated: bool,
+    folded: bool,
+}
+
+impl CharClass {
+    /// The characters in any of `ranges`, which may overlap and come in any
+    /// order; an empty range like `('b', 'a')` is dropped.
+    pub fn new(ranges: impl IntoIterator<Item = (char, char)>) -> Self {
+        let mut ranges: Vec<(char, char)> =
+            ranges.into_iter().filter(|(from, to)| from <= to).collect();
+        ranges.sort_unstable();
+
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(ranges.len());
+        for (from, to) in ranges {
+            match merged.last_mut() {
+                Some(last) if from as u32 <= last.1 as u32 + 1 => last.1 = last.1.max(to),
+                _ => merged.push((from, to)),
+            }
+        }
+        Self {
+            ranges: merged,
+            shorthands: vec![],
+            negated: false,
+            folded: false,
+        }
+    }
+
+    /// The characters in any of `ranges` in any case: those that fold like
+    /// one of them.
+    pub fn folded(ranges: impl IntoIterator<Item = (char, char)>) -> Self {
+        let folded = ranges
+            .into_iter()
+            .flat_map(|(from, to)| from..=to)
+            .map(|c| (fold(c), fold(c)));
+        Self {
+            folded: true,
+            ..Self::new(folded)
+        }
+    }
+
+    /// This class and the characters of `shorthands` too.
+    pub fn with_shorthands(mut self, shorthands: impl IntoIterator<Item = Shorthand>) -> Self {
+        for shorthand in shorthands {
+            if !self.shorthands.contains(&shorthand) {
+                self.shorthands.push(shorthand);
+            }
+        }
+        self
+    }
+
+    /// Exactly `chars`.
+    pub fn of(chars: impl IntoIterator<Item = char>) -> Self {
+        Self::new(chars.into_iter().map(|c| (c, c)))
+    }
+
+    /// Every character this class does not hold.
+    pub fn negate(mut self) -> Self {
+        self.negated = !self.negated;
+        self
+    }
+
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+
+    pub fn shorthands(&self) -> &[Shorthand] {
+        &self.shorthands
+    }
+
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        let looked_up = match self.folded {
+            true => fold(c),
+            false => c,
+        };
+        let found = self.ranges.binary_search_by(|(from, to)| match () {
+            _ if *to < looked_up => Ordering::Less,
+            _ if *from > looked_up => Ordering::Greater,
+            _ => Ordering::Equal,
+        });
+        (found.is_ok() || self.shorthands.iter().any(|shorthand| shorthand.accepts(c)))
+            != self.negated
+    }
+}
+
+impl fmt::Display for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.folded {
+            write!(f, "(?i)")?;
+        }
+        // A newline is written `\n`, so the set stays on one line.
+        let shown = |c: char| match c {
+            '\n' => "\\n".to_string(),
+            c => c.to_string(),
+        };
+        if let ([(from, to)], [], false) = (&self.ranges[..], &self.shorthands[..], self.negated) {
+            // A lone `ε` is bracketed, so it does not read as an epsilon.
+            if from == to && *from != EPLISON {
+                return write!(f, "{}", shown(*from));
+            }
+        }
+        write!(f, "[{}", if self.negated { "^" } else { "" })?;
+        for (from, to) in &self.ranges {
+            match from == to {
+                true => write!(f, "{}", shown(*from))?,
+                false => write!(f, "{}-{}", shown(*from), shown(*to))?,
+            }
+        }
+        for shorthand in &self.shorthands {
+            write!(f, "{shorthand}")?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// What a transition consumes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Label {
+    /// Nothing: the transition is taken without reading a character.
+    Epsilon,
+    Class(CharClass),
+    /// Any character [`char::is_numeric`] accepts: `\d`.
+    Numeric,
+    /// A word character, as [`Shorthand::Word`] accepts: `\w`.
+    Alphanumeric,
+    /// Nothing, between a word character and anything else: `\b`.
+    WordBoundary,
+    /// Nothing, anywhere `\b` does not match: `\B`.
+    NotWordBoundary,
+    /// Nothing, at the start of the line: the `^` of one of several patterns
+    /// searched together; see [`tagged_union`].
+    LineStart,
+    /// Nothing, where such a `$` matches; see [`Around::at_line_end`].
+    LineEnd,
+    /// Nothing: records the position in capture slot `n`, the start of group
+    /// `n / 2` if `n` is even and its end if not; see [`NFA::captures`].
+    Save(usize),
 }
 
-#[derive(Debug)]
+impl Label {
+    /// Whether the transition can be taken on `c`.
+    pub fn accepts(&self, c: char) -> bool {
+        match self {
+            Label::Epsilon
+            | Label::WordBoundary
+            | Label::NotWordBoundary
+            | Label::LineStart
+            | Label::LineEnd
+            | Label::Save(_) => false,
+            Label::Class(class) => class.contains(c),
+            Label::Numeric => c.is_numeric(),
+            Label::Alphanumeric => Shorthand::Word.accepts(c),
+        }
+    }
+
+    /// Whether the transition can be taken without reading a character, at
+    /// a position with `around` it: an epsilon always, an assertion where it
+    /// holds. With no position every assertion holds.
+    pub fn passes(&self, around: Option<Around>) -> bool {
+        match self {
+            Label::Epsilon | Label::Save(_) => true,
+            Label::WordBoundary => around.map_or(true, |around| around.is_word_boundary()),
+            Label::NotWordBoundary => around.map_or(true, |around| !around.is_word_boundary()),
+            Label::LineStart => around.map_or(true, |around| around.before.is_none()),
+            Label::LineEnd => around.map_or(true, |around| around.at_line_end),
+            _ => false,
+        }
+    }
+
+    /// Whether the transition reads a character.
+    pub fn reads_char(&self) -> bool {
+        !matches!(
+            self,
+            Label::Epsilon
+                | Label::WordBoundary
+                | Label::NotWordBoundary
+                | Label::LineStart
+                | Label::LineEnd
+                | Label::Save(_)
+        )
+    }
+}
+
+/// The characters on either side of a position in a line, which assertions
+/// like `\b` look at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Around {
+    pub before: Option<char>,
+    pub after: Option<char>,
+    /// Whether a `$` matches here: at the end of the line, or before a `\r`
+    /// ending it.
+    pub at_line_end: bool,
+}
+
+impl Around {
+    /// The characters around byte `at` of `line`.
+    pub fn at(line: &str, at: usize) -> Self {
+        Self {
+            before: line[..at].chars().next_back(),
+            after: line[at..].chars().next(),
+            at_line_end: at == line_end(line),
+        }
+    }
+
+    /// Whether exactly one side is a word character, one `\w` matches. The
+    /// start and end of the line are not.
+    pub fn is_word_boundary(&self) -> bool {
+        let is_word = |c: Option<char>| c.is_some_and(|c| Shorthand::Word.accepts(c));
+        is_word(self.before) != is_word(self.after)
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Label::Epsilon => write!(f, "{EPLISON}"),
+            Label::Class(class) => write!(f, "{class}"),
+            Label::Numeric => write!(f, "\\d"),
+            Label::Alphanumeric => write!(f, "\\w"),
+            Label::WordBoundary => write!(f, "\\b"),
+            Label::NotWordBoundary => write!(f, "\\B"),
+            Label::LineStart => write!(f, "^"),
+            Label::LineEnd => write!(f, "$"),
+            Label::Save(slot) if slot % 2 == 0 => write!(f, "({}", slot / 2),
+            Label::Save(slot) => write!(f, "){}", slot / 2),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Transition {
-    pub on: char,
-    pub to: RcMut<State>,
+    pub on: Label,
+    pub to: StateId,
 }
 
 impl Transition {
-    pub fn new(on: char, to: RcMut<State>) -> Self {
+    pub fn new(on: Label, to: StateId) -> Self {
         Self { on, to }
     }
 }
 
 impl fmt::Display for Transition {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "'{}' -> {}", self.on, (*self.to).borrow().name)
+        write!(f, "'{}' -> {}", self.on, self.to)
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum StateKind {
     Normal,
-    Failed,
     Initial,
     Final,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct State {
     pub name: String,
     pub transitions: Vec<Transition>,
@@ -113,171 +371,154 @@ impl State {
         }
     }
 
-    pub fn add_transition(&mut self, on: char, to: &RcMut<State>) {
-        let transition = Transition::new(on, Rc::clone(to));
-        self.transitions.push(transition);
+    pub fn add_transition(&mut self, on: Label, to: StateId) {
+        self.transitions.push(Transition::new(on, to));
     }
 }
 
+/// Where in a line matches must start and end; see [`NfaOptions::anchored`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Anchor {
+    #[default]
+    None,
+    /// At the start, as if the pattern began with `^`.
+    Start,
+    /// At the start and the end, so a match is the whole line, as with
+    /// grep's `-x`.
+    Both,
+}
+
 #[derive(Clone, Debug)]
 pub struct NfaOptions {
     pub ignore_case: bool,
+    /// Ignores case when the pattern has no uppercase letter of its own, as
+    /// ripgrep's smart case does; see [`NfaOptions::ignores_case`].
+    pub smart_case: bool,
     pub count: bool,
     pub context: u32,
+    /// Where matches must start and end. Anchored at the start, no offset
+    /// but the beginning of each line is tried.
+    pub anchored: Anchor,
+    /// Also matches text within this many edits of the pattern; see
+    /// [`NFA::fuzzy`].
+    pub max_errors: usize,
+    /// An escape perg's engine does not know, like `\q`, matches the
+    /// character it escapes instead of being an error.
+    pub literal_escapes: bool,
+    /// `.` and a negated set, like `[^a]`, match a newline too. Lines are
+    /// searched one at a time, so it only shows in text matched whole, as
+    /// by [`NFA::is_full_match`].
+    pub dot_matches_newline: bool,
 }
 
 impl Default for NfaOptions {
     fn default() -> Self {
         Self {
             ignore_case: false,
+            smart_case: false,
             count: false,
             context: 1,
+            anchored: Anchor::None,
+            max_errors: 0,
+            literal_escapes: false,
+            dot_matches_newline: false,
         }
     }
 }
 
-impl From<&Args> for NfaOptions {
-    fn from(value: &Args) -> Self {
-        Self {
-            ignore_case: value.ignore_case,
-            count: value.count,
-            context: value.context,
+impl NfaOptions {
+    /// Whether `pattern` is matched ignoring case: always with
+    /// `ignore_case`, and with `smart_case` unless it has an uppercase
+    /// letter. What a backslash escapes, as in `\W`, does not count.
+    pub fn ignores_case(&self, pattern: &str) -> bool {
+        let mut chars = pattern.chars().peekable();
+        let mut has_uppercase = false;
+        while let Some(c) = chars.next() {
+            match c {
+                // The hex digits of `\x4A` or `\u{1F600}` are not letters of the pattern.
+                SLASH => match chars.next() {
+                    Some('x') => {
+                        chars.next_if(char::is_ascii_hexdigit);
+                        chars.next_if(char::is_ascii_hexdigit);
+                    }
+                    Some('u') if chars.next_if_eq(&'{').is_some() => {
+                        while chars.next_if(|c| *c != '}').is_some() {}
+                    }
+                    _ => {}
+                },
+                c => has_uppercase |= c.is_uppercase(),
+            }
         }
+        self.ignore_case || self.smart_case && !has_uppercase
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct NFA {
-    pub states: Vec<RcMut<State>>,
-    pub initial_state: RcMut<State>,
-    pub final_states: Vec<RcMut<State>>,
-}
+/// A state of [`NFA::slots_in`] with the capture slots set on the way to it.
+type Thread = (StateId, Vec<Option<usize>>);
 
-#[derive(Debug)]
-pub struct Match {
-    pub from: usize,
-    pub to: usize,
+/// The span of a match and of the capture groups in it, in bytes within line
+/// `line`; see [`NFA::captures`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Captures {
     pub line: usize,
+    /// The start and end of each group in turn, group 0 being the match.
+    slots: Vec<Option<usize>>,
 }
 
-#[derive(Debug)]
-pub struct FileMatch {
-    pub file_path: Option<PathBuf>,
-    pub matches: Vec<Match>,
-}
-
-impl FileMatch {
-    pub fn print_count(&self) {
-        if self.matches.is_empty() || self.file_path.is_none() {
-            return;
+impl Captures {
+    /// The span of group `i`, counted from 1 in the order the groups open,
+    /// or of the whole match for 0. `None` for a group that took no part in
+    /// the match, or that the pattern does not have.
+    pub fn get(&self, i: usize) -> Option<Range<usize>> {
+        match (self.slots.get(2 * i)?, self.slots.get(2 * i + 1)?) {
+            (Some(from), Some(to)) => Some(*from..*to),
+            _ => None,
         }
-
-        let path = self.file_path.as_ref().unwrap();
-
-        println!("{}:{}", path.to_str().unwrap().blue(), self.matches.len());
-
     }
+}
 
-    pub fn print_matches(&self, options: &NfaOptions) {
-        if self.matches.is_empty() {
-            return;
-        }
-
-        if self.file_path.is_none() {
-            return;
-        }
-
-        let path = self.file_path.as_ref().unwrap();
-        let file = File::open(path).expect(&format!(
-            "Failed to read file: '{}'",
-            path.to_str().unwrap()
-        ));
-
-        println!("{}", path.to_str().unwrap().blue());
-        let reader = io::BufReader::new(file);
-
-        let lines: Vec<_> = reader.lines().collect();
-        let max_match = self.matches.iter().max_by_key(|x| x.line);
-
-        let line_number_col_size = if max_match.is_some() {
-            max_match.unwrap().line.to_string().len()
-        } else {
-            1
-        };
-
-        let mut lines_to_print: BTreeMap<usize, String> = BTreeMap::new();
-        for m in &self.matches {
-            let err_msg = format!(
-                "Failed to read line: '{}' from: '{}' line",
-                m.line,
-                path.to_str().unwrap(),
-            );
-
-            let low = misc::clamp(
-                m.line as isize - options.context as isize,
-                0 as isize,
-                (lines.len() - 1) as isize,
-            );
-
-            let low = low as usize;
-            let high = misc::clamp(m.line + options.context as usize, 0, lines.len() - 1);
-
-            let line = lines[m.line].as_ref().expect(&err_msg);
-            let before  = &line[..m.from];
-            let matched = &line[m.from..m.to];
-            let after   = &line[m.to..];
-
-            let mut counter = low;
-            for l in &lines[low..=high] {
-                if counter == m.line {
-                    let formatted_line = 
-                    format!(
-                        "{:<line_number_col_size$} {}{}{}",
-                        (m.line + 1).to_string().green(),
-                        before,
-                        matched.red(),
-                        after
-                    );
-                    lines_to_print.insert(counter, formatted_line);
-                } else {
-                    if !lines_to_print.contains_key(&counter) {
-                        let formatted_line = format!(
-                            "{:<line_number_col_size$} {}",
-                             (counter + 1).to_string().green(),
-                             l.as_ref().unwrap()
-                        );
-                        lines_to_print.insert(counter, formatted_line);
-                    }
-                }
-
-                counter += 1;
-            }
-        }
-
-        for (_, formatted_line) in lines_to_print {
-            println!("{formatted_line}");
-        }
-    }
+/// States live in a single arena and refer to each other by index, which keeps
+/// the automaton immutable once built and cheap to share between worker threads.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Debug)]
+pub struct NFA {
+    pub states: Vec<State>,
+    pub initial_state: StateId,
+    pub final_states: Vec<StateId>,
+    /// Holds a lazy repetition, so matches follow the priority of the
+    /// transitions instead of being the longest; see [`NFA::find_matches`].
+    pub lazy: bool,
+    /// Only matches starting at offset 0 count; see [`NfaOptions::anchored`].
+    pub anchored: bool,
+    /// Only matches ending at the end of the line count, before the `\r` of
+    /// a line ending in `\r\n`: the pattern ended with `$`.
+    pub anchored_end: bool,
+    /// The number of states in each row of an automaton built by
+    /// [`NFA::fuzzy`], so state `id` is reached after `id / row_len` edits.
+    pub row_len: Option<usize>,
+    /// The pattern each of the final states is of, for an automaton built by
+    /// [`tagged_union`]; empty for any other, whose matches are of pattern 0.
+    pub final_patterns: Vec<usize>,
+    /// The number of capture groups; see [`NFA::captures`].
+    pub groups: usize,
 }
 
 impl fmt::Display for NFA {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut final_states_names = vec![];
         for state in &self.final_states {
-            let inner_value = (**state).borrow();
-            final_states_names.push(inner_value.name.to_string());
+            final_states_names.push(self.states[*state].name.to_string());
         }
 
         writeln!(f, "Number of states: {}", self.states.len())?;
-        writeln!(f, "Initial state: {}", (*self.initial_state).borrow().name)?;
+        writeln!(f, "Initial state: {}", self.states[self.initial_state].name)?;
         writeln!(f, "Final states: {}", final_states_names.join(", "))?;
         writeln!(f, "Transitions:")?;
 
         for state in &self.states {
-            let inner_value = (**state).borrow();
-            writeln!(f, "\t\"{}\" ({:?})", inner_value.name, inner_value.kind)?;
-            for trans in &inner_value.transitions {
-                writeln!(f, "\t\t{}", trans)?;
+            writeln!(f, "\t\"{}\" ({:?})", state.name, state.kind)?;
+            for trans in &state.transitions {
+                writeln!(f, "\t\t'{}' -> {}", trans.on, self.states[trans.to].name)?;
             }
         }
 
@@ -286,490 +527,1516 @@ impl fmt::Display for NFA {
 }
 
 impl NFA {
-    pub fn new(
-        states: Vec<RcMut<State>>,
-        initial_state: RcMut<State>,
-        final_states: Vec<RcMut<State>>,
-    ) -> Self {
+    pub fn new(states: Vec<State>, initial_state: StateId, final_states: Vec<StateId>) -> Self {
         Self {
             states,
             initial_state,
             final_states,
+            lazy: false,
+            anchored: false,
+            anchored_end: false,
+            row_len: None,
+            final_patterns: vec![],
+            groups: 0,
         }
     }
 
+    pub fn add_state(&mut self, state: State) -> StateId {
+        self.states.push(state);
+        self.states.len() - 1
+    }
+
+    /// Moves every state of `other` into this automaton and returns `other`'s
+    /// initial and final states translated to their new ids.
+    fn absorb(&mut self, other: NFA) -> (StateId, Vec<StateId>) {
+        self.lazy |= other.lazy;
+        self.groups = self.groups.max(other.groups);
+        let offset = self.states.len();
+        self.states.extend(other.states.into_iter().map(|mut state| {
+            for transition in &mut state.transitions {
+                transition.to += offset;
+            }
+            state
+        }));
+
+        let final_states = other.final_states.iter().map(|id| id + offset).collect();
+        (other.initial_state + offset, final_states)
+    }
+
+    /// Every match in `text`, line by line. At each position the longest
+    /// match wins and the scan resumes where it ended, so matches never
+    /// overlap. A pattern with a lazy repetition such as `*?` follows
+    /// priorities instead, as backtracking engines do: alternatives are tried
+    /// from the left, `*` repeats as often as it can and `*?` as rarely, and
+    /// the first match found in that order wins even if a longer one exists.
+    /// An empty match is reported at most once per position, and not at the
+    /// position where the previous match ended; after one the scan moves on
+    /// by a character.
     pub fn find_matches(&self, text: &str) -> Vec<Match> {
-        if text.len() == 0 {
-            return vec![];
-        }
+        self.find_matches_while(text, &mut || true).unwrap_or_default()
+    }
 
+    /// [`NFA::find_matches`], asking `go_on` before every [`POLL_EVERY`]th
+    /// position a match is tried at; `None` once it says no.
+    pub fn find_matches_while(
+        &self,
+        text: &str,
+        go_on: &mut dyn FnMut() -> bool,
+    ) -> Option<Vec<Match>> {
         let mut all_matches: Vec<Match> = vec![];
-        let lines = text.split('\n');
-        for (line_number, line) in lines.enumerate() {
-            for (k, _) in line.char_indices() {
-                let mut matches = self.find_matches_inner(&line[k..], k, line_number);
-                if !matches.is_empty() {
-                    all_matches.append(&mut matches);
+        let mut tries = 0usize;
+        for (line_number, line) in text.split('\n').enumerate() {
+            let mut start = 0;
+            let mut last_end = None;
+            while start <= line.len() && (start == 0 || !self.anchored) {
+                tries += 1;
+                if tries % POLL_EVERY == 0 && !go_on() {
+                    return None;
                 }
+                if let Some(to) = self
+                    .best_match_at(line, start)
+                    .filter(|to| *to > start || last_end != Some(start))
+                {
+                    debug_assert!(to >= start && line.is_char_boundary(to));
+                    all_matches.push(Match {
+                        from: start,
+                        to,
+                        line: line_number,
+                        errors: self.errors(line, start, to),
+                        pattern: self.pattern_of(line, start, to),
+                    });
+                    last_end = Some(to);
+                    if to > start {
+                        start = to;
+                        continue;
+                    }
+                }
+                start += line[start..].chars().next().map_or(1, char::len_utf8);
             }
         }
-        all_matches
+        Some(all_matches)
     }
 
-    pub fn find_match(&self, text: &str) -> bool {
-        if text.len() == 0 {
-            return self.find_match_inner(text, 0);
-        }
+    /// The matches [`NFA::find_matches`] finds in `text`, with the spans of
+    /// the capture groups in each. Within a match the groups take the spans
+    /// of the first way to match it in priority order, as in backtracking
+    /// engines: alternatives from the left, and `*` repeating as often as it
+    /// can. A group under a repetition keeps its last span.
+    pub fn captures(&self, text: &str) -> Vec<Captures> {
+        let lines: Vec<&str> = text.split('\n').collect();
+        self.find_matches(text)
+            .into_iter()
+            .map(|m| {
+                let mut slots = self.slots_in(lines[m.line], m.from, m.to);
+                slots[0] = Some(m.from);
+                slots[1] = Some(m.to);
+                Captures { line: m.line, slots }
+            })
+            .collect()
+    }
 
-        for (k, _) in text.char_indices() {
-            if self.find_match_inner(&text[k..], k) {
-                return true;
+    /// The first match in `text`; see [`NFA::find_at`].
+    pub fn find(&self, text: &str) -> Option<Match> {
+        self.find_at(text, 0)
+    }
+
+    /// The first match that starts at byte `start` of `text` or after it,
+    /// chosen at its start as [`NFA::find_matches`] chooses. Unlike there,
+    /// `from` and `to` are offsets into the whole of `text`; `line` is still
+    /// the 0-based line the match is on. An empty match at `start` counts, so
+    /// to go on after a match resume at `to`, or a character later if the
+    /// match was empty. Panics if `start` is not on a character boundary.
+    pub fn find_at(&self, text: &str, start: usize) -> Option<Match> {
+        let mut line_start = text[..start].rfind('\n').map_or(0, |newline| newline + 1);
+        let first_line = text[..line_start].matches('\n').count();
+        let mut from = start - line_start;
+        for (line_number, line) in (first_line..).zip(text[line_start..].split('\n')) {
+            let starts = line[from..].char_indices().map(|(k, _)| from + k).chain([line.len()]);
+            for at in starts.take_while(|at| *at == 0 || !self.anchored) {
+                if let Some(to) = self.best_match_at(line, at) {
+                    return Some(Match {
+                        from: line_start + at,
+                        to: line_start + to,
+                        line: line_number,
+                        errors: self.errors(line, at, to),
+                        pattern: self.pattern_of(line, at, to),
+                    });
+                }
             }
+            line_start += line.len() + 1;
+            from = 0;
         }
-        false
+        None
     }
 
-    fn find_matches_inner(&self, text: &str, start_index: usize, line_number: usize) -> Vec<Match> {
-        let mut matches = vec![];
-        let mut states_for_curr_symbol: Vec<RcMut<State>> = vec![Rc::clone(&self.initial_state)];
-        let mut states_for_next_symbol: Vec<RcMut<State>> = vec![];
+    /// The end of the match starting at byte `start` of `line`, by the rule
+    /// the pattern follows.
+    fn match_at(&self, line: &str, start: usize) -> Option<usize> {
+        if self.anchored_end {
+            // The one end allowed is the longest, closest and first match.
+            let end = line_end(line);
+            return (start <= end && self.is_full_match(line, start, end)).then_some(end);
+        }
+        match (self.row_len, self.lazy) {
+            (Some(row_len), _) => self.closest_match_at(line, start, row_len),
+            (None, true) => self.first_match_at(line, start),
+            (None, false) => self.longest_match_at(line, start),
+        }
+    }
 
-        let mut final_index: Option<usize> = None;
-        for (k, c) in text.char_indices() {
-            let mut i = 0;
-            while i < states_for_curr_symbol.len() {
-                let current_state = Rc::clone(&states_for_curr_symbol[i]);
+    /// [`NFA::match_at`], except that a fuzzy match is passed over when one
+    /// starting a character later has fewer edits, so it does not take in
+    /// text in front that the pattern does not need.
+    fn best_match_at(&self, line: &str, start: usize) -> Option<usize> {
+        let to = self.match_at(line, start)?;
+        let next = line[start..].chars().next().map(|c| start + c.len_utf8());
+        if let (Some(_), false, Some(next)) = (self.row_len, self.anchored, next) {
+            let later = self.match_at(line, next);
+            if later
+                .is_some_and(|later| self.errors(line, next, later) < self.errors(line, start, to))
+            {
+                return None;
+            }
+        }
+        Some(to)
+    }
 
-                let current_state_borrowed = (*current_state).borrow();
+    /// Whether any line of `text` has a match.
+    pub fn find_match(&self, text: &str) -> bool {
+        text.split('\n').any(|line| {
+            let starts = line.char_indices().map(|(k, _)| k).chain([line.len()]);
+            starts
+                .take_while(|at| *at == 0 || !self.anchored)
+                .any(|at| self.has_match_at(line, at))
+        })
+    }
 
-                match current_state_borrowed.kind {
-                    StateKind::Final => {
-                        final_index = Some(start_index + k);
+    /// The automaton in Graphviz's DOT language, for `dot -Tsvg` and the like:
+    /// a node per state, named after it and doubly circled if final, with an
+    /// arrow into the initial one and an edge per transition.
+    pub fn to_dot(&self) -> String {
+        let quoted =
+            |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = String::from("digraph nfa {\n    rankdir=LR;\n    start [shape=point];\n");
+        for (id, state) in self.states.iter().enumerate() {
+            let shape = match self.final_states.contains(&id) {
+                true => "doublecircle",
+                false => "circle",
+            };
+            dot.push_str(&format!("    {id} [label={}, shape={shape}];\n", quoted(&state.name)));
+        }
+        dot.push_str(&format!("    start -> {};\n", self.initial_state));
+        for (id, state) in self.states.iter().enumerate() {
+            for transition in &state.transitions {
+                let label = quoted(&transition.on.to_string());
+                dot.push_str(&format!("    {id} -> {} [label={label}];\n", transition.to));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// This automaton, made to also accept text within `max_errors` edits of
+    /// what it accepts, counted as in the Levenshtein distance: a character
+    /// inserted, deleted or substituted is one edit each. It is made of
+    /// `max_errors + 1` copies, or rows, of the states; row `i` is where `i`
+    /// edits were made, and every edit moves one row down. A match at a
+    /// position ends where the fewest edits are needed, the furthest such end
+    /// if there are several, and [`Match::errors`] is that number; a position
+    /// is passed over when the next one starts a match with fewer edits.
+    ///
+    /// A match never starts or ends with an inserted character: the same
+    /// match without it would have fewer edits.
+    pub fn fuzzy(&self, max_errors: usize) -> NFA {
+        let row_len = self.states.len();
+        let mut at_start = vec![self.initial_state];
+        self.epsilon_closure(&mut at_start, None);
+        let at_end: Vec<bool> = (0..row_len)
+            .map(|id| {
+                let mut closure = vec![id];
+                self.epsilon_closure(&mut closure, None);
+                closure.iter().any(|id| self.final_states.contains(id))
+            })
+            .collect();
+        let any = Label::Class(CharClass::new([]).negate());
+
+        let mut states = Vec::with_capacity(row_len * (max_errors + 1));
+        for row in 0..=max_errors {
+            let id_in = |row: usize, id: StateId| row * row_len + id;
+            for (id, state) in self.states.iter().enumerate() {
+                let mut transitions: Vec<Transition> = state
+                    .transitions
+                    .iter()
+                    .map(|t| Transition::new(t.on.clone(), id_in(row, t.to)))
+                    .collect();
+                if row < max_errors {
+                    for t in state.transitions.iter().filter(|t| t.on.reads_char()) {
+                        // Substitution, then deletion.
+                        transitions.push(Transition::new(any.clone(), id_in(row + 1, t.to)));
+                        transitions.push(Transition::new(Label::Epsilon, id_in(row + 1, t.to)));
+                    }
+                    if !at_start.contains(&id) && !at_end[id] {
+                        transitions.push(Transition::new(any.clone(), id_in(row + 1, id)));
                     }
-                    _ => {}
                 }
+                let kind = match state.kind {
+                    StateKind::Initial if row > 0 => StateKind::Normal,
+                    ref kind => kind.clone(),
+                };
+                states.push(State::new(format!("{}/{row}", state.name), transitions, kind));
+            }
+        }
 
-                let mut any_character_transition: Option<&Transition> = None;
-
-                let mut matches_given_char = false;
-                for transition in &current_state_borrowed.transitions {
-                    if transition.on == EPLISON {
-                        states_for_curr_symbol.push(Rc::clone(&transition.to));
-                    }
+        let final_states = (0..=max_errors)
+            .flat_map(|row| self.final_states.iter().map(move |id| row * row_len + id))
+            .collect();
+        NFA {
+            states,
+            initial_state: self.initial_state,
+            final_states,
+            lazy: self.lazy,
+            anchored: self.anchored,
+            anchored_end: self.anchored_end,
+            row_len: Some(row_len),
+            final_patterns: self.final_patterns.repeat(max_errors + 1),
+            groups: self.groups,
+        }
+    }
 
-                    if transition.on == ANY_OTHER_CHAR {
-                        any_character_transition = Some(transition);
-                    }
+    /// The fewest edits `line[from..to]` is from the pattern, for a match
+    /// of an automaton built by [`NFA::fuzzy`]; 0 for any other.
+    fn errors(&self, line: &str, from: usize, to: usize) -> usize {
+        let Some(row_len) = self.row_len else {
+            return 0;
+        };
+        self.states_after(line, from, to)
+            .iter()
+            .filter(|state| self.final_states.contains(state))
+            .map(|state| state / row_len)
+            .min()
+            .unwrap_or_default()
+    }
 
-                    if transition.on == c
-                        || (transition.on == ANY_DIGIT && c.is_numeric())
-                        || (transition.on == ANY_ALPHANUMERIC && c.is_alphanumeric())
-                    {
-                        matches_given_char = true;
-                        let appended_state = Rc::clone(&transition.to);
-                        states_for_next_symbol.push(appended_state.clone());
-                    }
-                }
+    /// The first pattern whose final state `line[from..to]` reaches, for a
+    /// match of an automaton built by [`tagged_union`]; 0 for any other.
+    fn pattern_of(&self, line: &str, from: usize, to: usize) -> usize {
+        if self.final_patterns.is_empty() {
+            return 0;
+        }
+        self.states_after(line, from, to)
+            .iter()
+            .filter_map(|state| self.final_states.iter().position(|id| id == state))
+            .map(|k| self.final_patterns[k])
+            .min()
+            .unwrap_or_default()
+    }
 
-                if !matches_given_char && any_character_transition.is_some() {
-                    states_for_next_symbol.push(Rc::clone(&any_character_transition.unwrap().to));
-                }
+    /// Whether the pattern accepts the whole of `line[from..to]`, not just a
+    /// part of it. Assertions like `\b` look at the rest of the line.
+    pub fn is_full_match(&self, line: &str, from: usize, to: usize) -> bool {
+        self.states_after(line, from, to).iter().any(|state| self.final_states.contains(state))
+    }
 
-                i += 1;
+    /// The states the automaton is in after reading `line[from..to]` from its
+    /// initial state, with their epsilon closure.
+    fn states_after(&self, line: &str, from: usize, to: usize) -> Vec<StateId> {
+        let mut states = vec![self.initial_state];
+        for (k, c) in line[from..to].char_indices() {
+            self.epsilon_closure(&mut states, Some(Around::at(line, from + k)));
+            let mut next = vec![];
+            for state in &states {
+                self.step(*state, c, &mut next);
             }
-
-            if final_index.is_some() {
-                matches.push(Match {
-                    from: start_index,
-                    to: final_index.unwrap(),
-                    line: line_number,
-                });
-                final_index = None;
+            if next.is_empty() {
+                return next;
             }
-
-            states_for_curr_symbol = states_for_next_symbol.clone();
-            states_for_next_symbol.clear();
+            next.sort_unstable();
+            next.dedup();
+            states = next;
         }
+        self.epsilon_closure(&mut states, Some(Around::at(line, to)));
+        states
+    }
 
+    /// Adds the states reachable from `states` through epsilon transitions
+    /// and the assertions that hold `around` the position; see
+    /// [`Label::passes`].
+    fn epsilon_closure(&self, states: &mut Vec<StateId>, around: Option<Around>) {
         let mut i = 0;
-        while i < states_for_curr_symbol.len() {
-            let state = Rc::clone(&states_for_curr_symbol[i]);
-            let current_state = (*state).borrow();
-            for transition in &current_state.transitions {
-                if transition.on == EPLISON {
-                    states_for_curr_symbol.push(Rc::clone(&transition.to));
+        while i < states.len() {
+            for transition in &self.states[states[i]].transitions {
+                if transition.on.passes(around) && !states.contains(&transition.to) {
+                    states.push(transition.to);
                 }
             }
             i += 1;
         }
-
-        matches
     }
 
-    fn find_match_inner(&self, text: &str, start_index: usize) -> bool {
-        let mut states_for_curr_symbol: Vec<RcMut<State>> = vec![Rc::clone(&self.initial_state)];
-        let mut states_for_next_symbol: Vec<RcMut<State>> = vec![];
-
-        let mut final_index: Option<usize> = None;
-        let mut k = 0;
-        for c in text.chars() {
-            let mut i = 0;
-            while i < states_for_curr_symbol.len() {
-                let current_state = Rc::clone(&states_for_curr_symbol[i]);
+    /// The end of the longest match that starts at byte `start` of `line`.
+    fn longest_match_at(&self, line: &str, start: usize) -> Option<usize> {
+        let mut longest = None;
+        let mut states_for_curr_symbol: Vec<StateId> = vec![self.initial_state];
+        let mut states_for_next_symbol: Vec<StateId> = vec![];
 
-                let current_state_borrowed = (*current_state).borrow();
+        for (k, c) in line[start..].char_indices() {
+            self.epsilon_closure(&mut states_for_curr_symbol, Some(Around::at(line, start + k)));
 
-                match current_state_borrowed.kind {
-                    StateKind::Final => {
-                        final_index = Some(start_index + k);
-                    }
-                    _ => {}
+            for state in &states_for_curr_symbol {
+                if let StateKind::Final = self.states[*state].kind {
+                    longest = Some(start + k);
                 }
+                self.step(*state, c, &mut states_for_next_symbol);
+            }
 
-                let mut any_character_transition: Option<&Transition> = None;
+            if states_for_next_symbol.is_empty() {
+                return longest;
+            }
+            // States reached on several paths, as in a fuzzy automaton, are
+            // followed once.
+            states_for_next_symbol.sort_unstable();
+            states_for_next_symbol.dedup();
+            std::mem::swap(&mut states_for_curr_symbol, &mut states_for_next_symbol);
+            states_for_next_symbol.clear();
+        }
 
-                let mut matches_given_char = false;
-                for transition in &current_state_borrowed.transitions {
-                    if transition.on == EPLISON {
-                        states_for_curr_symbol.push(Rc::clone(&transition.to));
-                    }
+        // A match that runs up to the end of the line is only visible once the
+        // last character has been consumed.
+        self.epsilon_closure(&mut states_for_curr_symbol, Some(Around::at(line, line.len())));
+        if states_for_curr_symbol
+            .iter()
+            .any(|state| matches!(self.states[*state].kind, StateKind::Final))
+        {
+            longest = Some(line.len());
+        }
+        longest
+    }
 
-                    if transition.on == ANY_OTHER_CHAR {
-                        any_character_transition = Some(transition);
-                    }
+    /// The end of the match starting at byte `start` of `line` with the
+    /// fewest edits, and the longest of those, for an automaton built by
+    /// [`NFA::fuzzy`] with rows of `row_len` states.
+    fn closest_match_at(&self, line: &str, start: usize, row_len: usize) -> Option<usize> {
+        let mut closest: Option<(usize, usize)> = None;
+        let mut states: Vec<StateId> = vec![self.initial_state];
+        let positions = line[start..].char_indices().map(|(k, c)| (start + k, Some(c)));
+        for (at, c) in positions.chain([(line.len(), None)]) {
+            self.epsilon_closure(&mut states, Some(Around::at(line, at)));
+            let errors = states
+                .iter()
+                .filter(|state| matches!(self.states[**state].kind, StateKind::Final))
+                .map(|state| state / row_len)
+                .min();
+            if let Some(errors) =
+                errors.filter(|errors| closest.map_or(true, |(fewest, _)| *errors <= fewest))
+            {
+                closest = Some((errors, at));
+            }
 
-                    if transition.on == c
-                        || (transition.on == ANY_DIGIT && c.is_numeric())
-                        || (transition.on == ANY_ALPHANUMERIC && c.is_alphanumeric())
-                    {
-                        matches_given_char = true;
-                        let appended_state = Rc::clone(&transition.to);
-                        states_for_next_symbol.push(appended_state.clone());
-                    }
-                }
+            let Some(c) = c else { break };
+            let mut next = vec![];
+            for state in &states {
+                self.step(*state, c, &mut next);
+            }
+            if next.is_empty() {
+                break;
+            }
+            next.sort_unstable();
+            next.dedup();
+            states = next;
+        }
+        closest.map(|(_, end)| end)
+    }
 
-                if !matches_given_char && any_character_transition.is_some() {
-                    states_for_next_symbol.push(Rc::clone(&any_character_transition.unwrap().to));
-                }
+    /// Adds the states `state` moves to on `c`, in the order of its
+    /// transitions.
+    fn step(&self, state: StateId, c: char, next: &mut Vec<StateId>) {
+        for transition in &self.states[state].transitions {
+            if transition.on.accepts(c) {
+                next.push(transition.to);
+            }
+        }
+    }
 
-                i += 1;
+    /// `states` and the states reachable from them through epsilon
+    /// transitions and assertions that hold `around` the position, in
+    /// priority order: depth first, earlier transitions first. A state keeps
+    /// the place where it is first reached.
+    fn prioritized_closure(&self, states: &[StateId], around: Around) -> Vec<StateId> {
+        let mut closure = vec![];
+        let mut seen = vec![false; self.states.len()];
+        let mut stack: Vec<StateId> = states.iter().rev().copied().collect();
+        while let Some(state) = stack.pop() {
+            if std::mem::replace(&mut seen[state], true) {
+                continue;
             }
-            k += 1;
-
-            if final_index.is_some() {
-                println!(
-                    "Found pattern in: '{}' from: '{}:{}'",
-                    text,
-                    start_index,
-                    final_index.unwrap()
-                );
-                return true;
+            closure.push(state);
+            let epsilons = self.states[state].transitions.iter().rev();
+            stack.extend(epsilons.filter(|t| t.on.passes(Some(around))).map(|t| t.to));
+        }
+        closure
+    }
+
+    /// The capture slots of the first way, in priority order, to accept the
+    /// whole of `line[from..to]`, simulated as a Pike VM: each thread carries
+    /// the slots the [`Label::Save`] transitions it took set.
+    fn slots_in(&self, line: &str, from: usize, to: usize) -> Vec<Option<usize>> {
+        let unset = vec![None; 2 * (self.groups + 1)];
+        let mut threads =
+            self.closure_with_slots(vec![(self.initial_state, unset.clone())], line, from);
+        for (k, c) in line[from..to].char_indices() {
+            let mut next = vec![];
+            for (state, slots) in &threads {
+                let transitions = self.states[*state].transitions.iter();
+                next.extend(transitions.filter(|t| t.on.accepts(c)).map(|t| (t.to, slots.clone())));
             }
+            threads = self.closure_with_slots(next, line, from + k + c.len_utf8());
+        }
+        threads
+            .into_iter()
+            .find(|(state, _)| self.final_states.contains(state))
+            .map_or(unset, |(_, slots)| slots)
+    }
 
-            states_for_curr_symbol = states_for_next_symbol.clone();
-            states_for_next_symbol.clear();
+    /// [`NFA::prioritized_closure`] of `threads` at byte `at` of `line`, each
+    /// with its capture slots.
+    fn closure_with_slots(&self, threads: Vec<Thread>, line: &str, at: usize) -> Vec<Thread> {
+        let around = Around::at(line, at);
+        let mut closure = vec![];
+        let mut seen = vec![false; self.states.len()];
+        let mut stack: Vec<Thread> = threads.into_iter().rev().collect();
+        while let Some((state, slots)) = stack.pop() {
+            if std::mem::replace(&mut seen[state], true) {
+                continue;
+            }
+            for transition in self.states[state].transitions.iter().rev() {
+                if transition.on.passes(Some(around)) {
+                    let mut slots = slots.clone();
+                    if let Label::Save(slot) = transition.on {
+                        slots[slot] = Some(at);
+                    }
+                    stack.push((transition.to, slots));
+                }
+            }
+            closure.push((state, slots));
         }
+        closure
+    }
 
-        let mut i = 0;
-        while i < states_for_curr_symbol.len() {
-            let state = Rc::clone(&states_for_curr_symbol[i]);
-            let current_state = (*state).borrow();
-            for transition in &current_state.transitions {
-                if transition.on == EPLISON {
-                    states_for_curr_symbol.push(Rc::clone(&transition.to));
+    /// The end of the match starting at byte `start` of `line` that comes
+    /// first in priority order. The threads of the simulation are kept in
+    /// that order, and once one reaches the final state the threads behind
+    /// it are dropped; the ones ahead may still find a preferred match.
+    fn first_match_at(&self, line: &str, start: usize) -> Option<usize> {
+        let mut first = None;
+        let mut threads = self.prioritized_closure(&[self.initial_state], Around::at(line, start));
+        let positions = line[start..].char_indices().map(|(k, c)| (start + k, Some(c)));
+        for (at, c) in positions.chain([(line.len(), None)]) {
+            let mut next = vec![];
+            for state in &threads {
+                if let StateKind::Final = self.states[*state].kind {
+                    first = Some(at);
+                    break;
+                }
+                if let Some(c) = c {
+                    self.step(*state, c, &mut next);
                 }
             }
-            i += 1;
+            if next.is_empty() {
+                break;
+            }
+            let after = at + c.map_or(0, char::len_utf8);
+            threads = self.prioritized_closure(&next, Around::at(line, after));
         }
+        first
+    }
+
+    /// Whether a match starts at byte `start` of `line`, stopping at the
+    /// first one found.
+    fn has_match_at(&self, line: &str, start: usize) -> bool {
+        if self.anchored_end {
+            return self.match_at(line, start).is_some();
+        }
+        let mut states_for_curr_symbol: Vec<StateId> = vec![self.initial_state];
+        let mut states_for_next_symbol: Vec<StateId> = vec![];
+        let is_final =
+            |states: &[StateId]| states.iter().any(|state| self.final_states.contains(state));
+
+        for (k, c) in line[start..].char_indices() {
+            self.epsilon_closure(&mut states_for_curr_symbol, Some(Around::at(line, start + k)));
+            if is_final(&states_for_curr_symbol) {
+                return true;
+            }
 
-        for final_state in &self.final_states {
             for state in &states_for_curr_symbol {
-                if Rc::ptr_eq(final_state, state) {
-                    return true;
-                }
+                self.step(*state, c, &mut states_for_next_symbol);
+            }
+            if states_for_next_symbol.is_empty() {
+                return false;
             }
+            // States reached on several paths, as in a fuzzy automaton, are
+            // followed once.
+            states_for_next_symbol.sort_unstable();
+            states_for_next_symbol.dedup();
+            std::mem::swap(&mut states_for_curr_symbol, &mut states_for_next_symbol);
+            states_for_next_symbol.clear();
         }
 
-        false
+        self.epsilon_closure(&mut states_for_curr_symbol, Some(Around::at(line, line.len())));
+        is_final(&states_for_curr_symbol)
     }
 }
 
-pub fn negative_set_of_chars(chars: &Vec<char>, options: &NfaOptions) -> NFA {
-    let initial_state = Rc::new(RefCell::new(State::new(
-        format!("initial"),
-        vec![],
-        StateKind::Initial,
-    )));
-    let final_state = Rc::new(RefCell::new(State::new(
-        format!("final"),
-        vec![],
-        StateKind::Final,
-    )));
-    let failed_state = Rc::new(RefCell::new(State::new(
-        format!("failed"),
-        vec![],
-        StateKind::Failed,
-    )));
+impl Matcher for NFA {
+    fn find_matches(&self, haystack: &str) -> Vec<Match> {
+        NFA::find_matches(self, haystack)
+    }
 
-    let states = vec![initial_state, final_state, failed_state];
+    fn find_matches_while(
+        &self,
+        haystack: &str,
+        go_on: &mut dyn FnMut() -> bool,
+    ) -> Option<Vec<Match>> {
+        NFA::find_matches_while(self, haystack, go_on)
+    }
 
-    if options.ignore_case {
-        for c in chars {
-            states[0]
-                .borrow_mut()
-                .add_transition(naive_lowercase(*c), &states[2]);
-            states[0]
-                .borrow_mut()
-                .add_transition(naive_uppercase(*c), &states[2]);
-        }
-    } else {
-        for c in chars {
-            states[0].borrow_mut().add_transition(*c, &states[2]);
-        }
+    fn is_match(&self, haystack: &str) -> bool {
+        self.find_match(haystack)
     }
+}
 
-    states[0]
-        .borrow_mut()
-        .add_transition(ANY_OTHER_CHAR, &states[1]);
+/// Where a `$` matches in `line`: at its end, or before a `\r` ending it.
+fn line_end(line: &str) -> usize {
+    line.strip_suffix('\r').unwrap_or(line).len()
+}
 
-    let starting_state = Rc::clone(&states[0]);
+/// The automaton that takes the one transition `on`, which reads a
+/// character or asserts something: an initial state (0) with a single
+/// transition to the final one (1).
+fn single_char(on: Label) -> NFA {
+    let states = vec![
+        State::new(format!("initial_{on}"), vec![Transition::new(on, 1)], StateKind::Initial),
+        State::new("final", vec![], StateKind::Final),
+    ];
+    NFA::new(states, 0, vec![1])
+}
 
-    let final_states = vec![Rc::clone(&states[1])];
+/// The characters of the inclusive `ranges`, in any case when ignoring
+/// case.
+fn class_of(ranges: &[(char, char)], options: &NfaOptions) -> CharClass {
+    match options.ignore_case {
+        true => CharClass::folded(ranges.iter().copied()),
+        false => CharClass::new(ranges.iter().copied()),
+    }
+}
 
-    NFA::new(states, starting_state, final_states)
+/// One character in the inclusive `ranges` or of `shorthands`, as in
+/// `[a-z_\d]`, or one of neither when `negated`, as in `[^a-z_\d]`.
+pub fn char_set(
+    ranges: &[(char, char)],
+    shorthands: &[Shorthand],
+    negated: bool,
+    options: &NfaOptions,
+) -> NFA {
+    // A negated set leaves out a newline too, unless told not to.
+    let newline = (negated && !options.dot_matches_newline).then_some(('\n', '\n'));
+    let ranges: Vec<(char, char)> = ranges.iter().copied().chain(newline).collect();
+    let class = class_of(&ranges, options).with_shorthands(shorthands.iter().copied());
+    single_char(Label::Class(match negated {
+        true => class.negate(),
+        false => class,
+    }))
 }
 
-pub fn set_of_chars(chars: &Vec<char>, options: &NfaOptions) -> NFA {
-    let initial_state = Rc::new(RefCell::new(State::new(
-        format!("initial"),
-        vec![],
-        StateKind::Initial,
-    )));
-    let final_state = Rc::new(RefCell::new(State::new(
-        format!("final"),
-        vec![],
-        StateKind::Final,
-    )));
-    let failed_state = Rc::new(RefCell::new(State::new(
-        format!("failed"),
-        vec![],
-        StateKind::Failed,
-    )));
-
-    let states = vec![initial_state, final_state, failed_state];
-
-    if options.ignore_case {
-        for c in chars {
-            //From initial to final
-            states[0]
-                .borrow_mut()
-                .add_transition(naive_uppercase(*c), &states[1]);
-            states[0]
-                .borrow_mut()
-                .add_transition(naive_lowercase(*c), &states[1]);
-        }
-    } else {
-        for c in chars {
-            //From initial to final
-            states[0].borrow_mut().add_transition(*c, &states[1]);
-        }
-    }
-
-    //From initial to failed
-    states[0]
-        .borrow_mut()
-        .add_transition(ANY_OTHER_CHAR, &states[2]);
-    //from final to failed
-    states[1]
-        .borrow_mut()
-        .add_transition(ANY_OTHER_CHAR, &states[2]);
-
-    let starting_state = Rc::clone(&states[0]);
-
-    let final_states = vec![Rc::clone(&states[1])];
-
-    NFA::new(states, starting_state, final_states)
+/// `\b`, or `\B` when `negated`; see [`Around::is_word_boundary`].
+pub fn word_boundary(negated: bool) -> NFA {
+    single_char(match negated {
+        true => Label::NotWordBoundary,
+        false => Label::WordBoundary,
+    })
+}
+
+/// `^`, or `$` when `end`.
+pub fn line_anchor(end: bool) -> NFA {
+    single_char(match end {
+        true => Label::LineEnd,
+        false => Label::LineStart,
+    })
 }
 
 pub fn digits() -> NFA {
-    let mut opt = NfaOptions::default();
-    opt.ignore_case = true;
-    concat(symbol(ANY_DIGIT, &opt), kleen(symbol(ANY_DIGIT, &opt)))
+    plus(digit())
 }
 
-pub fn alphanumeric(options: &NfaOptions) -> NFA {
-    symbol(ANY_ALPHANUMERIC, options)
+pub fn alphanumeric(_options: &NfaOptions) -> NFA {
+    single_char(Label::Alphanumeric)
 }
 
 pub fn digit() -> NFA {
-    let mut opt = NfaOptions::default();
-    opt.ignore_case = true;
-    symbol(ANY_DIGIT, &opt)
+    single_char(Label::Numeric)
 }
 
-fn naive_uppercase(c: char) -> char {
-    c.to_uppercase().collect::<Vec<_>>()[0]
+/// `c` folded for matching without case, as Unicode's simple case folding
+/// does: its lowercase form when that is one character, so `Ǆ`, `ǅ` and `ǆ`
+/// fold alike, and a few lowercase letters further, like `ς` to `σ`. A
+/// character whose lowercase form expands, like `İ` to "i̇", folds to
+/// itself, as does `ß`, whose uppercase is "SS", and `ı`, which only Turkish
+/// folds with `I`. Folding the haystack a character at a time keeps match
+/// offsets in bytes of the line as it was read.
+fn fold(c: char) -> char {
+    if c.is_ascii() {
+        return c.to_ascii_lowercase();
+    }
+    let mut lowercase = c.to_lowercase();
+    let lower = match (lowercase.next(), lowercase.next()) {
+        (Some(lower), None) => lower,
+        _ => c,
+    };
+    match lower {
+        'ς' => 'σ',
+        'ſ' => 's',
+        'ϐ' => 'β',
+        'ϑ' => 'θ',
+        'ϕ' => 'φ',
+        'ϖ' => 'π',
+        'ϰ' => 'κ',
+        'ϱ' => 'ρ',
+        'ϵ' => 'ε',
+        'ẛ' => 'ṡ',
+        '\u{1fbe}' => 'ι',
+        lower => lower,
+    }
 }
 
-fn naive_lowercase(c: char) -> char {
-    c.to_lowercase().collect::<Vec<_>>()[0]
+pub fn symbol(c: char, options: &NfaOptions) -> NFA {
+    char_set(&[(c, c)], &[], false, options)
 }
 
-pub fn symbol(c: char, options: &NfaOptions) -> NFA {
-    let initial_state = Rc::new(RefCell::new(State::new(
-        format!("initial_{c}"),
-        vec![],
+pub fn union(mut a: NFA, b: NFA) -> NFA {
+    let (b_initial_state, b_final_states) = a.absorb(b);
+
+    let new_initial_state = State::new(
+        "initial_n",
+        vec![
+            Transition::new(Label::Epsilon, a.initial_state),
+            Transition::new(Label::Epsilon, b_initial_state),
+        ],
+        StateKind::Initial,
+    );
+    a.initial_state = a.add_state(new_initial_state);
+
+    let new_final_state = a.add_state(State::new("final_n", vec![], StateKind::Final));
+
+    for final_state in a.final_states.iter().chain(&b_final_states) {
+        let final_state = &mut a.states[*final_state];
+        final_state.add_transition(Label::Epsilon, new_final_state);
+        final_state.kind = StateKind::Normal;
+    }
+
+    a.final_states = vec![new_final_state];
+
+    a
+}
+
+/// Matches what any of `nfas` matches, trying them in order for the priority
+/// of lazy patterns; with none it matches nothing. Unlike folding [`union`],
+/// the alternatives share one new initial and final state, and every state
+/// is moved once.
+pub fn union_many(nfas: Vec<NFA>) -> NFA {
+    if nfas.len() == 1 {
+        return nfas.into_iter().next().unwrap();
+    }
+    let capacity = nfas.iter().map(|nfa| nfa.states.len()).sum::<usize>() + 2;
+    let mut union = NFA::new(Vec::with_capacity(capacity), 0, vec![]);
+    let mut new_initial_state = State::new(
+        "initial_n",
+        Vec::with_capacity(nfas.len()),
         StateKind::Initial,
-    )));
-    let final_state = Rc::new(RefCell::new(State::new(
-        format!("final_{c}"),
-        vec![],
-        StateKind::Final,
-    )));
-    let failed_state = Rc::new(RefCell::new(State::new(
-        format!("failed_{c}"),
-        vec![],
-        StateKind::Failed,
-    )));
-
-    let states = vec![initial_state, final_state, failed_state];
-
-    //From initial to final
-    //TODO: convert transitions so they ternsition on String not on char
-    if options.ignore_case {
-        states[0]
-            .borrow_mut()
-            .add_transition(naive_uppercase(c), &states[1]);
-        states[0]
-            .borrow_mut()
-            .add_transition(naive_lowercase(c), &states[1]);
-    } else {
-        states[0].borrow_mut().add_transition(c, &states[1]);
-    }
-    //From initial to failed
-    states[0]
-        .borrow_mut()
-        .add_transition(ANY_OTHER_CHAR, &states[2]);
-    //from final to failed
-    states[1]
-        .borrow_mut()
-        .add_transition(ANY_OTHER_CHAR, &states[2]);
-
-    let starting_state = Rc::clone(&states[0]);
-
-    let final_states = vec![Rc::clone(&states[1])];
-
-    NFA::new(states, starting_state, final_states)
+    );
+    let mut final_states = vec![];
+    for nfa in nfas {
+        let (initial_state, nfa_final_states) = union.absorb(nfa);
+        new_initial_state.add_transition(Label::Epsilon, initial_state);
+        final_states.extend(nfa_final_states);
+    }
+    union.initial_state = union.add_state(new_initial_state);
+
+    let new_final_state = union.add_state(State::new("final_n", vec![], StateKind::Final));
+    for final_state in final_states {
+        let final_state = &mut union.states[final_state];
+        final_state.add_transition(Label::Epsilon, new_final_state);
+        final_state.kind = StateKind::Normal;
+    }
+    union.final_states = vec![new_final_state];
+
+    union
 }
 
-pub fn union(mut a: NFA, mut b: NFA) -> NFA {
-    a.states.append(&mut b.states);
-    let new_inital_state = Rc::new(RefCell::new(State::new(
-        "initial_n".to_string(),
-        vec![],
+/// Matches what any of `nfas` matches, like [`union_many`], but keeps the
+/// final states of each apart, tagged with its index in `nfas`, so a match
+/// tells which of them it is of; see [`Match::pattern`]. Patterns anchored
+/// with `^` or `$` keep their anchors as assertions, unless all of them are
+/// anchored alike.
+pub fn tagged_union(nfas: Vec<NFA>) -> NFA {
+    let anchors = |nfa: &NFA| (nfa.anchored, nfa.anchored_end);
+    let first = nfas.first().map(anchors).unwrap_or_default();
+    let shared = match nfas.iter().all(|nfa| anchors(nfa) == first) {
+        true => first,
+        false => (false, false),
+    };
+
+    let capacity = nfas.iter().map(|nfa| nfa.states.len() + 2).sum::<usize>() + 1;
+    let mut union = NFA::new(Vec::with_capacity(capacity), 0, vec![]);
+    let mut new_initial_state = State::new(
+        "initial_n",
+        Vec::with_capacity(nfas.len()),
         StateKind::Initial,
-    )));
-    {
-        let mut new_initial_state_borrowed = (*new_inital_state).borrow_mut();
-        new_initial_state_borrowed.add_transition(EPLISON, &a.initial_state);
-        new_initial_state_borrowed.add_transition(EPLISON, &b.initial_state);
+    );
+    for (pattern, mut nfa) in nfas.into_iter().enumerate() {
+        if nfa.anchored && !shared.0 {
+            let mut anchor = State::new("initial_n", vec![], StateKind::Initial);
+            anchor.add_transition(Label::LineStart, nfa.initial_state);
+            nfa.initial_state = nfa.add_state(anchor);
+        }
+        if nfa.anchored_end && !shared.1 {
+            let new_final_state = nfa.add_state(State::new("final_n", vec![], StateKind::Final));
+            for final_state in std::mem::replace(&mut nfa.final_states, vec![new_final_state]) {
+                let final_state = &mut nfa.states[final_state];
+                final_state.add_transition(Label::LineEnd, new_final_state);
+                final_state.kind = StateKind::Normal;
+            }
+        }
+        let (initial_state, final_states) = union.absorb(nfa);
+        new_initial_state.add_transition(Label::Epsilon, initial_state);
+        union.final_patterns.extend(final_states.iter().map(|_| pattern));
+        union.final_states.extend(final_states);
     }
-    a.states.push(new_inital_state);
-    a.initial_state = Rc::clone(&a.states[a.states.len() - 1]);
+    union.initial_state = union.add_state(new_initial_state);
+    (union.anchored, union.anchored_end) = shared;
+    union
+}
+
+/// Makes `a` capture group `group`: what it matches is recorded by
+/// [`NFA::captures`].
+pub fn capture(mut a: NFA, group: usize) -> NFA {
+    let new_final_state = a.add_state(State::new("final_n", vec![], StateKind::Final));
+    for final_state in &a.final_states {
+        let final_state = &mut a.states[*final_state];
+        final_state.add_transition(Label::Save(2 * group + 1), new_final_state);
+        final_state.kind = StateKind::Normal;
+    }
+    a.final_states = vec![new_final_state];
+
+    let mut new_initial_state = State::new("initial_n", vec![], StateKind::Initial);
+    new_initial_state.add_transition(Label::Save(2 * group), a.initial_state);
+    a.initial_state = a.add_state(new_initial_state);
+    a.groups = a.groups.max(group);
 
-    let new_final_state = Rc::new(RefCell::new(State::new(
-        "final_n",
-        vec![],
-        StateKind::Final,
-    )));
-    a.states.push(new_final_state);
+    a
+}
 
-    let new_final_state = &a.states[a.states.len() - 1];
+pub fn kleen(mut a: NFA) -> NFA {
+    let new_final_state = a.add_state(State::new("final_n", vec![], StateKind::Final));
 
+    // Repeating comes before leaving, for the priority of lazy patterns.
     for final_state in &a.final_states {
-        let mut final_state_borrowed = (*final_state).borrow_mut();
-        final_state_borrowed.add_transition(EPLISON, new_final_state);
-        final_state_borrowed.kind = StateKind::Normal;
+        let final_state = &mut a.states[*final_state];
+        final_state.add_transition(Label::Epsilon, a.initial_state);
+        final_state.add_transition(Label::Epsilon, new_final_state);
+        final_state.kind = StateKind::Normal;
     }
 
-    for final_state in &b.final_states {
-        let mut final_state_borrowed = (*final_state).borrow_mut();
-        final_state_borrowed.add_transition(EPLISON, new_final_state);
-        final_state_borrowed.kind = StateKind::Normal;
+    let mut new_initial_state = State::new("initial_n", vec![], StateKind::Initial);
+    new_initial_state.add_transition(Label::Epsilon, a.initial_state);
+    for final_state in &a.final_states {
+        new_initial_state.add_transition(Label::Epsilon, *final_state);
     }
 
-    a.final_states.clear();
+    a.initial_state = a.add_state(new_initial_state);
+    a.final_states = vec![new_final_state];
 
-    a.final_states.push(Rc::clone(new_final_state));
+    a
+}
+
+/// Matches one or more repetitions of `a`: `+`.
+pub fn plus(mut a: NFA) -> NFA {
+    let new_final_state = a.add_state(State::new("final_n", vec![], StateKind::Final));
+
+    // Repeating comes before leaving, as in `kleen`.
+    for final_state in &a.final_states {
+        let final_state = &mut a.states[*final_state];
+        final_state.add_transition(Label::Epsilon, a.initial_state);
+        final_state.add_transition(Label::Epsilon, new_final_state);
+        final_state.kind = StateKind::Normal;
+    }
+    a.final_states = vec![new_final_state];
 
     a
 }
 
-pub fn kleen(mut a: NFA) -> NFA {
-    {
-        let new_final_state = Rc::new(RefCell::new(State::new(
-            "final_n",
-            vec![],
-            StateKind::Final,
-        )));
-        a.states.push(new_final_state);
+/// Like [`plus`], but prefers as few repetitions of `a` as possible: `+?`.
+pub fn lazy_plus(mut a: NFA) -> NFA {
+    let new_final_state = a.add_state(State::new("final_n", vec![], StateKind::Final));
 
-        let new_final_state = a.states.last().unwrap();
+    for final_state in &a.final_states {
+        let final_state = &mut a.states[*final_state];
+        final_state.add_transition(Label::Epsilon, new_final_state);
+        final_state.add_transition(Label::Epsilon, a.initial_state);
+        final_state.kind = StateKind::Normal;
+    }
+    a.final_states = vec![new_final_state];
+    a.lazy = true;
 
-        for final_state in &a.final_states {
-            let mut final_state_borrowed = (*final_state).borrow_mut();
-            final_state_borrowed.add_transition(EPLISON, new_final_state);
-            final_state_borrowed.add_transition(EPLISON, &a.initial_state);
-            final_state_borrowed.kind = StateKind::Normal;
-        }
+    a
+}
+
+/// Like [`kleen`], but prefers as few repetitions of `a` as possible: `*?`.
+pub fn lazy_kleen(mut a: NFA) -> NFA {
+    let new_final_state = a.add_state(State::new("final_n", vec![], StateKind::Final));
+
+    for final_state in &a.final_states {
+        let final_state = &mut a.states[*final_state];
+        final_state.add_transition(Label::Epsilon, new_final_state);
+        final_state.add_transition(Label::Epsilon, a.initial_state);
+        final_state.kind = StateKind::Normal;
     }
 
-    let new_inital_state = Rc::new(RefCell::new(State::new(
-        "initial_n".to_string(),
-        vec![],
+    let new_initial_state = State::new(
+        "initial_n",
+        vec![
+            Transition::new(Label::Epsilon, new_final_state),
+            Transition::new(Label::Epsilon, a.initial_state),
+        ],
         StateKind::Initial,
-    )));
-    {
-        let mut new_initial_state_borrowed = (*new_inital_state).borrow_mut();
-        new_initial_state_borrowed.add_transition(EPLISON, &a.initial_state);
+    );
 
-        for final_state in &a.final_states {
-            new_initial_state_borrowed.add_transition(EPLISON, final_state);
-        }
+    a.initial_state = a.add_state(new_initial_state);
+    a.final_states = vec![new_final_state];
+    a.lazy = true;
+
+    a
+}
+
+/// Matches what `a` matches, or nothing.
+pub fn optional(mut a: NFA) -> NFA {
+    let new_final_state = a.add_state(State::new("final_n", vec![], StateKind::Final));
+
+    for final_state in &a.final_states {
+        let final_state = &mut a.states[*final_state];
+        final_state.add_transition(Label::Epsilon, new_final_state);
+        final_state.kind = StateKind::Normal;
     }
-    a.states.push(new_inital_state);
-    a.initial_state = Rc::clone(a.states.last().unwrap());
-    a.final_states.clear();
 
-    let new_final_state = &a.states[a.states.len() - 2];
-    a.final_states.push(Rc::clone(new_final_state));
+    let new_initial_state = State::new(
+        "initial_n",
+        vec![
+            Transition::new(Label::Epsilon, a.initial_state),
+            Transition::new(Label::Epsilon, new_final_state),
+        ],
+        StateKind::Initial,
+    );
+
+    a.initial_state = a.add_state(new_initial_state);
+    a.final_states = vec![new_final_state];
 
     a
 }
 
-pub fn concat(mut a: NFA, mut b: NFA) -> NFA {
-    a.states.append(&mut b.states);
+/// Like [`optional`], but prefers matching nothing: `??`.
+pub fn lazy_optional(mut a: NFA) -> NFA {
+    let new_final_state = a.add_state(State::new("final_n", vec![], StateKind::Final));
 
-    for final_state in a.final_states {
-        let mut final_state_borrowed = (*final_state).borrow_mut();
-        final_state_borrowed.add_transition(EPLISON, &b.initial_state);
-        final_state_borrowed.kind = StateKind::Normal;
+    for final_state in &a.final_states {
+        let final_state = &mut a.states[*final_state];
+        final_state.add_transition(Label::Epsilon, new_final_state);
+        final_state.kind = StateKind::Normal;
     }
-    a.final_states = b.final_states;
+
+    let new_initial_state = State::new(
+        "initial_n",
+        vec![
+            Transition::new(Label::Epsilon, new_final_state),
+            Transition::new(Label::Epsilon, a.initial_state),
+        ],
+        StateKind::Initial,
+    );
+
+    a.initial_state = a.add_state(new_initial_state);
+    a.final_states = vec![new_final_state];
+    a.lazy = true;
+
+    a
+}
+
+/// Matches `a` repeated from `min` to `max` times, or at least `min` times
+/// without a `max`: `{min,max}`.
+pub fn repeat(a: NFA, min: usize, max: Option<usize>) -> NFA {
+    // Even no copies of a lazy `a` keep the priority of the pattern.
+    let lazy = a.lazy;
+    let mut parts: Vec<NFA> = (0..min).map(|_| a.clone()).collect();
+    match max {
+        Some(max) => parts.extend((min..max).map(|_| optional(a.clone()))),
+        None => parts.push(kleen(a)),
+    }
+    let mut repeated = concat_many(parts);
+    repeated.lazy = lazy;
+    repeated
+}
+
+/// Like [`repeat`], but prefers as few repetitions of `a` past `min` as
+/// possible: `{min,max}?`.
+pub fn lazy_repeat(a: NFA, min: usize, max: Option<usize>) -> NFA {
+    let mut parts: Vec<NFA> = (0..min).map(|_| a.clone()).collect();
+    match max {
+        Some(max) => parts.extend((min..max).map(|_| lazy_optional(a.clone()))),
+        None => parts.push(lazy_kleen(a)),
+    }
+    let mut repeated = concat_many(parts);
+    repeated.lazy = true;
+    repeated
+}
+
+pub fn concat(mut a: NFA, b: NFA) -> NFA {
+    let (b_initial_state, b_final_states) = a.absorb(b);
+
+    for final_state in &a.final_states {
+        let final_state = &mut a.states[*final_state];
+        final_state.add_transition(Label::Epsilon, b_initial_state);
+        final_state.kind = StateKind::Normal;
+    }
+    a.final_states = b_final_states;
 
     a
 }
 
+/// Matches what each of `nfas` matches, one after another; with none it
+/// matches the empty string. Like folding [`concat`], but every state is
+/// moved once, into room reserved up front.
+pub fn concat_many(nfas: Vec<NFA>) -> NFA {
+    let capacity = nfas.iter().map(|nfa| nfa.states.len()).sum::<usize>();
+    let mut nfas = nfas.into_iter();
+    let Some(mut concatenation) = nfas.next() else {
+        return NFA::new(vec![State::new("initial_n", vec![], StateKind::Final)], 0, vec![0]);
+    };
+    concatenation.states.reserve(capacity - concatenation.states.len());
+    for nfa in nfas {
+        let (initial_state, final_states) = concatenation.absorb(nfa);
+        for final_state in &concatenation.final_states {
+            let final_state = &mut concatenation.states[*final_state];
+            final_state.add_transition(Label::Epsilon, initial_state);
+            final_state.kind = StateKind::Normal;
+        }
+        concatenation.final_states = final_states;
+    }
+
+    concatenation
+}
+
 #[cfg(test)]
 mod tests {
     use crate::re::regex_to_nfa;
 
     use super::*;
 
+    fn spans(nfa: &NFA, text: &str) -> Vec<(usize, usize)> {
+        nfa.find_matches(text).iter().map(|m| (m.from, m.to)).collect()
+    }
+
+    /// The number of states and of transitions of `nfa`.
+    fn size(nfa: &NFA) -> (usize, usize) {
+        (nfa.states.len(), nfa.states.iter().map(|state| state.transitions.len()).sum())
+    }
+
+    #[test]
+    fn to_dot_draws_every_state_and_transition() {
+        let nfa = regex_to_nfa("a\"b", &NfaOptions::default()).unwrap();
+        let dot = nfa.to_dot();
+
+        assert!(dot.starts_with("digraph nfa {\n"), "{dot}");
+        assert!(dot.ends_with("}\n"), "{dot}");
+        assert_eq!(dot.matches("shape=doublecircle").count(), nfa.final_states.len(), "{dot}");
+        assert!(dot.contains(&format!("    start -> {};\n", nfa.initial_state)), "{dot}");
+        assert_eq!(dot.matches(" -> ").count(), size(&nfa).1 + 1, "{dot}");
+        assert!(dot.contains("[label=\"\\\"\"];"), "{dot}");
+    }
+
+    #[test]
+    fn a_literal_epsilon_is_not_drawn_as_an_epsilon() {
+        assert_eq!(Label::Epsilon.to_string(), "ε");
+        assert_eq!(Label::Class(CharClass::new([('ε', 'ε')])).to_string(), "[ε]");
+        assert_eq!(Label::Class(CharClass::new([('#', '#')])).to_string(), "#");
+    }
+
+    #[test]
+    fn union_many_matches_like_folded_unions_with_fewer_states() {
+        let opt = NfaOptions::default();
+        let words = ["foo", "bar", "ba", "bazz", "q*", "x[yz]"];
+        let compile = || words.iter().map(|word| regex_to_nfa(word, &opt).unwrap());
+        let folded = compile().reduce(union).unwrap();
+        let many = union_many(compile().collect());
+
+        for text in ["foo bar", "bazzbaq", "xz xy", "", "fo ba"] {
+            assert_eq!(many.find_matches(text), folded.find_matches(text), "{text}");
+        }
+        let (many_states, folded_states) = (size(&many).0, size(&folded).0);
+        assert_eq!(folded_states - many_states, 2 * (words.len() - 2));
+
+        // The first alternative keeps priority.
+        let lazy = union_many(vec![regex_to_nfa("a*?", &opt).unwrap(), symbol('a', &opt)]);
+        assert_eq!(spans(&lazy, "aa"), vec![(0, 0), (1, 1), (2, 2)]);
+        assert!(union_many(vec![]).find_matches("abc").is_empty());
+    }
+
+    #[test]
+    fn concat_many_matches_like_folded_concats() {
+        let opt = NfaOptions::default();
+        let parts = || {
+            ["a", "b*", "[cd]", "\\d"]
+                .into_iter()
+                .map(|part| regex_to_nfa(part, &opt).unwrap())
+        };
+        let folded = parts().reduce(concat).unwrap();
+        let many = concat_many(parts().collect());
+
+        for text in ["ac1", "abbbd22 ad", "a1", "ab"] {
+            assert_eq!(many.find_matches(text), folded.find_matches(text), "{text}");
+        }
+        assert_eq!(size(&many), size(&folded));
+        assert_eq!(spans(&concat_many(vec![]), "ab"), vec![(0, 0), (1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn fuzzy_patterns_match_within_their_edit_distance() {
+        let errors = |text: &str, max_errors: usize| {
+            let opt = NfaOptions { max_errors, ..Default::default() };
+            let matches = regex_to_nfa("recieve", &opt).unwrap().find_matches(text);
+            matches.iter().map(|m| (m.from, m.to, m.errors)).collect::<Vec<_>>()
+        };
+
+        for (text, expected) in [
+            ("recieve", [Some(0), Some(0), Some(0)]),
+            ("receve", [None, Some(1), Some(1)]),
+            ("receive", [None, None, Some(2)]),
+            ("rceive", [None, None, None]),
+        ] {
+            for (max_errors, expected) in expected.into_iter().enumerate() {
+                let found = expected
+                    .map(|errors| (0, text.len(), errors))
+                    .into_iter()
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    errors(text, max_errors),
+                    found,
+                    "{text} within {max_errors}"
+                );
+            }
+        }
+        assert_eq!(errors("we recieve, we receve", 1), vec![(3, 10, 0), (15, 21, 1)]);
+        assert_eq!(errors("rceive", 3), vec![(0, 6, 3)]);
+    }
+
+    #[test]
+    fn fuzzy_errors_are_the_levenshtein_distance() {
+        fn distance(a: &[char], b: &[char]) -> usize {
+            let mut row: Vec<usize> = (0..=b.len()).collect();
+            for (i, &x) in a.iter().enumerate() {
+                let mut previous = row[0];
+                row[0] = i + 1;
+                for (j, &y) in b.iter().enumerate() {
+                    let substituted = previous + usize::from(x != y);
+                    previous = row[j + 1];
+                    row[j + 1] = substituted.min(row[j] + 1).min(previous + 1);
+                }
+            }
+            row[b.len()]
+        }
+
+        let pattern = "abc".chars().collect::<Vec<_>>();
+        let nfa = regex_to_nfa("abc", &NfaOptions { max_errors: 2, ..Default::default() }).unwrap();
+        for text in ["abc", "ab", "abxc", "axc", "bc", "acb", "xbcx", "a", "cab"] {
+            for m in nfa.find_matches(text) {
+                let found = text[m.from..m.to].chars().collect::<Vec<_>>();
+                assert_eq!(m.errors, distance(&found, &pattern), "{text}: {m:?}");
+                assert!(m.errors <= 2, "{text}: {m:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn char_classes_merge_ranges() {
+        let class = CharClass::new([('x', 'z'), ('d', 'f'), ('a', 'c'), ('b', 'b'), ('q', 'p')]);
+
+        assert_eq!(class.ranges(), &[('a', 'f'), ('x', 'z')]);
+        assert!(class.contains('a') && class.contains('e') && class.contains('z'));
+        assert!(!class.contains('g') && !class.contains('`'));
+        assert!(class.clone().negate().contains('g'));
+        assert_eq!(class.to_string(), "[a-fx-z]");
+        assert_eq!(class.negate().to_string(), "[^a-fx-z]");
+        assert_eq!(CharClass::of(['ś']).to_string(), "ś");
+    }
+
+    #[test]
+    fn a_set_is_one_transition() {
+        let opt = NfaOptions::default();
+        let ignore_case = NfaOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let set = regex_to_nfa("[abcdefghij]", &ignore_case).unwrap();
+
+        assert_eq!(size(&set), (2, 1));
+        assert_eq!(
+            set.states[0].transitions[0].on,
+            Label::Class(CharClass::folded([('A', 'J'), ('a', 'j')]))
+        );
+        assert_eq!(set.states[0].transitions[0].on.to_string(), "(?i)[a-j]");
+        assert_eq!(size(&regex_to_nfa("[^abcdefghij]", &ignore_case).unwrap()), (2, 1));
+        assert_eq!(size(&symbol('a', &ignore_case)), (2, 1));
+        assert_eq!(size(&regex_to_nfa("abc", &opt).unwrap()), (6, 5));
+        assert_eq!(size(&regex_to_nfa("a*", &opt).unwrap()), (4, 5));
+        assert_eq!(size(&regex_to_nfa("\\d", &opt).unwrap()), (2, 1));
+    }
+
+    #[test]
+    fn zero_width_matches_once_per_position() {
+        let opt = NfaOptions::default();
+        let a_star = regex_to_nfa("a*", &opt).unwrap();
+        let x_opt = optional(symbol('x', &opt));
+
+        assert_eq!(spans(&a_star, "bbb"), vec![(0, 0), (1, 1), (2, 2), (3, 3)]);
+        assert_eq!(spans(&a_star, ""), vec![(0, 0)]);
+        // No empty match where a longer one starts or the previous one ended.
+        assert_eq!(spans(&a_star, "baab"), vec![(0, 0), (1, 3), (4, 4)]);
+        assert_eq!(spans(&x_opt, "yxy"), vec![(0, 0), (1, 2), (3, 3)]);
+        assert_eq!(spans(&x_opt, "ść"), vec![(0, 0), (2, 2), (4, 4)]);
+    }
+
+    #[test]
+    fn matches_are_longest_and_do_not_overlap() {
+        let nfa = regex_to_nfa("a(a*)", &NfaOptions::default()).unwrap();
+
+        assert_eq!(spans(&nfa, "aaa baa"), vec![(0, 3), (5, 7)]);
+    }
+
+    #[test]
+    fn lazy_star_stops_at_the_nearest_closing_quote() {
+        let opt = NfaOptions::default();
+        let line = r#"say "hi" and "bye" ok"#;
+        let greedy = regex_to_nfa(r#""(\w| |")*""#, &opt).unwrap();
+        let lazy = regex_to_nfa(r#""(\w| |")*?""#, &opt).unwrap();
+
+        assert!(!greedy.lazy && lazy.lazy);
+        assert_eq!(spans(&greedy, line), vec![(4, 18)]);
+        assert_eq!(spans(&lazy, line), vec![(4, 8), (13, 18)]);
+    }
+
+    #[test]
+    fn lazy_star_prefers_fewer_repetitions_but_still_matches() {
+        let opt = NfaOptions::default();
+
+        assert_eq!(spans(&regex_to_nfa("xa*?", &opt).unwrap(), "xaa"), vec![(0, 1)]);
+        assert_eq!(spans(&regex_to_nfa("xa*?b", &opt).unwrap(), "xaab"), vec![(0, 4)]);
+        // Greedy parts of a lazy pattern still take as much as they can.
+        assert_eq!(
+            spans(&regex_to_nfa("a*b*?", &opt).unwrap(), "aabb"),
+            vec![(0, 2), (3, 3), (4, 4)]
+        );
+    }
+
+    #[test]
+    fn lazy_plus_optional_and_counts_prefer_fewer_repetitions() {
+        let opt = NfaOptions::default();
+        let matched =
+            |pattern: &str, text: &str| spans(&regex_to_nfa(pattern, &opt).unwrap(), text);
+
+        assert_eq!(matched(r#""\w+?""#, r#"say "hi" and "bye""#), vec![(4, 8), (13, 18)]);
+        assert_eq!(matched("xa+?", "xaa"), vec![(0, 2)]);
+        assert_eq!(matched("xa+?b", "xaab"), vec![(0, 4)]);
+        assert_eq!(matched("xa??", "xa"), vec![(0, 1)]);
+        assert_eq!(matched("xa??b", "xab"), vec![(0, 3)]);
+        assert_eq!(matched("xa{2,4}?", "xaaaa"), vec![(0, 3)]);
+        assert_eq!(matched("xa{2,}?", "xaaaa"), vec![(0, 3)]);
+        assert_eq!(matched("xa{2,4}?b", "xaaab"), vec![(0, 5)]);
+    }
+
+    #[test]
+    fn alternatives_of_lazy_patterns_are_tried_from_the_left() {
+        let opt = NfaOptions::default();
+
+        // Longest wins without a lazy star, the first alternative with one.
+        assert_eq!(spans(&regex_to_nfa("(a|ab)c*", &opt).unwrap(), "abc"), vec![(0, 3)]);
+        assert_eq!(spans(&regex_to_nfa("(a|ab)c*?", &opt).unwrap(), "abc"), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn anchored_patterns_only_match_at_the_start() {
+        let opt = NfaOptions {
+            anchored: Anchor::Start,
+            ..Default::default()
+        };
+        let nfa = regex_to_nfa("ab", &opt).unwrap();
+
+        assert!(!nfa.find_match("xab"));
+        assert!(nfa.find_match("abx"));
+        assert_eq!(spans(&nfa, "xab"), vec![]);
+        assert_eq!(spans(&nfa, "abx"), vec![(0, 2)]);
+        assert_eq!(
+            nfa.find_matches("xab\nabab"),
+            vec![Match {
+                from: 0,
+                to: 2,
+                line: 1,
+                errors: 0,
+                pattern: 0
+            }]
+        );
+        assert_eq!(
+            spans(&regex_to_nfa("a*", &opt).unwrap(), "baa"),
+            vec![(0, 0)]
+        );
+    }
+
+    #[test]
+    fn patterns_anchored_at_both_ends_match_whole_lines() {
+        let opt = NfaOptions {
+            anchored: Anchor::Both,
+            ..Default::default()
+        };
+        let nfa = regex_to_nfa("abc", &opt).unwrap();
+
+        assert!(nfa.find_match("abc"));
+        assert!(!nfa.find_match("abcd"));
+        assert!(!nfa.find_match("xabc"));
+        assert_eq!(spans(&nfa, "abc\r"), vec![(0, 3)]);
+        let lines: Vec<usize> = nfa
+            .find_matches("abc\nabcd\nxabc\nabc")
+            .iter()
+            .map(|m| m.line)
+            .collect();
+        assert_eq!(lines, vec![0, 3]);
+        assert_eq!(spans(&regex_to_nfa("a|ab", &opt).unwrap(), "ab"), vec![(0, 2)]);
+        assert_eq!(spans(&regex_to_nfa("a*", &opt).unwrap(), ""), vec![(0, 0)]);
+        assert_eq!(spans(&regex_to_nfa("a*", &opt).unwrap(), "ab"), vec![]);
+    }
+
+    #[test]
+    fn find_at_reports_offsets_into_the_whole_text() {
+        let nfa = regex_to_nfa("ść*", &NfaOptions::default()).unwrap();
+        let text = "żółw ść\nab śćć ś";
+        let m = |from, to, line| {
+            Some(Match {
+                from,
+                to,
+                line,
+                errors: 0,
+                pattern: 0,
+            })
+        };
+
+        assert_eq!(nfa.find(text), m(8, 12, 0));
+        assert_eq!(&text[8..12], "ść");
+        assert_eq!(nfa.find_at(text, 10), m(16, 22, 1));
+        assert_eq!(&text[16..22], "śćć");
+        assert_eq!(nfa.find_at(text, 12), m(16, 22, 1));
+        assert_eq!(nfa.find_at(text, 22), m(23, 25, 1));
+        assert_eq!(nfa.find_at(text, text.len()), None);
+    }
+
+    #[test]
+    fn find_at_agrees_with_find_matches() {
+        let nfa = regex_to_nfa("a(b|c)*", &NfaOptions::default()).unwrap();
+        let text = "xabcab\nąa\n\nab";
+
+        let mut found = vec![];
+        let mut at = 0;
+        while let Some(m) = nfa.find_at(text, at) {
+            at = m.to;
+            found.push(m);
+        }
+
+        let line_starts: Vec<usize> = [0]
+            .into_iter()
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        let expected: Vec<Match> = nfa
+            .find_matches(text)
+            .into_iter()
+            .map(|m| Match {
+                from: line_starts[m.line] + m.from,
+                to: line_starts[m.line] + m.to,
+                line: m.line,
+                errors: 0,
+                pattern: 0,
+            })
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn nested_kleene_stars_terminate() {
+        let nfa = regex_to_nfa("(a*)*b", &NfaOptions::default()).unwrap();
+
+        assert_eq!(spans(&nfa, "xaab"), vec![(1, 4)]);
+        assert!(nfa.find_match("xaab"));
+        assert!(!nfa.find_match("xaa"));
+    }
+
+    #[test]
+    fn only_the_set_after_a_caret_is_negated() {
+        let nfa = regex_to_nfa("[^a][b]", &NfaOptions::default()).unwrap();
+
+        assert_eq!(spans(&nfa, "xb ab xc"), vec![(0, 2)]);
+    }
+
+    #[test]
+    fn negated_set_ignoring_case_excludes_every_case() {
+        let opt = NfaOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let nfa = regex_to_nfa("[^ab]", &opt).unwrap();
+
+        for (text, expected) in [
+            ("a", false),
+            ("A", false),
+            ("b", false),
+            ("B", false),
+            ("c", true),
+            ("C", true),
+        ] {
+            assert_eq!(nfa.find_match(text), expected, "'[^ab]' on '{text}'");
+        }
+    }
+
+    #[test]
+    fn dot_matches_a_newline_only_when_told_to() {
+        let dotall = NfaOptions {
+            dot_matches_newline: true,
+            ..Default::default()
+        };
+
+        assert!(!regex_to_nfa("a.b", &NfaOptions::default()).unwrap().is_full_match("a\nb", 0, 3));
+        assert!(regex_to_nfa("a.b", &dotall).unwrap().is_full_match("a\nb", 0, 3));
+        assert!(regex_to_nfa("a.b", &NfaOptions::default()).unwrap().is_full_match("a-b", 0, 3));
+    }
+
+    #[test]
+    fn negated_sets_match_a_newline_only_when_told_to() {
+        let dotall = NfaOptions {
+            dot_matches_newline: true,
+            ..Default::default()
+        };
+
+        for (pattern, text) in [("a[^x]b", "a\nb"), ("a[^x\\d]b", "a\nb"), ("a[^\\n]b", "a-b")] {
+            let nfa = regex_to_nfa(pattern, &NfaOptions::default()).unwrap();
+            assert_eq!(
+                nfa.is_full_match(text, 0, text.len()),
+                !text.contains('\n'),
+                "'{pattern}' on {text:?}"
+            );
+        }
+        let nfa = regex_to_nfa("a[^x]b", &dotall).unwrap();
+        assert!(nfa.is_full_match("a\nb", 0, 3));
+        assert!(!nfa.is_full_match("axb", 0, 3));
+        assert!(!regex_to_nfa("a[^\\n]b", &dotall)
+            .unwrap()
+            .is_full_match("a\nb", 0, 3));
+        assert!(!regex_to_nfa("a[x\\d]b", &dotall)
+            .unwrap()
+            .is_full_match("a\nb", 0, 3));
+        assert_eq!(
+            char_set(&[('a', 'a')], &[], true, &NfaOptions::default()).states[0].transitions[0]
+                .on
+                .to_string(),
+            "[^\\na]"
+        );
+    }
+
+    #[test]
+    fn ignore_case_skips_expanding_case_mappings() {
+        let opt = NfaOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+
+        assert!(regex_to_nfa("ß", &opt).unwrap().find_match("ß"));
+        assert!(!regex_to_nfa("ß", &opt).unwrap().find_match("S"));
+        assert!(regex_to_nfa("[^ß]", &opt).unwrap().find_match("S"));
+        assert!(regex_to_nfa("ß", &opt).unwrap().find_match("ẞ"));
+    }
+
+    #[test]
+    fn ignore_case_folds_characters_as_unicode_does() {
+        let opt = NfaOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            nfa.find_matches(text).iter().map(|m| (m.from, m.to)).collect()
+        };
+
+        // Title case folds with upper and lower case.
+        for (pattern, text) in [("ǅ", "ǆ"), ("ǆ", "Ǆ"), ("Ǆ", "ǅ"), ("[ǅ]", "Ǆ")] {
+            assert!(
+                regex_to_nfa(pattern, &opt).unwrap().find_match(text),
+                "'{pattern}' on '{text}'"
+            );
+        }
+        // Only Turkish folds the dotted and dotless i with I and i.
+        for (pattern, text) in [
+            ("i", "İ"),
+            ("İ", "i"),
+            ("ı", "I"),
+            ("I", "ı"),
+            ("[a-z]", "ı"),
+        ] {
+            assert!(
+                !regex_to_nfa(pattern, &opt).unwrap().find_match(text),
+                "'{pattern}' on '{text}'"
+            );
+        }
+        assert_eq!(spans("İ", "iİı"), vec![(1, 3)]);
+        assert_eq!(spans("ı", "Iıi"), vec![(1, 3)]);
+        assert_eq!(spans("σ", "ΣσςΑ"), vec![(0, 2), (2, 4), (4, 6)]);
+        assert_eq!(spans("привет", "ПРИВЕТ мир, Привет"), vec![(0, 12), (21, 33)]);
+        assert_eq!(spans("[а-я]+", "МИР"), vec![(0, 6)]);
+        assert_eq!(spans("zażółć", "ZAŻÓŁĆ gęślą, ZaŻóŁć"), vec![(0, 10), (21, 31)]);
+        assert_eq!(spans("[śćźż]+", "ŚćŹż"), vec![(0, 8)]);
+        assert_eq!(spans("[^ą]", "Ąą"), vec![]);
+    }
+
+    #[test]
+    fn smart_case_ignores_case_without_uppercase_letters() {
+        let smart = NfaOptions {
+            smart_case: true,
+            ..Default::default()
+        };
+
+        assert!(smart.ignores_case("error"));
+        assert!(smart.ignores_case("\\W\\B[a-z]\\d"));
+        assert!(smart.ignores_case("\\x4A\\u{1F600}"));
+        assert!(!smart.ignores_case("Error"));
+        assert!(!smart.ignores_case("[A-Z]"));
+        assert!(!smart.ignores_case("źŁe"));
+        assert!(!NfaOptions::default().ignores_case("error"));
+        let both = NfaOptions {
+            ignore_case: true,
+            ..smart
+        };
+        assert!(both.ignores_case("Error"));
+
+        let lower = regex_to_nfa("error", &smart).unwrap();
+        assert!(lower.find_match("ERROR: x"));
+        let upper = regex_to_nfa("Error", &smart).unwrap();
+        assert!(upper.find_match("Error: x"));
+        assert!(!upper.find_match("ERROR: x"));
+        assert!(!regex_to_nfa("(?-i)error", &smart).unwrap().find_match("ERROR"));
+    }
+
     #[test]
     fn find_match_negative_characters_set() {
         let opt = NfaOptions::default();
-        let nfa = negative_set_of_chars(&vec!['a', 'b'], &opt);
+        let nfa = char_set(&[('a', 'b')], &[], true, &opt);
 
         let tests = vec![
             ("apple", true),
@@ -858,7 +2125,7 @@ mod tests {
     #[test]
     fn find_match_complex_3() {
         let opt = NfaOptions::default();
-        let nfa = regex_to_nfa("\\d\\dabc", &opt);
+        let nfa = regex_to_nfa("\\d\\dabc", &opt).unwrap();
 
         let tests = vec![
             ("01abc", true),
@@ -905,7 +2172,7 @@ mod tests {
     #[test]
     fn find_match_character_sets() {
         let opt = NfaOptions::default();
-        let nfa = regex_to_nfa("[abc]", &opt);
+        let nfa = regex_to_nfa("[abc]", &opt).unwrap();
 
         let tests = vec![
             ("a", true),
@@ -953,8 +2220,10 @@ mod tests {
 
     #[test]
     fn find_match_single_symbol_ignore_case() {
-        let mut opt = NfaOptions::default();
-        opt.ignore_case = true;
+        let opt = NfaOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
         let nfa = symbol('a', &opt);
 
         let tests = vec![
@@ -1088,15 +2357,17 @@ mod tests {
         let opt = NfaOptions::default();
         let nfa = kleen(symbol('a', &opt));
 
+        // find_match searches the whole text and 'a*' also matches the empty
+        // string, so every input contains a match.
         let tests = vec![
-            ("c", false),
+            ("c", true),
             ("", true),
             ("a", true),
             ("aa", true),
             ("aaa", true),
-            ("ab", false),
-            ("b", false),
-            ("bbbbb", false),
+            ("ab", true),
+            ("b", true),
+            ("bbbbb", true),
         ];
 
         for (text, expected) in tests {
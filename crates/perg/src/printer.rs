@@ -0,0 +1,1073 @@
+use colored::*;
+use lazy_static::lazy_static;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::io::{self, BufWriter, LineWriter, Write};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::error::PergError;
+use crate::matcher::Match;
+use crate::progress::Progress;
+use crate::search::{FileStats, MatchRecord, SearchEvent};
+use crate::searcher::LongLines;
+use crate::template::{Template, VIMGREP};
+
+lazy_static! {
+    static ref VIMGREP_TEMPLATE: Template = VIMGREP.parse().unwrap();
+}
+
+/// The colors matches are highlighted in, by pattern: the first pattern gets
+/// the first color, and patterns past the end start over.
+pub const PATTERN_COLORS: [Color; 6] = [
+    Color::Red,
+    Color::Yellow,
+    Color::Magenta,
+    Color::Cyan,
+    Color::BrightRed,
+    Color::BrightCyan,
+];
+
+#[derive(Debug, Default)]
+pub struct FileMatch {
+    pub file_path: Option<PathBuf>,
+    pub matches: Vec<Match>,
+    /// The matched lines and their context, by 0-based line number; nothing
+    /// else of the file is kept.
+    pub lines: BTreeMap<usize, String>,
+}
+
+impl FileMatch {
+    pub fn write_count(&self, out: &mut dyn Write) -> io::Result<()> {
+        if self.matches.is_empty() || self.file_path.is_none() {
+            return Ok(());
+        }
+
+        let path = self.file_path.as_ref().unwrap();
+
+        writeln!(out, "{}:{}", path.to_string_lossy().blue(), self.matches.len())
+    }
+
+    /// Writes the lines kept for this file. Lines longer than `max_columns`
+    /// bytes are replaced by a note, and so are matches whose line was not
+    /// kept or does not contain them. With `trim`, each run of adjacent lines
+    /// loses the indentation all of its lines share. A match is highlighted in
+    /// the color of its pattern in `colors`.
+    pub fn write_matches(
+        &self,
+        out: &mut dyn Write,
+        max_columns: Option<usize>,
+        trim: bool,
+        colors: &[Color],
+    ) -> io::Result<()> {
+        if self.matches.is_empty() {
+            return Ok(());
+        }
+
+        let Some(path) = self.file_path.as_ref() else {
+            return Ok(());
+        };
+
+        writeln!(out, "{}", path.to_string_lossy().blue())?;
+
+        let mut highlights: BTreeMap<usize, Vec<&Match>> = BTreeMap::new();
+        for m in &self.matches {
+            highlights.entry(m.line).or_default().push(m);
+        }
+        let numbers: BTreeSet<usize> = self
+            .lines
+            .keys()
+            .chain(highlights.keys())
+            .copied()
+            .collect();
+
+        let line_number_col_size = match numbers.last() {
+            Some(last) => (last + 1).to_string().len(),
+            None => 1,
+        };
+
+        let mut indents = HashMap::new();
+        if trim {
+            let mut runs: Vec<Vec<usize>> = vec![];
+            for &number in &numbers {
+                match runs.last_mut() {
+                    Some(run) if run.last().is_some_and(|last| last + 1 == number) => {
+                        run.push(number)
+                    }
+                    _ => runs.push(vec![number]),
+                }
+            }
+            for run in runs {
+                let indent = common_indent(run.iter().filter_map(|n| self.lines.get(n)));
+                indents.extend(run.into_iter().map(|number| (number, indent)));
+            }
+        }
+
+        for counter in numbers {
+            let Some(line) = self.lines.get(&counter) else {
+                writeln!(
+                    out,
+                    "{:<line_number_col_size$} [line unavailable]",
+                    (counter + 1).to_string().green()
+                )?;
+                continue;
+            };
+            let omitted = max_columns.is_some_and(|max| line.len() > max);
+            // The indentation is ASCII, so any cut within it is on a char boundary.
+            let indent = indents.get(&counter).map_or(0, |indent| line.len().min(*indent));
+            let line = &line[indent..];
+            match highlights.get(&counter) {
+                Some(matches) if omitted => writeln!(
+                    out,
+                    "{:<line_number_col_size$} [Omitted long line with {} matches]",
+                    (counter + 1).to_string().green(),
+                    matches.len()
+                )?,
+                Some(matches) => {
+                    let matches: Vec<Match> = matches.iter().map(|m| shift(m, indent)).collect();
+                    match highlight(line, &matches, line_number_col_size, colors) {
+                        Some(highlighted) => writeln!(out, "{highlighted}")?,
+                        None => writeln!(
+                            out,
+                            "{:<line_number_col_size$} {} [match unavailable]",
+                            (counter + 1).to_string().green(),
+                            printable(line)
+                        )?,
+                    }
+                }
+                None if omitted => writeln!(
+                    out,
+                    "{:<line_number_col_size$} [Omitted long context line]",
+                    (counter + 1).to_string().green()
+                )?,
+                None => writeln!(
+                    out,
+                    "{:<line_number_col_size$} {}",
+                    (counter + 1).to_string().green(),
+                    printable(line)
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The bytes of leading spaces and tabs shared by every line with other
+/// content; blank lines do not count.
+fn common_indent<'a>(lines: impl Iterator<Item = &'a String>) -> usize {
+    let mut common: Option<&str> = None;
+    for line in lines {
+        let content = line.trim_start_matches([' ', '\t']);
+        if content.is_empty() {
+            continue;
+        }
+        let indent = &line[..line.len() - content.len()];
+        common = Some(match common {
+            Some(common) => {
+                let shared = common.bytes().zip(indent.bytes()).take_while(|(a, b)| a == b);
+                &common[..shared.count()]
+            }
+            None => indent,
+        });
+    }
+    common.map_or(0, str::len)
+}
+
+/// `m` after `by` bytes were cut from the start of its line.
+fn shift(m: &Match, by: usize) -> Match {
+    Match {
+        from: m.from.saturating_sub(by),
+        to: m.to.saturating_sub(by),
+        line: m.line,
+        errors: m.errors,
+        pattern: m.pattern,
+    }
+}
+
+/// `line` numbered and with the spans of `matches`, which are in order, all
+/// highlighted in the color of their pattern in `colors`, which must not be
+/// empty; `None` if a span is not within the line. A span overlapping the one
+/// before it is left out.
+fn highlight(
+    line: &str,
+    matches: &[Match],
+    line_number_col_size: usize,
+    colors: &[Color],
+) -> Option<String> {
+    // Each part is made printable on its own, so the highlight still covers
+    // exactly the matched text.
+    let mut highlighted = String::new();
+    let mut end = 0;
+    for m in matches {
+        if m.from < end {
+            continue;
+        }
+        highlighted.push_str(&printable(line.get(end..m.from)?));
+        let color = colors[m.pattern % colors.len()];
+        highlighted.push_str(&printable(line.get(m.from..m.to)?).color(color).to_string());
+        end = m.to;
+    }
+    highlighted.push_str(&printable(line.get(end..)?));
+
+    Some(format!(
+        "{:<line_number_col_size$} {highlighted}",
+        (matches.first()?.line + 1).to_string().green()
+    ))
+}
+
+/// `text` with control characters other than tab written as `\x1b` style
+/// escapes, so a searched line cannot move the cursor, clear the screen or
+/// retitle the terminal it is printed to.
+pub(crate) fn printable(text: &str) -> Cow<'_, str> {
+    if !text.chars().any(|c| c.is_control() && c != '\t') {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        match c {
+            '\t' => escaped.push(c),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// How [`Printer`] renders what a search finds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Matched lines with surrounding context, grouped under each file.
+    #[default]
+    Matches,
+    /// `path:count` for every file with at least one match.
+    Count,
+    /// The path of every file with at least one match.
+    FilesWithMatches,
+    Vimgrep,
+    Json,
+    /// Every match rendered with the template given to [`Printer::template`].
+    Template,
+}
+
+/// Totals over every file searched, printed by `--stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    pub matches: usize,
+    pub files_with_matches: usize,
+    pub files_searched: usize,
+    pub bytes: usize,
+    pub long_lines: usize,
+    /// Files left out because they are not valid UTF-8, or their `--pre`
+    /// command failed.
+    pub skipped: usize,
+    /// Files whose search ran out of `--timeout`; they count as searched too.
+    pub timed_out: usize,
+}
+
+impl SearchStats {
+    fn add(&mut self, file: &FileStats) {
+        self.matches += file.matches;
+        self.files_with_matches += usize::from(file.matches > 0);
+        self.files_searched += 1;
+        self.bytes += file.bytes;
+        self.long_lines += file.long_lines;
+        self.timed_out += usize::from(file.timed_out);
+    }
+}
+
+/// A search sink that prints results to stdout and problems to stderr. It is
+/// the only part of perg that writes to stdout: each file's results are
+/// rendered into a buffer first and written in one piece, so output from
+/// different files never interleaves.
+pub struct Printer {
+    mode: OutputMode,
+    out: Box<dyn Write>,
+    progress: Option<Arc<Progress>>,
+    max_columns: Option<usize>,
+    trim: bool,
+    long_lines: LongLines,
+    template: Option<Template>,
+    count_lines: bool,
+    pending: HashMap<PathBuf, FileMatch>,
+    stats: SearchStats,
+    max_results: Option<usize>,
+    /// Records printed so far, counted against `max_results`.
+    results: usize,
+    /// Whether the reader of the output went away.
+    closed: bool,
+    no_messages: bool,
+    /// The patterns searched for, for the legend of their colors.
+    patterns: Vec<String>,
+    /// Colors chosen for patterns by their index, instead of [`PATTERN_COLORS`].
+    pattern_colors: HashMap<usize, Color>,
+    legend_written: bool,
+    exit_code: i32,
+}
+
+impl Printer {
+    pub fn new(mode: OutputMode) -> Self {
+        Self {
+            mode,
+            out: Box::new(BufWriter::new(io::stdout())),
+            progress: None,
+            max_columns: None,
+            trim: false,
+            long_lines: LongLines::default(),
+            template: None,
+            count_lines: false,
+            pending: HashMap::new(),
+            stats: SearchStats::default(),
+            max_results: None,
+            results: 0,
+            closed: false,
+            no_messages: false,
+            patterns: vec![],
+            pattern_colors: HashMap::new(),
+            legend_written: false,
+            exit_code: 0,
+        }
+    }
+
+    /// Flushes stdout after every line instead of whenever the buffer fills.
+    pub fn line_buffered(mut self, line_buffered: bool) -> Self {
+        if line_buffered {
+            self.out = Box::new(LineWriter::new(io::stdout()));
+        }
+        self
+    }
+
+    /// Writes to `out` instead of stdout.
+    pub fn writer(mut self, out: impl Write + 'static) -> Self {
+        self.out = Box::new(out);
+        self
+    }
+
+    /// Clears the `--progress` status line before writing anything, and
+    /// flushes each write so the line is not redrawn over pending output.
+    pub fn progress(mut self, progress: Option<Arc<Progress>>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Omits lines longer than `max_columns` bytes from the default output.
+    /// Unlike the line length cap of the search, this only affects display.
+    pub fn max_columns(mut self, max_columns: Option<usize>) -> Self {
+        self.max_columns = max_columns;
+        self
+    }
+
+    /// Strips leading indentation from printed lines; see [`FileMatch::write_matches`].
+    pub fn trim(mut self, trim: bool) -> Self {
+        self.trim = trim;
+        self
+    }
+
+    /// Renders every match with `template`, for [`OutputMode::Template`].
+    pub fn template(mut self, template: Option<Template>) -> Self {
+        self.template = template;
+        self
+    }
+
+    /// How the search treats long lines, for the note printed about them.
+    pub fn long_lines(mut self, long_lines: LongLines) -> Self {
+        self.long_lines = long_lines;
+        self
+    }
+
+    /// With `--count`, counts the lines that match in each file rather than
+    /// the matches, as `--all-match` asks.
+    pub fn count_lines(mut self, count_lines: bool) -> Self {
+        self.count_lines = count_lines;
+        self
+    }
+
+    /// Cancels the search once `max_results` records are printed: matches,
+    /// or with `--count` and `-l` one line per file.
+    pub fn max_results(mut self, max_results: Option<usize>) -> Self {
+        self.max_results = max_results;
+        self
+    }
+
+    /// Does not print warnings about single files, such as those skipped or
+    /// timed out; errors are still reported.
+    pub fn no_messages(mut self, no_messages: bool) -> Self {
+        self.no_messages = no_messages;
+        self
+    }
+
+    /// The patterns searched for, in order. With more than one and colors
+    /// on, the default output starts with a legend of the color each one's
+    /// matches are highlighted in.
+    pub fn patterns(mut self, patterns: Vec<String>) -> Self {
+        self.patterns = patterns;
+        self
+    }
+
+    /// Highlights the matches of the pattern at each 0-based index in its
+    /// color, instead of the one [`PATTERN_COLORS`] gives it.
+    pub fn pattern_colors(
+        mut self,
+        pattern_colors: impl IntoIterator<Item = (usize, Color)>,
+    ) -> Self {
+        self.pattern_colors = pattern_colors.into_iter().collect();
+        self
+    }
+
+    /// The color of every pattern, by index; at least one.
+    fn colors(&self) -> Vec<Color> {
+        (0..self.patterns.len().max(1))
+            .map(|pattern| {
+                let default = PATTERN_COLORS[pattern % PATTERN_COLORS.len()];
+                self.pattern_colors
+                    .get(&pattern)
+                    .copied()
+                    .unwrap_or(default)
+            })
+            .collect()
+    }
+
+    /// Writes the legend of pattern colors, once, if there is one to write.
+    fn write_legend(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        if self.legend_written
+            || self.patterns.len() < 2
+            || !control::SHOULD_COLORIZE.should_colorize()
+        {
+            return Ok(());
+        }
+        self.legend_written = true;
+        for (number, (pattern, color)) in self.patterns.iter().zip(self.colors()).enumerate() {
+            writeln!(
+                out,
+                "pattern {}: {}",
+                number + 1,
+                printable(pattern).color(color)
+            )?;
+        }
+        writeln!(out)
+    }
+
+    /// Whether as many records were printed as [`Printer::max_results`] allows.
+    pub fn limit_reached(&self) -> bool {
+        self.max_results.is_some_and(|max| self.results >= max)
+    }
+
+    /// Drops the matches of `file_match` that would go past `max_results`,
+    /// with the lines kept for them only, and counts the rest.
+    fn keep_within_limit(&mut self, file_match: &mut FileMatch) {
+        if let Some(max) = self.max_results {
+            let kept = max.saturating_sub(self.results);
+            if kept < file_match.matches.len() {
+                let dropped = file_match.matches[kept].line;
+                let last = kept
+                    .checked_sub(1)
+                    .map(|last| file_match.matches[last].line);
+                file_match
+                    .lines
+                    .retain(|&line, _| line < dropped || last.is_some_and(|last| line <= last));
+                file_match.matches.truncate(kept);
+            }
+        }
+        self.results += file_match.matches.len();
+    }
+
+    pub fn stats(&self) -> SearchStats {
+        self.stats
+    }
+
+    pub fn print_stats(&mut self) -> Result<(), PergError> {
+        let verb = match self.long_lines {
+            LongLines::Skip => "skipped",
+            LongLines::Truncate => "truncated",
+        };
+        let stats = self.stats;
+        let written = writeln!(
+            self.out,
+            "\n{} matches\n{} files contained matches\n{} files searched\n{} bytes searched\n\
+             {} long lines {verb}\n{} files skipped\n{} files timed out",
+            stats.matches,
+            stats.files_with_matches,
+            stats.files_searched,
+            stats.bytes,
+            stats.long_lines,
+            stats.skipped,
+            stats.timed_out
+        );
+        written.or_else(closed_pipe).map_err(PergError::Output)
+    }
+
+    /// Writes out whatever is still buffered.
+    pub fn finish(&mut self) -> Result<(), PergError> {
+        self.out.flush().or_else(closed_pipe).map_err(PergError::Output)
+    }
+
+    /// 0, or the exit code of the last error reported.
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
+
+    /// Whether the search was cancelled because nobody reads the output, e.g.
+    /// when it is piped into `head`.
+    pub fn closed(&self) -> bool {
+        self.closed
+    }
+
+    fn report(&mut self, err: PergError) {
+        self.print_error(&err);
+        self.exit_code = err.exit_code();
+    }
+
+    /// Prints a warning about a single file, unless [`Printer::no_messages`].
+    fn warn(&self, message: impl fmt::Display) {
+        if !self.no_messages {
+            self.print_error(message);
+        }
+    }
+
+    fn print_error(&self, message: impl fmt::Display) {
+        let _hidden = self.progress.as_ref().map(|progress| progress.hide());
+        eprintln!("perg: {message}");
+    }
+
+    fn emit(&mut self, rendered: &[u8]) -> io::Result<()> {
+        match &self.progress {
+            _ if rendered.is_empty() => Ok(()),
+            Some(progress) => {
+                let _hidden = progress.hide();
+                self.out.write_all(rendered)?;
+                self.out.flush()
+            }
+            None => self.out.write_all(rendered),
+        }
+    }
+
+    pub fn handle(&mut self, event: SearchEvent) -> ControlFlow<()> {
+        if self.limit_reached() {
+            return ControlFlow::Break(());
+        }
+        if let SearchEvent::FileEnd(path, stats) = &event {
+            self.stats.add(stats);
+            if stats.long_lines > 0 {
+                let action = match self.long_lines {
+                    LongLines::Skip => "skipped",
+                    LongLines::Truncate => "searched only the start of",
+                };
+                self.warn(format_args!(
+                    "{}: {action} {} line(s) longer than --max-line-len",
+                    path.display(),
+                    stats.long_lines
+                ));
+            }
+            if stats.timed_out {
+                self.warn(format_args!(
+                    "{}: search timed out, results may be incomplete",
+                    path.display()
+                ));
+            }
+        }
+
+        // Each event is rendered whole before anything reaches the output.
+        let mut rendered = vec![];
+        let written = match (self.mode, event) {
+            // Not an error: like grep with binary files, perg leaves them out.
+            (_, SearchEvent::Error(PergError::Encoding { path })) => {
+                self.stats.skipped += 1;
+                self.pending.remove(&path);
+                self.warn(format_args!(
+                    "{}: skipped, not valid UTF-8; use --text to search it",
+                    path.display()
+                ));
+                Ok(())
+            }
+            // Skipped as asked, with -I.
+            (_, SearchEvent::Error(PergError::Binary { path })) => {
+                self.stats.skipped += 1;
+                self.pending.remove(&path);
+                Ok(())
+            }
+            (_, SearchEvent::Error(PergError::Preprocessor { path, reason })) => {
+                self.stats.skipped += 1;
+                self.pending.remove(&path);
+                self.warn(format_args!("{}: skipped, --pre failed: {reason}", path.display()));
+                Ok(())
+            }
+            (_, SearchEvent::Error(err)) => {
+                self.report(err);
+                Ok(())
+            }
+            (OutputMode::Vimgrep, SearchEvent::Match(mut record)) => {
+                // The column still counts from the start of the line in the file.
+                if self.trim {
+                    record.line = record.line.trim_start_matches([' ', '\t']).to_string();
+                }
+                writeln!(rendered, "{}", format_vimgrep(&record))
+            }
+            (OutputMode::Json, SearchEvent::Match(record)) => {
+                writeln!(rendered, "{}", format_json(&record))
+            }
+            (OutputMode::Template, SearchEvent::Match(record)) => match &self.template {
+                Some(template) => writeln!(rendered, "{}", template.render(&record)),
+                None => Ok(()),
+            },
+            // The matched lines of a binary file were not sent; machine
+            // readable output keeps to records and only gets a note.
+            (
+                OutputMode::Vimgrep | OutputMode::Json | OutputMode::Template,
+                SearchEvent::FileEnd(path, stats),
+            ) if stats.binary && stats.matches > 0 => {
+                self.warn(format_args!(
+                    "{}: binary file matches; use --text to print the matches",
+                    path.display()
+                ));
+                Ok(())
+            }
+            (OutputMode::Count, SearchEvent::FileEnd(path, stats)) if stats.matches > 0 => {
+                let count = match self.count_lines {
+                    true => stats.lines,
+                    false => stats.matches,
+                };
+                writeln!(rendered, "{}:{}", path.to_string_lossy().blue(), count)
+            }
+            (OutputMode::FilesWithMatches, SearchEvent::FileEnd(path, stats))
+                if stats.matches > 0 =>
+            {
+                writeln!(rendered, "{}", path.to_string_lossy().blue())
+            }
+            (OutputMode::Matches, SearchEvent::FileStart(path)) => {
+                let file_match = FileMatch {
+                    file_path: Some(path.clone()),
+                    ..Default::default()
+                };
+                self.pending.insert(path, file_match);
+                Ok(())
+            }
+            (OutputMode::Matches, SearchEvent::Match(record)) => {
+                if let Some(file_match) = self.pending.get_mut(&record.path) {
+                    let m = record.to_match();
+                    file_match.lines.insert(m.line, record.line);
+                    file_match.matches.push(m);
+                }
+                Ok(())
+            }
+            (OutputMode::Matches, SearchEvent::Context(context)) => {
+                if let Some(file_match) = self.pending.get_mut(&context.path) {
+                    file_match.lines.insert(context.line_number - 1, context.line);
+                }
+                Ok(())
+            }
+            (OutputMode::Matches, SearchEvent::FileEnd(path, stats)) => {
+                match self.pending.remove(&path) {
+                    Some(_) if stats.binary && stats.matches > 0 => {
+                        self.results += 1;
+                        writeln!(
+                            rendered,
+                            "Binary file {} matches",
+                            path.to_string_lossy().blue()
+                        )
+                    }
+                    Some(mut file_match) => {
+                        self.keep_within_limit(&mut file_match);
+                        let legend = match file_match.matches.is_empty() {
+                            true => Ok(()),
+                            false => self.write_legend(&mut rendered),
+                        };
+                        legend.and_then(|()| {
+                            file_match.write_matches(
+                                &mut rendered,
+                                self.max_columns,
+                                self.trim,
+                                &self.colors(),
+                            )
+                        })
+                    }
+                    None => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        };
+        // Other modes print at most one record per event.
+        if self.mode != OutputMode::Matches {
+            self.results += usize::from(!rendered.is_empty());
+        }
+        let written = written.and_then(|()| self.emit(&rendered));
+
+        match written {
+            Ok(()) if self.limit_reached() => ControlFlow::Break(()),
+            Ok(()) => ControlFlow::Continue(()),
+            // Nobody reads the rest, e.g. output piped into `head`.
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {
+                self.closed = true;
+                ControlFlow::Break(())
+            }
+            Err(err) => {
+                self.report(PergError::Output(err));
+                ControlFlow::Break(())
+            }
+        }
+    }
+}
+
+/// Treats a reader that went away as success; there is nobody left to tell.
+fn closed_pipe(err: io::Error) -> io::Result<()> {
+    match err.kind() {
+        io::ErrorKind::BrokenPipe => Ok(()),
+        _ => Err(err),
+    }
+}
+
+/// `path:line:column:text`, the format understood by vim's `:grep`.
+pub fn format_vimgrep(record: &MatchRecord) -> String {
+    VIMGREP_TEMPLATE.render(record)
+}
+
+/// `line:from..to text`, for `perg match`: the 1-based line number, the byte
+/// span of `m` within `line` and the matched text, highlighted. A fuzzy match
+/// with edits is followed by their number, as in `(1 error)`.
+pub fn format_span(m: &Match, line: &str) -> String {
+    let matched = printable(line.get(m.from..m.to).unwrap_or_default());
+    let errors = match m.errors {
+        0 => String::new(),
+        1 => " (1 error)".to_string(),
+        n => format!(" ({n} errors)"),
+    };
+    format!("{}:{}..{} {}{errors}", m.line + 1, m.from, m.to, matched.red())
+}
+
+/// One JSON object per match, suitable for JSON-lines consumers.
+pub fn format_json(record: &MatchRecord) -> String {
+    format!(
+        "{{\"path\":\"{}\",\"line_number\":{},\"column\":{},\
+         \"start\":{},\"end\":{},\"text\":\"{}\"}}",
+        json_escape(&record.path.to_string_lossy()),
+        record.line_number,
+        record.column,
+        record.span.0,
+        record.span.1,
+        json_escape(&record.line)
+    )
+}
+
+fn json_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfa::NfaOptions;
+    use std::sync::Mutex;
+
+    fn record(line: &str) -> MatchRecord {
+        MatchRecord {
+            path: PathBuf::from("dir/file.txt"),
+            line_number: 3,
+            column: 5,
+            span: (4, 7),
+            offset: 24,
+            line: line.to_string(),
+            pattern: 0,
+        }
+    }
+
+    struct ClosedPipe;
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    /// Output that stays readable after the printer took it.
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn closed_stdout_cancels_the_search_quietly() {
+        let mut printer = Printer::new(OutputMode::Vimgrep).writer(ClosedPipe);
+
+        assert!(printer.handle(SearchEvent::Match(record("abc"))).is_break());
+        assert!(printer.closed());
+        assert_eq!(printer.exit_code(), 0);
+        assert!(printer.finish().is_ok());
+    }
+
+    #[test]
+    fn highlight_covers_the_whole_match() {
+        colored::control::set_override(true);
+        let tests = vec![
+            ("abc", "xxabcxx", ("xx", "abc", "xx")),
+            ("abc", "abc", ("", "abc", "")),
+            ("Sop(l|e)ic", "Pan Soplica", ("Pan ", "Soplic", "a")),
+            ("a(b*)", "cabbbd", ("c", "abbb", "d")),
+            ("[xyz]q", "ayq!", ("a", "yq", "!")),
+            ("ść", "źść", ("ź", "ść", "")),
+        ];
+
+        for (pattern, line, (before, matched, after)) in tests {
+            let nfa = crate::re::regex_to_nfa(pattern, &Default::default()).unwrap();
+            let m = &nfa.find_matches(line)[0];
+            assert!(m.from < m.to);
+
+            let expected = format!("{} {before}{}{after}", "1".green(), matched.red());
+            assert_eq!(
+                highlight(line, std::slice::from_ref(m), 1, &PATTERN_COLORS),
+                Some(expected),
+                "'{pattern}' in '{line}'"
+            );
+        }
+    }
+
+    #[test]
+    fn highlight_ignoring_case_covers_the_original_text() {
+        colored::control::set_override(true);
+        let options = NfaOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let tests = vec![
+            ("straße", "Die STRASSE, die Straße", ("Die STRASSE, die ", "Straße", "")),
+            ("ẞE", "an der straße", ("an der stra", "ße", "")),
+            ("ﬁ", "ﬁne FINE", ("", "ﬁ", "ne FINE")),
+            ("i̇", "İi̇", ("İ", "i̇", "")),
+        ];
+
+        for (pattern, line, (before, matched, after)) in tests {
+            let nfa = crate::re::regex_to_nfa(pattern, &options).unwrap();
+            let m = &nfa.find_matches(line)[0];
+
+            let expected = format!("{} {before}{}{after}", "1".green(), matched.red());
+            assert_eq!(
+                highlight(line, std::slice::from_ref(m), 1, &PATTERN_COLORS),
+                Some(expected),
+                "'{pattern}' in '{line}'"
+            );
+        }
+    }
+
+    #[test]
+    fn highlight_covers_every_match_on_the_line() {
+        colored::control::set_override(true);
+        let matches = [
+            Match {
+                from: 0,
+                to: 5,
+                line: 2,
+                errors: 0,
+                pattern: 0,
+            },
+            Match {
+                from: 3,
+                to: 6,
+                line: 2,
+                errors: 0,
+                pattern: 0,
+            },
+            Match {
+                from: 7,
+                to: 11,
+                line: 2,
+                errors: 0,
+                pattern: 0,
+            },
+        ];
+
+        let expected = format!("{} {}: {}", "3".green(), "error".red(), "user".red());
+        assert_eq!(
+            highlight("error: user", &matches, 1, &PATTERN_COLORS),
+            Some(expected)
+        );
+    }
+
+    #[test]
+    fn highlight_colors_each_match_by_its_pattern() {
+        colored::control::set_override(true);
+        let matches = [
+            Match {
+                from: 0,
+                to: 5,
+                line: 0,
+                errors: 0,
+                pattern: 1,
+            },
+            Match {
+                from: 7,
+                to: 11,
+                line: 0,
+                errors: 0,
+                pattern: 0,
+            },
+        ];
+
+        let expected = format!("{} {}: {}", "1".green(), "error".yellow(), "user".red());
+        assert_eq!(
+            highlight("error: user", &matches, 1, &PATTERN_COLORS),
+            Some(expected)
+        );
+        let expected = format!("{} {}: {}", "1".green(), "error".blue(), "user".blue());
+        assert_eq!(
+            highlight("error: user", &matches, 1, &[Color::Blue]),
+            Some(expected)
+        );
+    }
+
+    #[test]
+    fn legend_comes_once_before_the_first_match() {
+        colored::control::set_override(true);
+        let out = Arc::new(Mutex::new(vec![]));
+        let mut printer = Printer::new(OutputMode::Matches)
+            .writer(Shared(out.clone()))
+            .patterns(vec!["user".to_string(), "error".to_string()])
+            .pattern_colors([(1, Color::Blue)]);
+        for path in ["a.txt", "b.txt"] {
+            let record = MatchRecord {
+                path: PathBuf::from(path),
+                span: (0, 5),
+                pattern: 1,
+                ..record("error: user")
+            };
+            let _ = printer.handle(SearchEvent::FileStart(record.path.clone()));
+            let _ = printer.handle(SearchEvent::Match(record));
+            let _ = printer.handle(SearchEvent::FileEnd(
+                PathBuf::from(path),
+                FileStats::default(),
+            ));
+        }
+        printer.finish().unwrap();
+
+        let out = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+        let file = |path: &str| {
+            format!(
+                "{}\n{} {}: user\n",
+                path.blue(),
+                "3".green(),
+                "error".blue()
+            )
+        };
+        let legend = format!(
+            "pattern 1: {}\npattern 2: {}\n\n",
+            "user".red(),
+            "error".blue()
+        );
+        assert_eq!(out, legend + &file("a.txt") + &file("b.txt"));
+    }
+
+    #[test]
+    fn highlight_escapes_control_characters_around_the_match() {
+        colored::control::set_override(true);
+        let line = "\x1b[2Jab\0c\td\x7f";
+        let m = Match {
+            from: 4,
+            to: 8,
+            line: 0,
+            errors: 0,
+            pattern: 0,
+        };
+
+        let expected = format!("{} \\x1b[2J{}\td\\x7f", "1".green(), "ab\\x00c".red());
+        assert_eq!(highlight(line, &[m], 1, &PATTERN_COLORS), Some(expected));
+    }
+
+    #[test]
+    fn write_matches_survives_missing_lines_and_spans() {
+        let file_match = FileMatch {
+            file_path: Some(PathBuf::from("log.txt")),
+            matches: vec![
+                Match {
+                    from: 0,
+                    to: 3,
+                    line: 0,
+                    errors: 0,
+                    pattern: 0,
+                },
+                // The file changed: the line is shorter than the match.
+                Match {
+                    from: 2,
+                    to: 9,
+                    line: 1,
+                    errors: 0,
+                    pattern: 0,
+                },
+                // The line was never kept.
+                Match {
+                    from: 0,
+                    to: 1,
+                    line: 11,
+                    errors: 0,
+                    pattern: 0,
+                },
+            ],
+            lines: BTreeMap::from([(0, "abc".to_string()), (1, "abc".to_string())]),
+        };
+
+        let mut out = vec![];
+        file_match
+            .write_matches(&mut out, None, false, &PATTERN_COLORS)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!(out.lines().count(), 4);
+        assert!(out.contains("abc [match unavailable]"), "{out}");
+        assert!(out.ends_with(" [line unavailable]\n"), "{out}");
+    }
+
+    #[test]
+    fn format_span_gives_the_line_span_and_match() {
+        colored::control::set_override(true);
+        let m = Match {
+            from: 2,
+            to: 5,
+            line: 1,
+            errors: 0,
+            pattern: 0,
+        };
+
+        assert_eq!(format_span(&m, "xxa\u{1}cyy"), format!("2:2..5 {}", "a\\x01c".red()));
+    }
+
+    #[test]
+    fn format_vimgrep_record() {
+        assert_eq!(format_vimgrep(&record("let abc = 1;")), "dir/file.txt:3:5:let abc = 1;");
+    }
+
+    #[test]
+    fn format_vimgrep_escapes_control_characters() {
+        assert_eq!(format_vimgrep(&record("a\x1b]0;x\x07b")), "dir/file.txt:3:5:a\\x1b]0;x\\x07b");
+    }
+
+    #[test]
+    fn format_json_escapes_text() {
+        assert_eq!(
+            format_json(&record("say \"hi\"\t\\ \u{1}")),
+            concat!(
+                r#"{"path":"dir/file.txt","line_number":3,"column":5,"start":4,"end":7,"#,
+                r#""text":"say \"hi\"\t\\ \u0001"}"#
+            )
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often [`Progress::draw_every`] redraws the status line.
+pub const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The status line is cut to this many characters so it never wraps on a
+/// standard terminal; a wrapped line could not be cleared with `\r`.
+const MAX_WIDTH: usize = 79;
+
+/// Counters a running search updates for `--progress`, and the status line
+/// drawn from them on stderr.
+#[derive(Debug, Default)]
+pub struct Progress {
+    found: AtomicUsize,
+    searched: AtomicUsize,
+    matches: AtomicUsize,
+    current: Mutex<Option<PathBuf>>,
+    /// Held while the status line or regular output is written; true while
+    /// the status line is on screen.
+    drawn: Mutex<bool>,
+    stopped: AtomicBool,
+}
+
+impl Progress {
+    /// A file was taken from the walk and is about to be searched.
+    pub fn start_file(&self, path: &Path) {
+        self.found.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut current) = self.current.lock() {
+            *current = Some(path.to_path_buf());
+        }
+    }
+
+    /// A file was searched to the end.
+    pub fn end_file(&self, matches: usize) {
+        self.searched.fetch_add(1, Ordering::Relaxed);
+        self.matches.fetch_add(matches, Ordering::Relaxed);
+    }
+
+    /// The status line, without any terminal control codes.
+    pub fn status(&self) -> String {
+        let mut status = format!(
+            "{} files found, {} searched, {} matches",
+            self.found.load(Ordering::Relaxed),
+            self.searched.load(Ordering::Relaxed),
+            self.matches.load(Ordering::Relaxed)
+        );
+        if let Some(current) = self.current.lock().ok().and_then(|c| c.clone()) {
+            status.push_str(": ");
+            status.push_str(&current.to_string_lossy());
+        }
+
+        // Keep the end of the path, which tells files apart.
+        let chars = status.chars().count();
+        if chars > MAX_WIDTH {
+            let tail: String = status.chars().skip(chars - MAX_WIDTH + 3).collect();
+            status = format!("...{tail}");
+        }
+        status
+    }
+
+    /// Clears the status line and keeps it from being redrawn until the
+    /// guard is dropped, so regular output can be written in between.
+    pub fn hide(&self) -> MutexGuard<'_, bool> {
+        let mut drawn = self.drawn.lock().unwrap_or_else(|err| err.into_inner());
+        if *drawn {
+            let _ = io::stderr().write_all(b"\r\x1b[K");
+            *drawn = false;
+        }
+        drawn
+    }
+
+    fn draw(&self) {
+        let mut drawn = self.hide();
+        let _ = write!(io::stderr(), "{}", self.status());
+        *drawn = true;
+    }
+
+    /// Redraws the status line on stderr every `interval` until
+    /// [`Progress::stop`] is called.
+    pub fn draw_every(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let progress = Arc::clone(self);
+        thread::spawn(move || {
+            while !progress.stopped.load(Ordering::Relaxed) {
+                progress.draw();
+                thread::park_timeout(interval);
+            }
+            drop(progress.hide());
+        })
+    }
+
+    /// Stops the thread started by [`Progress::draw_every`] and waits for it
+    /// to clear the status line.
+    pub fn stop(&self, drawer: JoinHandle<()>) {
+        self.stopped.store(true, Ordering::Relaxed);
+        drawer.thread().unpark();
+        let _ = drawer.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_counts_files_and_matches() {
+        let progress = Progress::default();
+        progress.start_file(Path::new("a.txt"));
+        progress.end_file(3);
+        progress.start_file(Path::new("b.txt"));
+
+        assert_eq!(progress.status(), "2 files found, 1 searched, 3 matches: b.txt");
+    }
+
+    #[test]
+    fn status_keeps_the_end_of_long_paths() {
+        let progress = Progress::default();
+        let path = format!("{}file.txt", "dir/".repeat(40));
+        progress.start_file(Path::new(&path));
+
+        let status = progress.status();
+        assert_eq!(status.chars().count(), MAX_WIDTH);
+        assert!(status.starts_with("..."));
+        assert!(status.ends_with("dir/file.txt"));
+    }
+}
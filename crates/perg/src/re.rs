@@ -1,338 +1,2144 @@
-use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::iter::Peekable;
+use std::vec;
+
+use thiserror::Error;
 
 use crate::nfa::{
-    alphanumeric, concat, digits, kleen, negative_set_of_chars, set_of_chars, symbol, union,
-    NfaOptions, CANNOT_CONCAT_CURRENT_CHAR, CANNOT_CONCAT_PREV_CHAR, CHAR_SET_END, CHAR_SET_START,
-    CONCAT, GROUP_END, GROUP_START, KLEEN, NFA, SLASH, UNION,
+    alphanumeric, capture, char_set, concat_many, digit, kleen, lazy_kleen, lazy_optional,
+    lazy_plus, lazy_repeat, line_anchor, optional, plus, repeat, symbol, tagged_union, union_many,
+    word_boundary, Anchor, NfaOptions, Shorthand, CHAR_SET_END, CHAR_SET_START, GROUP_END,
+    GROUP_START, KLEEN, NFA, OPTIONAL, PLUS, SLASH, UNION,
 };
 
-fn insert_concat_symbol(regex: &str) -> String {
-    let mut prev_symbol: Option<char> = None;
-    let mut output: Vec<char> = vec![];
-    let mut is_in_char_set = false;
-    for c in regex.chars() {
-        if c == CHAR_SET_START {
-            is_in_char_set = true;
-        }
-        if c == CHAR_SET_END {
-            is_in_char_set = false;
+#[derive(Debug, Error, PartialEq)]
+pub enum RegexError {
+    #[error("the pattern is empty")]
+    EmptyPattern,
+    #[error("unmatched ')' at position {position}")]
+    UnmatchedGroupEnd { position: usize },
+    #[error("the group opened at position {position} is never closed")]
+    UnclosedGroup { position: usize },
+    #[error("the character set opened at position {position} is never closed")]
+    UnclosedSet { position: usize },
+    #[error("not enough operands for '{operator}' at position {position}")]
+    MissingOperand { operator: char, position: usize },
+    #[error(
+        "'{operator}' at position {position} repeats a repetition; group it first, as in '(a*)*'"
+    )]
+    NestedRepetition { operator: char, position: usize },
+    #[error("nothing follows the '\\' at position {position}")]
+    TrailingBackslash { position: usize },
+    #[error("'\\n' at position {position} can never match, as lines are searched one at a time")]
+    NewlineInPattern { position: usize },
+    #[error("unknown escape '\\{escape}' at position {position}")]
+    UnknownEscape { escape: char, position: usize },
+    #[error("'\\{escape}' at position {position} has no meaning in a character set")]
+    EscapeInSet { escape: char, position: usize },
+    #[error("invalid escape '{escape}' at position {position}: {reason}")]
+    InvalidCodePoint {
+        escape: String,
+        position: usize,
+        reason: &'static str,
+    },
+    #[error("invalid repetition '{repetition}' at position {position}: {reason}")]
+    InvalidRepetition {
+        repetition: String,
+        position: usize,
+        reason: &'static str,
+    },
+    #[error("the range '{from}-{to}' at position {position} ends before it starts")]
+    InvalidRange { from: char, to: char, position: usize },
+    #[error("'{construct}' at position {position} is not supported by perg's engine yet")]
+    Unsupported { construct: String, position: usize },
+    #[error("{0}")]
+    Backend(String),
+}
+
+impl RegexError {
+    /// Where in the pattern the error is, counted in characters from 0, if it
+    /// is at one place.
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            Self::UnmatchedGroupEnd { position }
+            | Self::UnclosedGroup { position }
+            | Self::UnclosedSet { position }
+            | Self::MissingOperand { position, .. }
+            | Self::NestedRepetition { position, .. }
+            | Self::TrailingBackslash { position }
+            | Self::NewlineInPattern { position }
+            | Self::UnknownEscape { position, .. }
+            | Self::EscapeInSet { position, .. }
+            | Self::InvalidCodePoint { position, .. }
+            | Self::InvalidRepetition { position, .. }
+            | Self::InvalidRange { position, .. }
+            | Self::Unsupported { position, .. } => Some(*position),
+            Self::EmptyPattern | Self::Backend(_) => None,
         }
+    }
+}
 
-        let can_concat = !is_in_char_set
-            && !CANNOT_CONCAT_CURRENT_CHAR.contains(&c)
-            && prev_symbol.is_some_and(|prev_c| !CANNOT_CONCAT_PREV_CHAR.contains(&prev_c));
+/// A [`RegexError`] with the pattern it is in, shown under the message with
+/// a caret below the character the error is at:
+///
+/// ```text
+/// unmatched ')' at position 1
+///     a)b
+///      ^
+/// ```
+#[derive(Debug, Error, PartialEq)]
+pub struct PatternError {
+    pub pattern: String,
+    #[source]
+    pub error: RegexError,
+}
 
-        if can_concat {
-            output.push(CONCAT);
+impl PatternError {
+    pub fn new(pattern: &str, error: RegexError) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            error,
         }
+    }
+}
 
-        output.push(c);
-        prev_symbol = Some(c);
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        if let Some(position) = self.error.position() {
+            write!(f, "\n    {}\n    {}^", self.pattern, " ".repeat(position))?;
+        }
+        Ok(())
     }
+}
 
-    output.into_iter().collect()
+/// A piece of a pattern as [`tokenize`] reads it: an operand, or an operator
+/// for [`Parser`] to apply.
+#[derive(Debug, PartialEq)]
+enum Piece {
+    /// A character that matches itself: any but an operator, an escaped
+    /// operator, or what an escape like `\t` or `\x41` stands for.
+    Literal(char),
+    /// A shorthand like `\d` or `\S`.
+    Shorthand(Shorthand),
+    /// `.`
+    Dot,
+    /// `\b` or `\B`.
+    Assertion(Assertion),
+    /// A whole character set, `[...]`.
+    Set(CharSet),
+    /// `*`, `+`, `?` or `{n,m}`, or one of them made lazy by a `?` after
+    /// it, whose first character is `operator`.
+    Repetition {
+        min: usize,
+        max: Option<usize>,
+        lazy: bool,
+        operator: char,
+    },
+    Union,
+    /// `(`, or `(?:` when not `capturing`.
+    GroupStart { capturing: bool },
+    GroupEnd,
+    /// `(?i)`, or `(?-i)` when not `true`.
+    CaseFlag(bool),
+    /// `^`, which only starts a top-level alternative.
+    LineStart,
+    /// `$`, which only ends a top-level alternative.
+    LineEnd,
 }
 
-fn shunting_yard(raw_regex: &str) -> String {
-    let mut operators = VecDeque::new();
-    let mut output = Vec::new();
-    let precedence: HashMap<char, u8> = HashMap::from([
-        (GROUP_START, 0),
-        (GROUP_END, 0),
-        (KLEEN, 4),
-        (UNION, 2),
-        (CONCAT, 3),
-    ]);
-
-    let regex = insert_concat_symbol(raw_regex);
-
-    let mut is_in_char_set = false;
-    for c in regex.chars() {
-        match c {
-            CHAR_SET_END => {
-                is_in_char_set = false;
-                output.push(c);
-            }
-            _ if is_in_char_set => {
-                output.push(c);
-            }
-            KLEEN | UNION | CONCAT if !is_in_char_set => {
-                if operators.is_empty() {
-                    operators.push_back(c);
-                } else {
-                    loop {
-                        let top_operator = operators.pop_back();
-
-                        if top_operator.is_none() {
-                            break;
-                        }
+/// A piece of the pattern with the position errors about it point at.
+type Token = (Piece, usize);
+
+/// Escapes understood by common regex engines that perg cannot compile yet.
+const UNSUPPORTED_ESCAPES: &str = "pPAzZfv";
 
-                        let top_operator = top_operator.unwrap();
+/// Escapes that assert something about a position, like `\b`; they have no
+/// meaning in a character set.
+const ASSERTION_ESCAPES: &str = "bBAzZ";
 
-                        if precedence.get(&top_operator).unwrap() >= precedence.get(&c).unwrap() {
-                            output.push(top_operator);
-                        } else {
-                            operators.push_back(top_operator);
-                            break;
+/// The same inside a character set, where anchors like `\b` are errors
+/// instead.
+const UNSUPPORTED_SET_ESCAPES: &str = "pPfv";
+
+/// The character an escape like `\t` stands for.
+fn control_char(escape: char) -> Option<char> {
+    match escape {
+        't' => Some('\t'),
+        'n' => Some('\n'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        _ => None,
+    }
+}
+
+/// The largest count a repetition like `{n,m}` may have; each one is a
+/// copy of what it repeats.
+const MAX_REPETITION: usize = 1000;
+
+/// Characters that match themselves when escaped, so operators can be searched for.
+const LITERAL_ESCAPES: &str = "\\+*?()[]{}^$.|-/";
+
+/// Where a scan of a pattern is with respect to character sets. A `]` right
+/// after `[` or `[^` is a member, not the end of the set, as in `[]a]`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum SetScan {
+    #[default]
+    Outside,
+    /// Just after `[`, where a `^` negates the set.
+    Opened,
+    /// Just after `[^`.
+    Negated,
+    Inside,
+}
+
+impl SetScan {
+    /// The state after `c`. The character a backslash escapes is skipped by
+    /// the caller; the backslash itself is a member like any other.
+    fn after(self, c: char) -> Self {
+        match (self, c) {
+            (Self::Outside, CHAR_SET_START) => Self::Opened,
+            (Self::Outside, _) => Self::Outside,
+            (Self::Opened, '^') => Self::Negated,
+            (Self::Inside, CHAR_SET_END) => Self::Outside,
+            _ => Self::Inside,
+        }
+    }
+
+    fn is_inside(self) -> bool {
+        self != Self::Outside
+    }
+}
+
+/// Finds the first construct perg does not implement, and where it is, so
+/// callers can report it (or hand the pattern to another engine) instead of
+/// silently mis-compiling it.
+fn find_unsupported(regex: &str) -> Option<(String, usize)> {
+    let chars: Vec<char> = regex.chars().collect();
+    let mut set = SetScan::default();
+    // Where the current top-level alternative starts after any leading
+    // flags, like `(?i)`.
+    let mut start = 0;
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < chars.len() {
+        let was_in_set = set.is_inside();
+        set = set.after(chars[i]);
+        match chars[i] {
+            SLASH => {
+                let unsupported = match was_in_set {
+                    true => UNSUPPORTED_SET_ESCAPES,
+                    false => UNSUPPORTED_ESCAPES,
+                };
+                if let Some(c) = chars.get(i + 1).filter(|c| unsupported.contains(**c)) {
+                    return Some((format!("\\{c}"), i));
+                }
+                i += 1;
+            }
+            _ if was_in_set || set.is_inside() => {}
+            // `(?:` groups, and a `(?` that ends the pattern is left for the
+            // parser to report as never closed.
+            GROUP_START
+                if chars.get(i + 1) == Some(&'?')
+                    && !matches!(chars.get(i + 2), Some(':') | None) =>
+            {
+                match case_flag(&chars[i..]) {
+                    Some((_, len)) => {
+                        if start == i {
+                            start += len;
                         }
+                        i += len;
+                        continue;
                     }
-
-                    operators.push_back(c);
+                    None => return Some(("(?".to_string(), i)),
                 }
             }
-            CHAR_SET_START => {
-                is_in_char_set = true;
-                output.push(c);
+            GROUP_START => depth += 1,
+            GROUP_END => depth = depth.saturating_sub(1),
+            UNION if depth == 0 => start = i + 1,
+            // Only a `^` that starts a top-level alternative and a `$` that
+            // ends one are understood.
+            '^' if i > start => return Some(("^".to_string(), i)),
+            '$' if depth > 0 || !matches!(chars.get(i + 1), Some(&UNION) | None) => {
+                return Some(("$".to_string(), i))
             }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
 
-            GROUP_START => {
-                operators.push_back(c);
-            }
-            GROUP_END => loop {
-                let operator = operators
-                    .pop_back()
-                    .expect("No more symbols!, cannot find matching parenthesis");
+/// Whether the `(?i)` or `(?-i)` that `chars` start with ignores case, and
+/// how long it is.
+fn case_flag(chars: &[char]) -> Option<(bool, usize)> {
+    match chars {
+        [GROUP_START, '?', 'i', GROUP_END, ..] => Some((true, 4)),
+        [GROUP_START, '?', '-', 'i', GROUP_END, ..] => Some((false, 5)),
+        _ => None,
+    }
+}
 
-                if operator == GROUP_START {
-                    break;
+/// The least and the most times a counted repetition matches; no most for
+/// `{n,}`.
+type Bounds = (usize, Option<usize>);
+
+/// `regex` as the pieces it is made of. Braces around anything but digits
+/// and commas are literal, as is a `]` that closes no set; a `(?` that ends
+/// the pattern opens a group, so it is reported as never closed. Escapes
+/// unknown to perg are literals with `literal_escapes`, and errors without.
+fn tokenize(regex: &str, literal_escapes: bool) -> Result<Vec<Token>, RegexError> {
+    let chars: Vec<char> = regex.chars().collect();
+    let mut output = Vec::with_capacity(chars.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let repetition = |min, max, lazy| Piece::Repetition {
+            min,
+            max,
+            lazy,
+            operator: chars[i],
+        };
+        let (piece, len) = match chars[i] {
+            SLASH => escape(&chars, i, literal_escapes)?,
+            CHAR_SET_START => set(&chars, i, literal_escapes)?,
+            KLEEN => (repetition(0, None, false), 1),
+            PLUS => (repetition(1, None, false), 1),
+            OPTIONAL => (repetition(0, Some(1), false), 1),
+            '{' => {
+                let body: String = chars[i + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit() || **c == ',')
+                    .collect();
+                match !body.is_empty() && chars.get(i + 1 + body.len()) == Some(&'}') {
+                    true => {
+                        let (min, max) = parse_repetition(&body).map_err(|reason| {
+                            RegexError::InvalidRepetition {
+                                repetition: format!("{{{body}}}"),
+                                position: i,
+                                reason,
+                            }
+                        })?;
+                        (repetition(min, max, false), body.len() + 2)
+                    }
+                    false => (Piece::Literal('{'), 1),
                 }
-
-                output.push(operator);
+            }
+            UNION => (Piece::Union, 1),
+            GROUP_START => match (case_flag(&chars[i..]), &chars[i + 1..]) {
+                (Some((flag, len)), _) => (Piece::CaseFlag(flag), len),
+                (None, ['?', ':', ..]) => (Piece::GroupStart { capturing: false }, 3),
+                (None, ['?']) => (Piece::GroupStart { capturing: false }, 2),
+                (None, _) => (Piece::GroupStart { capturing: true }, 1),
             },
-            _ => {
-                output.push(c);
+            GROUP_END => (Piece::GroupEnd, 1),
+            // `find_unsupported` leaves no `^` but one that starts a
+            // top-level alternative, and no `$` but one that ends it.
+            '^' => (Piece::LineStart, 1),
+            '$' => (Piece::LineEnd, 1),
+            '.' => (Piece::Dot, 1),
+            c => (Piece::Literal(c), 1),
+        };
+        // A `?` right after a repetition makes it lazy.
+        let (piece, len) = match piece {
+            Piece::Repetition { min, max, .. } if chars.get(i + len) == Some(&OPTIONAL) => {
+                (repetition(min, max, true), len + 1)
             }
+            piece => (piece, len),
         };
+        output.push((piece, i));
+        i += len;
     }
+    Ok(output)
+}
 
-    while !operators.is_empty() {
-        let operator = operators.pop_back().unwrap();
-        output.push(operator);
-    }
-
-    output.into_iter().collect()
+/// The character the `\xHH` or `\u{...}` at `chars[i]` stands for, and how
+/// many characters it takes.
+fn code_point_at(chars: &[char], i: usize) -> Result<(char, usize), RegexError> {
+    code_point(&chars[i..]).map_err(|(len, reason)| RegexError::InvalidCodePoint {
+        escape: chars[i..i + len].iter().collect(),
+        position: i,
+        reason,
+    })
 }
 
-pub fn regex_to_nfa(regex: &str, options: &NfaOptions) -> NFA {
-    let normalized = shunting_yard(regex);
-    let mut nfa_queque: VecDeque<NFA> = VecDeque::new();
-    let mut symbols = normalized.chars().peekable();
-    let mut c = symbols.next();
-
-    let mut is_in_char_group = false;
-    let mut negation = false;
-    let mut character_set: Vec<char> = vec![];
-    while c.is_some() {
-        match c.unwrap() {
-            '^' if is_in_char_group => {
-                negation = true;
-            }
-            '^' => {}
-            CHAR_SET_END => {
-                let nfa = if !negation {
-                    set_of_chars(&character_set, options)
-                } else {
-                    negative_set_of_chars(&character_set, options)
-                };
-                nfa_queque.push_back(nfa);
-                character_set.clear();
-                is_in_char_group = false;
-            }
-            _ if is_in_char_group => {
-                character_set.push(c.unwrap());
+/// The piece the escape at `chars[i]`, outside a set, stands for, and how
+/// many characters it takes.
+fn escape(chars: &[char], i: usize, literal_escapes: bool) -> Result<(Piece, usize), RegexError> {
+    let escaped = *chars.get(i + 1).ok_or(RegexError::TrailingBackslash { position: i })?;
+    let piece = match escaped {
+        'x' | 'u' => {
+            return match code_point_at(chars, i)? {
+                ('\n', _) => Err(RegexError::NewlineInPattern { position: i }),
+                (c, len) => Ok((Piece::Literal(c), len)),
             }
-            CHAR_SET_START => {
-                is_in_char_group = true;
+        }
+        'd' => Piece::Shorthand(Shorthand::Digit),
+        'D' => Piece::Shorthand(Shorthand::NotDigit),
+        'w' => Piece::Shorthand(Shorthand::Word),
+        'W' => Piece::Shorthand(Shorthand::NotWord),
+        's' => Piece::Shorthand(Shorthand::Space),
+        'S' => Piece::Shorthand(Shorthand::NotSpace),
+        'b' => Piece::Assertion(Assertion::WordBoundary),
+        'B' => Piece::Assertion(Assertion::NotWordBoundary),
+        'n' => return Err(RegexError::NewlineInPattern { position: i }),
+        c if LITERAL_ESCAPES.contains(c) => Piece::Literal(c),
+        escape => match control_char(escape) {
+            Some(control) => Piece::Literal(control),
+            None if literal_escapes => Piece::Literal(escape),
+            None => return Err(RegexError::UnknownEscape { escape, position: i }),
+        },
+    };
+    Ok((piece, 2))
+}
+
+/// The character set that opens at `chars[start]`, and how many characters
+/// it takes. A `]` right after `[` or `[^` is a member, not the end of the
+/// set, as in `[]a]`.
+fn set(chars: &[char], start: usize, literal_escapes: bool) -> Result<(Piece, usize), RegexError> {
+    let mut set = CharSet::default();
+    let mut i = start + 1;
+    if chars.get(i) == Some(&'^') {
+        set.negated = true;
+        i += 1;
+    }
+    let first = i;
+    // A set left open takes in the rest of the pattern, groups and all.
+    while let Some(&c) = chars.get(i) {
+        match c {
+            CHAR_SET_END if i > first => {
+                return Ok((Piece::Set(set), i + 1 - start));
             }
             SLASH => {
-                let next_symbol = symbols.peek().expect("Nothing follows '\' symbol");
-                let nfa: Option<NFA> = match *next_symbol {
-                    'd' => Some(digits()),
-                    'w' => Some(alphanumeric(options)),
-                    _ => None,
-                };
-
-                if nfa.is_some() {
-                    nfa_queque.push_back(nfa.unwrap());
-                    symbols.next();
+                let escaped = *chars
+                    .get(i + 1)
+                    .ok_or(RegexError::TrailingBackslash { position: i })?;
+                match escaped {
+                    'x' | 'u' => {
+                        let (c, len) = code_point_at(chars, i)?;
+                        set.push_char(c, i)?;
+                        i += len;
+                        continue;
+                    }
+                    c if LITERAL_ESCAPES.contains(c) => set.push_char(c, i + 1)?,
+                    c if ASSERTION_ESCAPES.contains(c)
+                        || UNSUPPORTED_ESCAPES.contains(c)
+                            && Shorthand::from_escape(c).is_none() =>
+                    {
+                        return Err(RegexError::EscapeInSet {
+                            escape: c,
+                            position: i,
+                        })
+                    }
+                    // A `\n` here is harmless: `[^\n]` matches anything in a line.
+                    escape => match (Shorthand::from_escape(escape), control_char(escape)) {
+                        (Some(shorthand), _) => set.push_shorthand(shorthand),
+                        (None, Some(control)) => set.push_char(control, i + 1)?,
+                        (None, None) if literal_escapes => set.push_char(escape, i + 1)?,
+                        (None, None) => {
+                            return Err(RegexError::UnknownEscape {
+                                escape,
+                                position: i,
+                            })
+                        }
+                    },
                 }
+                i += 1;
             }
-            KLEEN => {
-                let a = nfa_queque
-                    .pop_back()
-                    .expect("Not enough NFA to star operation");
+            '-' => set.push_dash(i)?,
+            c => set.push_char(c, i)?,
+        }
+        i += 1;
+    }
+    Err(RegexError::UnclosedSet { position: start })
+}
+
+/// The character the `\xHH` or `\u{...}` that `chars` start with stands for,
+/// and how many characters it takes; or how many it got through before it
+/// went wrong, and why.
+fn code_point(chars: &[char]) -> Result<(char, usize), (usize, &'static str)> {
+    let hex_digits = |from: usize, most: usize| {
+        chars[from..]
+            .iter()
+            .take(most)
+            .take_while(|c| c.is_ascii_hexdigit())
+            .count()
+    };
+    let (digits, len) = match chars[1] {
+        'x' => match hex_digits(2, 2) {
+            2 => (&chars[2..4], 4),
+            n => return Err((2 + n, "expected two hex digits")),
+        },
+        _ if chars.get(2) != Some(&'{') => return Err((2, "expected '{' and a code point in hex")),
+        _ => match hex_digits(3, 7) {
+            n @ 1..=6 if chars.get(3 + n) == Some(&'}') => (&chars[3..3 + n], n + 4),
+            n => return Err(((4 + n).min(chars.len()), "expected one to six hex digits and '}'")),
+        },
+    };
+    let value = u32::from_str_radix(&digits.iter().collect::<String>(), 16).unwrap_or(u32::MAX);
+    char::from_u32(value).map(|c| (c, len)).ok_or((len, "not a Unicode scalar value"))
+}
 
-                nfa_queque.push_back(kleen(a));
+/// The bounds `n,m`, `n,` or `n` stand for.
+fn parse_repetition(body: &str) -> Result<Bounds, &'static str> {
+    let count = |n: &str| -> Result<usize, &'static str> {
+        if n.is_empty() || n.contains(',') {
+            return Err("expected {n}, {n,} or {n,m}");
+        }
+        n.parse().ok().filter(|n| *n <= MAX_REPETITION).ok_or("counts go up to 1000")
+    };
+    match body.split_once(',') {
+        None => count(body).map(|n| (n, Some(n))),
+        Some((min, "")) => Ok((count(min)?, None)),
+        Some((min, max)) => match (count(min)?, count(max)?) {
+            (min, max) if min > max => Err("the minimum is larger than the maximum"),
+            (min, max) => Ok((min, Some(max))),
+        },
+    }
+}
+
+/// What an [`Ast::Anchor`] asserts about where it is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Assertion {
+    /// `^`: the start of the line.
+    LineStart,
+    /// `$`: the end of the line.
+    LineEnd,
+    /// `\b`
+    WordBoundary,
+    /// `\B`
+    NotWordBoundary,
+}
+
+/// A pattern as [`parse`] reads it. Chains of concatenations and of
+/// alternatives are kept flat, so `abc` is one [`Ast::Concat`] of three
+/// literals, and a non-capturing group leaves only what it holds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ast {
+    /// What an empty group matches: the empty string.
+    Empty,
+    /// One character, in any case when `ignore_case`.
+    Literal { c: char, ignore_case: bool },
+    /// A character set, `[...]`, or `[^...]` when `negated`. A lone
+    /// character is a range of one.
+    Class {
+        ranges: Vec<(char, char)>,
+        shorthands: Vec<Shorthand>,
+        negated: bool,
+        ignore_case: bool,
+    },
+    /// A shorthand like `\d` or `\S` outside a character set.
+    ClassShorthand(Shorthand),
+    /// `.`: any character but a newline, unless
+    /// [`NfaOptions::dot_matches_newline`].
+    Dot,
+    Concat(Vec<Ast>),
+    Alt(Vec<Ast>),
+    /// `ast` repeated from `min` to `max` times, or at least `min` times
+    /// without a `max`: `*`, `+`, `?` and `{min,max}`, each `lazy` with a
+    /// `?` after it.
+    Repeat {
+        ast: Box<Ast>,
+        min: usize,
+        max: Option<usize>,
+        lazy: bool,
+    },
+    /// A capturing group, numbered from 1 in the order groups open.
+    Group { ast: Box<Ast>, index: usize },
+    Anchor(Assertion),
+}
+
+impl Ast {
+    fn repeated(self, min: usize, max: Option<usize>, lazy: bool) -> Ast {
+        Ast::Repeat {
+            ast: Box::new(self),
+            min,
+            max,
+            lazy,
+        }
+    }
+
+    /// The parts this node adds to a concatenation.
+    fn concat_parts(self) -> Vec<Ast> {
+        match self {
+            Ast::Concat(parts) => parts,
+            ast => vec![ast],
+        }
+    }
+
+    /// The alternatives this node adds to a union.
+    fn alternatives(self) -> Vec<Ast> {
+        match self {
+            Ast::Alt(alternatives) => alternatives,
+            ast => vec![ast],
+        }
+    }
+}
+
+/// The parts of a character set read so far.
+#[derive(Debug, Default, PartialEq)]
+struct CharSet {
+    ranges: Vec<(char, char)>,
+    shorthands: Vec<Shorthand>,
+    negated: bool,
+    /// A `-` after a lone character, which the next character ends a range
+    /// with; at the end of the set it is a literal dash.
+    dash: bool,
+    /// The last part read is a lone character, so a `-` starts a range.
+    after_char: bool,
+    /// Where the last lone character is, which a range would start with.
+    char_position: usize,
+}
+
+impl CharSet {
+    fn push_char(&mut self, c: char, position: usize) -> Result<(), RegexError> {
+        match self.dash {
+            true => {
+                let (from, _) = self.ranges.pop().expect("a dash follows a character");
+                if from > c {
+                    return Err(RegexError::InvalidRange {
+                        from,
+                        to: c,
+                        position: self.char_position,
+                    });
+                }
+                self.ranges.push((from, c));
+                self.dash = false;
+                self.after_char = false;
             }
-            CONCAT => {
-                let b = nfa_queque
-                    .pop_back()
-                    .expect("Not enough NFA to perform concatenation");
-                let a = nfa_queque
-                    .pop_back()
-                    .expect("Not enough NFA to perform concatenation");
-                nfa_queque.push_back(concat(a, b));
+            false => {
+                self.ranges.push((c, c));
+                self.after_char = true;
+                self.char_position = position;
             }
-            UNION => {
-                let b = nfa_queque
-                    .pop_back()
-                    .expect("Not enough NFA to perform union");
-                let a = nfa_queque
-                    .pop_back()
-                    .expect("Not enough NFA to perform union");
-                nfa_queque.push_back(union(a, b));
+        }
+        Ok(())
+    }
+
+    /// An unescaped `-`, which is literal at the start or end of the set.
+    fn push_dash(&mut self, position: usize) -> Result<(), RegexError> {
+        match self.after_char && !self.dash {
+            true => {
+                self.dash = true;
+                Ok(())
             }
-            _ => {
-                nfa_queque.push_back(symbol(c.unwrap(), options));
+            false => self.push_char('-', position),
+        }
+    }
+
+    fn push_shorthand(&mut self, shorthand: Shorthand) {
+        self.end_range();
+        self.shorthands.push(shorthand);
+        self.after_char = false;
+    }
+
+    /// Reads a `-` nothing followed as a literal dash.
+    fn end_range(&mut self) {
+        if self.dash {
+            self.ranges.push(('-', '-'));
+            self.dash = false;
+        }
+    }
+
+    fn into_ast(mut self, ignore_case: bool) -> Ast {
+        self.end_range();
+        Ast::Class {
+            ranges: self.ranges,
+            shorthands: self.shorthands,
+            negated: self.negated,
+            ignore_case,
+        }
+    }
+}
+
+/// Reads the pieces of a pattern into an [`Ast`] by recursive descent:
+/// an alternation is concatenations separated by `|`, a concatenation is
+/// operands one after another, each perhaps repeated once, and a group is an
+/// alternation in parentheses.
+struct Parser {
+    pieces: Peekable<vec::IntoIter<Token>>,
+    /// Whether what is read next ignores case, as the flags before it say.
+    ignore_case: bool,
+    /// How many capturing groups have opened; they are numbered from 1 in
+    /// the order they open.
+    groups: usize,
+}
+
+impl Parser {
+    /// Alternatives up to the end of the pattern or of the group they are in.
+    fn alternation(&mut self) -> Result<Ast, RegexError> {
+        let mut alternatives = self.concatenation()?.alternatives();
+        while self.pieces.next_if(|(piece, _)| *piece == Piece::Union).is_some() {
+            alternatives.extend(self.concatenation()?.alternatives());
+        }
+        Ok(match alternatives.len() {
+            1 => alternatives.pop().expect("one alternative"),
+            _ => Ast::Alt(alternatives),
+        })
+    }
+
+    /// Operands up to a `|` or the end of the pattern or of a group; none
+    /// match the empty string, as either side of `|` in `(|a)` does.
+    fn concatenation(&mut self) -> Result<Ast, RegexError> {
+        let mut parts = vec![];
+        while let Some((piece, position)) = self
+            .pieces
+            .next_if(|(piece, _)| !matches!(piece, Piece::Union | Piece::GroupEnd))
+        {
+            let operand = match piece {
+                Piece::Literal(c) => Ast::Literal {
+                    c,
+                    ignore_case: self.ignore_case,
+                },
+                Piece::Shorthand(shorthand) => Ast::ClassShorthand(shorthand),
+                Piece::Dot => Ast::Dot,
+                Piece::Assertion(assertion) => Ast::Anchor(assertion),
+                Piece::Set(set) => set.into_ast(self.ignore_case),
+                Piece::GroupStart { capturing } => self.group(capturing, position)?,
+                Piece::LineStart => Ast::Anchor(Assertion::LineStart),
+                Piece::LineEnd => Ast::Anchor(Assertion::LineEnd),
+                Piece::CaseFlag(ignore_case) => {
+                    self.ignore_case = ignore_case;
+                    continue;
+                }
+                Piece::Repetition { operator, .. } => {
+                    return Err(RegexError::MissingOperand { operator, position })
+                }
+                Piece::Union | Piece::GroupEnd => unreachable!("they end the concatenation"),
+            };
+            parts.extend(self.repetition(operand)?.concat_parts());
+        }
+        Ok(match parts.len() {
+            0 => Ast::Empty,
+            1 => parts.pop().expect("one part"),
+            _ => Ast::Concat(parts),
+        })
+    }
+
+    /// `operand`, repeated if a repetition follows it. Another right after
+    /// is an error rather than a repetition of the repetition.
+    fn repetition(&mut self, operand: Ast) -> Result<Ast, RegexError> {
+        let is_repetition = |(piece, _): &Token| matches!(piece, Piece::Repetition { .. });
+        let Some((Piece::Repetition { min, max, lazy, .. }, _)) =
+            self.pieces.next_if(is_repetition)
+        else {
+            return Ok(operand);
+        };
+        if let Some((Piece::Repetition { operator, .. }, position)) =
+            self.pieces.next_if(is_repetition)
+        {
+            return Err(RegexError::NestedRepetition { operator, position });
+        }
+        Ok(operand.repeated(min, max, lazy))
+    }
+
+    /// The group opened at `position`, up to its `)`. A flag inside it
+    /// holds until the group closes.
+    fn group(&mut self, capturing: bool, position: usize) -> Result<Ast, RegexError> {
+        let outer = self.ignore_case;
+        let index = capturing.then(|| {
+            self.groups += 1;
+            self.groups
+        });
+        let ast = self.alternation()?;
+        self.pieces
+            .next_if(|(piece, _)| *piece == Piece::GroupEnd)
+            .ok_or(RegexError::UnclosedGroup { position })?;
+        self.ignore_case = outer;
+        Ok(match index {
+            Some(index) => Ast::Group {
+                ast: Box::new(ast),
+                index,
+            },
+            None => ast,
+        })
+    }
+}
+
+/// The tree of `regex`, read with the default [`NfaOptions`]; see
+/// [`parse_with`].
+pub fn parse(regex: &str) -> Result<Ast, RegexError> {
+    parse_with(regex, &NfaOptions::default())
+}
+
+/// The tree of `regex`, read with the options that change what it says:
+/// `ignore_case` and `smart_case`, settled into each literal and set, and
+/// `literal_escapes`.
+pub fn parse_with(regex: &str, options: &NfaOptions) -> Result<Ast, RegexError> {
+    if let Some((construct, position)) = find_unsupported(regex) {
+        return Err(RegexError::Unsupported { construct, position });
+    }
+
+    let pieces = tokenize(regex, options.literal_escapes)?;
+    if pieces.iter().all(|(piece, _)| matches!(piece, Piece::CaseFlag(_))) {
+        return Err(RegexError::EmptyPattern);
+    }
+
+    let mut parser = Parser {
+        pieces: pieces.into_iter().peekable(),
+        ignore_case: options.ignores_case(regex),
+        groups: 0,
+    };
+    let body = parser.alternation()?;
+    // Only a `)` stops the alternation before the end.
+    if let Some((_, position)) = parser.pieces.next() {
+        return Err(RegexError::UnmatchedGroupEnd { position });
+    }
+    Ok(body)
+}
+
+/// The automaton of `ast`, with its characters read as [`Ast::Literal`]
+/// and [`Ast::Class`] say rather than as `options.ignore_case` does.
+fn build(ast: &Ast, options: &NfaOptions) -> NFA {
+    let cased = |ignore_case: bool| NfaOptions {
+        ignore_case,
+        ..options.clone()
+    };
+    match ast {
+        Ast::Empty => concat_many(vec![]),
+        Ast::Literal { c, ignore_case } => symbol(*c, &cased(*ignore_case)),
+        Ast::Class {
+            ranges,
+            shorthands,
+            negated,
+            ignore_case,
+        } => char_set(ranges, shorthands, *negated, &cased(*ignore_case)),
+        Ast::ClassShorthand(Shorthand::Digit) => digit(),
+        Ast::ClassShorthand(Shorthand::Word) => alphanumeric(options),
+        Ast::ClassShorthand(shorthand) => char_set(&[], &[*shorthand], false, options),
+        // A negated set of nothing, so it leaves out a newline as `[^a]` does.
+        Ast::Dot => char_set(&[], &[], true, options),
+        Ast::Concat(parts) => concat_many(parts.iter().map(|part| build(part, options)).collect()),
+        Ast::Alt(alternatives) => union_many(
+            alternatives
+                .iter()
+                .map(|alternative| build(alternative, options))
+                .collect(),
+        ),
+        Ast::Repeat {
+            ast,
+            min,
+            max,
+            lazy,
+        } => {
+            let a = build(ast, options);
+            match (min, max, lazy) {
+                (0, None, false) => kleen(a),
+                (1, None, false) => plus(a),
+                (0, Some(1), false) => optional(a),
+                (_, _, false) => repeat(a, *min, *max),
+                (0, None, true) => lazy_kleen(a),
+                (1, None, true) => lazy_plus(a),
+                (0, Some(1), true) => lazy_optional(a),
+                (_, _, true) => lazy_repeat(a, *min, *max),
             }
         }
+        Ast::Group { ast, index } => capture(build(ast, options), *index),
+        Ast::Anchor(Assertion::LineStart) => line_anchor(false),
+        Ast::Anchor(Assertion::LineEnd) => line_anchor(true),
+        Ast::Anchor(Assertion::WordBoundary) => word_boundary(false),
+        Ast::Anchor(Assertion::NotWordBoundary) => word_boundary(true),
+    }
+}
 
-        c = symbols.next();
+/// The automaton of `ast`, within `options.max_errors` edits of it. A `^`
+/// that starts the pattern and a `$` that ends it, like `options.anchored`,
+/// anchor the automaton instead of adding states, so an anchored search
+/// tries no offset but the start of a line.
+pub fn ast_to_nfa(ast: &Ast, options: &NfaOptions) -> NFA {
+    let parts = match ast {
+        Ast::Concat(parts) => parts.as_slice(),
+        ast => std::slice::from_ref(ast),
+    };
+    let (anchored, parts) = match parts {
+        [Ast::Anchor(Assertion::LineStart), rest @ ..] => (true, rest),
+        all => (false, all),
+    };
+    let (anchored_end, parts) = match parts {
+        [rest @ .., Ast::Anchor(Assertion::LineEnd)] => (true, rest),
+        all => (false, all),
+    };
+    // `^` or `$` alone matches at the start or end of every line.
+    let mut nfa = match parts {
+        [part] => build(part, options),
+        parts => concat_many(parts.iter().map(|part| build(part, options)).collect()),
+    };
+    if options.max_errors > 0 {
+        nfa = nfa.fuzzy(options.max_errors);
     }
+    nfa.anchored = options.anchored != Anchor::None || anchored;
+    nfa.anchored_end = options.anchored == Anchor::Both || anchored_end;
+    nfa
+}
+
+pub fn regex_to_nfa(regex: &str, options: &NfaOptions) -> Result<NFA, RegexError> {
+    Ok(ast_to_nfa(&parse_with(regex, options)?, options))
+}
 
-    nfa_queque.pop_back().expect("No NFA to pop!")
+/// The patterns compiled into one automaton, their [`tagged_union`], which
+/// finds the matches of all of them in a single pass; each match tells by
+/// [`Match::pattern`](crate::matcher::Match::pattern) which pattern it is
+/// of, the first when several match the same text.
+pub fn regexes_to_nfa(patterns: &[&str], options: &NfaOptions) -> Result<NFA, PatternError> {
+    if patterns.is_empty() {
+        return Err(PatternError::new("", RegexError::EmptyPattern));
+    }
+    // Edits are counted across the union, not in each pattern.
+    let exact = NfaOptions {
+        max_errors: 0,
+        ..options.clone()
+    };
+    let nfas = patterns
+        .iter()
+        .map(|pattern| {
+            regex_to_nfa(pattern, &exact).map_err(|error| PatternError::new(pattern, error))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let union = tagged_union(nfas);
+    Ok(match options.max_errors {
+        0 => union,
+        max_errors => union.fuzzy(max_errors),
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::nfa::digits;
+    use std::ops::Range;
+
+    use crate::matcher::Match;
+    use crate::nfa::{char_set, concat, digit, union};
 
     use super::*;
 
+    /// How a repetition of `min` to `max` times is written.
+    fn repetition(min: usize, max: Option<usize>, lazy: bool) -> String {
+        let greedy = match (min, max) {
+            (0, None) => "*".to_string(),
+            (1, None) => "+".to_string(),
+            (0, Some(1)) => "?".to_string(),
+            (min, None) => format!("{{{min},}}"),
+            (min, Some(max)) if min == max => format!("{{{min}}}"),
+            (min, Some(max)) => format!("{{{min},{max}}}"),
+        };
+        match lazy {
+            true => greedy + "?",
+            false => greedy,
+        }
+    }
+
+    /// `ast` written back as a pattern, with concatenations spelled `·`
+    /// and empty alternatives `ε`.
+    fn shown(ast: &Ast) -> String {
+        let bracketed = |ast: &Ast| match ast {
+            Ast::Concat(_) | Ast::Alt(_) => format!("(?:{})", shown(ast)),
+            ast => shown(ast),
+        };
+        match ast {
+            Ast::Empty => "ε".to_string(),
+            Ast::Literal { c, .. } if "\\+*?()[]{}^$|.".contains(*c) => format!("\\{c}"),
+            Ast::Literal { c, .. } => c.to_string(),
+            Ast::Class { ranges, shorthands, negated, .. } => {
+                // A `]` is only a member unescaped right after the `[`.
+                let member = |c: char, first: bool| match c {
+                    ']' if !first => "\\]".to_string(),
+                    '\\' => "\\\\".to_string(),
+                    c => c.to_string(),
+                };
+                let mut set = String::from(if *negated { "[^" } else { "[" });
+                for (i, &(from, to)) in ranges.iter().enumerate() {
+                    set += &member(from, i == 0);
+                    if from != to {
+                        set += "-";
+                        set += &member(to, false);
+                    }
+                }
+                for shorthand in shorthands {
+                    set.push_str(&shown(&Ast::ClassShorthand(*shorthand)));
+                }
+                set + "]"
+            }
+            Ast::ClassShorthand(shorthand) => match shorthand {
+                Shorthand::Digit => "\\d",
+                Shorthand::NotDigit => "\\D",
+                Shorthand::Word => "\\w",
+                Shorthand::NotWord => "\\W",
+                Shorthand::Space => "\\s",
+                Shorthand::NotSpace => "\\S",
+            }
+            .to_string(),
+            Ast::Dot => ".".to_string(),
+            Ast::Concat(parts) => parts.iter().map(|part| match part {
+                Ast::Alt(_) => bracketed(part),
+                part => shown(part),
+            }).collect::<Vec<_>>().join("·"),
+            Ast::Alt(alternatives) => alternatives.iter().map(shown).collect::<Vec<_>>().join("|"),
+            Ast::Repeat { ast, min, max, lazy } => bracketed(ast) + &repetition(*min, *max, *lazy),
+            Ast::Group { ast, .. } => format!("({})", shown(ast)),
+            Ast::Anchor(Assertion::LineStart) => "^".to_string(),
+            Ast::Anchor(Assertion::LineEnd) => "$".to_string(),
+            Ast::Anchor(Assertion::WordBoundary) => "\\b".to_string(),
+            Ast::Anchor(Assertion::NotWordBoundary) => "\\B".to_string(),
+        }
+    }
+
+    /// `regex` parsed and written back, with concatenations spelled `·`.
+    fn infix(regex: &str) -> String {
+        shown(&parse(regex).unwrap())
+    }
+
+    /// `regex` parsed and written back in postfix, each operator after its
+    /// operands; a group is closed by a `)` after what it holds.
+    fn postfix(regex: &str) -> String {
+        fn written(ast: &Ast) -> String {
+            let folded = |operands: &[Ast], operator: char| {
+                let mut operands = operands.iter().map(written);
+                let first = operands.next().unwrap_or_default();
+                operands.fold(first, |written, operand| format!("{written}{operand}{operator}"))
+            };
+            match ast {
+                Ast::Concat(parts) => folded(parts, '·'),
+                Ast::Alt(alternatives) => folded(alternatives, '|'),
+                Ast::Repeat {
+                    ast,
+                    min,
+                    max,
+                    lazy,
+                } => written(ast) + &repetition(*min, *max, *lazy),
+                Ast::Group { ast, .. } => written(ast) + ")",
+                ast => shown(ast),
+            }
+        }
+        written(&parse(regex).unwrap())
+    }
+
+    #[test]
+    fn parse_dot() {
+        assert_eq!(parse("."), Ok(Ast::Dot));
+        assert_eq!("a·.·b", infix("a.b"));
+        assert_eq!("a·\\.·b", infix("a\\.b"));
+        assert_eq!("[.]", infix("[.]"));
+        assert_eq!(".*", infix(".*"));
+    }
+
+    #[test]
+    fn parse_concat_underscore() {
+        assert_eq!("a·_·b", infix("a_b"));
+    }
+
+    #[test]
+    fn parse_concat_no_insert_needed() {
+        assert_eq!("a", infix("a"));
+    }
+
+    #[test]
+    fn parse_concat_two_symbols() {
+        assert_eq!("a·b", infix("ab"));
+    }
+
+    #[test]
+    fn parse_concat_ignore_char_sets() {
+        assert_eq!("[abc]", infix("[abc]"));
+        assert_eq!("[]a]·b", infix("[]a]b"));
+        assert_eq!("[^]]·b", infix("[^]]b"));
+        assert_eq!("[a\\]b]·c", infix("[a\\]b]c"));
+    }
+
+    #[test]
+    fn parse_concat_ignore_char_sets_and_nothing_else_1() {
+        assert_eq!("[abc]·a", infix("[abc]a"));
+    }
+
+    #[test]
+    fn parse_concat_ignore_char_sets_and_nothing_else() {
+        assert_eq!("[abc]·a|b", infix("[abc]a|b"));
+    }
+
+    #[test]
+    fn parse_concat_lazy_star() {
+        assert_eq!("a*?·b", infix("a*?b"));
+        assert_eq!("[*?]", infix("[*?]"));
+    }
+
+    #[test]
+    fn parse_concat_lazy_plus_optional_and_count() {
+        assert_eq!("a+?·b", infix("a+?b"));
+        assert_eq!("a??·b", infix("a??b"));
+        assert_eq!("a{2,3}?·b", infix("a{2,3}?b"));
+        assert_eq!("a{2,}?", infix("a{2,}?"));
+        assert_eq!("\"·.+?·\"", infix("\".+?\""));
+    }
+
+    #[test]
+    fn parse_concat_decimal() {
+        assert_eq!("\\d", infix("\\d"));
+    }
+
+    #[test]
+    fn parse_concat_word() {
+        assert_eq!("\\w", infix("\\w"));
+    }
+
     #[test]
-    fn insert_concat_underscore() {
-        assert_eq!("a?_?b", insert_concat_symbol("a_b"));
+    fn parse_concat_space_and_negated_shorthands() {
+        assert_eq!("a·\\s·\\S·\\D·\\W", infix("a\\s\\S\\D\\W"));
+        assert_eq!("\\s+", infix("\\s+"));
     }
 
     #[test]
-    fn insert_concat_no_insert_needed() {
-        assert_eq!("a", insert_concat_symbol("a"));
+    fn parse_concat_escape_next_to_group_or_set() {
+        assert_eq!("\\d·(a)", infix("\\d(a)"));
+        assert_eq!("(a)·\\d", infix("(a)\\d"));
+        assert_eq!("\\d·[ab]", infix("\\d[ab]"));
+        assert_eq!("[ab]·\\d", infix("[ab]\\d"));
+        assert_eq!("\\d·\\w", infix("\\d\\w"));
+        assert_eq!("\\d*", infix("\\d*"));
+        assert_eq!("a·\\d·\\d", infix("a\\d\\d"));
+        assert_eq!("\\d·(a·b)*", infix("\\d(ab)*"));
+        assert_eq!("\\w·[abc]", infix("\\w[abc]"));
     }
 
     #[test]
-    fn insert_concat_two_symbols() {
-        assert_eq!("a?b", insert_concat_symbol("ab"));
+    fn regex_to_nfa_escape_next_to_group_or_set() {
+        let opt = NfaOptions::default();
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            nfa.find_matches(text).iter().map(|m| (m.from, m.to)).collect()
+        };
+
+        assert_eq!(spans("\\d(ab)*", "1abab 2 a"), vec![(0, 5), (6, 7)]);
+        assert_eq!(spans("\\w[abc]", "xa b1c"), vec![(0, 2), (4, 6)]);
+        assert_eq!(spans("a\\d\\d", "a1 a12"), vec![(3, 6)]);
+        assert_eq!(spans("(a)\\d*", "a1 b a"), vec![(0, 2), (5, 6)]);
     }
 
     #[test]
-    fn insert_concat_ignore_char_sets() {
-        assert_eq!("[abc]", insert_concat_symbol("[abc]"));
+    fn parse_concat_escaped_operator() {
+        assert_eq!("a·\\+·b", infix("a\\+b"));
+        assert_eq!("a·\\**", infix("a\\**"));
+        assert_eq!("\\(·a·\\)", infix("\\(a\\)"));
+        assert_eq!("\\[·a·\\]", infix("\\[a\\]"));
+        assert_eq!("a·\\\\·(b)", infix("a\\\\(b)"));
     }
 
     #[test]
-    fn insert_concat_ignore_char_sets_and_nothing_else_1() {
-        assert_eq!("[abc]?a", insert_concat_symbol("[abc]a"));
+    fn parse_escaped_operators_are_operands() {
+        assert_eq!("a\\**·", postfix("a\\**"));
+        assert_eq!("\\(a·\\)·", postfix("\\(a\\)"));
+        assert_eq!("\\[a·\\]·", postfix("\\[a\\]"));
+        assert_eq!("a\\|·b|", postfix("a\\||b"));
     }
 
     #[test]
-    fn insert_concat_ignore_char_sets_and_nothing_else() {
-        assert_eq!("[abc]?a+b", insert_concat_symbol("[abc]a+b"));
+    fn parse_concat_plus() {
+        assert_eq!("a·b+·c", infix("ab+c"));
+        assert_eq!("[+]·\\d+", infix("[+]\\d+"));
     }
 
     #[test]
-    fn insert_concat_decimal() {
-        assert_eq!("\\d", insert_concat_symbol("\\d"));
+    fn parse_concat_optional() {
+        assert_eq!("a·b?·c", infix("ab?c"));
+        assert_eq!("a?·b", infix("a?b"));
+        assert_eq!("a·\\?", infix("a\\?"));
     }
 
     #[test]
-    fn insert_concat_word() {
-        assert_eq!("\\w", insert_concat_symbol("\\w"));
+    fn parse_concat_complex() {
+        assert_eq!("a·(a|b)*·b", infix("a(a|b)*b"));
     }
 
     #[test]
-    fn insert_concat_complex() {
-        assert_eq!("a?(a+b)*?b", insert_concat_symbol("a(a+b)*b"));
+    fn tokenize_repetitions_outside_sets_and_escapes() {
+        let pieces = tokenize("a{2}[{3}]\\{4}b{1,}c{0,2}{}", false).unwrap();
+        let repetitions: Vec<_> = pieces
+            .iter()
+            .filter_map(|(piece, at)| match piece {
+                Piece::Repetition { min, max, .. } => Some((*at, *min, *max)),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(repetitions, vec![(1, 2, Some(2)), (14, 1, None), (19, 0, Some(2))]);
+        assert_eq!(infix("a{2}[{3}]\\{4}b{1,}c{0,2}{}"), "a{2}·[{3}]·\\{·4·\\}·b+·c{0,2}·\\{·\\}");
     }
 
     #[test]
-    fn shunting_yard_empty_input() {
-        let output = shunting_yard("");
-        assert_eq!(output, String::from(""));
+    fn parse_empty_input() {
+        assert_eq!(parse(""), Err(RegexError::EmptyPattern));
+        assert_eq!(parse("(?i)"), Err(RegexError::EmptyPattern));
     }
 
     #[test]
-    fn shunting_yard_ignore_negative_character_groups() {
-        let output = shunting_yard("[^abc]");
+    fn parse_ignore_negative_character_groups() {
+        let output = postfix("[^abc]");
         assert_eq!(output, String::from("[^abc]"));
     }
 
     #[test]
-    fn shunting_yard_ignore_negative_character_groups_and_nothing_else_1() {
-        let output = shunting_yard("[^abc]a");
-        assert_eq!(output, String::from("[^abc]a?"));
+    fn parse_ignore_negative_character_groups_and_nothing_else_1() {
+        let output = postfix("[^abc]a");
+        assert_eq!(output, String::from("[^abc]a·"));
     }
 
     #[test]
-    fn shunting_yard_ignore_character_groups() {
-        let output = shunting_yard("[abc]");
+    fn parse_ignore_character_groups() {
+        let output = postfix("[abc]");
         assert_eq!(output, String::from("[abc]"));
+        assert_eq!("[]|*]a·", postfix("[]|*]a"));
+        assert_eq!("[a\\]*]b·", postfix("[a\\]*]b"));
     }
 
     #[test]
-    fn shunting_yard_ignore_character_groups_and_nothing_else_1() {
-        let output = shunting_yard("[abc]a");
-        assert_eq!(output, String::from("[abc]a?"));
+    fn parse_ignore_character_groups_and_nothing_else_1() {
+        let output = postfix("[abc]a");
+        assert_eq!(output, String::from("[abc]a·"));
     }
 
     #[test]
-    fn shunting_yard_concat_of_groups() {
-        let output = shunting_yard("(ab)(ab)");
-        assert_eq!(output, String::from("ab?ab??"));
+    fn parse_concat_of_groups() {
+        let output = postfix("(ab)(ab)");
+        assert_eq!(output, String::from("ab·)ab·)·"));
     }
 
     #[test]
-    fn shunting_yard_complex_example() {
-        let output = shunting_yard("a(a+b)*b");
-        assert_eq!(output, String::from("aab+*?b?"));
+    fn parse_complex_example() {
+        let output = postfix("a(a|b)*b");
+        assert_eq!(output, String::from("aab|)*·b·"));
     }
 
     #[test]
-    fn shunting_yard_concat_with_char_set() {
-        let output = shunting_yard("[ab]c");
-        assert_eq!(output, String::from("[ab]c?"));
+    fn parse_concat_with_char_set() {
+        let output = postfix("[ab]c");
+        assert_eq!(output, String::from("[ab]c·"));
     }
 
     #[test]
-    fn shunting_yard_underscore() {
-        let output = shunting_yard("a_b");
-        assert_eq!(output, String::from("a_?b?"));
+    fn parse_concat_before_char_set() {
+        let output = postfix("a[bc]");
+        assert_eq!(output, String::from("a[bc]·"));
     }
 
     #[test]
-    fn shunting_yard_long_concat() {
-        let output = shunting_yard("abcdefghijk");
-        assert_eq!(output, String::from("ab?c?d?e?f?g?h?i?j?k?"));
+    fn parse_underscore() {
+        let output = postfix("a_b");
+        assert_eq!(output, String::from("a_·b·"));
     }
 
     #[test]
-    fn shunting_yard_concat() {
-        let output = shunting_yard("ab");
-        assert_eq!(output, String::from("ab?"));
+    fn parse_long_concat() {
+        let output = postfix("abcdefghijk");
+        assert_eq!(output, String::from("ab·c·d·e·f·g·h·i·j·k·"));
     }
 
     #[test]
-    fn shunting_yard_decimal() {
-        let output = shunting_yard("\\d");
+    fn parse_concat() {
+        let output = postfix("ab");
+        assert_eq!(output, String::from("ab·"));
+    }
+
+    #[test]
+    fn parse_decimal() {
+        let output = postfix("\\d");
         assert_eq!(output, String::from("\\d"));
     }
 
     #[test]
-    fn shunting_yard_word() {
-        let output = shunting_yard("\\w");
+    fn parse_word() {
+        let output = postfix("\\w");
         assert_eq!(output, String::from("\\w"));
     }
 
     #[test]
-    fn shunting_yard_union() {
-        let output = shunting_yard("a+b");
-        assert_eq!(output, String::from("ab+"));
+    fn parse_union() {
+        let output = postfix("a|b");
+        assert_eq!(output, String::from("ab|"));
+    }
+
+    #[test]
+    fn parse_optional() {
+        assert_eq!(postfix("ab?c"), "ab?·c·");
+        assert_eq!(postfix("(abc)?d"), "ab·c·)?d·");
+    }
+
+    #[test]
+    fn parse_plus() {
+        assert_eq!(postfix("ab+c"), "ab+·c·");
+        assert_eq!(postfix("(ab)+|c"), "ab·)+c|");
+    }
+
+    #[test]
+    fn parse_alternation() {
+        assert_eq!(postfix("a|bc"), "abc·|");
+        assert_eq!(postfix("(ab|cd)*"), "ab·cd·|)*");
+    }
+
+    #[test]
+    fn parse_concat_empty_alternatives() {
+        assert_eq!(infix("|a"), "ε|a");
+        assert_eq!(infix("a|"), "a|ε");
+        assert_eq!(infix("(a||b)"), "(a|ε|b)");
+        assert_eq!(infix("[|]\\|"), "[|]·\\|");
+    }
+
+    #[test]
+    fn parse_unmatched_group_end() {
+        let output = parse("a)b");
+        assert_eq!(output, Err(RegexError::UnmatchedGroupEnd { position: 1 }));
+    }
+
+    #[test]
+    fn parse_unclosed_groups_and_sets() {
+        let tests = vec![
+            ("(ab", RegexError::UnclosedGroup { position: 0 }),
+            ("((a)b", RegexError::UnclosedGroup { position: 0 }),
+            ("(a(b)", RegexError::UnclosedGroup { position: 0 }),
+            ("[ab", RegexError::UnclosedSet { position: 0 }),
+            ("[]", RegexError::UnclosedSet { position: 0 }),
+            // The set takes in the `)` that would close the group.
+            ("a(b[c)", RegexError::UnclosedSet { position: 3 }),
+            ("a(b[c", RegexError::UnclosedSet { position: 3 }),
+        ];
+
+        for (pattern, error) in tests {
+            assert_eq!(parse(pattern), Err(error), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn regex_to_nfa_empty_pattern() {
+        let opt = NfaOptions::default();
+        let outcome = regex_to_nfa("", &opt);
+        assert!(matches!(outcome, Err(RegexError::EmptyPattern)));
+    }
+
+    #[test]
+    fn regex_to_nfa_empty_groups_match_the_empty_string() {
+        let opt = NfaOptions::default();
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            nfa.find_matches(text).iter().map(|m| (m.from, m.to)).collect()
+        };
+
+        for pattern in ["()", "()*", "(?:)", "(())"] {
+            assert_eq!(spans(pattern, "ab"), vec![(0, 0), (1, 1), (2, 2)], "{pattern}");
+        }
+        assert_eq!(spans("a()b", "xab a b"), vec![(1, 3)]);
+        assert_eq!(spans("a(|)b", "ab"), vec![(0, 2)]);
+        assert_eq!(spans("\\(\\)", "f()"), vec![(1, 3)]);
+        assert_eq!(regex_to_nfa("a()b", &opt).unwrap().captures("ab")[0].get(1), Some(1..1));
+    }
+
+    #[test]
+    fn regex_to_nfa_trailing_backslash() {
+        let opt = NfaOptions::default();
+        let outcome = regex_to_nfa("\\", &opt);
+        assert_eq!(outcome.err(), Some(RegexError::TrailingBackslash { position: 0 }));
+    }
+
+    #[test]
+    fn regex_to_nfa_trailing_backslash_after_pattern() {
+        let opt = NfaOptions::default();
+        let outcome = regex_to_nfa("ab\\", &opt);
+        assert_eq!(
+            outcome.err(),
+            Some(RegexError::TrailingBackslash { position: 2 })
+        );
+        assert_eq!(
+            regex_to_nfa("[a\\", &opt).err(),
+            Some(RegexError::TrailingBackslash { position: 2 })
+        );
+    }
+
+    #[test]
+    fn regex_to_nfa_unknown_escape() {
+        let opt = NfaOptions::default();
+        let tests = vec![
+            ("\\q", 'q', 0),
+            ("ab\\k", 'k', 2),
+            ("a\\qb", 'q', 1),
+            ("[\\]\\\\\\é]", 'é', 5),
+        ];
+
+        for (pattern, escape, position) in tests {
+            let outcome = regex_to_nfa(pattern, &opt);
+            assert_eq!(
+                outcome.err(),
+                Some(RegexError::UnknownEscape { escape, position })
+            );
+        }
+        assert_eq!(
+            RegexError::UnknownEscape {
+                escape: 'q',
+                position: 1
+            }
+            .to_string(),
+            "unknown escape '\\q' at position 1"
+        );
+    }
+
+    #[test]
+    fn regex_to_nfa_control_escapes() {
+        let opt = NfaOptions::default();
+        let tests = vec![
+            (
+                "foo\\tbar",
+                vec!["foo\tbar", "1\tfoo\tbar\t2"],
+                vec!["foo bar", "footbar", "foo\\tbar"],
+            ),
+            ("a\\r", vec!["a\r"], vec!["a", "ar"]),
+            ("\\0+", vec!["\0\0"], vec!["0", "\\0"]),
+            ("[\\t ]x", vec!["\tx", " x"], vec!["tx"]),
+            ("a[^\\n]", vec!["ab", "a\t"], vec!["a"]),
+        ];
+
+        for (pattern, matching, not_matching) in tests {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            for text in matching {
+                assert!(nfa.find_match(text), "{pattern} should match {text:?}");
+            }
+            for text in not_matching {
+                assert!(!nfa.find_match(text), "{pattern} should not match {text:?}");
+            }
+        }
+        assert_eq!(
+            regex_to_nfa("ab\\ncd", &opt).err(),
+            Some(RegexError::NewlineInPattern { position: 2 })
+        );
+    }
+
+    #[test]
+    fn regex_to_nfa_captures_groups() {
+        let spans = |pattern: &str, text: &str| -> Vec<Vec<Option<Range<usize>>>> {
+            let nfa = regex_to_nfa(pattern, &NfaOptions::default()).unwrap();
+            nfa.captures(text)
+                .iter()
+                .map(|caps| (0..=nfa.groups).map(|i| caps.get(i)).collect())
+                .collect()
+        };
+
+        assert_eq!(
+            spans("(\\d+)-(\\d+)", "x 12-345\n6-7"),
+            vec![vec![Some(2..8), Some(2..4), Some(5..8)], vec![Some(0..3), Some(0..1), Some(2..3)]]
+        );
+        assert_eq!(
+            spans("((a)(b))c", "abc"),
+            vec![vec![Some(0..3), Some(0..2), Some(0..1), Some(1..2)]]
+        );
+        assert_eq!(spans("x(é+)", "xééy"), vec![vec![Some(0..5), Some(1..5)]]);
+        // A group under a repetition keeps its last span.
+        assert_eq!(spans("(a|b)*c", "abbc"), vec![vec![Some(0..4), Some(2..3)]]);
+        assert_eq!(spans("(a(b)?)+", "aba"), vec![vec![Some(0..3), Some(2..3), Some(1..2)]]);
+        // Groups that take no part in the match.
+        assert_eq!(spans("(a)|(b)", "b"), vec![vec![Some(0..1), None, Some(0..1)]]);
+        assert_eq!(spans("x(a)*", "x"), vec![vec![Some(0..1), None]]);
+        // Flags are not groups.
+        assert_eq!(spans("(?i)(a)", "A"), vec![vec![Some(0..1), Some(0..1)]]);
+
+        let nfa = regex_to_nfa("ab", &NfaOptions::default()).unwrap();
+        assert_eq!(nfa.captures("ab")[0].get(1), None);
+    }
+
+    #[test]
+    fn regex_to_nfa_non_capturing_groups() {
+        let opt = NfaOptions::default();
+        assert_eq!(postfix("(?:ab)*(c)"), "ab·*c)·");
+
+        let nfa = regex_to_nfa("(?:ab)+c", &opt).unwrap();
+        assert!(nfa.find_match("xababc"));
+        assert!(!nfa.find_match("ac"));
+        assert_eq!(nfa.groups, 0);
+
+        let nfa = regex_to_nfa("(a(?:b|c))(?:(?i)d)(e)", &opt).unwrap();
+        let captures = nfa.captures("acDe");
+        assert_eq!((captures[0].get(1), captures[0].get(2)), (Some(0..2), Some(3..4)));
+        assert!(!nfa.find_match("acDE"));
+
+        for (pattern, position) in [("(?", 0), ("a(?", 1), ("(?:ab", 0), ("(?:(a)", 0)] {
+            assert_eq!(
+                regex_to_nfa(pattern, &opt).err(),
+                Some(RegexError::UnclosedGroup { position }),
+                "{pattern}"
+            );
+        }
+    }
+
+    fn lit(c: char) -> Ast {
+        Ast::Literal { c, ignore_case: false }
+    }
+
+    #[test]
+    fn parse_binds_repetition_tighter_than_concatenation_than_alternation() {
+        assert_eq!(
+            parse("ab|c"),
+            Ok(Ast::Alt(vec![
+                Ast::Concat(vec![lit('a'), lit('b')]),
+                lit('c')
+            ]))
+        );
+        assert_eq!(
+            parse("ab*|c+d?"),
+            Ok(Ast::Alt(vec![
+                Ast::Concat(vec![lit('a'), lit('b').repeated(0, None, false)]),
+                Ast::Concat(vec![
+                    lit('c').repeated(1, None, false),
+                    lit('d').repeated(0, Some(1), false)
+                ]),
+            ]))
+        );
+        assert_eq!(parse("a|b|c"), Ok(Ast::Alt(vec![lit('a'), lit('b'), lit('c')])));
+        assert_eq!(
+            parse("a{2,3}b*?"),
+            Ok(Ast::Concat(vec![
+                lit('a').repeated(2, Some(3), false),
+                lit('b').repeated(0, None, true)
+            ]))
+        );
+    }
+
+    #[test]
+    fn parse_groups() {
+        let group = |ast, index| Ast::Group {
+            ast: Box::new(ast),
+            index,
+        };
+
+        assert_eq!(
+            parse("a(b|c)*"),
+            Ok(Ast::Concat(vec![
+                lit('a'),
+                group(Ast::Alt(vec![lit('b'), lit('c')]), 1).repeated(0, None, false)
+            ]))
+        );
+        assert_eq!(
+            parse("((a)b)(c)"),
+            Ok(Ast::Concat(vec![
+                group(Ast::Concat(vec![group(lit('a'), 2), lit('b')]), 1),
+                group(lit('c'), 3)
+            ]))
+        );
+        // A non-capturing group only groups.
+        assert_eq!(parse("(?:ab)c"), Ok(Ast::Concat(vec![lit('a'), lit('b'), lit('c')])));
+        assert_eq!(parse("(?:a|b)|c"), Ok(Ast::Alt(vec![lit('a'), lit('b'), lit('c')])));
+        assert_eq!(parse("()"), Ok(group(Ast::Empty, 1)));
+        assert_eq!(parse("a)"), Err(RegexError::UnmatchedGroupEnd { position: 1 }));
+    }
+
+    #[test]
+    fn parse_rejects_operators_without_operands() {
+        let tests = vec![
+            ("*a", RegexError::MissingOperand { operator: '*', position: 0 }),
+            ("(*a)", RegexError::MissingOperand { operator: '*', position: 1 }),
+            ("a|+b", RegexError::MissingOperand { operator: '+', position: 2 }),
+            ("a(?i)*", RegexError::MissingOperand { operator: '*', position: 5 }),
+            ("a**", RegexError::NestedRepetition { operator: '*', position: 2 }),
+            ("(a)+??", RegexError::NestedRepetition { operator: '?', position: 5 }),
+            ("\\", RegexError::TrailingBackslash { position: 0 }),
+            ("a(b\\", RegexError::TrailingBackslash { position: 3 }),
+        ];
+
+        for (pattern, error) in tests {
+            assert_eq!(parse(pattern), Err(error), "{pattern}");
+        }
+        assert_eq!(infix("a+"), "a+");
+        assert_eq!(infix("\\*+\\(\\\\"), "\\*+·\\(·\\\\");
+    }
+
+    #[test]
+    fn parse_anchors_sets_and_case() {
+        assert_eq!(
+            parse("^a|b$"),
+            Ok(Ast::Alt(vec![
+                Ast::Concat(vec![Ast::Anchor(Assertion::LineStart), lit('a')]),
+                Ast::Concat(vec![lit('b'), Ast::Anchor(Assertion::LineEnd)]),
+            ]))
+        );
+        assert_eq!(parse("^"), Ok(Ast::Anchor(Assertion::LineStart)));
+        assert_eq!(
+            parse("\\bx\\$"),
+            Ok(Ast::Concat(vec![Ast::Anchor(Assertion::WordBoundary), lit('x'), lit('$')]))
+        );
+        assert_eq!(
+            parse("[^a-c_\\d]\\w"),
+            Ok(Ast::Concat(vec![
+                Ast::Class {
+                    ranges: vec![('a', 'c'), ('_', '_')],
+                    shorthands: vec![Shorthand::Digit],
+                    negated: true,
+                    ignore_case: false,
+                },
+                Ast::ClassShorthand(Shorthand::Word),
+            ]))
+        );
+        assert_eq!(
+            parse("a(?i)b"),
+            Ok(Ast::Concat(vec![lit('a'), Ast::Literal { c: 'b', ignore_case: true }]))
+        );
+        let options = NfaOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        assert_eq!(parse_with("a", &options), Ok(Ast::Literal { c: 'a', ignore_case: true }));
+        assert_eq!(parse(""), Err(RegexError::EmptyPattern));
+    }
+
+    #[test]
+    fn regexes_to_nfa_tags_matches_with_their_pattern() {
+        let nfa = regexes_to_nfa(&["ab", "abc", "ab", "^x", "y$"], &NfaOptions::default()).unwrap();
+        let found = |text| {
+            nfa.find_matches(text)
+                .iter()
+                .map(|m| (m.from, m.to, m.pattern))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(found("ab abc"), vec![(0, 2, 0), (3, 6, 1)]);
+        assert_eq!(found("xy xy\nxy\r"), vec![(0, 1, 3), (4, 5, 4), (0, 1, 3), (1, 2, 4)]);
+        assert_eq!(nfa.find_at("yx\nab", 1).map(|m| (m.from, m.to, m.pattern)), Some((3, 5, 0)));
+
+        let fuzzy = NfaOptions {
+            max_errors: 1,
+            ..Default::default()
+        };
+        let nfa = regexes_to_nfa(&["abc", "xyz"], &fuzzy).unwrap();
+        let found: Vec<_> = nfa
+            .find_matches("abd xyz")
+            .iter()
+            .map(|m| (m.from, m.to, m.errors, m.pattern))
+            .collect();
+        assert_eq!(found, vec![(0, 3, 1, 0), (4, 7, 0, 1)]);
+
+        let error = regexes_to_nfa(&["a", "b)"], &NfaOptions::default()).err();
+        assert_eq!(
+            error,
+            Some(PatternError::new(
+                "b)",
+                RegexError::UnmatchedGroupEnd { position: 1 }
+            ))
+        );
+        let error = regexes_to_nfa(&[], &NfaOptions::default()).err();
+        assert_eq!(error, Some(PatternError::new("", RegexError::EmptyPattern)));
+    }
+
+    #[test]
+    fn regex_to_nfa_former_sentinels_are_literals() {
+        let opt = NfaOptions::default();
+        let tests = vec![
+            ("#include", vec!["#include <x>"], vec!["1include", "include"]),
+            ("x = 1", vec!["x = 1"], vec!["x a 1", "x 5 1"]),
+            ("a&b", vec!["a&b"], vec!["a-b", "acb"]),
+            ("λε+", vec!["λεε"], vec!["λ", "λa"]),
+            ("[#=&ε]x", vec!["#x", "=x", "&x", "εx"], vec!["1x", "ax", "-x"]),
+        ];
+
+        for (pattern, matching, not_matching) in tests {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            for text in matching {
+                assert!(nfa.find_match(text), "{pattern} should match {text:?}");
+            }
+            for text in not_matching {
+                assert!(!nfa.find_match(text), "{pattern} should not match {text:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn regex_to_nfa_code_point_escapes() {
+        let opt = NfaOptions::default();
+        let tests = vec![
+            ("\\x41b", vec!["Ab"], vec!["ab", "x41b"]),
+            ("a\\x2A", vec!["a*"], vec!["a", "aa"]),
+            ("\\x64", vec!["d"], vec!["1"]),
+            ("\\u{e9}+", vec!["éé"], vec!["e"]),
+            ("[\\x41-\\x43\\u{5D}]", vec!["B", "]"], vec!["D", "x"]),
+            ("\\x5Cd", vec!["\\d"], vec!["1", "d"]),
+        ];
+
+        for (pattern, matching, not_matching) in tests {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            for text in matching {
+                assert!(nfa.find_match(text), "{pattern} should match {text:?}");
+            }
+            for text in not_matching {
+                assert!(!nfa.find_match(text), "{pattern} should not match {text:?}");
+            }
+        }
+
+        let emoji = regex_to_nfa("a\\u{1F600}+b", &opt).unwrap();
+        let spans: Vec<(usize, usize)> = emoji
+            .find_matches("éa😀😀b a😀b")
+            .iter()
+            .map(|m| (m.from, m.to))
+            .collect();
+        assert_eq!(spans, vec![(2, 12), (13, 19)]);
+
+        let invalid = |escape: &str, position, reason| RegexError::InvalidCodePoint {
+            escape: escape.to_string(),
+            position,
+            reason,
+        };
+        let errors = vec![
+            ("a\\x4", invalid("\\x4", 1, "expected two hex digits")),
+            ("\\xZZ", invalid("\\x", 0, "expected two hex digits")),
+            ("\\u41", invalid("\\u", 0, "expected '{' and a code point in hex")),
+            ("\\u{}", invalid("\\u{}", 0, "expected one to six hex digits and '}'")),
+            ("\\u{41", invalid("\\u{41", 0, "expected one to six hex digits and '}'")),
+            ("\\u{1000000}", invalid("\\u{1000000}", 0, "expected one to six hex digits and '}'")),
+            ("\\u{110000}", invalid("\\u{110000}", 0, "not a Unicode scalar value")),
+            ("[\\u{D800}]", invalid("\\u{D800}", 1, "not a Unicode scalar value")),
+            ("\\x0A", RegexError::NewlineInPattern { position: 0 }),
+        ];
+        for (pattern, error) in errors {
+            assert_eq!(regex_to_nfa(pattern, &opt).err(), Some(error), "{pattern}");
+        }
+    }
+
+    #[test]
+    fn regex_to_nfa_unknown_escapes_can_be_literal() {
+        let opt = NfaOptions {
+            literal_escapes: true,
+            ..Default::default()
+        };
+        let tests = vec![
+            ("\\q", vec!["q"], vec!["\\", "a"]),
+            ("a\\qb", vec!["aqb"], vec!["ab", "a\\b"]),
+            ("[\\q\\d]", vec!["q", "1"], vec!["\\", "a"]),
+        ];
+
+        for (pattern, matching, not_matching) in tests {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            for text in matching {
+                assert!(nfa.find_match(text), "{pattern} should match {text}");
+            }
+            for text in not_matching {
+                assert!(!nfa.find_match(text), "{pattern} should not match {text}");
+            }
+        }
+        assert_eq!(
+            regex_to_nfa("a\\", &opt).err(),
+            Some(RegexError::TrailingBackslash { position: 1 })
+        );
+        assert!(matches!(
+            regex_to_nfa("\\pL", &opt),
+            Err(RegexError::Unsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn regex_to_nfa_escaped_operators_are_literal() {
+        let opt = NfaOptions::default();
+        let tests = vec![
+            ("a\\+b", "a+b", "ab"),
+            ("\\(x\\)", "(x)", "x"),
+            ("\\[ab\\]", "[ab]", "a"),
+            ("1\\*2", "1*2", "12"),
+            ("why\\?", "why?", "why"),
+            ("a\\\\b", "a\\b", "ab"),
+            ("\\.\\$", ".$", "a$"),
+            ("\\)", ")", "("),
+            ("\\]", "]", "["),
+            ("vec\\.push\\(", "v.push(1); vec.push(2)", "vec.push"),
+        ];
+
+        for (pattern, matching, not_matching) in tests {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            println!("{pattern}");
+            assert!(nfa.find_match(matching));
+            assert!(!nfa.find_match(not_matching));
+        }
+    }
+
+    #[test]
+    fn regex_to_nfa_escaped_symbol_in_expression() {
+        let opt = NfaOptions::default();
+        let nfa = regex_to_nfa("(\\+a)*b", &opt).unwrap();
+        assert_eq!(
+            nfa.find_matches("+a+ab"),
+            vec![Match {
+                from: 0,
+                to: 5,
+                line: 0,
+                errors: 0,
+                pattern: 0
+            }]
+        );
+
+        let nfa = regex_to_nfa("a\\**", &opt).unwrap();
+        assert_eq!(
+            nfa.find_matches("a***b"),
+            vec![Match {
+                from: 0,
+                to: 4,
+                line: 0,
+                errors: 0,
+                pattern: 0
+            }]
+        );
+        let nfa = regex_to_nfa("\\[[ab]+\\]", &opt).unwrap();
+        assert_eq!(
+            nfa.find_matches("x[ab]"),
+            vec![Match {
+                from: 1,
+                to: 5,
+                line: 0,
+                errors: 0,
+                pattern: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn regex_to_nfa_question_marks_and_pluses_in_source_code() {
+        let opt = NfaOptions::default();
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            nfa.find_matches(text).iter().map(|m| (m.from, m.to)).collect()
+        };
+
+        assert_eq!(spans("i\\+1", "a[i+1] = a[i1] + a[ii+1];"), vec![(2, 5), (20, 23)]);
+        assert_eq!(spans("\\? \\w+ :", "int x = ok ? yes : no;"), vec![(11, 18)]);
+        assert_eq!(spans("\\?\\?=", "a ??= b; c ?= d"), vec![(2, 5)]);
+        // Unescaped, they are operators again.
+        assert_eq!(spans("a+b", "a+b aab"), vec![(4, 7)]);
+        assert_eq!(spans("colou?r", "color colour colouur"), vec![(0, 5), (6, 12)]);
+    }
+
+    #[test]
+    fn regex_to_nfa_alternation() {
+        let opt = NfaOptions::default();
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            nfa.find_matches(text).iter().map(|m| (m.from, m.to)).collect()
+        };
+
+        assert_eq!(spans("error|warning", "a warning, an error"), vec![(2, 9), (14, 19)]);
+        assert_eq!(spans("(ab|cd)*x", "abcdabx cx"), vec![(0, 7), (9, 10)]);
+        assert_eq!(spans("a(b|c(d|e))f", "abf acdf acef acf"), vec![(0, 3), (4, 8), (9, 13)]);
+        assert_eq!(spans("[abc]|xyz", "b xy xyz"), vec![(0, 1), (5, 8)]);
+        assert_eq!(spans("xyz|[abc]", "b xy xyz"), vec![(0, 1), (5, 8)]);
+        assert_eq!(spans("a\\|b", "a|b"), vec![(0, 3)]);
+        assert_eq!(spans("a+|b+", "aab bc"), vec![(0, 2), (2, 3), (4, 5)]);
+    }
+
+    #[test]
+    fn regex_to_nfa_empty_alternatives_match_the_empty_string() {
+        let opt = NfaOptions::default();
+        for pattern in ["ab|", "|ab", "(|ab)", "(ab|)", "(a||b)"] {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            assert!(nfa.find_match("xy"), "{pattern}");
+        }
+        let nfa = regex_to_nfa("x(|ab)y", &opt).unwrap();
+        assert!(nfa.find_match("xy"));
+        assert!(nfa.find_match("xaby"));
+        assert!(!nfa.find_match("xay"));
+    }
+
+    #[test]
+    fn regex_to_nfa_plus() {
+        let opt = NfaOptions::default();
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            nfa.find_matches(text).iter().map(|m| (m.from, m.to)).collect()
+        };
+
+        assert_eq!(spans("a+", "b aaa a"), vec![(2, 5), (6, 7)]);
+        assert_eq!(spans("ab+c", "ac abc abbbc"), vec![(3, 6), (7, 12)]);
+        assert_eq!(spans("(ab)+", "a abab abb"), vec![(2, 6), (7, 9)]);
+        assert_eq!(spans("[0-9]+", "x 2024 y 7"), vec![(2, 6), (9, 10)]);
+        assert_eq!(spans("\\d+", "x 2024 y 7"), vec![(2, 6), (9, 10)]);
+        assert_eq!(spans("a\\+", "a aa+"), vec![(3, 5)]);
+
+        let nfa = concat(symbol('a', &opt), concat(plus(symbol('b', &opt)), symbol('c', &opt)));
+        let outcome = regex_to_nfa("ab+c", &opt).unwrap();
+        for example in ["ac", "abc", "abbc", "xabbcx", "abx"] {
+            assert_eq!(nfa.find_matches(example), outcome.find_matches(example), "{example}");
+        }
+    }
+
+    #[test]
+    fn regex_to_nfa_optional() {
+        let opt = NfaOptions::default();
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            nfa.find_matches(text).iter().map(|m| (m.from, m.to)).collect()
+        };
+
+        assert_eq!(spans("colou?r", "color colour colouur"), vec![(0, 5), (6, 12)]);
+        assert_eq!(spans("ab?c", "ac abc abbc"), vec![(0, 2), (3, 6)]);
+        assert_eq!(spans("(abc)?d", "d abcd abd"), vec![(0, 1), (2, 6), (9, 10)]);
+        assert_eq!(spans("a?b", "b ab aab"), vec![(0, 1), (2, 4), (6, 8)]);
+        assert_eq!(spans("a?", "ba"), vec![(0, 0), (1, 2)]);
+        assert_eq!(spans("why\\?", "why? why"), vec![(0, 4)]);
+
+        let nfa = concat(symbol('a', &opt), concat(optional(symbol('b', &opt)), symbol('c', &opt)));
+        let outcome = regex_to_nfa("ab?c", &opt).unwrap();
+        for example in ["ac", "abc", "abbc", "xabcx", "abx"] {
+            assert_eq!(nfa.find_matches(example), outcome.find_matches(example), "{example}");
+        }
+    }
+
+    #[test]
+    fn regex_to_nfa_counted_repetition() {
+        let opt = NfaOptions::default();
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            nfa.find_matches(text).iter().map(|m| (m.from, m.to)).collect()
+        };
+
+        assert_eq!(spans("a{2,5}", "a aa aaaaaaa"), vec![(2, 4), (5, 10), (10, 12)]);
+        assert_eq!(spans("ba{2}", "ba baa baaa"), vec![(3, 6), (7, 10)]);
+        assert_eq!(spans("ba{2,}", "ba baa baaa"), vec![(3, 6), (7, 11)]);
+        assert_eq!(spans("ba{0}c", "bc bac"), vec![(0, 2)]);
+        assert_eq!(spans("\\d{4}-\\d{2}", "on 2024-10-16"), vec![(3, 10)]);
+        assert_eq!(spans("(ab){2}c", "abc ababc"), vec![(4, 9)]);
+        assert_eq!(spans("(a|bc){3}", "abca bcbcbc aa"), vec![(0, 4), (5, 11)]);
+        assert_eq!(spans("[xy]{1,2}z", "z xz xyyz"), vec![(2, 4), (6, 9)]);
+        assert_eq!(spans("x(a{2}b)+", "xaab xaabaab xab"), vec![(0, 4), (5, 12)]);
+        assert_eq!(spans("a\\{2}", "aa a{2}"), vec![(3, 7)]);
+    }
+
+    #[test]
+    fn regex_to_nfa_malformed_repetitions_are_errors() {
+        let opt = NfaOptions::default();
+        let tests = vec![
+            ("a{,}", "{,}", 1, "expected {n}, {n,} or {n,m}"),
+            ("ab{,3}", "{,3}", 2, "expected {n}, {n,} or {n,m}"),
+            ("a{1,2,3}", "{1,2,3}", 1, "expected {n}, {n,} or {n,m}"),
+            ("(a){3,1}", "{3,1}", 3, "the minimum is larger than the maximum"),
+            ("a{1001}", "{1001}", 1, "counts go up to 1000"),
+        ];
+
+        for (pattern, repetition, position, reason) in tests {
+            assert_eq!(
+                regex_to_nfa(pattern, &opt).err(),
+                Some(RegexError::InvalidRepetition {
+                    repetition: repetition.to_string(),
+                    position,
+                    reason
+                }),
+                "{pattern}"
+            );
+        }
+        assert_eq!(
+            regex_to_nfa("a{3,1}", &opt).unwrap_err().to_string(),
+            "invalid repetition '{3,1}' at position 1: the minimum is larger than the maximum"
+        );
+        assert_eq!(
+            regex_to_nfa("{3}", &opt).unwrap_err(),
+            RegexError::MissingOperand {
+                operator: '{',
+                position: 0
+            }
+        );
+    }
+
+    #[test]
+    fn regex_to_nfa_start_anchor() {
+        let opt = NfaOptions::default();
+        let nfa = regex_to_nfa("^abc", &opt).unwrap();
+
+        assert!(nfa.anchored);
+        assert_eq!(
+            nfa.find_matches("abc"),
+            vec![Match {
+                from: 0,
+                to: 3,
+                line: 0,
+                errors: 0,
+                pattern: 0
+            }]
+        );
+        assert_eq!(nfa.find_matches("xabc"), vec![]);
+        assert_eq!(
+            nfa.find_matches("abcabc\nx\nabc abc"),
+            vec![
+                Match { from: 0, to: 3, line: 0, errors: 0, pattern: 0 },
+                Match { from: 0, to: 3, line: 2, errors: 0, pattern: 0 }
+            ]
+        );
+        assert!(!regex_to_nfa("\\^abc", &opt).unwrap().anchored);
+        assert!(regex_to_nfa("^(a|b)*", &opt).unwrap().find_match("ab"));
+        assert_eq!(
+            regex_to_nfa("^", &opt).unwrap().find_matches("x\n"),
+            regex_to_nfa("^a*", &opt).unwrap().find_matches("x\n")
+        );
+    }
+
+    #[test]
+    fn regex_to_nfa_start_anchor_holds_for_its_alternative_only() {
+        let opt = NfaOptions::default();
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            nfa.find_matches(text).iter().map(|m| (m.from, m.to)).collect()
+        };
+
+        assert!(!regex_to_nfa("^a|b", &opt).unwrap().anchored);
+        assert_eq!(spans("^a|b", "xb"), vec![(1, 2)]);
+        assert_eq!(spans("^a|b", "ab a"), vec![(0, 1), (1, 2)]);
+        assert_eq!(spans("x|^a", "ax a"), vec![(0, 1), (1, 2)]);
+        assert_eq!(spans("x|(?i)^a", "Ax"), vec![(0, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn regex_to_nfa_end_anchor() {
+        let opt = NfaOptions::default();
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize, usize)> {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            nfa.find_matches(text).iter().map(|m| (m.line, m.from, m.to)).collect()
+        };
+
+        assert_eq!(
+            spans("abc$", "abc\nabcd\nxabc\r\nabc abc"),
+            vec![(0, 0, 3), (2, 1, 4), (3, 4, 7)]
+        );
+        assert_eq!(
+            spans("^abc$", "abc\nabcabc\nxabc\nabc\r"),
+            vec![(0, 0, 3), (3, 0, 3)]
+        );
+        assert_eq!(spans("a*$", "baa\nb"), vec![(0, 1, 3), (1, 1, 1)]);
+        assert_eq!(spans("(ab)*$", "abab x\nabab"), vec![(0, 6, 6), (1, 0, 4)]);
+        assert_eq!(spans("a*?$", "baa"), vec![(0, 1, 3)]);
+        assert_eq!(spans("^$", "a\n\n\r"), vec![(1, 0, 0), (2, 0, 0)]);
+        assert_eq!(spans("a\\$", "a$ a"), vec![(0, 0, 2)]);
+        assert_eq!(spans("a\\\\$", "a\\$ a\\"), vec![(0, 4, 6)]);
+
+        let nfa = regex_to_nfa(";$", &opt).unwrap();
+        assert!(nfa.find_match("let a = 1;\r"));
+        assert!(!nfa.find_match("f(a; b)"));
+
+        // A `$` anchors only the alternative it ends.
+        assert!(!regex_to_nfa("a|b$", &opt).unwrap().anchored_end);
+        assert_eq!(spans("a|b$", "ab"), vec![(0, 0, 1), (0, 1, 2)]);
+        assert_eq!(spans("a|b$", "ba"), vec![(0, 1, 2)]);
+        assert_eq!(spans("a$|b", "ab"), vec![(0, 1, 2)]);
+        assert_eq!(spans("^a$|b", "a\nab"), vec![(0, 0, 1), (1, 1, 2)]);
+    }
+
+    #[test]
+    fn regex_to_nfa_word_boundaries() {
+        let opt = NfaOptions::default();
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            nfa.find_matches(text).iter().map(|m| (m.from, m.to)).collect()
+        };
+
+        assert_eq!(spans("\\bfoo\\b", "foo foobar barfoo (foo)"), vec![(0, 3), (19, 22)]);
+        assert_eq!(spans("\\bfoo\\b", "foo_bar foo1 foo"), vec![(13, 16)]);
+        assert_eq!(spans("\\bźle\\b", "źle,nieźle"), vec![(0, 4)]);
+        assert_eq!(spans("\\Boo\\B", "foo fooo oo"), vec![(5, 7)]);
+        assert_eq!(spans("o\\B", "foo fooo"), vec![(1, 2), (5, 6), (6, 7)]);
+        assert_eq!(spans("\\b", "ab c"), vec![(0, 0), (2, 2), (3, 3), (4, 4)]);
+        assert_eq!(spans("a+\\b", "aab aa"), vec![(4, 6)]);
+        assert_eq!(spans("\\b(ab)+", "abab xabab"), vec![(0, 4)]);
+        // `\w` and `\b` agree on what a word character is.
+        assert_eq!(spans("\\b\\w+\\b", "foo_bar baz"), vec![(0, 7), (8, 11)]);
+        assert_eq!(spans("\\bx", "_x x"), vec![(3, 4)]);
+
+        let nfa = regex_to_nfa("\\bfoo\\b", &opt).unwrap();
+        assert!(nfa.find_match("foo"));
+        assert!(nfa.find_match("x\nfoo"));
+        assert!(!nfa.find_match("foobar\nbarfoo"));
+    }
+
+    #[test]
+    fn regex_to_nfa_missing_operand() {
+        let opt = NfaOptions::default();
+        let outcome = regex_to_nfa("*", &opt);
+        assert!(matches!(
+            outcome,
+            Err(RegexError::MissingOperand { operator: KLEEN, position: 0 })
+        ));
+        assert_eq!(
+            regex_to_nfa("+a", &opt).unwrap_err(),
+            RegexError::MissingOperand {
+                operator: PLUS,
+                position: 0
+            }
+        );
+        assert_eq!(
+            regex_to_nfa("?", &opt).unwrap_err(),
+            RegexError::MissingOperand {
+                operator: OPTIONAL,
+                position: 0
+            }
+        );
+    }
+
+    #[test]
+    fn regex_to_nfa_errors_point_at_their_position() {
+        let opt = NfaOptions::default();
+        let tests = vec![
+            ("a(b", RegexError::UnclosedGroup { position: 1 }),
+            ("a)b", RegexError::UnmatchedGroupEnd { position: 1 }),
+            ("[abc", RegexError::UnclosedSet { position: 0 }),
+            ("a**", RegexError::NestedRepetition { operator: '*', position: 2 }),
+            ("ab{2}+", RegexError::NestedRepetition { operator: '+', position: 5 }),
+            ("x*?{3}", RegexError::NestedRepetition { operator: '{', position: 3 }),
+            ("x+??", RegexError::NestedRepetition { operator: '?', position: 3 }),
+        ];
+
+        for (pattern, error) in tests {
+            assert_eq!(regex_to_nfa(pattern, &opt).err(), Some(error), "{pattern}");
+        }
+        assert!(regex_to_nfa("(a*)*[*+]+\\**?", &opt).is_ok());
+    }
+
+    #[test]
+    fn pattern_errors_show_a_caret_under_the_position() {
+        let opt = NfaOptions::default();
+        let error = |pattern: &str| {
+            PatternError::new(pattern, regex_to_nfa(pattern, &opt).unwrap_err()).to_string()
+        };
+
+        assert_eq!(error("ab)c"), "unmatched ')' at position 2\n    ab)c\n      ^");
+        assert_eq!(
+            error("źle**"),
+            concat!(
+                "'*' at position 4 repeats a repetition; group it first, as in '(a*)*'\n",
+                "    źle**\n",
+                "        ^"
+            )
+        );
+        assert_eq!(error(""), "the pattern is empty");
+    }
+
+    #[test]
+    fn regex_to_nfa_unsupported_constructs() {
+        let opt = NfaOptions::default();
+        let tests = vec![
+            ("a\\pLb", "\\p", 1),
+            ("\\Aword", "\\A", 0),
+            ("(?s)abc", "(?", 0),
+            ("a(?i:b)", "(?", 1),
+            ("a^b", "^", 1),
+            ("(^a|b)", "^", 1),
+            ("x|a^b", "^", 3),
+            ("a$b|c", "$", 1),
+            ("a$b", "$", 1),
+            ("(a$)", "$", 2),
+        ];
+
+        for (pattern, construct, position) in tests {
+            let outcome = regex_to_nfa(pattern, &opt);
+            assert_eq!(
+                outcome.err(),
+                Some(RegexError::Unsupported {
+                    construct: construct.to_string(),
+                    position,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn regex_to_nfa_braces_without_count_are_literal() {
+        let opt = NfaOptions::default();
+        let outcome = regex_to_nfa("fn {}", &opt).unwrap();
+        assert!(outcome.find_match("fn {}"));
     }
 
     #[test]
     fn regex_to_nfa_negative_character_set() {
         let opt = NfaOptions::default();
-        let nfa = negative_set_of_chars(&vec!['a', 'b'], &opt);
-        let outcome = regex_to_nfa("[^ab]", &opt);
+        let nfa = char_set(&[('a', 'b')], &[], true, &opt);
+        let outcome = regex_to_nfa("[^ab]", &opt).unwrap();
 
         let tests = vec!["a", "b", "c", "ab", "ac", "abc", "", "xyz"];
         for example in tests {
@@ -341,11 +2147,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn regex_to_nfa_negated_ranges_and_shorthands() {
+        let opt = NfaOptions::default();
+        let folded = NfaOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let tests = vec![
+            ("[^a-z0-9]", &opt, vec!["A", "-", "é"], vec!["a", "q", "5"]),
+            ("[^a-z]", &folded, vec!["1", "_"], vec!["a", "Q"]),
+            ("[A-C]", &folded, vec!["b", "C"], vec!["d"]),
+            ("[^\\d]", &opt, vec!["a", " "], vec!["5"]),
+            ("[^\\dx]", &opt, vec!["a", "X"], vec!["x", "7"]),
+            ("[^-a]", &opt, vec!["b", "^"], vec!["-", "a"]),
+            ("[^\\w]", &opt, vec!["-", " "], vec!["a", "5"]),
+        ];
+
+        for (pattern, options, matching, not_matching) in tests {
+            let nfa = regex_to_nfa(pattern, options).unwrap();
+            for text in matching {
+                assert!(nfa.find_match(text), "{pattern} should match {text}");
+            }
+            for text in not_matching {
+                assert!(!nfa.find_match(text), "{pattern} should not match {text}");
+            }
+        }
+    }
+
     #[test]
     fn regex_to_nfa_character_set() {
         let opt = NfaOptions::default();
-        let nfa = set_of_chars(&vec!['a', 'b', 'c'], &opt);
-        let outcome = regex_to_nfa("[abc]", &opt);
+        let nfa = char_set(&[('a', 'c')], &[], false, &opt);
+        let outcome = regex_to_nfa("[abc]", &opt).unwrap();
 
         let tests = vec!["a", "b", "c", "ab", "ac", "abc", "", "xyz"];
         for example in tests {
@@ -354,11 +2188,217 @@ mod tests {
         }
     }
 
+    #[test]
+    fn regex_to_nfa_leading_bracket_is_a_member() {
+        let opt = NfaOptions::default();
+        let tests = vec![
+            ("[]]", vec!["]"], vec!["a", "["]),
+            ("[]a]", vec!["]", "a"], vec!["b", "["]),
+            ("[^]a]", vec!["b", "["], vec!["]", "a"]),
+            ("[]-a]", vec!["]", "^", "a"], vec!["b", "-"]),
+            ("x[]]*y", vec!["xy", "x]]y"], vec!["x]", "x]a]y"]),
+            ("[a^]", vec!["a", "^"], vec!["b"]),
+        ];
+
+        for (pattern, matching, not_matching) in tests {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            for text in matching {
+                assert!(nfa.find_match(text), "{pattern} should match {text}");
+            }
+            for text in not_matching {
+                assert!(!nfa.find_match(text), "{pattern} should not match {text}");
+            }
+        }
+    }
+
+    #[test]
+    fn regex_to_nfa_metacharacters_in_character_sets_are_members() {
+        let opt = NfaOptions::default();
+        let tests = vec![
+            ("[*+?]", vec!["*", "+", "?"], vec!["a", ""]),
+            ("[().]", vec!["(", ")", "."], vec!["a"]),
+            ("[[]", vec!["["], vec!["]", "a"]),
+            ("[a?b]", vec!["a", "?", "b"], vec!["c"]),
+            ("x[|{}^$]y", vec!["x|y", "x{y", "x}y", "x^y", "x$y"], vec!["xy", "x|{y"]),
+            ("\\[[ab]\\]", vec!["[a]", "[b]"], vec!["[ab]", "a"]),
+            ("[[]]", vec!["[]"], vec!["[", "]"]),
+            ("[(]a[)]", vec!["(a)"], vec!["a", "(a"]),
+            ("[*]+", vec!["**"], vec!["+"]),
+        ];
+
+        for (pattern, matching, not_matching) in tests {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            for text in matching {
+                assert!(nfa.find_match(text), "{pattern} should match {text}");
+            }
+            for text in not_matching {
+                assert!(!nfa.find_match(text), "{pattern} should not match {text}");
+            }
+        }
+    }
+
+    #[test]
+    fn regex_to_nfa_dot_matches_any_character() {
+        let opt = NfaOptions::default();
+        let tests = vec![
+            ("a.b", vec!["axb", "a.b", "aśb", "a b"], vec!["ab", "a\nb", "axxb"]),
+            ("a\\.b", vec!["a.b"], vec!["axb"]),
+            ("a[.]b", vec!["a.b"], vec!["axb"]),
+            ("^.*$", vec!["", "anything"], vec![]),
+        ];
+
+        for (pattern, matching, not_matching) in tests {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            for example in matching {
+                assert!(nfa.find_match(example), "{pattern} should match {example:?}");
+            }
+            for example in not_matching {
+                assert!(!nfa.find_match(example), "{pattern} should not match {example:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn regex_to_nfa_shorthands_outside_character_sets() {
+        let opt = NfaOptions::default();
+        let tests = vec![
+            ("\\s", vec![" ", "\t", "a b"], vec!["a", "_", ""]),
+            ("\\S", vec!["a", "-", " x"], vec![" ", "\t", ""]),
+            ("\\D", vec!["a", " ", "1x"], vec!["1", "٣", ""]),
+            ("\\W", vec!["-", " ", "a!"], vec!["a", "1", "_", ""]),
+            ("a\\s+b", vec!["a b", "a \t b"], vec!["ab", "a-b"]),
+        ];
+
+        for (pattern, matching, not_matching) in tests {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            for example in matching {
+                assert!(nfa.find_match(example), "{pattern} should match {example:?}");
+            }
+            for example in not_matching {
+                assert!(!nfa.find_match(example), "{pattern} should not match {example:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn regex_to_nfa_shorthands_in_character_sets() {
+        let opt = NfaOptions::default();
+        let tests = vec![
+            ("[\\d_]", vec!["1", "_", "٣"], vec!["a", "-", " "]),
+            ("[^\\w\\s]", vec!["-", "!"], vec!["a", "1", "_", " ", "\t", ""]),
+            ("[a\\d\\-]", vec!["a", "7", "-"], vec!["b", "\\"]),
+            ("[^\\d]", vec!["a", " "], vec!["1", ""]),
+            ("[\\D]", vec!["a", " "], vec!["1", ""]),
+            ("[\\]\\\\]", vec!["]", "\\"], vec!["a"]),
+            ("[a\\]b]", vec!["a", "]", "b"], vec!["\\", "c"]),
+            ("[a\\-z]", vec!["a", "-", "z"], vec!["m"]),
+            ("[\\--/]", vec!["-", ".", "/"], vec!["a", "\\"]),
+            ("[\\d_-]", vec!["1", "_", "-"], vec!["a", "\\"]),
+            ("[^\\s]", vec!["a", "-"], vec![" ", "\t", ""]),
+            ("[\\sx]", vec![" ", "x"], vec!["s", "y"]),
+            ("[x\\s]", vec![" ", "x"], vec!["s", "y"]),
+        ];
+
+        for (pattern, matching, not_matching) in tests {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            for example in matching {
+                assert!(nfa.find_match(example), "{pattern} should match {example:?}");
+            }
+            for example in not_matching {
+                assert!(!nfa.find_match(example), "{pattern} should not match {example:?}");
+            }
+        }
+
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            nfa.find_matches(text).iter().map(|m| (m.from, m.to)).collect()
+        };
+        assert_eq!(spans("[\\d_-]+", "id: 12_3-4 ab 5"), vec![(4, 10), (14, 15)]);
+        assert_eq!(spans("[^\\s]+", "é٣ x\ty"), vec![(0, 4), (5, 6), (7, 8)]);
+        assert_eq!(spans("[\\s\\d]+", "a 1 b"), vec![(1, 4)]);
+    }
+
+    #[test]
+    fn regex_to_nfa_ranges_in_character_sets() {
+        let opt = NfaOptions::default();
+        let tests = vec![
+            ("[a-z]", vec!["a", "m", "z"], vec!["A", "-", "0", "ź"]),
+            ("[a-zA-Z0-9_]", vec!["q", "Q", "5", "_"], vec!["-", " ", "ś"]),
+            ("[0-9A-F]", vec!["0", "9", "C"], vec!["c", "G", "-"]),
+            ("[-abc]", vec!["-", "b"], vec!["d"]),
+            ("[a-]", vec!["a", "-"], vec!["b"]),
+            ("[a-c-e]", vec!["b", "-", "e"], vec!["d"]),
+            ("[\\d-z]", vec!["1", "-", "z"], vec!["a"]),
+            ("[a\\-z]", vec!["a", "-", "z"], vec!["b"]),
+            ("[\\[-\\]]", vec!["[", "\\", "]"], vec!["a"]),
+            ("[^a-z]", vec!["A", "-", "0"], vec!["a", "m", "z"]),
+            ("[^a-zA-Z0-9_]", vec!["-", " ", "ś"], vec!["q", "Q", "5", "_"]),
+            ("[^-abc]", vec!["d"], vec!["-", "b"]),
+            ("[^a-]", vec!["b"], vec!["a", "-"]),
+        ];
+
+        for (pattern, matching, not_matching) in tests {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            for example in matching {
+                assert!(nfa.find_match(example), "{pattern} should match {example:?}");
+            }
+            for example in not_matching {
+                assert!(!nfa.find_match(example), "{pattern} should not match {example:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn regex_to_nfa_ranges_are_one_transition_and_fold_case() {
+        let ignore_case = NfaOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let nfa = regex_to_nfa("[a-zA-Z]", &NfaOptions::default()).unwrap();
+        assert_eq!(nfa.states.len(), 2);
+
+        let nfa = regex_to_nfa("[a-c]x", &ignore_case).unwrap();
+        assert!(nfa.find_match("BX"));
+        assert!(!nfa.find_match("DX"));
+    }
+
+    #[test]
+    fn regex_to_nfa_backward_ranges_are_errors() {
+        let opt = NfaOptions::default();
+
+        assert_eq!(
+            regex_to_nfa("[z-a]", &opt).err(),
+            Some(RegexError::InvalidRange { from: 'z', to: 'a', position: 1 })
+        );
+        assert_eq!(
+            regex_to_nfa("x[ab9-0]", &opt).unwrap_err().to_string(),
+            "the range '9-0' at position 4 ends before it starts"
+        );
+    }
+
+    #[test]
+    fn regex_to_nfa_anchors_in_character_sets_are_errors() {
+        let opt = NfaOptions::default();
+        let tests = vec![("[a\\b]", 'b', 2), ("x[\\Z]", 'Z', 2)];
+
+        for (pattern, escape, position) in tests {
+            let outcome = regex_to_nfa(pattern, &opt);
+            assert_eq!(outcome.err(), Some(RegexError::EscapeInSet { escape, position }));
+        }
+        assert_eq!(
+            regex_to_nfa("[\\f]", &opt).err(),
+            Some(RegexError::Unsupported {
+                construct: "\\f".to_string(),
+                position: 1,
+            })
+        );
+    }
+
     #[test]
     fn regex_to_nfa_alphanumeric() {
         let opt = NfaOptions::default();
         let nfa = alphanumeric(&opt);
-        let outcome = regex_to_nfa("\\w", &opt);
+        let outcome = regex_to_nfa("\\w", &opt).unwrap();
 
         let tests = vec!["0", "123", "aa", "", "a", "bb", "abababa"];
         for example in tests {
@@ -369,8 +2409,8 @@ mod tests {
     #[test]
     fn regex_to_nfa_digits() {
         let opt = NfaOptions::default();
-        let nfa = digits();
-        let outcome = regex_to_nfa("\\d", &opt);
+        let nfa = digit();
+        let outcome = regex_to_nfa("\\d", &opt).unwrap();
 
         let tests = vec!["0", "123", "aa", "", "a", "bb", "abababa"];
         for example in tests {
@@ -378,12 +2418,62 @@ mod tests {
         }
     }
 
+    #[test]
+    fn regex_to_nfa_digit_is_a_single_digit() {
+        let opt = NfaOptions::default();
+        let two = regex_to_nfa("\\d\\d", &opt).unwrap();
+        assert!(!two.find_match("7"));
+        assert!(two.find_match("77"));
+
+        let spans = |pattern: &str, text: &str| -> Vec<(usize, usize)> {
+            let nfa = regex_to_nfa(pattern, &opt).unwrap();
+            nfa.find_matches(text).into_iter().map(|m| (m.from, m.to)).collect()
+        };
+        assert_eq!(spans("\\d", "2023"), vec![(0, 1), (1, 2), (2, 3), (3, 4)]);
+        assert_eq!(spans("\\d\\d-\\d\\d", "on 2024-10-16"), vec![(5, 10)]);
+    }
+
+    #[test]
+    fn regex_to_nfa_case_flags() {
+        let opt = NfaOptions::default();
+        let ignore_case = NfaOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        let flagged = regex_to_nfa("(?i)abc", &opt).unwrap();
+        let folded = regex_to_nfa("abc", &ignore_case).unwrap();
+        for text in ["abc", "ABC", "aBc", "ab", "xAbCx", "a b c"] {
+            assert_eq!(flagged.find_matches(text), folded.find_matches(text), "{text}");
+        }
+
+        let tests = vec![
+            ("a(?i)b[cd]", &opt, vec!["aBc", "abD", "aBC"], vec!["Abc", "ABd"]),
+            ("(a(?i)b)c", &opt, vec!["aBc"], vec!["aBC", "ABc"]),
+            ("(?i)a(?-i)b", &opt, vec!["Ab", "ab"], vec!["aB"]),
+            ("(?-i)ab", &ignore_case, vec!["ab"], vec!["Ab", "aB"]),
+            ("(?i)^ab$", &opt, vec!["AB"], vec!["xab", "abx"]),
+            ("(?i)x|y", &opt, vec!["X", "Y"], vec!["z"]),
+            ("[(?i)]A", &opt, vec!["iA", "?A"], vec!["ia", "IA"]),
+        ];
+        for (pattern, options, matching, not_matching) in tests {
+            let nfa = regex_to_nfa(pattern, options).unwrap();
+            for text in matching {
+                assert!(nfa.find_match(text), "{pattern} should match {text}");
+            }
+            for text in not_matching {
+                assert!(!nfa.find_match(text), "{pattern} should not match {text}");
+            }
+        }
+    }
+
     #[test]
     fn regex_to_nfa_single_char_ignore_case() {
-        let mut opt = NfaOptions::default();
-        opt.ignore_case = true;
+        let opt = NfaOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
         let nfa = symbol('a', &opt);
-        let outcome = regex_to_nfa("a", &opt);
+        let outcome = regex_to_nfa("a", &opt).unwrap();
 
         let tests = vec!["aa", "", "a", "bb", "abababa", "A"];
         for example in tests {
@@ -395,7 +2485,7 @@ mod tests {
     fn regex_to_nfa_single_char() {
         let opt = NfaOptions::default();
         let nfa = symbol('a', &opt);
-        let outcome = regex_to_nfa("a", &opt);
+        let outcome = regex_to_nfa("a", &opt).unwrap();
 
         let tests = vec!["aa", "", "a", "bb", "abababa"];
         for example in tests {
@@ -405,11 +2495,12 @@ mod tests {
 
     #[test]
     fn regex_to_nfa_ignore_case() {
-        let mut opt = NfaOptions::default();
-        opt.ignore_case = true;
-        opt.ignore_case = true;
+        let opt = NfaOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
         let nfa = kleen(symbol('a', &opt));
-        let outcome = regex_to_nfa("a*", &opt);
+        let outcome = regex_to_nfa("a*", &opt).unwrap();
 
         let tests = vec!["a", "aa", "A", "aaa", "ab", "bbb"];
         for example in tests {
@@ -421,7 +2512,7 @@ mod tests {
     fn regex_to_nfa_kleen() {
         let opt = NfaOptions::default();
         let nfa = kleen(symbol('a', &opt));
-        let outcome = regex_to_nfa("a*", &opt);
+        let outcome = regex_to_nfa("a*", &opt).unwrap();
 
         let tests = vec!["a", "aa", "aaa", "ab", "bbb"];
         for example in tests {
@@ -432,7 +2523,7 @@ mod tests {
     #[test]
     fn regex_to_nfa_complex_2() {
         let opt = NfaOptions::default();
-        let outcome = regex_to_nfa("(0+11+10(00+1)*01)*", &opt);
+        let outcome = regex_to_nfa("(0+11+10(00+1)*01)*", &opt).unwrap();
         let nfa = kleen(union(
             symbol('0', &opt),
             union(
@@ -464,7 +2555,7 @@ mod tests {
             concat(symbol('a', &opt), symbol('b', &opt)),
             symbol('a', &opt),
         ));
-        let outcome = regex_to_nfa("(ab+a)*", &opt);
+        let outcome = regex_to_nfa("(ab|a)*", &opt).unwrap();
 
         let tests = vec!["ab", "", "aa", "ababab", "bbbaaa"];
         for example in tests {
@@ -0,0 +1,1750 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::num::NonZeroUsize;
+use std::ops::{ControlFlow, Range};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{thread, vec};
+
+use bolg::{glob, FilterDecision};
+use flate2::read::GzDecoder;
+
+use crate::error::PergError;
+use crate::matcher::{build_matchers, Engine, Match, Matcher};
+use crate::nfa::NfaOptions;
+use crate::progress::Progress;
+use crate::searcher::{LineEvent, LongLines, Searcher, MAX_LINE_LEN};
+
+/// A root of `-` searches standard input; its matches are reported under this path.
+pub const STDIN_PATH: &str = "<stdin>";
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchOptions {
+    pub pattern: NfaOptions,
+    pub engine: Engine,
+    /// More patterns searched for along with the one passed to the search;
+    /// a line matches if any of them does.
+    pub patterns: Vec<String>,
+    /// A line only matches if every pattern does.
+    pub all_match: bool,
+    /// Files under each root are searched when they match any of these globs;
+    /// with none, every file is.
+    pub globs: Vec<String>,
+    /// Roots are taken as they are, even with `*`, `?` or `[` in them;
+    /// otherwise such a root is a glob of its own. See [`search`].
+    pub literal_paths: bool,
+    /// Only files at most this many directories below a root are searched;
+    /// `None` means no limit.
+    pub max_depth: Option<usize>,
+    /// Directories below a root whose name or path below it matches one of
+    /// these globs are not walked into.
+    pub exclude_dirs: Vec<String>,
+    /// Directories on another file system than their root are not walked into.
+    pub one_file_system: bool,
+    /// Files and directories found under a root that these rules leave out
+    /// are not searched or walked into.
+    pub ignore: bolg::Ignore,
+    /// Files found under a root are only searched when their extension is in
+    /// this set, compared without case; `None` searches every file.
+    pub extensions: Option<HashSet<String>>,
+    /// Worker threads used by [`search_with`]; 0 picks one per available core.
+    pub threads: usize,
+    /// Lines are separated by NUL bytes instead of newlines.
+    pub null_data: bool,
+    /// Files ending in `.gz` are decompressed before searching.
+    pub search_zip: bool,
+    /// Invalid UTF-8 is replaced with U+FFFD instead of failing the file
+    /// with [`PergError::Encoding`].
+    pub text: bool,
+    pub binary: BinaryFiles,
+    pub mmap: MmapMode,
+    /// Files are searched through the standard output of this command, run
+    /// with the file's path as its only argument.
+    pub pre: Option<PathBuf>,
+    /// Only files matching one of these globs go through `pre`; empty means
+    /// every file does.
+    pub pre_globs: Vec<String>,
+    /// Stop searching a file after this many matches.
+    pub max_count: Option<usize>,
+    /// Files of at least this many bytes are searched a line at a time rather
+    /// than read whole; `None` means [`STREAM_THRESHOLD`].
+    pub stream_threshold: Option<u64>,
+    /// Lines longer than this many bytes are handled as `long_lines` says;
+    /// `None` means [`MAX_LINE_LEN`].
+    pub max_line_len: Option<usize>,
+    pub long_lines: LongLines,
+    /// Searching a file stops once it took this long; see
+    /// [`FileStats::timed_out`]. `None` means no limit.
+    pub timeout: Option<Duration>,
+    /// Only lines in these ranges of 0-based line numbers are matched; empty
+    /// means every line is. See [`Searcher::line_ranges`].
+    pub line_ranges: Vec<Range<usize>>,
+    pub sort: Sort,
+    pub path_display: PathDisplay,
+    /// Setting this flag stops the search early, e.g. from a Ctrl-C handler:
+    /// workers stop at their next match or file, as when the sink returns
+    /// `Break`. The search also sets it when the sink cancels.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Counts files and matches as workers get to them, for `--progress`.
+    pub progress: Option<Arc<Progress>>,
+}
+
+/// The order in which [`search_with`] delivers the events of different files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Sort {
+    /// As workers finish them; events of different files may interleave.
+    #[default]
+    None,
+    /// In the order files were found, which for a walk is sorted by path. The
+    /// events of each file arrive together.
+    Path,
+}
+
+/// What [`search_with`] does with files that look binary: those with a NUL
+/// byte in them or, when streamed, in the first block read. With
+/// `null_data` no file does. [`search`] yields the records of binary files
+/// unless they are skipped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BinaryFiles {
+    /// Count their matches without sending the lines, as grep prints
+    /// `Binary file ... matches`; see [`FileStats::binary`].
+    #[default]
+    Summary,
+    /// Search them like text.
+    Text,
+    /// Leave them out with [`PergError::Binary`].
+    Skip,
+}
+
+/// How the paths of searched files are reported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PathDisplay {
+    /// Relative to the working directory: as given, or with the working
+    /// directory cut off an absolute path below it.
+    #[default]
+    Relative,
+    /// Absolute, with `.` and `..` resolved; see [`bolg::lexical_absolute`].
+    Absolute,
+}
+
+/// Extensions perg searches by default: source code, scripts and text.
+pub const DEFAULT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "rst", "adoc", "tex", "csv", "tsv", "log", "json", "xml", "html", "htm", "css",
+    "scss", "rs", "c", "h", "cc", "cpp", "cxx", "hpp", "hh", "cs", "java", "kt", "scala", "go",
+    "py", "rb", "pl", "php", "js", "jsx", "ts", "tsx", "vue", "swift", "m", "mm", "lua", "hs",
+    "ml", "ex", "exs", "erl", "clj", "el", "vim", "r", "jl", "dart", "zig", "nim", "sql", "proto",
+    "sh", "bash", "zsh", "fish", "ps1", "bat", "cmake", "mk",
+];
+
+/// Files this large are streamed unless they are memory-mapped.
+pub const STREAM_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Whether files are memory-mapped instead of read into a buffer. Mapping
+/// needs the `mmap` feature; without it, or when mapping a file fails, perg
+/// reads the file instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MmapMode {
+    /// Map regular files of at least [`MMAP_THRESHOLD`] bytes.
+    #[default]
+    Auto,
+    Never,
+    /// Map every non-empty regular file.
+    Always,
+}
+
+/// Below this size reading a file is about as fast as mapping it.
+pub const MMAP_THRESHOLD: u64 = 4 * 1024 * 1024;
+
+impl SearchOptions {
+    /// Compiles `pattern` together with `patterns`.
+    fn matcher(&self, pattern: &str) -> Result<Arc<dyn Matcher>, PergError> {
+        let patterns: Vec<&str> = std::iter::once(pattern)
+            .chain(self.patterns.iter().map(String::as_str))
+            .collect();
+        let started = Instant::now();
+        let matcher = build_matchers(&patterns, &self.pattern, self.engine, self.all_match)?;
+        crate::info!("compiled {} pattern(s) in {:?}", patterns.len(), started.elapsed());
+        Ok(matcher)
+    }
+
+    fn searcher(&self) -> Searcher {
+        let terminator = if self.null_data { b'\0' } else { b'\n' };
+        Searcher::new()
+            .terminator(terminator)
+            .context(self.pattern.context as usize)
+            .max_count(self.max_count)
+            .max_line_len(self.max_line_len.unwrap_or(MAX_LINE_LEN), self.long_lines)
+            .lossy(self.text)
+            .timeout(self.timeout)
+            .line_ranges(self.line_ranges.clone())
+    }
+
+    /// Whether `contents` count as binary; see [`BinaryFiles`].
+    fn looks_binary(&self, contents: &mut Contents) -> bool {
+        !self.null_data && contents.is_binary()
+    }
+
+    /// Whether `path` is searched through the `pre` command.
+    fn is_preprocessed(&self, path: &Path) -> bool {
+        self.pre.is_some()
+            && path != Path::new("-")
+            && (self.pre_globs.is_empty()
+                || self
+                    .pre_globs
+                    .iter()
+                    .any(|glob| bolg::matches(glob, path).unwrap_or(false)))
+    }
+
+    fn is_compressed(&self, path: &Path) -> bool {
+        self.search_zip && path.extension().is_some_and(|ext| ext == "gz")
+    }
+
+    /// Whether `extensions` lets a walk search `path`. A compressed file is
+    /// judged by the extension under `.gz`; a preprocessed file always passes,
+    /// as the command turns it into text.
+    fn has_allowed_extension(&self, path: &Path) -> bool {
+        let Some(extensions) = &self.extensions else {
+            return true;
+        };
+        if self.is_preprocessed(path) {
+            return true;
+        }
+        let path = match self.is_compressed(path) {
+            true => Path::new(path.file_stem().unwrap_or_default()),
+            false => path,
+        };
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| extensions.contains(&ext.to_lowercase()))
+    }
+}
+
+/// One match, flattened together with everything needed to display it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatchRecord {
+    pub path: PathBuf,
+    /// 1-based line number.
+    pub line_number: usize,
+    /// 1-based byte column of the start of the match.
+    pub column: usize,
+    /// Byte span of the match within `line`.
+    pub span: (usize, usize),
+    /// 0-based byte offset of the start of the match in the searched input;
+    /// for compressed or preprocessed files, in what was searched.
+    pub offset: usize,
+    pub line: String,
+    /// Which pattern matched; see [`Match::pattern`].
+    pub pattern: usize,
+}
+
+impl MatchRecord {
+    /// The match within its line, with the 0-based line index.
+    pub fn to_match(&self) -> Match {
+        Match {
+            from: self.span.0,
+            to: self.span.1,
+            line: self.line_number - 1,
+            errors: 0,
+            pattern: self.pattern,
+        }
+    }
+
+    /// The text the pattern matched.
+    pub fn matched(&self) -> &str {
+        self.line.get(self.span.0..self.span.1).unwrap_or_default()
+    }
+}
+
+/// Reads a whole file as UTF-8 text.
+pub fn read_file(path: &Path) -> Result<String, PergError> {
+    let bytes = fs::read(path).map_err(|err| PergError::io(path, err))?;
+    decode(path, bytes, false)
+}
+
+/// `bytes` as text, with invalid UTF-8 replaced when `lossy`.
+fn decode(path: &Path, bytes: Vec<u8>, lossy: bool) -> Result<String, PergError> {
+    match String::from_utf8(bytes) {
+        Ok(text) => Ok(text),
+        Err(err) if lossy => Ok(String::from_utf8_lossy(err.as_bytes()).into_owned()),
+        Err(_) => Err(PergError::Encoding {
+            path: path.to_path_buf(),
+        }),
+    }
+}
+
+/// The contents of a file, ready to be searched.
+enum Contents<'a> {
+    Read(String),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+    /// Searched a line at a time: standard input, decompressed files and files
+    /// of at least the stream threshold.
+    Stream(Box<dyn io::BufRead + 'a>),
+    /// Streamed from the `pre` command.
+    Preprocessed(BufReader<Preprocessed>),
+}
+
+impl Contents<'_> {
+    fn open(path: &Path, options: &SearchOptions) -> Result<Self, PergError> {
+        if path == Path::new("-") {
+            return Ok(Self::Stream(Box::new(io::stdin().lock())));
+        }
+
+        if let Some(command) = options
+            .pre
+            .as_deref()
+            .filter(|_| options.is_preprocessed(path))
+        {
+            return Preprocessed::spawn(command, path)
+                .map(|output| Self::Preprocessed(BufReader::new(output)));
+        }
+
+        let mut file = File::open(path).map_err(|err| PergError::io(path, err))?;
+        if options.is_compressed(path) {
+            return Ok(Self::Stream(Box::new(BufReader::new(GzDecoder::new(file)))));
+        }
+
+        #[cfg(feature = "mmap")]
+        if let Some(map) = map_file(path, &file, options.mmap)? {
+            return Ok(Self::Mapped(map));
+        }
+
+        let len = file.metadata().map_err(|err| PergError::io(path, err))?.len();
+        if len >= options.stream_threshold.unwrap_or(STREAM_THRESHOLD) {
+            return Ok(Self::Stream(Box::new(BufReader::new(file))));
+        }
+
+        let mut bytes = Vec::with_capacity(len as usize);
+        file.read_to_end(&mut bytes)
+            .map_err(|err| PergError::io(path, err))?;
+        decode(path, bytes, options.text).map(Self::Read)
+    }
+
+    /// Whether the contents hold a NUL byte; a stream is only checked as far
+    /// as its first block.
+    fn is_binary(&mut self) -> bool {
+        match self {
+            Self::Read(text) => text.as_bytes().contains(&0),
+            #[cfg(feature = "mmap")]
+            Self::Mapped(map) => map.contains(&0),
+            Self::Stream(reader) => reader.fill_buf().is_ok_and(|block| block.contains(&0)),
+            Self::Preprocessed(reader) => reader.fill_buf().is_ok_and(|block| block.contains(&0)),
+        }
+    }
+
+    /// Runs `searcher` over the contents and returns how many bytes it read.
+    /// Mapped files are checked for UTF-8 here and streams as they are read,
+    /// so both fail like [`read_file`] does.
+    fn search<F>(
+        self,
+        path: &Path,
+        searcher: Searcher,
+        matcher: &dyn Matcher,
+        sink: F,
+    ) -> Result<(usize, ControlFlow<()>), PergError>
+    where
+        F: FnMut(LineEvent) -> ControlFlow<()>,
+    {
+        let text = match self {
+            Self::Read(text) => text,
+            #[cfg(feature = "mmap")]
+            Self::Mapped(map) => {
+                let text = match std::str::from_utf8(&map) {
+                    Ok(text) => std::borrow::Cow::Borrowed(text),
+                    Err(_) if searcher.is_lossy() => String::from_utf8_lossy(&map),
+                    Err(_) => {
+                        return Err(PergError::Encoding {
+                            path: path.to_path_buf(),
+                        })
+                    }
+                };
+                return Ok((map.len(), searcher.search_str(matcher, &text, sink)));
+            }
+            Self::Stream(reader) => {
+                return search_stream(reader, searcher, matcher, sink, |err| {
+                    stream_error(path, err)
+                })
+            }
+            Self::Preprocessed(reader) => {
+                return search_stream(reader, searcher, matcher, sink, |err| match err.kind() {
+                    io::ErrorKind::InvalidData => stream_error(path, err),
+                    _ => PergError::Preprocessor {
+                        path: path.to_path_buf(),
+                        reason: err.to_string(),
+                    },
+                })
+            }
+        };
+
+        Ok((text.len(), searcher.search_str(matcher, &text, sink)))
+    }
+}
+
+fn search_stream<R, F>(
+    reader: R,
+    searcher: Searcher,
+    matcher: &dyn Matcher,
+    sink: F,
+    failed: impl FnOnce(io::Error) -> PergError,
+) -> Result<(usize, ControlFlow<()>), PergError>
+where
+    R: io::BufRead,
+    F: FnMut(LineEvent) -> ControlFlow<()>,
+{
+    let mut reader = Counted {
+        inner: reader,
+        bytes: 0,
+    };
+    let flow = searcher
+        .search_reader(matcher, &mut reader, sink)
+        .map_err(failed)?;
+    Ok((reader.bytes, flow))
+}
+
+/// The standard output of a `pre` command run on one file. Reading past its
+/// end waits for the command, and fails if the command did. Each worker runs
+/// at most one command at a time; one dropped early is killed, so a search
+/// stopped part way leaves no processes behind.
+struct Preprocessed {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Preprocessed {
+    fn spawn(command: &Path, path: &Path) -> Result<Self, PergError> {
+        let failed = |reason: String| PergError::Preprocessor {
+            path: path.to_path_buf(),
+            reason,
+        };
+        // The command must not read perg's standard input, which may be a
+        // file list, nor write over the output.
+        let mut child = Command::new(command)
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| failed(format!("cannot run {}: {err}", command.display())))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| failed("no output".to_string()))?;
+        Ok(Self { child, stdout })
+    }
+}
+
+impl Read for Preprocessed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("command {status}")));
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for Preprocessed {
+    fn drop(&mut self) {
+        // Fails harmlessly if the command already exited.
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Maps `path` when `mode` asks for it, or returns `None` to have it read.
+///
+/// A mapped file that is truncated while it is being searched makes the OS
+/// raise SIGBUS (or an access violation on Windows) when perg touches the
+/// missing pages, which kills the process. Output is written a whole line at
+/// a time from the printing thread, so what was printed before stays intact.
+#[cfg(feature = "mmap")]
+fn map_file(path: &Path, file: &File, mode: MmapMode) -> Result<Option<memmap2::Mmap>, PergError> {
+    let threshold = match mode {
+        MmapMode::Never => return Ok(None),
+        MmapMode::Auto => MMAP_THRESHOLD,
+        MmapMode::Always => 1,
+    };
+
+    let metadata = file.metadata().map_err(|err| PergError::io(path, err))?;
+    if !metadata.is_file() || metadata.len() < threshold {
+        return Ok(None);
+    }
+
+    // SAFETY: perg never writes through the map; see above for what happens
+    // when another process truncates the file.
+    Ok(unsafe { memmap2::Mmap::map(file) }.ok())
+}
+
+/// A line printed around a match, see [`NfaOptions::context`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContextLine {
+    pub path: PathBuf,
+    /// 1-based line number.
+    pub line_number: usize,
+    pub line: String,
+}
+
+/// Everything a sink is told about a search. Events for one file always arrive
+/// in order (`FileStart`, its `Match`es and `Context` lines by line number,
+/// `FileEnd`), but events for different files may interleave. A file that
+/// fails part way through gets an `Error` instead of its `FileEnd`.
+#[derive(Debug)]
+pub enum SearchEvent {
+    FileStart(PathBuf),
+    Match(MatchRecord),
+    Context(ContextLine),
+    FileEnd(PathBuf, FileStats),
+    Error(PergError),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FileStats {
+    pub matches: usize,
+    /// Lines with a match, each counted once however many matches it has.
+    pub lines: usize,
+    /// Bytes read; for compressed files, after decompression. Streamed files
+    /// stop being read once `max_count` is reached.
+    pub bytes: usize,
+    /// Lines over the line length cap, skipped or truncated.
+    pub long_lines: usize,
+    /// The file looked binary, so its matches were only counted: no `Match`
+    /// or `Context` events were sent for it. See [`BinaryFiles::Summary`].
+    pub binary: bool,
+    /// The search ran out of `timeout` and stopped part way; the matches
+    /// found before were sent.
+    pub timed_out: bool,
+}
+
+/// The glob a walk uses when none are given: it matches every path.
+const ALL_FILES: &str = "*";
+
+/// Hands out the files under every `(root, glob, relative)` triple, one at a
+/// time; the files of a relative walk are yielded as paths below its root.
+pub(crate) struct Walk {
+    stdin: bool,
+    walks: VecDeque<(PathBuf, String, bool)>,
+    /// The files handed out so far, when a file could be found by more than
+    /// one walk.
+    seen: Option<HashSet<PathBuf>>,
+    options: SearchOptions,
+    files: Option<bolg::Paths>,
+    /// What the files of the current walk are joined onto, when they are
+    /// yielded relative to its root.
+    prefix: Option<PathBuf>,
+}
+
+impl Walk {
+    pub(crate) fn new<P: AsRef<Path>>(roots: &[P], options: &SearchOptions) -> Self {
+        let is_stdin = |root: &&P| root.as_ref() == Path::new("-");
+        let stdin = roots.iter().any(|root| is_stdin(&root));
+        let globs = match options.globs.is_empty() {
+            true => &[ALL_FILES.to_string()][..],
+            false => &options.globs[..],
+        };
+        let mut walks = VecDeque::new();
+        for root in roots.iter().filter(|root| !is_stdin(root)) {
+            let root = root.as_ref();
+            let split = root.to_str().and_then(bolg::split_glob).filter(|_| !options.literal_paths);
+            match split {
+                // The root's own glob comes first, then those of -g. Like a
+                // shell, `*/*.md` gives `docs/a.md`, not `./docs/a.md`.
+                Some((dir, pattern)) => walks.extend(
+                    std::iter::once(pattern)
+                        .chain(options.globs.iter().cloned())
+                        .map(|pattern| (PathBuf::from(dir), pattern, dir == ".")),
+                ),
+                None => walks.extend(
+                    globs
+                        .iter()
+                        .map(|pattern| (root.to_path_buf(), pattern.clone(), false)),
+                ),
+            }
+        }
+        let roots = roots.iter().filter(|root| !is_stdin(root)).count();
+
+        Self {
+            stdin,
+            seen: (walks.len() > roots).then(HashSet::new),
+            walks,
+            options: options.clone(),
+            files: None,
+            prefix: None,
+        }
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Result<PathBuf, PergError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if std::mem::take(&mut self.stdin) {
+            return Some(Ok(PathBuf::from("-")));
+        }
+
+        loop {
+            if let Some(path) = self.files.as_mut().and_then(|files| files.next()) {
+                let path = match &self.prefix {
+                    Some(prefix) => prefix.join(path),
+                    None => path,
+                };
+                if self.seen.as_mut().is_some_and(|seen| !seen.insert(path.clone())) {
+                    continue;
+                }
+                return Some(Ok(path));
+            }
+
+            let (root, pattern, relative) = self.walks.pop_front()?;
+            crate::debug!("walking {} for '{pattern}'", root.display());
+            match glob(&pattern, &root) {
+                Ok(files) => {
+                    // Files named by the user are not filtered: a walk never
+                    // asks about its root.
+                    // An absolute root below the working directory is
+                    // shown from there, as a relative one is.
+                    self.prefix = match self.options.path_display {
+                        PathDisplay::Relative if files.root().is_absolute() => {
+                            below_working_dir(files.root())
+                        }
+                        _ => None,
+                    };
+                    let options = self.options.clone();
+                    let files = files
+                        .relative(relative || self.prefix.is_some())
+                        .max_depth(self.options.max_depth)
+                        .exclude_dirs(&self.options.exclude_dirs)
+                        .one_file_system(self.options.one_file_system)
+                        .ignore(self.options.ignore.clone())
+                        .with_filter(move |path, meta| {
+                            match meta.is_file() && !options.has_allowed_extension(path) {
+                                true => {
+                                    crate::debug!(
+                                        "{}: skipped, extension not searched",
+                                        path.display()
+                                    );
+                                    FilterDecision::SkipFile
+                                }
+                                false => FilterDecision::Keep,
+                            }
+                        });
+                    self.files = Some(files);
+                }
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+    }
+}
+
+/// `dir` relative to the working directory, if it is below it.
+fn below_working_dir(dir: &Path) -> Option<PathBuf> {
+    let dir = bolg::lexical_absolute(dir).ok()?;
+    let cwd = env::current_dir().ok()?;
+    dir.strip_prefix(cwd).ok().map(Path::to_path_buf)
+}
+
+/// The path matches are reported under; worked out once per file.
+fn display_path(path: PathBuf, display: PathDisplay) -> PathBuf {
+    if path == Path::new("-") {
+        return PathBuf::from(STDIN_PATH);
+    }
+    match display {
+        // Walks yield such paths relative already; files named in a list
+        // or on the command line are cut here.
+        PathDisplay::Relative if path.is_absolute() => env::current_dir()
+            .ok()
+            .and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf))
+            .filter(|relative| !relative.as_os_str().is_empty())
+            .unwrap_or(path),
+        PathDisplay::Relative => path,
+        PathDisplay::Absolute => bolg::lexical_absolute(&path).unwrap_or(path),
+    }
+}
+
+fn record(path: &Path, m: Match, line: &str, line_offset: usize) -> MatchRecord {
+    MatchRecord {
+        path: path.to_path_buf(),
+        line_number: m.line + 1,
+        column: m.from + 1,
+        span: (m.from, m.to),
+        offset: line_offset + m.from,
+        line: line.to_string(),
+        pattern: m.pattern,
+    }
+}
+
+/// Counts the bytes consumed from a stream.
+struct Counted<R> {
+    inner: R,
+    bytes: usize,
+}
+
+impl<R: io::Read> io::Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes += read;
+        Ok(read)
+    }
+}
+
+impl<R: io::BufRead> io::BufRead for Counted<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.bytes += amount;
+        self.inner.consume(amount);
+    }
+}
+
+/// Maps a failure while streaming `path` onto the errors of the whole-file path.
+fn stream_error(path: &Path, err: io::Error) -> PergError {
+    match err.kind() {
+        io::ErrorKind::InvalidData => PergError::Encoding {
+            path: path.to_path_buf(),
+        },
+        _ => PergError::io(path, err),
+    }
+}
+
+/// Lazily walks the roots and yields every match in every file. Problems with
+/// a single root or file are yielded as `Err` items and the search carries on.
+pub struct SearchIter {
+    matcher: Arc<dyn Matcher>,
+    options: SearchOptions,
+    walk: Walk,
+    pending: vec::IntoIter<Result<MatchRecord, PergError>>,
+}
+
+impl SearchIter {
+    fn search_file(&self, path: PathBuf) -> Vec<Result<MatchRecord, PergError>> {
+        let mut contents = match Contents::open(&path, &self.options) {
+            Ok(contents) => contents,
+            Err(err) => return vec![Err(err)],
+        };
+        let path = display_path(path, self.options.path_display);
+        if self.options.binary == BinaryFiles::Skip && self.options.looks_binary(&mut contents) {
+            return vec![Err(PergError::Binary { path })];
+        }
+
+        // Records carry their line, so context is not needed here.
+        let searcher = self.options.searcher().context(0);
+        let mut records = vec![];
+        let result = contents.search(&path, searcher, self.matcher.as_ref(), |event| {
+            if let LineEvent::Match(m, line, offset) = event {
+                records.push(Ok(record(&path, m, line, offset)));
+            }
+            ControlFlow::Continue(())
+        });
+
+        if let Err(err) = result {
+            records.push(Err(err));
+        }
+        records
+    }
+}
+
+impl Iterator for SearchIter {
+    type Item = Result<MatchRecord, PergError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(record) = self.pending.next() {
+                return Some(record);
+            }
+
+            match self.walk.next()? {
+                Ok(path) => self.pending = self.search_file(path).into_iter(),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+/// Searches every file under `roots` matching one of `options.globs`.
+///
+/// A root with `*`, `?` or `[` in it is a glob itself, unless
+/// `options.literal_paths`: the directories it starts with are walked for
+/// the files it matches, and for those matching `options.globs`; see
+/// [`bolg::split_glob`].
+///
+/// Only compiling the pattern can fail up front; everything else is reported
+/// through the iterator.
+pub fn search<P: AsRef<Path>>(
+    pattern: &str,
+    roots: &[P],
+    options: &SearchOptions,
+) -> Result<SearchIter, PergError> {
+    let matcher = options.matcher(pattern)?;
+
+    Ok(SearchIter {
+        matcher,
+        options: options.clone(),
+        walk: Walk::new(roots, options),
+        pending: vec![].into_iter(),
+    })
+}
+
+/// Reads a list of paths, one per `separator`-terminated entry, as produced by
+/// `find` or `git diff --name-only`. Blank entries are skipped; `name` is used
+/// to report read errors.
+pub fn file_list<R>(
+    name: impl Into<PathBuf>,
+    reader: R,
+    separator: u8,
+) -> impl Iterator<Item = Result<PathBuf, PergError>> + Send
+where
+    R: BufRead + Send,
+{
+    let name = name.into();
+    reader.split(separator).filter_map(move |entry| {
+        let mut entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => return Some(Err(PergError::io(&name, err))),
+        };
+        if separator == b'\n' && entry.last() == Some(&b'\r') {
+            entry.pop();
+        }
+        if entry.iter().all(u8::is_ascii_whitespace) {
+            return None;
+        }
+        Some(Ok(path_from_bytes(entry)))
+    })
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Events a worker may queue before it blocks waiting for the sink.
+const EVENT_BUFFER: usize = 256;
+
+/// The files a search goes through, numbered in the order they were found;
+/// `Err` items are reported and skipped.
+type Files<'a> = dyn Iterator<Item = (usize, Result<PathBuf, PergError>)> + Send + 'a;
+
+/// What workers send to the sink's thread.
+enum Message {
+    /// An event of the file with this index.
+    Event(usize, SearchEvent),
+    /// The file with this index has no more events.
+    Done(usize),
+}
+
+/// Holds back the events of files that finished out of order, so the sink
+/// sees file `i` only after files `0..i`. The events of the earliest
+/// unfinished file pass straight through; the others are kept until it is
+/// done. Only lines that will be printed are held, never file contents.
+struct Reorder<F> {
+    sink: F,
+    next: usize,
+    /// Events of later files and whether each has finished.
+    held: BTreeMap<usize, (Vec<SearchEvent>, bool)>,
+}
+
+impl<F: FnMut(SearchEvent) -> ControlFlow<()>> Reorder<F> {
+    fn new(sink: F) -> Self {
+        Self { sink, next: 0, held: BTreeMap::new() }
+    }
+
+    fn push(&mut self, message: Message) -> ControlFlow<()> {
+        match message {
+            Message::Event(index, event) if index == self.next => (self.sink)(event),
+            Message::Event(index, event) => {
+                self.held.entry(index).or_default().0.push(event);
+                ControlFlow::Continue(())
+            }
+            Message::Done(index) if index == self.next => self.advance(),
+            Message::Done(index) => {
+                self.held.entry(index).or_default().1 = true;
+                ControlFlow::Continue(())
+            }
+        }
+    }
+
+    /// Moves past the finished file `next`, flushing whatever later files
+    /// have buffered up to the first one still running.
+    fn advance(&mut self) -> ControlFlow<()> {
+        loop {
+            self.next += 1;
+            let Some((events, done)) = self.held.remove(&self.next) else {
+                return ControlFlow::Continue(());
+            };
+            for event in events {
+                (self.sink)(event)?;
+            }
+            if !done {
+                return ControlFlow::Continue(());
+            }
+        }
+    }
+}
+
+/// Shared by the workers of one [`search_with`] call.
+struct Worker<'a> {
+    /// Tells the workers apart in the log.
+    id: usize,
+    matcher: &'a dyn Matcher,
+    options: &'a SearchOptions,
+    files: &'a Mutex<Files<'a>>,
+    cancelled: &'a AtomicBool,
+    events: SyncSender<Message>,
+}
+
+impl Worker<'_> {
+    /// Sends `message` unless the search was cancelled; `Break` means stop working.
+    fn send(&self, message: Message) -> ControlFlow<()> {
+        if self.cancelled.load(Ordering::Relaxed) || self.events.send(message).is_err() {
+            return ControlFlow::Break(());
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn run(&self) {
+        let started = Instant::now();
+        let mut searched = 0;
+        self.work(&mut searched);
+        crate::debug!("worker {}: searched {searched} file(s) in {:?}", self.id, started.elapsed());
+    }
+
+    /// Takes files until there are none left or the search is cancelled,
+    /// counting them in `searched`.
+    fn work(&self, searched: &mut usize) {
+        while !self.cancelled.load(Ordering::Relaxed) {
+            let next = match self.files.lock() {
+                Ok(mut files) => files.next(),
+                Err(_) => return,
+            };
+
+            let Some((index, file)) = next else {
+                return;
+            };
+            *searched += 1;
+            let flow = match file {
+                Ok(path) => {
+                    if let Some(progress) = &self.options.progress {
+                        progress.start_file(&path);
+                    }
+                    self.search_file(index, path)
+                }
+                Err(err) => self.event(index, SearchEvent::Error(err)),
+            };
+
+            if flow.is_break() || self.send(Message::Done(index)).is_break() {
+                return;
+            }
+        }
+    }
+
+    fn event(&self, index: usize, event: SearchEvent) -> ControlFlow<()> {
+        self.send(Message::Event(index, event))
+    }
+
+    fn search_file(&self, index: usize, path: PathBuf) -> ControlFlow<()> {
+        let mut contents = match Contents::open(&path, self.options) {
+            Ok(contents) => contents,
+            Err(err) => return self.event(index, SearchEvent::Error(err)),
+        };
+        let path = display_path(path, self.options.path_display);
+
+        let mut stats = FileStats::default();
+        if self.options.binary != BinaryFiles::Text && self.options.looks_binary(&mut contents) {
+            if self.options.binary == BinaryFiles::Skip {
+                crate::debug!("{}: skipped, binary file", path.display());
+                return self.event(index, SearchEvent::Error(PergError::Binary { path }));
+            }
+            stats.binary = true;
+        }
+        self.event(index, SearchEvent::FileStart(path.clone()))?;
+
+        let mut last_line = None;
+        let result = contents.search(&path, self.options.searcher(), self.matcher, |event| {
+            if let LineEvent::Match(m, ..) = &event {
+                stats.matches += 1;
+                stats.lines += usize::from(last_line.replace(m.line) != Some(m.line));
+            }
+            match event {
+                LineEvent::Match(..) if stats.binary => ControlFlow::Continue(()),
+                LineEvent::Match(m, line, offset) => {
+                    self.event(index, SearchEvent::Match(record(&path, m, line, offset)))
+                }
+                LineEvent::LongLine(_) => {
+                    stats.long_lines += 1;
+                    ControlFlow::Continue(())
+                }
+                LineEvent::TimedOut(_) => {
+                    stats.timed_out = true;
+                    ControlFlow::Continue(())
+                }
+                LineEvent::Context(..) if stats.binary => ControlFlow::Continue(()),
+                LineEvent::Context(number, line) => self.event(
+                    index,
+                    SearchEvent::Context(ContextLine {
+                        path: path.clone(),
+                        line_number: number + 1,
+                        line: line.to_string(),
+                    }),
+                ),
+            }
+        });
+
+        match result {
+            Ok((bytes, flow)) => {
+                flow?;
+                stats.bytes = bytes;
+                if let Some(progress) = &self.options.progress {
+                    progress.end_file(stats.matches);
+                }
+                self.event(index, SearchEvent::FileEnd(path, stats))
+            }
+            Err(err) => self.event(index, SearchEvent::Error(err)),
+        }
+    }
+}
+
+/// Searches like [`search`], but pushes [`SearchEvent`]s to `sink` as worker
+/// threads produce them. The sink runs on the calling thread; returning
+/// `ControlFlow::Break` cancels the search, and workers stop at the next match
+/// or file boundary.
+///
+/// Returns `Break` if the sink or `options.cancel` cancelled the search.
+pub fn search_with<P, F>(
+    pattern: &str,
+    roots: &[P],
+    options: &SearchOptions,
+    sink: F,
+) -> Result<ControlFlow<()>, PergError>
+where
+    P: AsRef<Path>,
+    F: FnMut(SearchEvent) -> ControlFlow<()>,
+{
+    search_files_with(pattern, Walk::new(roots, options), options, sink)
+}
+
+/// Like [`search_with`], but searches exactly the paths `files` yields instead
+/// of walking roots with `options.globs`. A path of `-` is standard input, and
+/// `Err` items are passed on to the sink as `Error` events.
+pub fn search_files_with<I, F>(
+    pattern: &str,
+    files: I,
+    options: &SearchOptions,
+    mut sink: F,
+) -> Result<ControlFlow<()>, PergError>
+where
+    I: Iterator<Item = Result<PathBuf, PergError>> + Send,
+    F: FnMut(SearchEvent) -> ControlFlow<()>,
+{
+    let matcher = options.matcher(pattern)?;
+    let files = Mutex::new(files.enumerate());
+    let cancelled = options.cancel.clone().unwrap_or_default();
+
+    let threads = match options.threads {
+        0 => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        n => n,
+    };
+
+    crate::info!("searching with {threads} worker thread(s)");
+
+    thread::scope(|scope| {
+        let (events, received) = sync_channel(EVENT_BUFFER);
+
+        for id in 0..threads {
+            let worker = Worker {
+                id,
+                matcher: matcher.as_ref(),
+                options,
+                files: &files,
+                cancelled: &cancelled,
+                events: events.clone(),
+            };
+            thread::Builder::new()
+                .spawn_scoped(scope, move || worker.run())
+                .map_err(|err| PergError::Worker(err.to_string()))?;
+        }
+        drop(events);
+
+        let mut reorder = Reorder::new(&mut sink);
+        // Returning drops `received`, which wakes any worker blocked on a full
+        // buffer before the scope joins it.
+        while let Ok(message) = received.recv() {
+            let flow = match (options.sort, message) {
+                (Sort::Path, message) => reorder.push(message),
+                (Sort::None, Message::Event(_, event)) => (reorder.sink)(event),
+                (Sort::None, Message::Done(_)) => ControlFlow::Continue(()),
+            };
+            if flow.is_break() {
+                cancelled.store(true, Ordering::Relaxed);
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+
+        match cancelled.load(Ordering::Relaxed) {
+            true => Ok(ControlFlow::Break(())),
+            false => Ok(ControlFlow::Continue(())),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(globs: &[&str]) -> SearchOptions {
+        SearchOptions {
+            globs: globs.iter().map(|g| g.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn display_path_follows_the_option() {
+        let cwd = env::current_dir().unwrap();
+        let below = cwd.join("src/lib.rs");
+
+        assert_eq!(
+            display_path(below.clone(), PathDisplay::Relative),
+            PathBuf::from("src/lib.rs")
+        );
+        assert_eq!(
+            display_path("../x".into(), PathDisplay::Relative),
+            PathBuf::from("../x")
+        );
+        assert_eq!(
+            display_path("/elsewhere".into(), PathDisplay::Relative),
+            PathBuf::from("/elsewhere")
+        );
+        assert_eq!(
+            display_path("./src/lib.rs".into(), PathDisplay::Absolute),
+            below
+        );
+        assert_eq!(
+            display_path("-".into(), PathDisplay::Absolute),
+            PathBuf::from(STDIN_PATH)
+        );
+    }
+
+    #[test]
+    fn search_yields_flat_records() {
+        let records: Vec<MatchRecord> = search("abba", &["../../test_files"], &options(&["*.txt"]))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert!(record.path.ends_with("random.txt"));
+        assert_eq!(record.line_number, 1);
+        assert_eq!(record.column, 1);
+        assert_eq!(record.span, (0, 4));
+        assert!(record.line.starts_with("abba "));
+    }
+
+    #[test]
+    fn search_reports_errors_as_items_and_continues() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), [b'x', 0xFF]).unwrap();
+        fs::write(dir.path().join("b.txt"), "xyz\n").unwrap();
+
+        let roots = [dir.path().join("missing"), dir.path().to_path_buf()];
+        let items: Vec<_> = search("x", &roots, &options(&["*.txt"])).unwrap().collect();
+
+        assert_eq!(items.len(), 3);
+        assert!(matches!(items[0], Err(PergError::Glob(_))));
+        assert!(matches!(items[1], Err(PergError::Encoding { .. })));
+        assert!(items[2].as_ref().is_ok_and(|r| r.path.ends_with("b.txt")));
+    }
+
+    #[test]
+    fn search_with_reports_file_events_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "xyz\nabc x\n").unwrap();
+
+        let mut events = vec![];
+        let flow = search_with("x", &[dir.path()], &options(&["*.txt"]), |event| {
+            events.push(event);
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+
+        assert!(flow.is_continue());
+        assert_eq!(events.len(), 4);
+        assert!(matches!(&events[0], SearchEvent::FileStart(p) if p.ends_with("a.txt")));
+        assert!(matches!(&events[1], SearchEvent::Match(r) if r.line_number == 1));
+        assert!(matches!(&events[2], SearchEvent::Match(r) if r.line_number == 2));
+        assert!(matches!(
+            &events[3],
+            SearchEvent::FileEnd(_, FileStats { matches: 2, bytes: 10, .. })
+        ));
+    }
+
+    #[test]
+    fn search_with_only_counts_the_matches_of_binary_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "x\0\nabc x\n").unwrap();
+        let run = |binary| {
+            let mut events = vec![];
+            let options = SearchOptions {
+                binary,
+                ..options(&["*.txt"])
+            };
+            let flow = search_with("x", &[dir.path()], &options, |event| {
+                events.push(event);
+                ControlFlow::Continue(())
+            });
+            assert!(flow.unwrap().is_continue());
+            events
+        };
+
+        let summary = run(BinaryFiles::Summary);
+        assert_eq!(summary.len(), 2);
+        assert!(matches!(
+            &summary[1],
+            SearchEvent::FileEnd(_, FileStats { matches: 2, binary: true, .. })
+        ));
+        assert_eq!(run(BinaryFiles::Text).len(), 4);
+        assert!(matches!(
+            &run(BinaryFiles::Skip)[..],
+            [SearchEvent::Error(PergError::Binary { .. })]
+        ));
+    }
+
+    #[test]
+    fn search_with_cancels_mid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.txt", "b.txt"] {
+            fs::write(dir.path().join(name), "x\n".repeat(10_000)).unwrap();
+        }
+
+        let mut options = options(&["*.txt"]);
+        options.threads = 2;
+
+        let mut matches = 0;
+        let mut ended = 0;
+        let flow = search_with("x", &[dir.path()], &options, |event| {
+            match event {
+                SearchEvent::Match(_) => matches += 1,
+                SearchEvent::FileEnd(..) => ended += 1,
+                _ => {}
+            }
+            if matches == 3 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .unwrap();
+
+        assert!(flow.is_break());
+        assert_eq!(matches, 3);
+        assert_eq!(ended, 0);
+    }
+
+    #[test]
+    fn search_with_updates_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "x\nx\n").unwrap();
+        fs::write(dir.path().join("b.txt"), "y\n").unwrap();
+
+        let progress = Arc::new(Progress::default());
+        let options = SearchOptions {
+            progress: Some(progress.clone()),
+            ..options(&["*.txt"])
+        };
+        let _ = search_with("x", &[dir.path()], &options, |_| ControlFlow::Continue(())).unwrap();
+
+        assert!(progress.status().starts_with("2 files found, 2 searched, 2 matches: "));
+    }
+
+    #[test]
+    fn search_with_stops_when_cancel_flag_is_set() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "x\n".repeat(10_000)).unwrap();
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let options = SearchOptions {
+            threads: 1,
+            cancel: Some(cancel.clone()),
+            ..options(&["*.txt"])
+        };
+
+        let mut matches = 0;
+        let flow = search_with("x", &[dir.path()], &options, |event| {
+            if let SearchEvent::Match(_) = event {
+                matches += 1;
+                // As a Ctrl-C handler would, from outside the sink.
+                cancel.store(true, Ordering::Relaxed);
+            }
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+
+        assert!(flow.is_break());
+        assert!(matches < 10_000, "{matches}");
+    }
+
+    #[test]
+    fn search_with_reports_errors_as_events() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), [b'x', 0xFF]).unwrap();
+
+        let mut events = vec![];
+        let flow = search_with("x", &[dir.path()], &options(&["*.txt"]), |event| {
+            events.push(event);
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+
+        assert!(flow.is_continue());
+        assert!(matches!(&events[..], [SearchEvent::Error(PergError::Encoding { .. })]));
+    }
+
+    #[test]
+    fn extension_filter_skips_only_files_found_in_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("script");
+        fs::write(&script, "needle\n").unwrap();
+        let options = SearchOptions {
+            extensions: Some(HashSet::from(["txt".to_string()])),
+            ..Default::default()
+        };
+
+        let named: Vec<PathBuf> = Walk::new(&[&script], &options).map(Result::unwrap).collect();
+        let found: Vec<PathBuf> = Walk::new(&[dir.path()], &options).map(Result::unwrap).collect();
+
+        assert_eq!(named, vec![script]);
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn compressed_files_are_filtered_by_their_inner_extension() {
+        let options = SearchOptions {
+            search_zip: true,
+            extensions: Some(HashSet::from(["txt".to_string()])),
+            ..Default::default()
+        };
+
+        assert!(options.has_allowed_extension(Path::new("dir/a.TXT")));
+        assert!(options.has_allowed_extension(Path::new("dir/a.txt.gz")));
+        assert!(!options.has_allowed_extension(Path::new("dir/a.gz")));
+        assert!(!options.has_allowed_extension(Path::new("dir/README")));
+    }
+
+    #[test]
+    fn search_with_decompresses_gzip_files() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"abc\r\nxyz\r\n").unwrap();
+        fs::write(dir.path().join("a.txt.gz"), encoder.finish().unwrap()).unwrap();
+
+        let mut options = options(&["*.gz"]);
+        options.search_zip = true;
+
+        let mut events = vec![];
+        let _ = search_with("xyz", &[dir.path()], &options, |event| {
+            events.push(event);
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+
+        assert_eq!(events.len(), 4);
+        assert!(
+            matches!(&events[1], SearchEvent::Context(c) if c.line_number == 1 && c.line == "abc")
+        );
+        assert!(
+            matches!(&events[2], SearchEvent::Match(r) if r.line_number == 2 && r.line == "xyz")
+        );
+        assert!(matches!(
+            &events[3],
+            SearchEvent::FileEnd(_, FileStats { matches: 1, bytes: 10, .. })
+        ));
+    }
+
+    /// An executable shell script in `dir` running `body`.
+    #[cfg(unix)]
+    fn script(dir: &Path, body: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.join("pre.sh");
+        fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    fn events(pattern: &str, root: &Path, options: &SearchOptions) -> Vec<SearchEvent> {
+        let mut events = vec![];
+        let _ = search_with(pattern, &[root], options, |event| {
+            events.push(event);
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        events
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pre_command_output_is_searched_under_the_original_path() {
+        let tools = tempfile::tempdir().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.doc"), "hello\n").unwrap();
+        fs::write(dir.path().join("b.txt"), "hello\n").unwrap();
+
+        let options = SearchOptions {
+            pre: Some(script(tools.path(), "tr a-z A-Z < \"$1\"")),
+            pre_globs: vec!["*.doc".to_string()],
+            extensions: Some(HashSet::from(["txt".to_string()])),
+            ..options(&["*"])
+        };
+        let matched: Vec<_> = events("HELLO", dir.path(), &options)
+            .into_iter()
+            .filter_map(|event| match event {
+                SearchEvent::Match(record) => Some(record),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].path, dir.path().join("a.doc"));
+        assert_eq!(matched[0].line, "HELLO");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failing_pre_command_fails_the_file() {
+        let tools = tempfile::tempdir().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "hello\n").unwrap();
+
+        let options = SearchOptions {
+            pre: Some(script(tools.path(), "echo hello; exit 3")),
+            ..options(&["*"])
+        };
+        let events = events("hello", dir.path(), &options);
+
+        assert!(matches!(
+            events.last(),
+            Some(SearchEvent::Error(PergError::Preprocessor { reason, .. })) if reason.contains("3")
+        ));
+    }
+
+    #[test]
+    fn missing_pre_command_fails_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "hello\n").unwrap();
+
+        let options = SearchOptions {
+            pre: Some(dir.path().join("no-such-command")),
+            ..options(&["*"])
+        };
+        let events = events("hello", dir.path(), &options);
+
+        assert!(matches!(
+            &events[..],
+            [SearchEvent::Error(PergError::Preprocessor { reason, .. })]
+                if reason.starts_with("cannot run")
+        ));
+    }
+
+    #[test]
+    fn mmap_modes_find_the_same_matches() {
+        let find = |mmap| {
+            let options = SearchOptions {
+                mmap,
+                ..options(&["*.txt"])
+            };
+            search("Tadeusz", &["../../test_files"], &options)
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+
+        let read = find(MmapMode::Never);
+
+        assert!(!read.is_empty());
+        assert_eq!(read, find(MmapMode::Always));
+        assert_eq!(read, find(MmapMode::Auto));
+    }
+
+    #[test]
+    fn mmap_reports_invalid_utf8_like_read() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), [b'x', 0xFF]).unwrap();
+
+        for mmap in [MmapMode::Never, MmapMode::Always] {
+            let options = SearchOptions {
+                mmap,
+                ..options(&["*.txt"])
+            };
+            let items: Vec<_> = search("x", &[dir.path()], &options).unwrap().collect();
+
+            assert!(matches!(&items[..], [Err(PergError::Encoding { .. })]), "{mmap:?}");
+        }
+    }
+
+    #[test]
+    fn streamed_and_read_files_report_the_same_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = "a\nx b\nc\nd\ne x\nf\n".repeat(50);
+        fs::write(dir.path().join("a.txt"), &text).unwrap();
+
+        let describe = |stream_threshold| {
+            let options = SearchOptions {
+                stream_threshold,
+                max_count: Some(30),
+                ..options(&["*.txt"])
+            };
+            let mut events = vec![];
+            let _ = search_with("x", &[dir.path()], &options, |event| {
+                // A stream stops reading at the match limit, so only the
+                // number of bytes read may differ.
+                events.push(match event {
+                    SearchEvent::FileEnd(_, stats) => format!("FileEnd({})", stats.matches),
+                    event => format!("{event:?}"),
+                });
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+            events
+        };
+
+        let read = describe(None);
+
+        assert_eq!(read.len(), 1 + 30 + 60 + 1);
+        assert_eq!(read.last().unwrap(), "FileEnd(30)");
+        assert_eq!(read, describe(Some(1)));
+    }
+
+    #[test]
+    fn enormous_line_is_skipped_quickly() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut text = "needle\n".to_string();
+        text.push_str(&"needle ".repeat(2 * MAX_LINE_LEN / 7));
+        text.push_str("\nneedle\n");
+        fs::write(dir.path().join("a.txt"), &text).unwrap();
+
+        for stream_threshold in [None, Some(1)] {
+            let options = SearchOptions {
+                stream_threshold,
+                ..options(&["*.txt"])
+            };
+            let mut stats = None;
+            let _ = search_with("needle", &[dir.path()], &options, |event| {
+                if let SearchEvent::FileEnd(_, end) = event {
+                    stats = Some(end);
+                }
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+
+            let stats = stats.unwrap();
+            assert_eq!((stats.matches, stats.long_lines), (2, 1), "{stream_threshold:?}");
+        }
+    }
+
+    #[test]
+    fn enormous_line_can_be_matched_up_to_the_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "x ".repeat(1000)).unwrap();
+
+        let options = SearchOptions {
+            max_line_len: Some(100),
+            long_lines: LongLines::Truncate,
+            ..options(&["*.txt"])
+        };
+        let records: Vec<_> = search("x", &[dir.path()], &options).unwrap().collect();
+
+        assert_eq!(records.len(), 50);
+    }
+
+    #[test]
+    fn file_list_skips_blank_entries() {
+        let list: &[u8] = b"a.txt\r\n\n  \nsub dir/b.txt\n";
+        let paths: Vec<PathBuf> = file_list("list", list, b'\n')
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(paths, vec![PathBuf::from("a.txt"), PathBuf::from("sub dir/b.txt")]);
+
+        let list: &[u8] = b"a\nb\0\0c";
+        let paths: Vec<PathBuf> = file_list("list", list, b'\0')
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(paths, vec![PathBuf::from("a\nb"), PathBuf::from("c")]);
+    }
+
+    #[test]
+    fn search_files_with_searches_exactly_the_listed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "x\n").unwrap();
+        fs::write(dir.path().join("b.txt"), "x\n").unwrap();
+        let list = format!(
+            "{}\n{}\n",
+            dir.path().join("a.txt").display(),
+            dir.path().join("missing.txt").display()
+        );
+
+        let mut events = vec![];
+        let _ = search_files_with(
+            "x",
+            file_list("list", list.as_bytes(), b'\n'),
+            &options(&[]),
+            |event| {
+                events.push(event);
+                ControlFlow::Continue(())
+            },
+        )
+        .unwrap();
+
+        let starts: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                SearchEvent::FileStart(path) => path.file_name(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(starts, vec!["a.txt"]);
+        assert!(events.iter().any(|event| matches!(
+            event,
+            SearchEvent::Error(PergError::Io { path, source })
+                if path.ends_with("missing.txt") && source.kind() == io::ErrorKind::NotFound
+        )));
+    }
+
+    #[test]
+    fn reorder_emits_files_in_index_order_despite_delays() {
+        let (messages, received) = sync_channel(EVENT_BUFFER);
+        thread::scope(|scope| {
+            // Later files finish first.
+            for index in 0..8 {
+                let messages = messages.clone();
+                scope.spawn(move || {
+                    thread::sleep(std::time::Duration::from_millis(5 * (8 - index) as u64));
+                    let path = PathBuf::from(index.to_string());
+                    messages
+                        .send(Message::Event(index, SearchEvent::FileStart(path.clone())))
+                        .unwrap();
+                    messages
+                        .send(Message::Event(
+                            index,
+                            SearchEvent::FileEnd(path, FileStats::default()),
+                        ))
+                        .unwrap();
+                    messages.send(Message::Done(index)).unwrap();
+                });
+            }
+            drop(messages);
+
+            let mut order = vec![];
+            let mut reorder = Reorder::new(|event| {
+                if let SearchEvent::FileStart(path) | SearchEvent::FileEnd(path, _) = event {
+                    order.push(path.display().to_string());
+                }
+                ControlFlow::Continue(())
+            });
+            while let Ok(message) = received.recv() {
+                let _ = reorder.push(message);
+            }
+
+            let expected: Vec<_> = (0..8).flat_map(|i| [i.to_string(), i.to_string()]).collect();
+            assert_eq!(order, expected);
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn idle_workers_take_files_queued_behind_a_large_one() {
+        let dir = tempfile::tempdir().unwrap();
+        // Opening a FIFO blocks until something writes to it, which holds the
+        // worker that takes 0.txt until it is released.
+        let gated = dir.path().join("0.txt");
+        assert!(Command::new("mkfifo").arg(&gated).status().unwrap().success());
+        let mut files = vec![gated.clone()];
+        for i in 1..16 {
+            let path = dir.path().join(format!("{i:02}.txt"));
+            fs::write(&path, "x\n").unwrap();
+            files.push(path);
+        }
+        let options = SearchOptions {
+            threads: 4,
+            ..options(&[])
+        };
+
+        let (release, released) = sync_channel(1);
+        let mut finished = vec![];
+        thread::scope(|scope| {
+            let gated = &gated;
+            scope.spawn(move || {
+                // With fixed chunks, the worker holding 0.txt would also own
+                // the files after it, which then never finish; stop waiting.
+                let _ = released.recv_timeout(Duration::from_secs(10));
+                fs::write(gated, "x\n").unwrap();
+            });
+            let _ = search_files_with("x", files.into_iter().map(Ok), &options, |event| {
+                if let SearchEvent::FileEnd(path, _) = event {
+                    finished.push(path);
+                    if finished.len() == 15 {
+                        let _ = release.send(());
+                    }
+                }
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        });
+
+        assert_eq!(finished.len(), 16);
+        assert!(finished.last().unwrap().ends_with("0.txt"));
+    }
+
+    #[test]
+    fn sorted_search_reports_files_in_walk_order() {
+        let dir = tempfile::tempdir().unwrap();
+        // The first file takes far longer to search than the rest.
+        fs::write(dir.path().join("0.txt"), "x\n".repeat(200_000)).unwrap();
+        for i in 1..16 {
+            fs::write(dir.path().join(format!("{i:02}.txt")), "x\n").unwrap();
+        }
+        let options = SearchOptions {
+            threads: 4,
+            sort: Sort::Path,
+            ..options(&["*.txt"])
+        };
+
+        let mut files = vec![];
+        let _ = search_with("x", &[dir.path()], &options, |event| {
+            match event {
+                SearchEvent::FileStart(path) => files.push(path),
+                SearchEvent::Match(record) => assert_eq!(Some(&record.path), files.last()),
+                _ => {}
+            }
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+
+        let walked: Vec<_> = Walk::new(&[dir.path()], &options).map(Result::unwrap).collect();
+        assert_eq!(files, walked);
+    }
+
+    #[test]
+    fn search_rejects_invalid_pattern_up_front() {
+        let result = search("a)", &["../../test_files"], &options(&["*"]));
+        assert!(matches!(result, Err(PergError::Pattern(_))));
+    }
+}
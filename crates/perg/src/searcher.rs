@@ -0,0 +1,652 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+use std::ops::{ControlFlow, Range};
+use std::str;
+use std::time::{Duration, Instant};
+
+use crate::matcher::{Match, Matcher};
+
+/// What a [`Searcher`] reports about a line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LineEvent<'a> {
+    /// A match on `line`, which starts this many bytes into the input;
+    /// `Match::line` is its 0-based number.
+    Match(Match, &'a str, usize),
+    /// A line within the context window of a match, with its 0-based number.
+    Context(usize, &'a str),
+    /// The line with this 0-based number is longer than the line length cap;
+    /// reported before the line's other events.
+    LongLine(usize),
+    /// The search ran out of time on the line with this 0-based number, and
+    /// ends there; see [`Searcher::timeout`]. The line's matches are lost.
+    TimedOut(usize),
+}
+
+/// What happens to lines longer than the line length cap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LongLines {
+    /// Do not match the line at all.
+    #[default]
+    Skip,
+    /// Match only the first bytes of the line, up to the cap.
+    Truncate,
+}
+
+/// Default line length cap. Matching restarts at every offset of a line, so a
+/// single huge line (minified code, base64 blobs) can stall a search.
+pub const MAX_LINE_LEN: usize = 256 * 1024;
+
+/// The clock is read once every this many lines, or positions a match is
+/// tried at within a line, while a search has a timeout.
+const POLL_EVERY: usize = 64;
+
+/// Splits input into lines and runs a matcher over each of them.
+///
+/// Whole files and streams go through the same splitting, so both report the
+/// same matches: a line ends at the terminator (which is not part of it), a
+/// `\r` before a `\n` terminator is dropped, and a terminator at the very end
+/// of the input does not start another, empty line.
+#[derive(Clone, Debug)]
+pub struct Searcher {
+    terminator: u8,
+    context: usize,
+    max_count: Option<usize>,
+    max_line_len: usize,
+    long_lines: LongLines,
+    lossy: bool,
+    timeout: Option<Duration>,
+    line_ranges: Vec<Range<usize>>,
+}
+
+impl Default for Searcher {
+    fn default() -> Self {
+        Self {
+            terminator: b'\n',
+            context: 0,
+            max_count: None,
+            max_line_len: MAX_LINE_LEN,
+            long_lines: LongLines::Skip,
+            lossy: false,
+            timeout: None,
+            line_ranges: vec![],
+        }
+    }
+}
+
+/// The time a search may take, spent once the clock passes `deadline`.
+struct Budget {
+    deadline: Instant,
+    polls: usize,
+    spent: bool,
+}
+
+impl Budget {
+    /// Whether the time is up; reads the clock only every [`POLL_EVERY`] calls.
+    fn spent(&mut self) -> bool {
+        self.polls += 1;
+        if self.polls % POLL_EVERY == 0 {
+            self.spent = Instant::now() >= self.deadline;
+        }
+        self.spent
+    }
+}
+
+/// Per-search state: the lines kept for before-context and how much
+/// after-context is still owed.
+struct Window<'s> {
+    context: usize,
+    before: VecDeque<(usize, String)>,
+    after: usize,
+    remaining: Option<usize>,
+    max_line_len: usize,
+    long_lines: LongLines,
+    budget: Option<Budget>,
+    line_ranges: &'s [Range<usize>],
+}
+
+impl<'s> Window<'s> {
+    fn new(searcher: &'s Searcher) -> Self {
+        Self {
+            context: searcher.context,
+            before: VecDeque::with_capacity(searcher.context),
+            after: 0,
+            remaining: searcher.max_count,
+            max_line_len: searcher.max_line_len,
+            long_lines: searcher.long_lines,
+            budget: searcher.timeout.map(|timeout| Budget {
+                deadline: Instant::now() + timeout,
+                polls: 0,
+                spent: false,
+            }),
+            line_ranges: &searcher.line_ranges,
+        }
+    }
+
+    /// Whether the line with this 0-based number may be matched.
+    fn in_range(&self, line_number: usize) -> bool {
+        self.line_ranges.is_empty()
+            || self
+                .line_ranges
+                .iter()
+                .any(|range| range.contains(&line_number))
+    }
+
+    /// The part of `line` that may be matched, or `None` if it is skipped.
+    fn searchable<'a>(&self, line: &'a str) -> Option<&'a str> {
+        if line.len() <= self.max_line_len {
+            return Some(line);
+        }
+
+        match self.long_lines {
+            LongLines::Skip => None,
+            LongLines::Truncate => {
+                let mut end = self.max_line_len;
+                while !line.is_char_boundary(end) {
+                    end -= 1;
+                }
+                Some(&line[..end])
+            }
+        }
+    }
+
+    /// The match limit was reached, or every line range ends before the line
+    /// with this 0-based number, and the after-context has been reported; or
+    /// the time ran out.
+    fn finished(&self, line_number: usize) -> bool {
+        let past_ranges = !self.line_ranges.is_empty()
+            && self
+                .line_ranges
+                .iter()
+                .all(|range| range.end <= line_number);
+        ((self.remaining == Some(0) || past_ranges) && self.after == 0)
+            || self.budget.as_ref().is_some_and(|budget| budget.spent)
+    }
+
+    /// Every match in `line`, or `None` if the time runs out first.
+    fn find_matches(&mut self, matcher: &dyn Matcher, line: &str) -> Option<Vec<Match>> {
+        let Some(budget) = self.budget.as_mut() else {
+            return Some(matcher.find_matches(line));
+        };
+        if budget.spent() {
+            return None;
+        }
+        matcher
+            .find_matches_while(line, &mut || !budget.spent())
+            .filter(|_| !budget.spent)
+    }
+
+    fn line<F>(
+        &mut self,
+        matcher: &dyn Matcher,
+        line_number: usize,
+        offset: usize,
+        line: &str,
+        sink: &mut F,
+    ) -> ControlFlow<()>
+    where
+        F: FnMut(LineEvent) -> ControlFlow<()>,
+    {
+        let in_range = self.in_range(line_number);
+        if in_range && line.len() > self.max_line_len {
+            sink(LineEvent::LongLine(line_number))?;
+        }
+
+        let mut matches = match (self.remaining, self.searchable(line).filter(|_| in_range)) {
+            (Some(0), _) | (_, None) => vec![],
+            (_, Some(searchable)) => match self.find_matches(matcher, searchable) {
+                Some(matches) => matches,
+                None => return sink(LineEvent::TimedOut(line_number)),
+            },
+        };
+
+        if matches.is_empty() {
+            if self.after > 0 {
+                self.after -= 1;
+                return sink(LineEvent::Context(line_number, line));
+            }
+            if self.context > 0 {
+                if self.before.len() == self.context {
+                    self.before.pop_front();
+                }
+                self.before.push_back((line_number, line.to_string()));
+            }
+            return ControlFlow::Continue(());
+        }
+
+        if let Some(remaining) = self.remaining.as_mut() {
+            matches.truncate(*remaining);
+            *remaining -= matches.len();
+        }
+
+        for (number, before) in self.before.drain(..) {
+            sink(LineEvent::Context(number, &before))?;
+        }
+        for m in matches {
+            sink(LineEvent::Match(
+                Match {
+                    line: line_number,
+                    ..m
+                },
+                line,
+                offset,
+            ))?;
+        }
+        self.after = self.context;
+        ControlFlow::Continue(())
+    }
+}
+
+impl Searcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the byte that separates lines; it must be ASCII.
+    pub fn terminator(mut self, terminator: u8) -> Self {
+        assert!(terminator.is_ascii(), "line terminator must be ASCII");
+        self.terminator = terminator;
+        self
+    }
+
+    /// Reports up to `context` lines before and after every matching line.
+    pub fn context(mut self, context: usize) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Stops searching after `max_count` matches (and their after-context).
+    pub fn max_count(mut self, max_count: Option<usize>) -> Self {
+        self.max_count = max_count;
+        self
+    }
+
+    /// Caps the bytes of a line that are matched; `long_lines` decides what
+    /// happens to longer lines. The cap is on matching only: long lines are
+    /// still reported in full as matches or context.
+    pub fn max_line_len(mut self, max_line_len: usize, long_lines: LongLines) -> Self {
+        self.max_line_len = max_line_len;
+        self.long_lines = long_lines;
+        self
+    }
+
+    /// Replaces invalid UTF-8 in a stream with U+FFFD instead of failing.
+    pub fn lossy(mut self, lossy: bool) -> Self {
+        self.lossy = lossy;
+        self
+    }
+
+    pub fn is_lossy(&self) -> bool {
+        self.lossy
+    }
+
+    /// Gives up on an input once searching it took longer than `timeout`,
+    /// with a [`LineEvent::TimedOut`]. The clock is read coarsely, so the
+    /// search may run a little over.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Matches only lines in `line_ranges`, by 0-based number; empty means
+    /// every line. Lines outside them are still reported as context of a
+    /// match inside, and reading stops once every range has ended.
+    pub fn line_ranges(mut self, line_ranges: Vec<Range<usize>>) -> Self {
+        self.line_ranges = line_ranges;
+        self
+    }
+
+    fn trim<'a>(&self, record: &'a str) -> &'a str {
+        let line = record
+            .strip_suffix(self.terminator as char)
+            .unwrap_or(record);
+        if self.terminator == b'\n' {
+            line.strip_suffix('\r').unwrap_or(line)
+        } else {
+            line
+        }
+    }
+
+    /// Calls `sink` with every match in `text` and its context lines.
+    pub fn search_str<F>(&self, matcher: &dyn Matcher, text: &str, mut sink: F) -> ControlFlow<()>
+    where
+        F: FnMut(LineEvent) -> ControlFlow<()>,
+    {
+        let mut window = Window::new(self);
+        let mut offset = 0;
+        for (line_number, record) in text.split_inclusive(self.terminator as char).enumerate() {
+            if window.finished(line_number) {
+                break;
+            }
+            window.line(matcher, line_number, offset, self.trim(record), &mut sink)?;
+            offset += record.len();
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Like [`Searcher::search_str`], but holds only one line of `reader` (plus
+    /// the before-context) in memory at a time. A line that is not valid UTF-8
+    /// stops the search with an `InvalidData` error, unless the searcher is
+    /// lossy.
+    pub fn search_reader<R, F>(
+        &self,
+        matcher: &dyn Matcher,
+        mut reader: R,
+        mut sink: F,
+    ) -> io::Result<ControlFlow<()>>
+    where
+        R: BufRead,
+        F: FnMut(LineEvent) -> ControlFlow<()>,
+    {
+        let mut window = Window::new(self);
+        let mut buffer = vec![];
+        let mut offset = 0;
+        for line_number in 0.. {
+            buffer.clear();
+            if window.finished(line_number) || reader.read_until(self.terminator, &mut buffer)? == 0
+            {
+                break;
+            }
+
+            let record = match str::from_utf8(&buffer) {
+                Ok(record) => Cow::Borrowed(record),
+                Err(_) if self.lossy => String::from_utf8_lossy(&buffer),
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            };
+            if window
+                .line(matcher, line_number, offset, self.trim(&record), &mut sink)
+                .is_break()
+            {
+                return Ok(ControlFlow::Break(()));
+            }
+            offset += buffer.len();
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{build_matcher, Engine};
+    use crate::nfa::NfaOptions;
+    use std::fs;
+
+    /// A line event with an owned line, so it can outlive the search.
+    type Owned = (Option<Match>, usize, String);
+
+    fn own(event: LineEvent) -> Owned {
+        match event {
+            LineEvent::Match(m, line, _) => (Some(m.clone()), m.line, line.to_string()),
+            LineEvent::Context(number, line) => (None, number, line.to_string()),
+            LineEvent::LongLine(number) => (None, number, "<long>".to_string()),
+            LineEvent::TimedOut(number) => (None, number, "<timed out>".to_string()),
+        }
+    }
+
+    fn collect_events_str(searcher: &Searcher, pattern: &str, text: &str) -> Vec<Owned> {
+        let matcher = build_matcher(pattern, &NfaOptions::default(), Engine::Nfa).unwrap();
+        let mut found = vec![];
+        let _ = searcher.search_str(matcher.as_ref(), text, |event| {
+            found.push(own(event));
+            ControlFlow::Continue(())
+        });
+        found
+    }
+
+    fn collect_events_reader(searcher: &Searcher, pattern: &str, text: &str) -> Vec<Owned> {
+        let matcher = build_matcher(pattern, &NfaOptions::default(), Engine::Nfa).unwrap();
+        let mut found = vec![];
+        let _ = searcher
+            .search_reader(matcher.as_ref(), text.as_bytes(), |event| {
+                found.push(own(event));
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        found
+    }
+
+    fn matches_only(events: Vec<Owned>) -> Vec<(Match, String)> {
+        events
+            .into_iter()
+            .filter_map(|(m, _, line)| Some((m?, line)))
+            .collect()
+    }
+
+    fn collect_str(searcher: &Searcher, pattern: &str, text: &str) -> Vec<(Match, String)> {
+        matches_only(collect_events_str(searcher, pattern, text))
+    }
+
+    fn collect_reader(searcher: &Searcher, pattern: &str, text: &str) -> Vec<(Match, String)> {
+        matches_only(collect_events_reader(searcher, pattern, text))
+    }
+
+    #[test]
+    fn whole_file_and_stream_paths_agree() {
+        let fixture = fs::read_to_string("../../test_files/pan-tadeusz.txt").unwrap();
+        // The opening of the poem is plenty and keeps the unoptimized NFA quick.
+        let fixture: String = fixture.split_inclusive('\n').take(1500).collect();
+        let crlf = fixture.replace('\n', "\r\n");
+
+        for text in [&fixture, &crlf] {
+            for pattern in ["Tadeusz", "Sop(l|e)ic", "zamek"] {
+                let searcher = Searcher::new().context(2);
+                let whole = collect_events_str(&searcher, pattern, text);
+                let streamed = collect_events_reader(&searcher, pattern, text);
+
+                assert!(!whole.is_empty(), "{pattern}");
+                assert_eq!(whole, streamed, "{pattern}");
+            }
+        }
+    }
+
+    #[test]
+    fn crlf_is_not_part_of_the_line() {
+        let found = collect_reader(&Searcher::new(), "b", "ab\r\nb\r\n");
+
+        assert_eq!(
+            found,
+            vec![
+                (
+                    Match {
+                        from: 1,
+                        to: 2,
+                        line: 0,
+                        errors: 0,
+                        pattern: 0
+                    },
+                    "ab".to_string()
+                ),
+                (
+                    Match {
+                        from: 0,
+                        to: 1,
+                        line: 1,
+                        errors: 0,
+                        pattern: 0
+                    },
+                    "b".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn trailing_terminator_does_not_add_a_line() {
+        // `x*` matches on every line, once before and once after the `a`.
+        let lines =
+            |found: Vec<(Match, String)>| found.iter().map(|(m, _)| m.line).collect::<Vec<_>>();
+        assert_eq!(lines(collect_str(&Searcher::new(), "x*", "a\n")), [0, 0]);
+        assert_eq!(lines(collect_reader(&Searcher::new(), "x*", "a\n")), [0, 0]);
+    }
+
+    #[test]
+    fn custom_terminator_splits_records() {
+        let searcher = Searcher::new().terminator(b'\0');
+        let text = "one\ntwo\0three two\0";
+
+        let whole = collect_str(&searcher, "two", text);
+
+        assert_eq!(whole, collect_reader(&searcher, "two", text));
+        assert_eq!(
+            whole.iter().map(|(m, _)| m.line).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(whole[0].1, "one\ntwo");
+    }
+
+    #[test]
+    fn stream_reports_invalid_utf8() {
+        let matcher = build_matcher("a", &NfaOptions::default(), Engine::Nfa).unwrap();
+        let input: &[u8] = b"a\n\xff\n";
+
+        let mut found = 0;
+        let result = Searcher::new().search_reader(matcher.as_ref(), input, |_| {
+            found += 1;
+            ControlFlow::Continue(())
+        });
+
+        assert_eq!(found, 1);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn lossy_stream_replaces_invalid_utf8() {
+        let matcher = build_matcher("b", &NfaOptions::default(), Engine::Nfa).unwrap();
+        let input: &[u8] = b"a\n\xffb\n";
+
+        let mut found = vec![];
+        let result = Searcher::new().lossy(true).search_reader(matcher.as_ref(), input, |event| {
+            found.push(own(event));
+            ControlFlow::Continue(())
+        });
+
+        assert!(result.unwrap().is_continue());
+        assert_eq!(
+            found,
+            vec![(
+                Some(Match {
+                    from: 3,
+                    to: 4,
+                    line: 1,
+                    errors: 0,
+                    pattern: 0
+                }),
+                1,
+                "\u{FFFD}b".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn context_lines_surround_matches_once() {
+        let text = "a\nb\nx\nc\nx\nd\ne\nf\n";
+
+        let events = collect_events_str(&Searcher::new().context(1), "x", text);
+
+        let lines: Vec<(bool, usize)> = events.iter().map(|(m, n, _)| (m.is_some(), *n)).collect();
+        assert_eq!(
+            lines,
+            vec![(false, 1), (true, 2), (false, 3), (true, 4), (false, 5)]
+        );
+        assert_eq!(events, collect_events_reader(&Searcher::new().context(1), "x", text));
+    }
+
+    #[test]
+    fn max_count_stops_after_trailing_context() {
+        let searcher = Searcher::new().context(1).max_count(Some(2));
+        let text = "x x\nx\ny\nx\n";
+
+        let events = collect_events_str(&searcher, "x", text);
+
+        let lines: Vec<(bool, usize)> = events.iter().map(|(m, n, _)| (m.is_some(), *n)).collect();
+        assert_eq!(lines, vec![(true, 0), (true, 0), (false, 1)]);
+        assert_eq!(events, collect_events_reader(&searcher, "x", text));
+    }
+
+    #[test]
+    fn line_ranges_limit_matches_but_not_context() {
+        let searcher = Searcher::new().context(1).line_ranges(vec![1..3, 5..6]);
+        let text = "x\nx\nb\nx\nc\nx\nx\n";
+
+        let events = collect_events_str(&searcher, "x", text);
+
+        // Lines 0, 3 and 6 are outside the ranges: 0 and 6 are context, 3 is not matched.
+        let lines: Vec<(bool, usize)> = events.iter().map(|(m, n, _)| (m.is_some(), *n)).collect();
+        assert_eq!(
+            lines,
+            vec![
+                (false, 0),
+                (true, 1),
+                (false, 2),
+                (false, 4),
+                (true, 5),
+                (false, 6)
+            ]
+        );
+        assert_eq!(events, collect_events_reader(&searcher, "x", text));
+    }
+
+    #[test]
+    fn streams_are_not_read_past_the_last_line_range() {
+        let matcher = build_matcher("x", &NfaOptions::default(), Engine::Nfa).unwrap();
+        let input: &[u8] = b"x\nx\n\xff\n";
+
+        let mut found = 0;
+        let result = Searcher::new().line_ranges(vec![0..1, 1..2]).search_reader(
+            matcher.as_ref(),
+            input,
+            |_| {
+                found += 1;
+                ControlFlow::Continue(())
+            },
+        );
+
+        assert!(result.unwrap().is_continue());
+        assert_eq!(found, 2);
+    }
+
+    #[test]
+    fn long_lines_are_skipped_or_truncated() {
+        let text = format!("x{}x\nx\n", "é".repeat(10));
+        let events = |long_lines| {
+            let searcher = Searcher::new().max_line_len(8, long_lines);
+            let found = collect_events_str(&searcher, "x", &text);
+            assert_eq!(found, collect_events_reader(&searcher, "x", &text));
+            found
+                .into_iter()
+                .map(|(m, number, line)| (m.map(|m| m.from), number, line.len()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(events(LongLines::Skip), vec![(None, 0, 6), (Some(0), 1, 1)]);
+        assert_eq!(
+            events(LongLines::Truncate),
+            vec![(None, 0, 6), (Some(0), 0, 22), (Some(0), 1, 1)]
+        );
+    }
+
+    #[test]
+    fn timeout_ends_the_search_at_the_first_clock_reading_past_it() {
+        let text = "x\n".repeat(1000);
+        let searcher = Searcher::new().timeout(Some(Duration::ZERO));
+
+        let events = collect_events_str(&searcher, "x", &text);
+        assert_eq!(events, collect_events_reader(&searcher, "x", &text));
+        assert_eq!(events.len(), POLL_EVERY);
+        assert_eq!(events.last().unwrap(), &(None, POLL_EVERY - 1, "<timed out>".to_string()));
+
+        // Within a line too, where the line's matches are lost.
+        let line = "x".repeat(100_000);
+        let events = collect_events_str(&searcher, "[x]", &line);
+        assert!(matches!(&events[..], [(None, 0, timed_out)] if timed_out == "<timed out>"));
+        let matcher = build_matcher("[x]", &NfaOptions::default(), Engine::Nfa).unwrap();
+        let mut found = 0;
+        let unlimited = Searcher::new().timeout(Some(Duration::from_secs(3600)));
+        let _ = unlimited.search_str(matcher.as_ref(), &line, |_| {
+            found += 1;
+            ControlFlow::Continue(())
+        });
+        assert_eq!(found, line.len());
+    }
+}
@@ -0,0 +1,178 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::printer::printable;
+use crate::search::MatchRecord;
+
+/// The `--vimgrep` format, `path:line:column:text`.
+pub const VIMGREP: &str = "{path}:{line}:{column}:{text}";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum TemplateError {
+    #[error(
+        "unknown placeholder '{{{0}}}'; expected one of \
+         {{path}} {{line}} {{column}} {{offset}} {{text}} {{match}}"
+    )]
+    UnknownPlaceholder(String),
+    #[error("'{{' is never closed; write '{{{{' for a literal brace")]
+    UnclosedPlaceholder,
+    #[error("unmatched '}}'; write '}}}}' for a literal brace")]
+    UnmatchedBrace,
+    #[error("unknown escape '\\{0}'; expected \\t, \\n or \\\\")]
+    UnknownEscape(char),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Path,
+    Line,
+    Column,
+    Offset,
+    Text,
+    Match,
+}
+
+/// An output format given to `--format`, rendered once for every match.
+///
+/// Placeholders in braces are replaced by a field of the match: `{path}`,
+/// `{line}` and `{column}` (1-based), `{offset}` (0-based byte offset of the
+/// match in the file), `{text}` (the whole line) and `{match}` (the matched
+/// text). `\t`, `\n` and `\\` are escapes, and `{{` and `}}` literal braces.
+///
+/// Each match is one record, ended by a newline: matches are not merged, so
+/// `{path}` alone prints a file's path once per match. `-l` lists every file
+/// once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some('\\') | None => literal.push('\\'),
+                    Some(other) => return Err(TemplateError::UnknownEscape(other)),
+                },
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '}' => return Err(TemplateError::UnmatchedBrace),
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest.find('}').ok_or(TemplateError::UnclosedPlaceholder)?;
+                    let part = match &rest[..end] {
+                        "path" => Part::Path,
+                        "line" => Part::Line,
+                        "column" => Part::Column,
+                        "offset" => Part::Offset,
+                        "text" => Part::Text,
+                        "match" => Part::Match,
+                        name => return Err(TemplateError::UnknownPlaceholder(name.to_string())),
+                    };
+                    chars = rest[end + 1..].chars();
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(part);
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Self { parts })
+    }
+}
+
+impl Template {
+    /// `record` in this format, without the newline that ends it. Control
+    /// characters in the line are escaped as in the other output formats.
+    pub fn render(&self, record: &MatchRecord) -> String {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            let _ = match part {
+                Part::Literal(text) => write!(rendered, "{text}"),
+                Part::Path => write!(rendered, "{}", record.path.display()),
+                Part::Line => write!(rendered, "{}", record.line_number),
+                Part::Column => write!(rendered, "{}", record.column),
+                Part::Offset => write!(rendered, "{}", record.offset),
+                Part::Text => write!(rendered, "{}", printable(&record.line)),
+                Part::Match => write!(rendered, "{}", printable(record.matched())),
+            };
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn record() -> MatchRecord {
+        MatchRecord {
+            path: PathBuf::from("dir/file.txt"),
+            line_number: 3,
+            column: 5,
+            span: (4, 7),
+            offset: 24,
+            line: "let abc = 1;".to_string(),
+            pattern: 0,
+        }
+    }
+
+    fn render(template: &str) -> String {
+        template.parse::<Template>().unwrap().render(&record())
+    }
+
+    #[test]
+    fn placeholders_are_replaced_by_the_match() {
+        assert_eq!(
+            render("{path}\\t{line}\\t{column}\\t{offset}\\t{match}\\t{text}"),
+            "dir/file.txt\t3\t5\t24\tabc\tlet abc = 1;"
+        );
+    }
+
+    #[test]
+    fn vimgrep_is_a_template() {
+        assert_eq!(render(VIMGREP), "dir/file.txt:3:5:let abc = 1;");
+    }
+
+    #[test]
+    fn braces_and_backslashes_can_be_literal() {
+        assert_eq!(render("{{{line}}} \\\\ {{match}}"), "{3} \\ {match}");
+        assert_eq!(render("line\\n{line}"), "line\n3");
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        let tests = vec![
+            ("{name}", TemplateError::UnknownPlaceholder("name".to_string())),
+            ("{path", TemplateError::UnclosedPlaceholder),
+            ("path}", TemplateError::UnmatchedBrace),
+            ("\\r", TemplateError::UnknownEscape('r')),
+        ];
+
+        for (template, expected) in tests {
+            assert_eq!(template.parse::<Template>(), Err(expected), "{template}");
+        }
+    }
+}
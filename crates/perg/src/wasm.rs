@@ -0,0 +1,103 @@
+//! JavaScript bindings for perg's engine, built with the `wasm` feature:
+//!
+//! ```text
+//! wasm-pack build crates/perg --target web -- --features wasm
+//! ```
+//!
+//! The engine itself, [`crate::nfa`] and [`crate::re`], uses neither the file
+//! system nor threads, so it runs in a browser; the rest of the library
+//! compiles for `wasm32-unknown-unknown` but has nothing to search there.
+
+use wasm_bindgen::prelude::*;
+
+use crate::nfa::{NfaOptions, NFA};
+use crate::re::regex_to_nfa;
+
+/// A pattern compiled by perg's engine, with the automaton it became.
+#[wasm_bindgen]
+pub struct Regex {
+    nfa: NFA,
+}
+
+/// The byte span `from..to` of a match within line `line`, counted from 0.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub line: usize,
+    pub from: usize,
+    pub to: usize,
+}
+
+/// Compiles `pattern`; `flags` may hold `i` to ignore case.
+#[wasm_bindgen]
+pub fn compile(pattern: &str, flags: &str) -> Result<Regex, JsError> {
+    let mut options = NfaOptions::default();
+    for flag in flags.chars() {
+        match flag {
+            'i' => options.ignore_case = true,
+            _ => return Err(JsError::new(&format!("unknown flag '{flag}'; expected i"))),
+        }
+    }
+    let nfa = regex_to_nfa(pattern, &options).map_err(|err| JsError::new(&err.to_string()))?;
+    Ok(Regex { nfa })
+}
+
+#[wasm_bindgen]
+impl Regex {
+    /// Every match in `text`, with lines separated by `\n`.
+    #[wasm_bindgen(js_name = findMatches)]
+    pub fn find_matches(&self, text: &str) -> Vec<Match> {
+        self.nfa
+            .find_matches(text)
+            .into_iter()
+            .map(|m| Match {
+                line: m.line,
+                from: m.from,
+                to: m.to,
+            })
+            .collect()
+    }
+
+    /// The automaton in Graphviz's DOT language; see [`NFA::to_dot`].
+    #[wasm_bindgen(js_name = toDot)]
+    pub fn to_dot(&self) -> String {
+        self.nfa.to_dot()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiled_patterns_find_matches_and_draw_themselves() {
+        let re = compile("ab*", "i").ok().unwrap();
+
+        assert_eq!(
+            re.find_matches("xAB\na"),
+            vec![Match { line: 0, from: 1, to: 3 }, Match { line: 1, from: 0, to: 1 }]
+        );
+        assert_eq!(re.to_dot(), re.nfa.to_dot());
+    }
+
+    /// Keeps the engine free of what a browser does not have; its tests may
+    /// use anything.
+    #[test]
+    fn the_engine_stays_portable() {
+        let sources = [("nfa.rs", include_str!("nfa.rs")), ("re.rs", include_str!("re.rs"))];
+
+        for (name, source) in sources {
+            let engine = source.split("#[cfg(test)]").next().unwrap();
+            for module in [
+                "std::fs",
+                "std::thread",
+                "std::process",
+                "std::net",
+                "std::env",
+                "std::io",
+            ] {
+                assert!(!engine.contains(module), "{name} uses {module}");
+            }
+        }
+    }
+}
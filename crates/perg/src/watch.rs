@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::search::{SearchOptions, Walk};
+
+/// How often `--watch` looks at the files again.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long the files must stay unchanged before `--watch` searches again,
+/// so a burst of writes such as a checkout leads to a single search.
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// The files a walk finds, with the size and modification time of each.
+/// Files are polled rather than watched for notifications, which works the
+/// same on every platform and file system.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    files: BTreeMap<PathBuf, (u64, Option<SystemTime>)>,
+}
+
+impl Snapshot {
+    /// Walks `roots` with the globs and filters of `options`. Files that
+    /// cannot be walked or read are left out, and show up once they can.
+    pub fn take<P: AsRef<Path>>(roots: &[P], options: &SearchOptions) -> Self {
+        let files = Walk::new(roots, options)
+            .filter_map(Result::ok)
+            .filter_map(|path| {
+                let metadata = fs::metadata(&path).ok()?;
+                Some((path, (metadata.len(), metadata.modified().ok())))
+            })
+            .collect();
+        Self { files }
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+/// Polls `roots` every `interval` until a file is added, removed or
+/// modified since `since`, then until nothing has changed for `debounce`.
+/// Returns the settled snapshot, or `None` once `cancel` is set.
+pub fn wait_for_change<P: AsRef<Path>>(
+    roots: &[P],
+    options: &SearchOptions,
+    since: &Snapshot,
+    interval: Duration,
+    debounce: Duration,
+    cancel: &AtomicBool,
+) -> Option<Snapshot> {
+    let mut latest = since.clone();
+    let mut changed_at = None;
+    loop {
+        thread::sleep(interval);
+        if cancel.load(Ordering::SeqCst) {
+            return None;
+        }
+
+        let snapshot = Snapshot::take(roots, options);
+        if snapshot != latest {
+            latest = snapshot;
+            changed_at = Some(Instant::now());
+        } else if changed_at.is_some_and(|at| at.elapsed() >= debounce) {
+            return Some(latest);
+        }
+    }
+}
+
+/// `time` as `HH:MM:SS` in UTC, for the header above each search.
+pub fn clock(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() % 86_400;
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const INTERVAL: Duration = Duration::from_millis(10);
+    const SETTLE: Duration = Duration::from_millis(50);
+
+    /// Applies `change` in the background while waiting for it.
+    fn wait_for(dir: &Path, change: impl FnOnce() + Send + 'static) -> Option<Snapshot> {
+        let options = SearchOptions::default();
+        let before = Snapshot::take(&[dir], &options);
+        let changer = thread::spawn(move || {
+            thread::sleep(SETTLE);
+            change();
+        });
+        let cancel = AtomicBool::new(false);
+        let after = wait_for_change(&[dir], &options, &before, INTERVAL, SETTLE, &cancel);
+        changer.join().unwrap();
+        after
+    }
+
+    #[test]
+    fn snapshot_lists_the_walked_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "a").unwrap();
+        fs::write(dir.path().join("b.bin"), "b").unwrap();
+        let options = SearchOptions {
+            globs: vec!["*.txt".to_string()],
+            ..Default::default()
+        };
+
+        let snapshot = Snapshot::take(&[dir.path()], &options);
+
+        assert_eq!(snapshot.len(), 1);
+        assert!(snapshot.files.contains_key(&dir.path().join("a.txt")));
+    }
+
+    #[test]
+    fn a_modified_file_is_a_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "a").unwrap();
+
+        let after = wait_for(dir.path(), move || fs::write(&file, "a longer line").unwrap());
+
+        assert_eq!(after.unwrap().files.values().next().unwrap().0, 13);
+    }
+
+    #[test]
+    fn added_and_removed_files_are_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("old.txt");
+        fs::write(&old, "a").unwrap();
+        let new = dir.path().join("new.txt");
+
+        let moved = {
+            let (old, new) = (old.clone(), new.clone());
+            wait_for(dir.path(), move || fs::rename(old, new).unwrap()).unwrap()
+        };
+
+        assert!(moved.files.contains_key(&new));
+        assert!(!moved.files.contains_key(&old));
+    }
+
+    #[test]
+    fn a_burst_of_writes_settles_into_one_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+
+        let after = wait_for(dir.path(), move || {
+            for i in 0..5 {
+                fs::write(root.join(format!("{i}.txt")), "a").unwrap();
+                thread::sleep(INTERVAL);
+            }
+        });
+
+        assert_eq!(after.unwrap().len(), 5);
+    }
+
+    #[test]
+    fn cancel_stops_waiting() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = SearchOptions::default();
+        let before = Snapshot::take(&[dir.path()], &options);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let canceller = {
+            let cancel = cancel.clone();
+            thread::spawn(move || {
+                thread::sleep(SETTLE);
+                cancel.store(true, Ordering::SeqCst);
+            })
+        };
+
+        let after = wait_for_change(&[dir.path()], &options, &before, INTERVAL, SETTLE, &cancel);
+        canceller.join().unwrap();
+
+        assert_eq!(after, None);
+    }
+
+    #[test]
+    fn clock_prints_utc_time_of_day() {
+        let time = UNIX_EPOCH + Duration::from_secs(19_000 * 86_400 + 13 * 3600 + 5 * 60 + 9);
+
+        assert_eq!(clock(time), "13:05:09");
+    }
+}
@@ -0,0 +1,90 @@
+#![cfg(feature = "capi")]
+
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use perg::capi::*;
+
+/// Compiles `pattern`, or returns the error message.
+fn compile(pattern: &str, flags: u32) -> Result<*mut PergRegex, String> {
+    let pattern = CString::new(pattern).unwrap();
+    let mut error = ptr::null_mut();
+    unsafe {
+        let re = perg_regex_compile(pattern.as_ptr(), flags, &mut error);
+        if re.is_null() {
+            let message = CStr::from_ptr(error).to_string_lossy().to_string();
+            perg_error_free(error);
+            return Err(message);
+        }
+        assert!(error.is_null());
+        Ok(re)
+    }
+}
+
+fn find(re: *const PergRegex, haystack: &[u8]) -> Result<Vec<PergMatch>, PergStatus> {
+    let mut matches = ptr::null_mut();
+    let mut count = 0;
+    unsafe {
+        match perg_regex_find_matches(
+            re,
+            haystack.as_ptr(),
+            haystack.len(),
+            &mut matches,
+            &mut count,
+        ) {
+            PergStatus::Ok if matches.is_null() => Ok(vec![]),
+            PergStatus::Ok => {
+                let found = std::slice::from_raw_parts(matches, count).to_vec();
+                perg_matches_free(matches, count);
+                Ok(found)
+            }
+            status => Err(status),
+        }
+    }
+}
+
+#[test]
+fn finds_matches_by_line_and_byte_span() {
+    let re = compile("ab*", PERG_IGNORE_CASE).unwrap();
+    let text = "xab\nźAB ab".as_bytes();
+
+    unsafe {
+        assert_eq!(perg_regex_is_match(re, text.as_ptr(), text.len()), 1);
+        assert_eq!(perg_regex_is_match(re, b"xy".as_ptr(), 2), 0);
+    }
+    assert_eq!(
+        find(re, text),
+        Ok(vec![
+            PergMatch { line: 0, from: 1, to: 3 },
+            PergMatch { line: 1, from: 2, to: 4 },
+            PergMatch { line: 1, from: 5, to: 7 },
+        ])
+    );
+    assert_eq!(find(re, b"zzz"), Ok(vec![]));
+    unsafe { perg_regex_free(re) };
+}
+
+#[test]
+fn anchored_patterns_match_at_line_starts_only() {
+    let re = compile("ab", PERG_ANCHORED).unwrap();
+
+    assert_eq!(find(re, b"ab\nxab"), Ok(vec![PergMatch { line: 0, from: 0, to: 2 }]));
+    unsafe { perg_regex_free(re) };
+}
+
+#[test]
+fn failures_are_status_codes_not_crashes() {
+    assert_eq!(compile("a)", 0).unwrap_err(), "unmatched ')' at position 1");
+
+    let re = compile("a", 0).unwrap();
+    assert_eq!(find(re, b"a\xff"), Err(PergStatus::InvalidUtf8));
+    assert_eq!(find(ptr::null(), b"a"), Err(PergStatus::NullPointer));
+    unsafe {
+        assert_eq!(perg_regex_is_match(re, ptr::null(), 1), PergStatus::NullPointer as i32);
+        assert_eq!(perg_regex_is_match(re, ptr::null(), 0), 0);
+        assert!(perg_regex_compile(ptr::null(), 0, ptr::null_mut()).is_null());
+        perg_regex_free(re);
+        perg_regex_free(ptr::null_mut());
+        perg_matches_free(ptr::null_mut(), 0);
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// Runs perg on `input` with `config_home` as the user's config directory,
+/// and `$PERG_CONFIG_PATH` set to `config_path` if given.
+fn perg(args: &[&str], input: &[u8], config_home: &Path, config_path: Option<&Path>) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_perg"));
+    command
+        .args(args)
+        .env("NO_COLOR", "1")
+        .env("XDG_CONFIG_HOME", config_home)
+        .env("APPDATA", config_home)
+        .env_remove("PERG_CONFIG_PATH")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(path) = config_path {
+        command.env("PERG_CONFIG_PATH", path);
+    }
+    let mut child = command.spawn().expect("Failed to run perg");
+    // perg may fail before it reads anything.
+    let _ = child.stdin.take().unwrap().write_all(input);
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: Output) -> String {
+    assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+/// A config directory holding `perg/config.toml` with `text`.
+fn config_home(text: &str) -> tempfile::TempDir {
+    let home = tempfile::tempdir().unwrap();
+    fs::create_dir(home.path().join("perg")).unwrap();
+    fs::write(home.path().join("perg").join("config.toml"), text).unwrap();
+    home
+}
+
+const INPUT: &[u8] = b"a\nx\nb\n";
+
+#[test]
+fn config_file_sets_defaults() {
+    let home = config_home("context = 0\n");
+
+    assert_eq!(stdout(perg(&["-p", "x", "-"], INPUT, home.path(), None)), "<stdin>\n2 x\n");
+}
+
+#[test]
+fn command_line_overrides_the_config_file() {
+    let home = config_home("context = 0\n");
+
+    let output = perg(&["-p", "x", "-C", "1", "-"], INPUT, home.path(), None);
+    assert_eq!(stdout(output), "<stdin>\n1 a\n2 x\n3 b\n");
+}
+
+#[test]
+fn config_path_variable_replaces_the_config_directory() {
+    let home = config_home("context = 0\n");
+    let other = home.path().join("other.toml");
+    fs::write(&other, "context = 1\ncount = true\n").unwrap();
+
+    let output = perg(&["-p", "x", "-"], INPUT, home.path(), Some(&other));
+    assert_eq!(stdout(output), "<stdin>:1\n");
+}
+
+#[test]
+fn no_config_reads_neither_file() {
+    let home = config_home("context = 0\n");
+    let other = home.path().join("other.toml");
+    fs::write(&other, "count = true\n").unwrap();
+
+    let output = perg(&["-p", "x", "--no-config", "-"], INPUT, home.path(), Some(&other));
+    assert_eq!(stdout(output), "<stdin>\n1 a\n2 x\n3 b\n");
+}
+
+#[test]
+fn lists_from_the_command_line_add_to_the_config_file() {
+    let home = config_home("exclude_dir = [\"target\"]\n");
+    let root = tempfile::tempdir().unwrap();
+    for dir in ["src", "target", "vendor"] {
+        fs::create_dir(root.path().join(dir)).unwrap();
+        fs::write(root.path().join(dir).join("a.txt"), "x\n").unwrap();
+    }
+    let root = root.path().to_str().unwrap();
+
+    let found = stdout(perg(
+        &["-p", "x", "-l", root, "--exclude-dir", "vendor"],
+        b"",
+        home.path(),
+        None,
+    ));
+    assert_eq!(found.lines().count(), 1, "{found}");
+    assert!(found.contains("src"), "{found}");
+}
+
+#[test]
+fn missing_config_file_is_only_an_error_when_named() {
+    let home = tempfile::tempdir().unwrap();
+    assert_eq!(
+        stdout(perg(&["-p", "x", "-C", "0", "-"], INPUT, home.path(), None)),
+        "<stdin>\n2 x\n"
+    );
+
+    let missing = home.path().join("missing.toml");
+    let output = perg(&["-p", "x", "-"], INPUT, home.path(), Some(&missing));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr.starts_with(&format!("perg: config file {}: ", missing.display())), "{stderr}");
+}
+
+#[test]
+fn invalid_config_file_names_the_file_and_line() {
+    let home = config_home("context = 0\nexclude_dir = [\"target\"\n");
+
+    let output = perg(&["-p", "x", "-"], INPUT, home.path(), None);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let path = home.path().join("perg").join("config.toml");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr.starts_with(&format!(
+            "perg: config file {}: TOML parse error at line 2",
+            path.display()
+        )),
+        "{stderr}"
+    );
+}
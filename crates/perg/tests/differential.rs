@@ -0,0 +1,548 @@
+#![cfg(feature = "fallback-regex")]
+//! Differential tests: random patterns from the grammar perg supports are
+//! compiled by perg's engine and by the regex crate, and both are run over
+//! random haystacks. Every line must match in both or in neither, at the same
+//! leftmost offset; whole spans are compared where both engines define them
+//! the same way. A divergence is shrunk before it is reported.
+//!
+//! `PERG_FUZZ_SEED` and `PERG_FUZZ_CASES` pick another run than the default
+//! one, e.g. `PERG_FUZZ_CASES=100000 cargo test --features fallback-regex
+//! --test differential`.
+
+use std::env;
+use std::fmt::Write;
+
+use perg::matcher::{build_matcher, Engine, Match};
+use perg::nfa::NfaOptions;
+
+const DEFAULT_SEED: u64 = 0x5eed_9e79;
+const DEFAULT_CASES: usize = 2000;
+
+/// Characters patterns are built from.
+const PATTERN_CHARS: &[char] = &['a', 'b', 'ś', '1', '.'];
+
+/// Letters of the shorthands sets may hold.
+const SHORTHANDS: &[char] = &['d', 'D', 'w', 'W', 's', 'S'];
+
+/// Characters haystacks are built from.
+const HAYSTACK_CHARS: &[char] = &['a', 'b', 'ś', '1', '2', '_', ' ', '\n'];
+
+/// A pattern in the grammar perg supports.
+#[derive(Clone, Debug)]
+enum Node {
+    Char(char),
+    /// `\d`, a single digit.
+    Digit,
+    /// `\w`, a single alphanumeric character or `_`.
+    Word,
+    /// `\b`, or `\B` when negated.
+    Boundary(bool),
+    /// `[...]` or `[^...]`, with shorthands like `\d` written after the
+    /// characters by their letter.
+    Set {
+        chars: Vec<char>,
+        shorthands: Vec<char>,
+        negated: bool,
+    },
+    Concat(Box<Node>, Box<Node>),
+    Union(Box<Node>, Box<Node>),
+    Star(Box<Node>),
+    LazyStar(Box<Node>),
+    Plus(Box<Node>),
+    Optional(Box<Node>),
+    /// `{min,max}`, or `{min,}` without a `max`.
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+impl Node {
+    fn random(rng: &mut Rng, depth: usize) -> Node {
+        let leaf = depth == 0 || rng.below(3) == 0;
+        match (leaf, rng.below(11)) {
+            (true, 0) => Node::Digit,
+            (true, 1) => Node::Word,
+            (true, 3) => Node::Boundary(rng.below(2) == 0),
+            (true, 2) => {
+                let chars = (0..=rng.below(2)).map(|_| rng.pick(PATTERN_CHARS)).collect();
+                let shorthands = (0..rng.below(3) / 2).map(|_| rng.pick(SHORTHANDS)).collect();
+                Node::Set {
+                    chars,
+                    shorthands,
+                    negated: rng.below(2) == 0,
+                }
+            }
+            (true, _) => Node::Char(rng.pick(PATTERN_CHARS)),
+            (false, 0..=3) => Node::Concat(Self::boxed(rng, depth), Self::boxed(rng, depth)),
+            (false, 4 | 5) => Node::Union(Self::boxed(rng, depth), Self::boxed(rng, depth)),
+            (false, 6) => Node::Star(Self::boxed(rng, depth)),
+            (false, 7) => Node::Plus(Self::boxed(rng, depth)),
+            (false, 8) => Node::Optional(Self::boxed(rng, depth)),
+            (false, 9) => {
+                let min = rng.below(3);
+                let max = [None, Some(min), Some(min + 1 + rng.below(2))][rng.below(3)];
+                Node::Repeat(Self::boxed(rng, depth), min, max)
+            }
+            (false, _) => Node::LazyStar(Self::boxed(rng, depth)),
+        }
+    }
+
+    fn boxed(rng: &mut Rng, depth: usize) -> Box<Node> {
+        Box::new(Self::random(rng, depth - 1))
+    }
+
+    /// The pattern in perg's syntax.
+    fn perg(&self) -> String {
+        match self {
+            Node::Char(c) => c.to_string(),
+            Node::Digit => "\\d".to_string(),
+            Node::Word => "\\w".to_string(),
+            Node::Boundary(false) => "\\b".to_string(),
+            Node::Boundary(true) => "\\B".to_string(),
+            Node::Set {
+                chars,
+                shorthands,
+                negated,
+            } => set(chars, shorthands.iter().map(|c| format!("\\{c}")), *negated),
+            Node::Concat(a, b) => format!("{}{}", a.perg(), b.perg()),
+            Node::Union(a, b) => format!("({}|{})", a.perg(), b.perg()),
+            Node::Star(a) => format!("{}*", a.perg_operand()),
+            Node::LazyStar(a) => format!("{}*?", a.perg_operand()),
+            Node::Plus(a) => format!("{}+", a.perg_operand()),
+            Node::Optional(a) => format!("{}?", a.perg_operand()),
+            Node::Repeat(a, min, max) => format!("{}{}", a.perg_operand(), counts(*min, *max)),
+        }
+    }
+
+    fn perg_operand(&self) -> String {
+        match self {
+            Node::Concat(..)
+            | Node::Star(_)
+            | Node::LazyStar(_)
+            | Node::Plus(_)
+            | Node::Optional(_)
+            | Node::Repeat(..) => {
+                format!("({})", self.perg())
+            }
+            _ => self.perg(),
+        }
+    }
+
+    /// The same pattern in the regex crate's syntax.
+    fn regex(&self) -> String {
+        match self {
+            Node::Char(c) => c.to_string(),
+            Node::Digit => "\\d".to_string(),
+            Node::Word => "[\\p{Alphabetic}\\p{N}_]".to_string(),
+            Node::Boundary(false) => "\\b".to_string(),
+            Node::Boundary(true) => "\\B".to_string(),
+            Node::Set {
+                chars,
+                shorthands,
+                negated,
+            } => set(chars, shorthands.iter().map(|c| regex_shorthand(*c)), *negated),
+            Node::Concat(a, b) => format!("{}{}", a.regex(), b.regex()),
+            Node::Union(a, b) => format!("(?:{}|{})", a.regex(), b.regex()),
+            Node::Star(a) => format!("{}*", a.regex_operand()),
+            Node::LazyStar(a) => format!("{}*?", a.regex_operand()),
+            Node::Plus(a) => format!("{}+", a.regex_operand()),
+            Node::Optional(a) => format!("{}?", a.regex_operand()),
+            Node::Repeat(a, min, max) => format!("{}{}", a.regex_operand(), counts(*min, *max)),
+        }
+    }
+
+    fn regex_operand(&self) -> String {
+        match self {
+            Node::Concat(..)
+            | Node::Star(_)
+            | Node::LazyStar(_)
+            | Node::Plus(_)
+            | Node::Optional(_)
+            | Node::Repeat(..) => {
+                format!("(?:{})", self.regex())
+            }
+            _ => self.regex(),
+        }
+    }
+
+    /// Whether perg and the regex crate pick the same span at a given start.
+    /// A lazy star makes perg try alternatives in order, as the regex crate
+    /// always does; otherwise perg takes the longest match, which only agrees
+    /// when there is a single way to match. Which of the ways to repeat an
+    /// empty match comes first is left to each engine.
+    fn spans_are_comparable(&self) -> bool {
+        let repeats_empty = self.has(&|node| match node {
+            Node::Star(a) | Node::LazyStar(a) | Node::Plus(a) | Node::Repeat(a, ..) => {
+                a.matches_empty()
+            }
+            _ => false,
+        });
+        let lazy = self.has(&|node| matches!(node, Node::LazyStar(_)));
+        let ambiguous = self.has(&|node| match node {
+            Node::Union(..) | Node::Star(_) | Node::Plus(_) | Node::Optional(_) => true,
+            Node::Repeat(_, min, max) => *max != Some(*min),
+            _ => false,
+        });
+        !repeats_empty && (lazy || !ambiguous)
+    }
+
+    fn matches_empty(&self) -> bool {
+        match self {
+            Node::Concat(a, b) => a.matches_empty() && b.matches_empty(),
+            Node::Union(a, b) => a.matches_empty() || b.matches_empty(),
+            Node::Star(_) | Node::LazyStar(_) | Node::Optional(_) | Node::Boundary(_) => true,
+            Node::Plus(a) => a.matches_empty(),
+            Node::Repeat(a, min, _) => *min == 0 || a.matches_empty(),
+            _ => false,
+        }
+    }
+
+    fn has(&self, predicate: &dyn Fn(&Node) -> bool) -> bool {
+        predicate(self)
+            || match self {
+                Node::Concat(a, b) | Node::Union(a, b) => a.has(predicate) || b.has(predicate),
+                Node::Star(a)
+                | Node::LazyStar(a)
+                | Node::Plus(a)
+                | Node::Optional(a)
+                | Node::Repeat(a, ..) => a.has(predicate),
+                _ => false,
+            }
+    }
+
+    /// Smaller patterns to try while shrinking a divergence.
+    fn shrinks(&self) -> Vec<Node> {
+        let mut smaller = vec![];
+        match self {
+            Node::Concat(a, b) | Node::Union(a, b) => {
+                smaller.push((**a).clone());
+                smaller.push((**b).clone());
+                let rebuild = |a, b| match self {
+                    Node::Concat(..) => Node::Concat(Box::new(a), Box::new(b)),
+                    _ => Node::Union(Box::new(a), Box::new(b)),
+                };
+                smaller.extend(a.shrinks().into_iter().map(|a| rebuild(a, (**b).clone())));
+                smaller.extend(b.shrinks().into_iter().map(|b| rebuild((**a).clone(), b)));
+            }
+            Node::Star(a)
+            | Node::LazyStar(a)
+            | Node::Plus(a)
+            | Node::Optional(a)
+            | Node::Repeat(a, ..) => {
+                smaller.push((**a).clone());
+                smaller.extend(a.shrinks().into_iter().map(|a| match self {
+                    Node::LazyStar(_) => Node::LazyStar(Box::new(a)),
+                    Node::Plus(_) => Node::Plus(Box::new(a)),
+                    Node::Optional(_) => Node::Optional(Box::new(a)),
+                    Node::Repeat(_, min, max) => Node::Repeat(Box::new(a), *min, *max),
+                    _ => Node::Star(Box::new(a)),
+                }));
+            }
+            Node::Set {
+                chars,
+                shorthands,
+                negated,
+            } if chars.len() > 1 || !shorthands.is_empty() => {
+                for i in 0..chars.len() {
+                    let mut chars = chars.clone();
+                    chars.remove(i);
+                    smaller.push(Node::Set {
+                        chars,
+                        shorthands: shorthands.clone(),
+                        negated: *negated,
+                    });
+                }
+                for i in 0..shorthands.len() {
+                    let mut shorthands = shorthands.clone();
+                    shorthands.remove(i);
+                    smaller.push(Node::Set {
+                        chars: chars.clone(),
+                        shorthands,
+                        negated: *negated,
+                    });
+                }
+            }
+            Node::Digit | Node::Word | Node::Set { .. } | Node::Boundary(_) => {
+                smaller.push(Node::Char('a'))
+            }
+            Node::Char(_) => {}
+        }
+        smaller
+    }
+}
+
+/// `{min,max}`, or `{min,}` without a `max`.
+fn counts(min: usize, max: Option<usize>) -> String {
+    match max {
+        Some(max) => format!("{{{min},{max}}}"),
+        None => format!("{{{min},}}"),
+    }
+}
+
+fn set(chars: &[char], shorthands: impl Iterator<Item = String>, negated: bool) -> String {
+    let chars: String = chars.iter().copied().map(String::from).chain(shorthands).collect();
+    match negated {
+        true => format!("[^{chars}]"),
+        false => format!("[{chars}]"),
+    }
+}
+
+/// perg's shorthand `\\c` in a set, in the regex crate's syntax.
+fn regex_shorthand(c: char) -> String {
+    match c {
+        'd' => "\\p{N}",
+        'D' => "\\P{N}",
+        'w' => "\\p{Alphabetic}\\p{N}_",
+        'W' => "[^\\p{Alphabetic}\\p{N}_]",
+        's' => "\\s",
+        _ => "\\S",
+    }
+    .to_string()
+}
+
+/// What both engines found for one pattern and haystack.
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    is_match: bool,
+    /// Every match when spans are comparable, else the first of each line
+    /// with its end left out.
+    matches: Vec<Match>,
+}
+
+fn outcome(pattern: &str, engine: Engine, haystack: &str, spans: bool) -> Result<Outcome, String> {
+    let matcher =
+        build_matcher(pattern, &NfaOptions::default(), engine).map_err(|err| err.to_string())?;
+    let mut matches = matcher.find_matches(haystack);
+    if !spans {
+        matches.dedup_by_key(|m| m.line);
+        matches.iter_mut().for_each(|m| m.to = m.from);
+    }
+    Ok(Outcome {
+        is_match: matcher.is_match(haystack),
+        matches,
+    })
+}
+
+/// Both engines' outcomes, if they disagree.
+fn divergence(node: &Node, haystack: &str) -> Option<String> {
+    let spans = node.spans_are_comparable();
+    let perg = outcome(&node.perg(), Engine::Nfa, haystack, spans);
+    let regex = outcome(&node.regex(), Engine::Regex, haystack, spans);
+    match perg == regex {
+        true => None,
+        false => Some(format!("perg:  {perg:?}\nregex: {regex:?}")),
+    }
+}
+
+/// Shrinks the pattern and then the haystack for as long as they still
+/// diverge, and describes the smallest case found.
+fn minimized(mut node: Node, mut haystack: String) -> String {
+    'pattern: loop {
+        for smaller in node.shrinks() {
+            if divergence(&smaller, &haystack).is_some() {
+                node = smaller;
+                continue 'pattern;
+            }
+        }
+        break;
+    }
+    'haystack: loop {
+        for (i, _) in haystack.char_indices() {
+            let mut shorter = haystack.clone();
+            shorter.remove(i);
+            if divergence(&node, &shorter).is_some() {
+                haystack = shorter;
+                continue 'haystack;
+            }
+        }
+        break;
+    }
+
+    let mut report = String::new();
+    let _ = writeln!(report, "pattern:  {}", node.perg());
+    let _ = writeln!(report, "as regex: {}", node.regex());
+    let _ = writeln!(report, "haystack: {haystack:?}");
+    let _ = write!(report, "{}", divergence(&node, &haystack).unwrap_or_default());
+    report
+}
+
+/// xorshift64*, so a seed always gives the same cases.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Spread nearby seeds apart; the state must not be zero.
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick(&mut self, chars: &[char]) -> char {
+        chars[self.below(chars.len())]
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+#[test]
+fn engines_agree_on_random_patterns() {
+    let seed = env_or("PERG_FUZZ_SEED", DEFAULT_SEED);
+    let cases = env_or("PERG_FUZZ_CASES", DEFAULT_CASES);
+    let mut rng = Rng::new(seed);
+
+    for case in 0..cases {
+        let node = Node::random(&mut rng, 4);
+        let haystack: String = (0..rng.below(16)).map(|_| rng.pick(HAYSTACK_CHARS)).collect();
+        if divergence(&node, &haystack).is_some() {
+            panic!(
+                "engines diverge on case {case} of seed {seed}\n{}",
+                minimized(node, haystack)
+            );
+        }
+    }
+}
+
+#[test]
+fn engines_agree_on_known_tricky_patterns() {
+    use Node::*;
+    let b = Box::new;
+    let tests = vec![
+        // Empty matches, between and after other matches.
+        (Star(b(Char('a'))), "bbb\n\nbaab"),
+        (Star(b(Concat(b(Char('a')), b(Char('b'))))), "xabab"),
+        // Nested stars that once looped forever.
+        (Concat(b(Star(b(Star(b(Char('a')))))), b(Char('b'))), "xaab"),
+        // Lazy stars, on their own and after a greedy one.
+        (Concat(b(Char('x')), b(LazyStar(b(Char('a'))))), "xaa"),
+        (
+            Concat(
+                b(Concat(b(Char('x')), b(LazyStar(b(Char('a')))))),
+                b(Char('b')),
+            ),
+            "xaab",
+        ),
+        (
+            Concat(b(Star(b(Char('a')))), b(LazyStar(b(Char('b'))))),
+            "aabb",
+        ),
+        (
+            Concat(
+                b(Union(b(Char('a')), b(Concat(b(Char('a')), b(Char('b')))))),
+                b(LazyStar(b(Char('c')))),
+            ),
+            "abc",
+        ),
+        // Multi-byte characters and sets.
+        (Concat(b(Char('ś')), b(Char('a'))), "aśśa"),
+        (
+            Concat(
+                b(Set {
+                    chars: vec!['ś', 'b'],
+                    shorthands: vec![],
+                    negated: false,
+                }),
+                b(Char('a')),
+            ),
+            "baśa",
+        ),
+        (
+            Star(b(Set {
+                chars: vec!['a'],
+                shorthands: vec![],
+                negated: true,
+            })),
+            "bśab\na",
+        ),
+        (
+            Concat(
+                b(Char('a')),
+                b(Set {
+                    chars: vec!['1'],
+                    shorthands: vec![],
+                    negated: false,
+                }),
+            ),
+            "1 a1",
+        ),
+        (
+            Concat(
+                b(Set {
+                    chars: vec!['ś'],
+                    shorthands: vec![],
+                    negated: true,
+                }),
+                b(Set {
+                    chars: vec!['a'],
+                    shorthands: vec![],
+                    negated: false,
+                }),
+            ),
+            "1a śa",
+        ),
+        // Shorthands in sets.
+        (
+            Star(b(Set {
+                chars: vec!['_'],
+                shorthands: vec!['d'],
+                negated: false,
+            })),
+            "a1_2 b",
+        ),
+        (
+            Set {
+                chars: vec![],
+                shorthands: vec!['w', 's'],
+                negated: true,
+            },
+            "a 1_ś\n",
+        ),
+        (
+            Concat(
+                b(Set {
+                    chars: vec!['a'],
+                    shorthands: vec!['D'],
+                    negated: true,
+                }),
+                b(Char('b')),
+            ),
+            "ab 1b śb",
+        ),
+        // Digits and word characters.
+        (Concat(b(Digit), b(Char('1'))), "a121 11"),
+        (Concat(b(Word), b(Word)), "_a ś1 b"),
+        // One or more, of a character and of a group.
+        (Concat(b(Plus(b(Char('a')))), b(Char('b'))), "b ab aab"),
+        (Plus(b(Concat(b(Char('a')), b(Char('b'))))), "a abab aba"),
+        // Zero or one, at the start and in the middle of a pattern.
+        (Concat(b(Optional(b(Char('a')))), b(Char('b'))), "b ab aab"),
+        (Concat(b(Concat(b(Char('a')), b(Optional(b(Char('ś')))))), b(Char('b'))), "ab aśb aśśb"),
+        // Counted repetitions, exact, bounded and open.
+        (Concat(b(Repeat(b(Char('a')), 2, Some(2))), b(Char('b'))), "ab aab aaab"),
+        (Repeat(b(Concat(b(Char('a')), b(Char('b')))), 1, Some(2)), "ab ababab"),
+        (Concat(b(Char('b')), b(Repeat(b(Digit), 2, None))), "b1 b12 b1a2"),
+        // Word boundaries, at the ends of lines and around `_`.
+        (
+            Concat(
+                b(Concat(b(Boundary(false)), b(Char('a')))),
+                b(Boundary(false)),
+            ),
+            "a ab_a a_ śa\na",
+        ),
+        (Concat(b(Char('a')), b(Boundary(true))), "ab a a_ aś"),
+    ];
+
+    for (node, haystack) in tests {
+        if let Some(divergence) = divergence(&node, haystack) {
+            panic!("'{}' in {haystack:?}\n{divergence}", node.perg());
+        }
+    }
+}
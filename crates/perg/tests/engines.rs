@@ -0,0 +1,55 @@
+#![cfg(feature = "fallback-regex")]
+
+use std::fs;
+
+use perg::matcher::{build_matcher, Engine, Match};
+use perg::nfa::NfaOptions;
+
+/// Runs `pattern` over a fixture with both engines and returns their matches.
+fn both_engines(pattern: &str, fixture: &str, options: &NfaOptions) -> (Vec<Match>, Vec<Match>) {
+    let text = fs::read_to_string(format!("../../test_files/{fixture}")).unwrap();
+    let nfa = build_matcher(pattern, options, Engine::Nfa).unwrap();
+    let regex = build_matcher(pattern, options, Engine::Regex).unwrap();
+
+    (nfa.find_matches(&text), regex.find_matches(&text))
+}
+
+#[test]
+fn engines_agree_on_fixture_searches() {
+    let options = NfaOptions::default();
+    let tests = vec![
+        ("Tadeusz", "pan-tadeusz.txt"),
+        ("Soplica", "pan-tadeusz.txt"),
+        ("abba", "random.txt"),
+        ("a_b_c", "random.txt"),
+        ("[xyz]q", "random.txt"),
+    ];
+
+    for (pattern, fixture) in tests {
+        let (nfa, regex) = both_engines(pattern, fixture, &options);
+        println!("'{pattern}' in {fixture}");
+        assert_eq!(nfa, regex);
+    }
+}
+
+#[test]
+fn engines_agree_on_matching_lines_ignoring_case() {
+    let options = NfaOptions {
+        ignore_case: true,
+        ..Default::default()
+    };
+
+    let (nfa, regex) = both_engines("litwo", "pan-tadeusz.txt", &options);
+    let lines = |matches: &[Match]| matches.iter().map(|m| m.line).collect::<Vec<_>>();
+
+    assert!(!nfa.is_empty());
+    assert_eq!(lines(&nfa), lines(&regex));
+}
+
+#[test]
+fn auto_engine_falls_back_for_unsupported_syntax() {
+    let matcher = build_matcher("Tadeusz\\pZ", &NfaOptions::default(), Engine::Auto).unwrap();
+    let text = fs::read_to_string("../../test_files/pan-tadeusz.txt").unwrap();
+
+    assert!(matcher.is_match(&text));
+}
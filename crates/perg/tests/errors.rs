@@ -0,0 +1,205 @@
+use std::fs;
+use std::process::{Command, Output};
+
+fn perg(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_perg"))
+        .args(args)
+        .output()
+        .expect("Failed to run perg")
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+#[test]
+fn invalid_pattern_exits_with_two() {
+    let output = perg(&["-p", "a)", "../../test_files", "-g", "*.txt"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("perg: invalid pattern: unmatched ')'"));
+}
+
+#[test]
+fn invalid_pattern_points_at_the_error() {
+    let output = perg(&["-p", "fn (a", "../../test_files", "-g", "*.txt"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        stderr(&output),
+        concat!(
+            "perg: invalid pattern: the group opened at position 3 is never closed\n",
+            "    fn (a\n",
+            "       ^\n"
+        )
+    );
+}
+
+#[test]
+fn invalid_glob_exits_with_two() {
+    let output = perg(&["-p", "a", "../../test_files", "-g", "*.[abc"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("perg: glob search failed: Invalid pattern"));
+}
+
+#[test]
+fn missing_path_exits_with_two() {
+    let output = perg(&["-p", "a", "../../test_files/does_not_exist", "-g", "*"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("does not exist"));
+}
+
+#[test]
+fn invalid_utf8_file_is_skipped_with_a_warning() {
+    let output = perg(&["-p", "caf", "../../test_files/latin1.csv", "-g", "*", "--stats"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stderr(&output).contains("latin1.csv: skipped, not valid UTF-8"));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("\n0 matches\n"), "{stdout}");
+    assert!(stdout.ends_with(
+        "\n0 files searched\n0 bytes searched\n0 long lines skipped\n1 files skipped\n\
+         0 files timed out\n"
+    ));
+}
+
+#[test]
+fn text_searches_invalid_utf8_lossily() {
+    let output = perg(&["-p", "caf", "-a", "--vimgrep", "../../test_files/latin1.csv", "-g", "*"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stderr(&output).is_empty());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "../../test_files/latin1.csv:1:1:caf\u{FFFD} au lait\n"
+    );
+}
+
+#[test]
+fn successful_search_exits_with_zero() {
+    let output = perg(&["-p", "abba", "../../test_files", "-g", "*.txt"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stderr(&output).is_empty());
+}
+
+#[test]
+fn search_without_matches_exits_with_zero() {
+    let output = perg(&["-p", "no such text", "../../test_files", "-g", "*.txt"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stderr(&output).is_empty());
+}
+
+#[test]
+fn globs_matching_nothing_exit_with_one() {
+    let output = perg(&["-p", "a", "../../test_files", "-g", "*.nothing"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "perg: no files matched the provided globs under ../../test_files\n"
+    );
+}
+
+#[test]
+fn filtered_out_files_exit_with_one() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("image.png"), "a\n").unwrap();
+
+    let output = perg(&["-p", "a", dir.path().to_str().unwrap(), "-g", "*"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("no files matched the provided globs under"));
+}
+
+#[test]
+fn empty_directory_without_globs_exits_with_one() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().to_str().unwrap();
+
+    let output = perg(&["-p", "a", path]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), format!("perg: no searchable files under {path}\n"));
+}
+
+#[test]
+fn vimgrep_reports_unreadable_files_and_continues() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), [b'x', 0xFF]).unwrap();
+    fs::write(dir.path().join("b.txt"), "xyz\n").unwrap();
+
+    let output = perg(&["-p", "x", "--vimgrep", dir.path().to_str().unwrap(), "-g", "*.txt"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stderr(&output).contains("a.txt: skipped, not valid UTF-8"));
+    assert!(String::from_utf8_lossy(&output.stdout).ends_with("b.txt:1:1:xyz\n"));
+}
+
+#[cfg(unix)]
+#[test]
+fn count_shows_a_non_utf8_file_name_lossily() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join(OsStr::from_bytes(b"a\xffb.txt")), "needle\nneedle\n").unwrap();
+
+    let output = perg(&["-p", "needle", dir.path().to_str().unwrap(), "-c"]);
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).ends_with("a\u{FFFD}b.txt:2\n"));
+}
+
+#[cfg(unix)]
+#[test]
+fn failing_pre_command_skips_the_file_with_a_warning() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let pre = dir.path().join("pre.sh");
+    fs::write(&pre, "#!/bin/sh\ncase \"$1\" in *bad*) exit 1;; esac\ncat \"$1\"\n").unwrap();
+    fs::set_permissions(&pre, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.path().join("bad.txt"), "x\n").unwrap();
+    fs::write(dir.path().join("good.txt"), "x\n").unwrap();
+
+    let args = ["-p", "x", "-l", "--stats", "--pre", pre.to_str().unwrap(), "--pre-glob", "*.txt"];
+    let output = perg(&[&args[..], &[dir.path().to_str().unwrap(), "-g", "*.txt"]].concat());
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(
+        stderr(&output).contains("bad.txt: skipped, --pre failed: command exit status: 1"),
+        "{}",
+        stderr(&output)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with(&format!("{}\n", dir.path().join("good.txt").display())),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("\n1 files searched\n")
+            && stdout.ends_with("\n1 files skipped\n0 files timed out\n"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn watch_refuses_output_that_is_not_a_terminal() {
+    let output = perg(&["--watch", "-p", "a", "../../test_files"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("--watch needs stdout to be a terminal"));
+}
+
+#[test]
+fn unknown_format_placeholder_exits_with_two_before_searching() {
+    let output = perg(&["-p", "a", "--format", "{path}:{name}", "../../test_files/does_not_exist"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("unknown placeholder '{name}'"), "{}", stderr(&output));
+    assert!(!stderr(&output).contains("does not exist"));
+}
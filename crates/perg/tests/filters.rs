@@ -0,0 +1,190 @@
+use std::fs;
+use std::process::Command;
+
+/// The files `perg -l` lists under `dir`, by name.
+fn listed(dir: &std::path::Path, extra: &[&str]) -> Vec<String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_perg"))
+        .args(["-p", "needle", "-l", dir.to_str().unwrap(), "-g", "*"])
+        .args(extra)
+        .env("NO_COLOR", "1")
+        .output()
+        .expect("Failed to run perg");
+
+    let mut names: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.rsplit('/').next().unwrap().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn extension_filter_is_configurable() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["Cargo.toml", "notes.md", "README"] {
+        fs::write(dir.path().join(name), "needle\n").unwrap();
+    }
+
+    assert_eq!(listed(dir.path(), &[]), ["notes.md"]);
+    assert_eq!(listed(dir.path(), &["--ext", "toml"]), ["Cargo.toml", "notes.md"]);
+    assert_eq!(listed(dir.path(), &["--ext", ".TOML"]), ["Cargo.toml", "notes.md"]);
+    assert_eq!(listed(dir.path(), &["--no-ext-filter"]), ["Cargo.toml", "README", "notes.md"]);
+}
+
+#[test]
+fn without_globs_every_file_is_searched() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("a.txt"), "foo\n").unwrap();
+    fs::write(dir.path().join("sub").join("b.rs"), "let foo = 1;\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_perg"))
+        .args(["-p", "foo", "-l", dir.path().to_str().unwrap()])
+        .env("NO_COLOR", "1")
+        .output()
+        .expect("Failed to run perg");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        format!(
+            "{}\n{}\n",
+            dir.path().join("a.txt").display(),
+            dir.path().join("sub/b.rs").display()
+        )
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn named_files_bypass_the_extension_filter() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("deploy");
+    fs::write(&script, "#!/bin/sh\necho needle\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let named = Command::new(env!("CARGO_BIN_EXE_perg"))
+        .args(["-p", "needle", "-l", script.to_str().unwrap()])
+        .env("NO_COLOR", "1")
+        .output()
+        .expect("Failed to run perg");
+    assert_eq!(named.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&named.stdout), format!("{}\n", script.display()));
+
+    assert!(listed(dir.path(), &[]).is_empty());
+}
+
+#[test]
+fn exclude_dir_skips_the_subtree_unless_it_is_the_path() {
+    let dir = tempfile::tempdir().unwrap();
+    for (sub, name) in [
+        ("src", "main.txt"),
+        ("target", "build.txt"),
+        ("node_modules/pkg", "index.txt"),
+    ] {
+        fs::create_dir_all(dir.path().join(sub)).unwrap();
+        fs::write(dir.path().join(sub).join(name), "needle\n").unwrap();
+    }
+
+    assert_eq!(listed(dir.path(), &[]), ["build.txt", "index.txt", "main.txt"]);
+    assert_eq!(
+        listed(dir.path(), &["--exclude-dir", "target", "--exclude-dir", "node_*"]),
+        ["main.txt"]
+    );
+    assert_eq!(listed(&dir.path().join("target"), &["--exclude-dir", "target"]), ["build.txt"]);
+}
+
+#[test]
+fn ignore_files_leave_out_what_their_rules_match() {
+    let dir = tempfile::tempdir().unwrap();
+    let files = [
+        ("", "a.log"),
+        ("", "keep.log"),
+        ("src", "main.txt"),
+        ("src", "b.log"),
+        ("build", "out.txt"),
+    ];
+    for (sub, name) in files {
+        fs::create_dir_all(dir.path().join(sub)).unwrap();
+        fs::write(dir.path().join(sub).join(name), "needle\n").unwrap();
+    }
+    let elsewhere = tempfile::tempdir().unwrap();
+    let unrelated = elsewhere.path().join("logs.ignore");
+    fs::write(&unrelated, "*.log\n").unwrap();
+    let logs = dir.path().join(".logs-ignore");
+    fs::write(&logs, "# logs\n*.log\n!keep.log\n").unwrap();
+    let src = dir.path().join("src").join(".ignore");
+    fs::write(&src, "/main.txt\n").unwrap();
+    let build = dir.path().join(".build-ignore");
+    fs::write(&build, "/build/\n").unwrap();
+
+    // Rules only apply below the directory of their file.
+    assert_eq!(
+        listed(dir.path(), &["--ignore-file", unrelated.to_str().unwrap()]),
+        ["a.log", "b.log", "keep.log", "main.txt", "out.txt"]
+    );
+    assert_eq!(
+        listed(dir.path(), &["--ignore-file", logs.to_str().unwrap()]),
+        ["keep.log", "main.txt", "out.txt"]
+    );
+    let all: Vec<&str> = [&logs, &src, &build]
+        .iter()
+        .flat_map(|file| ["--ignore-file", file.to_str().unwrap()])
+        .collect();
+    assert_eq!(listed(dir.path(), &all), ["keep.log"]);
+}
+
+#[test]
+fn a_missing_ignore_file_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("a.txt"), "needle\n").unwrap();
+    let missing = dir.path().join("missing.ignore");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_perg"))
+        .args([
+            "-p",
+            "needle",
+            dir.path().to_str().unwrap(),
+            "--ignore-file",
+            missing.to_str().unwrap(),
+        ])
+        .env("NO_COLOR", "1")
+        .output()
+        .expect("Failed to run perg");
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&missing.display().to_string()), "{stderr}");
+}
+
+#[test]
+fn a_path_with_wildcards_is_a_glob() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("src/bin")).unwrap();
+    for name in ["src/a.rs", "src/bin/b.rs", "src/c.md", "notes.md", "odd[1].txt"] {
+        fs::write(dir.path().join(name), "needle\n").unwrap();
+    }
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_perg"))
+            .args(["-p", "needle", "-l"])
+            .args(args)
+            .current_dir(dir.path())
+            .env("NO_COLOR", "1")
+            .output()
+            .expect("Failed to run perg");
+        (output.status.code(), String::from_utf8_lossy(&output.stdout).to_string())
+    };
+
+    assert_eq!(run(&["src/**/*.rs"]), (Some(0), "src/a.rs\nsrc/bin/b.rs\n".to_string()));
+    assert_eq!(run(&["src/*.rs"]), (Some(0), "src/a.rs\n".to_string()));
+    assert_eq!(run(&["*.md"]), (Some(0), "notes.md\n".to_string()));
+    // Files matching both globs are searched once.
+    let (_, stdout) = run(&["src/*.rs", "-g", "*.md", "-g", "*.rs"]);
+    assert_eq!(stdout, "src/a.rs\nsrc/c.md\nsrc/bin/b.rs\n");
+
+    assert_eq!(run(&["odd[1].txt"]).0, Some(1));
+    assert_eq!(run(&["odd[1].txt", "--literal-paths"]), (Some(0), "odd[1].txt\n".to_string()));
+}
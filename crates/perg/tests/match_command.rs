@@ -0,0 +1,161 @@
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs `perg match` with `args` and `stdin`, returning its exit code and
+/// stdout. The config file would have searches ignore case.
+fn perg_match(args: &[&str], stdin: &str) -> (Option<i32>, String) {
+    let config = tempfile::tempdir().unwrap();
+    let config_path = config.path().join("config.toml");
+    fs::write(&config_path, "ignore_case = true\n").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_perg"))
+        .arg("match")
+        .args(args)
+        .env("NO_COLOR", "1")
+        .env("PERG_CONFIG_PATH", &config_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run perg");
+
+    // perg does not read stdin when the text is given.
+    let _ = child.stdin.take().unwrap().write_all(stdin.as_bytes());
+    let output = child.wait_with_output().unwrap();
+    (output.status.code(), String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[test]
+fn prints_the_span_of_every_match() {
+    assert_eq!(
+        perg_match(&["-p", "(ab|a)*c", "xxabacyy"], ""),
+        (Some(0), "1:2..6 abac\n".to_string())
+    );
+    assert_eq!(
+        perg_match(&["-p", "a\\d", "xa12 a3"], ""),
+        (Some(0), "1:1..3 a1\n1:5..7 a3\n".to_string())
+    );
+}
+
+#[test]
+fn a_leading_caret_matches_at_line_starts_only() {
+    assert_eq!(
+        perg_match(&["-p", "^fn ", "fn a\n  fn b\nfn c"], ""),
+        (Some(0), "1:0..3 fn \n3:0..3 fn \n".to_string())
+    );
+}
+
+#[test]
+fn escaped_metacharacters_are_literal() {
+    assert_eq!(
+        perg_match(&["-p", "vec\\.push\\(", "v.push(1); vec.push(2)"], ""),
+        (Some(0), "1:11..20 vec.push(\n".to_string())
+    );
+}
+
+#[test]
+fn a_case_flag_ignores_case_for_the_rest_of_the_pattern() {
+    assert_eq!(
+        perg_match(&["-p", "e(?i)rror: ", "ERROR: a\nError: b\neRROR: c"], ""),
+        (Some(0), "3:0..7 eRROR: \n".to_string())
+    );
+}
+
+#[test]
+fn smart_case_ignores_case_for_lowercase_patterns_only() {
+    assert_eq!(
+        perg_match(&["-S", "-p", "error", "ERROR"], ""),
+        (Some(0), "1:0..5 ERROR\n".to_string())
+    );
+    assert_eq!(
+        perg_match(&["-S", "-p", "Error", "ERROR"], ""),
+        (Some(1), "no match\n".to_string())
+    );
+}
+
+#[test]
+fn tab_escapes_match_tabs() {
+    assert_eq!(
+        perg_match(&["-p", "foo\\tbar", "1\tfoo\tbar\tfoo bar"], ""),
+        (Some(0), "1:2..9 foo\tbar\n".to_string())
+    );
+}
+
+#[test]
+fn code_point_escapes_match_multi_byte_characters() {
+    assert_eq!(
+        perg_match(&["-p", "\\u{1F600}\\x21", "hi 😀!"], ""),
+        (Some(0), "1:3..8 😀!\n".to_string())
+    );
+}
+
+#[test]
+fn says_when_nothing_matched() {
+    assert_eq!(perg_match(&["-p", "q", "abc"], ""), (Some(1), "no match\n".to_string()));
+}
+
+#[test]
+fn reads_the_text_from_standard_input_without_an_argument() {
+    let (code, stdout) = perg_match(&["-p", "b*", "-i"], "ab\nBB\n");
+
+    assert_eq!(code, Some(0));
+    assert_eq!(stdout, "1:0..0 \n1:1..2 b\n2:0..2 BB\n");
+}
+
+#[test]
+fn json_gives_offsets_into_the_whole_text() {
+    let (_, stdout) = perg_match(&["-p", "cd", "--json"], "ab\ncd\n");
+
+    assert_eq!(
+        stdout,
+        concat!(
+            "{\"path\":\"<stdin>\",\"line_number\":2,\"column\":1,",
+            "\"start\":0,\"end\":2,\"text\":\"cd\"}\n"
+        )
+    );
+    assert_eq!(perg_match(&["-p", "x", "--json", "ab"], ""), (Some(1), String::new()));
+}
+
+#[test]
+fn debug_prints_the_automaton_first() {
+    let (_, stdout) = perg_match(&["-p", "ab", "--debug", "ab"], "");
+
+    assert!(stdout.starts_with("Number of states: 4\n"), "{stdout}");
+    assert!(stdout.ends_with("\n1:0..2 ab\n"), "{stdout}");
+}
+
+#[test]
+fn ignores_the_config_file() {
+    assert_eq!(perg_match(&["-p", "a", "A"], ""), (Some(1), "no match\n".to_string()));
+}
+
+#[test]
+fn invalid_patterns_are_errors() {
+    assert_eq!(perg_match(&["-p", "a)", "a"], "").0, Some(2));
+    assert_eq!(perg_match(&["-p", "", "a"], "").0, Some(2));
+}
+
+#[test]
+fn empty_groups_match_the_empty_string() {
+    assert_eq!(perg_match(&["-p", "a()b", "ab"], ""), (Some(0), "1:0..2 ab\n".to_string()));
+}
+
+#[test]
+fn fuzzy_matches_report_their_errors() {
+    assert_eq!(
+        perg_match(&["-p", "recieve", "--fuzzy", "1", "recieve receve"], ""),
+        (Some(0), "1:0..7 recieve\n1:8..14 receve (1 error)\n".to_string())
+    );
+    assert_eq!(
+        perg_match(&["-p", "recieve", "--max-errors", "2", "receive"], ""),
+        (Some(0), "1:0..7 receive (2 errors)\n".to_string())
+    );
+    assert_eq!(
+        perg_match(
+            &["-p", "recieve", "--fuzzy", "1", "--engine", "regex", "x"],
+            ""
+        )
+        .0,
+        Some(2)
+    );
+}
@@ -0,0 +1,80 @@
+//! Peak heap usage of a search, measured with a counting global allocator.
+//! This binary holds a single test so nothing else allocates concurrently.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use perg::search::{search_with, SearchEvent, SearchOptions};
+
+struct Counting;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        PEAK.fetch_max(current, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+const FILE_SIZE: usize = 1024 * 1024;
+
+/// Extra heap used at the peak of a count-only search of `dir`.
+fn peak_search_memory(dir: &std::path::Path, stream_threshold: Option<u64>) -> usize {
+    let options = SearchOptions {
+        globs: vec!["*.txt".to_string()],
+        threads: 1,
+        stream_threshold,
+        ..Default::default()
+    };
+
+    let baseline = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+
+    let mut matches = 0;
+    let _ = search_with("needle", &[dir], &options, |event| {
+        if let SearchEvent::FileEnd(_, stats) = event {
+            matches += stats.matches;
+        }
+        ControlFlow::Continue(())
+    })
+    .unwrap();
+
+    assert_eq!(matches, FILE_SIZE / 4096);
+    PEAK.load(Ordering::SeqCst) - baseline
+}
+
+#[test]
+fn streaming_keeps_peak_memory_flat() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let mut file = BufWriter::new(File::create(dir.path().join("big.txt")).unwrap());
+        let line = format!("{}\n", "x".repeat(63));
+        for i in 0..FILE_SIZE / line.len() {
+            if i % 64 == 0 {
+                write!(file, "needle {}", &line[7..]).unwrap();
+            } else {
+                file.write_all(line.as_bytes()).unwrap();
+            }
+        }
+    }
+
+    let streamed = peak_search_memory(dir.path(), Some(1));
+    let read = peak_search_memory(dir.path(), None);
+
+    assert!(read >= FILE_SIZE, "whole-file read peaked at {read} bytes");
+    assert!(streamed < FILE_SIZE / 16, "streaming peaked at {streamed} bytes");
+}
@@ -0,0 +1,523 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+fn perg_stdin(args: &[&str], input: &[u8]) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_perg"))
+        .args(args)
+        .env("NO_COLOR", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run perg");
+
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn searches_standard_input() {
+    let stdout = perg_stdin(&["-p", "needle", "--vimgrep", "-"], b"hay\r\nhay needle\r\n");
+
+    assert_eq!(stdout, "<stdin>:2:5:hay needle\n");
+}
+
+#[test]
+fn prints_context_from_standard_input() {
+    let stdout = perg_stdin(&["-p", "x", "-C", "1", "-"], b"a\nb\nx\nc\nd\n");
+
+    assert_eq!(stdout, "<stdin>\n2 b\n3 x\n4 c\n");
+}
+
+#[test]
+fn max_count_stops_after_matches() {
+    let stdout = perg_stdin(&["-p", "x", "-m", "2", "--vimgrep", "-"], b"x\nx\nx\n");
+
+    assert_eq!(stdout, "<stdin>:1:1:x\n<stdin>:2:1:x\n");
+}
+
+#[test]
+fn multiline_dotall_still_searches_a_line_at_a_time() {
+    let stdout = perg_stdin(
+        &["-p", "a[^x]b", "--multiline-dotall", "--vimgrep", "-"],
+        b"a\nb\naxb a-b\n",
+    );
+
+    assert_eq!(stdout, "<stdin>:3:5:axb a-b\n");
+}
+
+#[test]
+fn files_with_matches_lists_paths() {
+    let stdout = perg_stdin(&["-p", "x", "-l", "-"], b"x\nx\n");
+
+    assert_eq!(stdout, "<stdin>\n");
+}
+
+#[test]
+fn long_lines_are_reported_and_omitted() {
+    let input = format!("needle {}\nneedle\n", "x".repeat(100));
+
+    let skipped = perg_stdin(
+        &["-p", "needle", "--max-line-len", "50", "--stats", "-"],
+        input.as_bytes(),
+    );
+    assert!(skipped.starts_with("<stdin>\n1 "), "{skipped}");
+    assert!(skipped.contains("\n2 needle\n"));
+    assert!(skipped.ends_with(
+        "1 matches\n1 files contained matches\n1 files searched\n115 bytes searched\n\
+         1 long lines skipped\n0 files skipped\n0 files timed out\n"
+    ));
+
+    let omitted = perg_stdin(&["-p", "needle", "--max-columns", "50", "-"], input.as_bytes());
+    assert_eq!(omitted, "<stdin>\n1 [Omitted long line with 1 matches]\n2 needle\n");
+}
+
+#[test]
+fn control_characters_are_escaped_in_output() {
+    let fixture = std::fs::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../../test_files/control.txt"
+    ))
+    .unwrap();
+
+    // The NUL makes the fixture binary; --text prints its lines anyway.
+    for mode in ["-C1", "--vimgrep"] {
+        let stdout = perg_stdin(&["-p", "failed", "--text", mode, "-"], &fixture);
+        assert!(!stdout.contains(['\x1b', '\0', '\x07']), "{stdout:?}");
+        assert!(stdout.contains("build \\x1b]0;pwned\\x07 failed \\x1b[2J\\x1b[31mred\\x1b[0m\n"));
+        assert!(stdout.contains("nul\\x00 failed too\n"));
+    }
+}
+
+/// Runs perg on a file under `test_files/indent` with colors forced on.
+fn perg_indented(fixture: &str, args: &[&str]) -> String {
+    let path = format!("../../test_files/indent/{fixture}");
+    let output = Command::new(env!("CARGO_BIN_EXE_perg"))
+        .args(args)
+        .args([path.as_str(), "-g", "*"])
+        .env("CLICOLOR_FORCE", "1")
+        .output()
+        .expect("Failed to run perg");
+
+    assert_eq!(output.status.code(), Some(0));
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn trim_strips_shared_indentation_and_keeps_highlights() {
+    let tabs = perg_indented("tabs.rs", &["-p", "files", "-C", "1", "--trim"]);
+    assert_eq!(
+        tabs,
+        "\x1b[34m../../test_files/indent/tabs.rs\x1b[0m\n\
+         \x1b[32m2\x1b[0m fn next(&mut self) {\n\
+         \x1b[32m3\x1b[0m \tlet \x1b[31mfiles\x1b[0m = self.\x1b[31mfiles\x1b[0m;\n\
+         \x1b[32m4\x1b[0m \tif \x1b[31mfiles\x1b[0m.is_empty() {\n\
+         \x1b[32m5\x1b[0m \t\treturn None;\n"
+    );
+
+    let spaces = perg_indented("spaces.py", &["-p", "return", "-C", "1", "--trim"]);
+    assert_eq!(
+        spaces,
+        "\x1b[34m../../test_files/indent/spaces.py\x1b[0m\n\
+         \x1b[32m4\x1b[0m if not files:\n\
+         \x1b[32m5\x1b[0m     \x1b[31mreturn\x1b[0m None\n\
+         \x1b[32m6\x1b[0m \n\
+         \x1b[32m7\x1b[0m \x1b[31mreturn\x1b[0m files\n"
+    );
+}
+
+#[test]
+fn trim_keeps_vimgrep_columns() {
+    let stdout = perg_indented("spaces.py", &["-p", "return", "--vimgrep", "--trim"]);
+
+    assert_eq!(
+        stdout,
+        concat!(
+            "../../test_files/indent/spaces.py:5:13:return None\n",
+            "../../test_files/indent/spaces.py:7:9:return files\n"
+        )
+    );
+}
+
+#[test]
+fn path_display_absolute_resolves_relative_roots() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap().parent().unwrap();
+    let expected = root.join("test_files/indent/spaces.py");
+
+    let stdout = perg_stdin(
+        &[
+            "-p",
+            "return",
+            "-l",
+            "--path-display",
+            "absolute",
+            "--files-from",
+            "-",
+        ],
+        b"../../test_files/indent/spaces.py\n",
+    );
+    assert_eq!(stdout, format!("{}\n", expected.display()));
+
+    let json = perg_indented("spaces.py", &["-p", "None", "--json", "--path-display", "absolute"]);
+    assert!(json.starts_with(&format!("{{\"path\":\"{}\",", expected.display())), "{json}");
+}
+
+#[test]
+fn path_display_relative_cuts_absolute_roots_below_the_working_directory() {
+    let root = concat!(env!("CARGO_MANIFEST_DIR"), "/../perg/tests");
+
+    let stdout = perg_stdin(&["-p", "fn perg_stdin", "-l", root, "-g", "*.rs"], b"");
+
+    assert_eq!(stdout, format!("{}\n", Path::new("tests").join("streams.rs").display()));
+}
+
+#[test]
+fn all_match_reports_lines_with_every_pattern() {
+    let input = b"user ok\nerror\nerror: user\n";
+
+    let any = perg_stdin(&["-e", "user", "-e", "error", "-c", "-"], input);
+    assert_eq!(any, "<stdin>:4\n");
+
+    let all = perg_stdin(&["-e", "user", "-e", "error", "--all-match", "-C", "0", "-"], input);
+    assert_eq!(all, "<stdin>\n3 error: user\n");
+}
+
+#[test]
+fn all_match_counts_lines_not_matches() {
+    let input = b"user error user error\nuser\nerror\n";
+
+    let count = perg_stdin(&["-e", "user", "-e", "error", "--all-match", "-c", "-"], input);
+    assert_eq!(count, "<stdin>:1\n");
+}
+
+#[test]
+fn files_from_searches_only_the_listed_files() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../../test_files");
+    let list = format!("{dir}/short.txt\r\n\n{dir}/random.txt\n");
+    let stdout = perg_stdin(&["-p", "e", "-l", "--files-from", "-"], list.as_bytes());
+    let nul = perg_stdin(
+        &["-p", "e", "-l", "--files-from0", "-"],
+        list.replace("\r\n\n", "\0").replace('\n', "\0").as_bytes(),
+    );
+
+    assert_eq!(stdout.lines().count(), 2, "{stdout}");
+    assert!(stdout.contains("short.txt") && stdout.contains("random.txt"));
+    assert_eq!(nul, stdout);
+}
+
+#[test]
+fn output_of_many_files_does_not_interleave() {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..300 {
+        let lines: String = (0..5).map(|n| format!("needle {i} {n}\n")).collect();
+        std::fs::write(dir.path().join(format!("{i}.txt")), lines).unwrap();
+    }
+    let root = dir.path().to_str().unwrap();
+    let name = |path: &str| path.rsplit('/').next().unwrap().trim_end_matches(".txt").to_string();
+
+    let vimgrep = perg_stdin(&["-p", "needle", "--vimgrep", root, "-g", "*.txt"], b"");
+    assert_eq!(vimgrep.lines().count(), 1500);
+    for line in vimgrep.lines() {
+        let (path, rest) = line.split_once(".txt:").unwrap();
+        let (line_number, text) = rest.split_once(":1:").unwrap();
+        let n = line_number.parse::<usize>().unwrap() - 1;
+        assert_eq!(text, format!("needle {} {n}", name(path)));
+    }
+
+    // A path line followed by that file's five lines.
+    let grouped = perg_stdin(&["-p", "needle", "-C", "0", root, "-g", "*.txt"], b"");
+    let lines: Vec<_> = grouped.lines().collect();
+    assert_eq!(lines.len(), 300 * 6);
+    for block in lines.chunks(6) {
+        let file = name(block[0]);
+        for (n, line) in block[1..].iter().enumerate() {
+            assert_eq!(*line, format!("{} needle {file} {n}", n + 1));
+        }
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_prints_partial_results_and_stats() {
+    use std::time::Duration;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_perg"))
+        .args(["-p", "needle", "--vimgrep", "--stats", "-"])
+        .env("NO_COLOR", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run perg");
+
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"needle\n").unwrap();
+    std::thread::sleep(Duration::from_millis(500));
+    let killed = Command::new("kill").args(["-INT", &child.id().to_string()]).status().unwrap();
+    assert!(killed.success());
+    // The search notices at the next line.
+    std::thread::sleep(Duration::from_millis(200));
+    let _ = stdin.write_all(b"needle\n");
+    drop(stdin);
+
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(130));
+    assert!(stdout.starts_with("<stdin>:1:1:needle\n"), "{stdout}");
+    assert!(stdout.contains("files searched\n"), "{stdout}");
+}
+
+#[test]
+fn format_renders_a_tab_separated_record_per_match() {
+    let template = "{path}\\t{line}\\t{column}\\t{offset}\\t{match}";
+    let stdout = perg_stdin(&["-p", "ab*", "--format", template, "-"], b"xx\r\nab abb\n");
+
+    assert_eq!(stdout, "<stdin>\t2\t1\t4\tab\n<stdin>\t2\t4\t7\tabb\n");
+}
+
+#[test]
+fn format_with_only_the_path_repeats_it_for_every_match() {
+    let stdout = perg_stdin(&["-p", "x", "--format", "{path}", "-"], b"x\nx x\n");
+
+    assert_eq!(stdout, "<stdin>\n<stdin>\n<stdin>\n");
+}
+
+#[test]
+fn logs_go_to_stderr_and_leave_stdout_alone() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../../test_files");
+    let run = |verbose: &[&str], env: &str| {
+        Command::new(env!("CARGO_BIN_EXE_perg"))
+            .args(["-p", "ab", "--json", dir])
+            .args(verbose)
+            .env("NO_COLOR", "1")
+            .env("PERG_LOG", env)
+            .output()
+            .unwrap()
+    };
+
+    let quiet = run(&[], "");
+    let verbose = run(&["-vv"], "");
+    let from_env = run(&[], "debug");
+    let stderr = String::from_utf8_lossy(&verbose.stderr);
+
+    assert!(!quiet.stdout.is_empty());
+    assert_eq!(verbose.stdout, quiet.stdout);
+    assert_eq!(from_env.stdout, quiet.stdout);
+    assert!(String::from_utf8_lossy(&from_env.stderr).contains("perg: debug: worker 0: "));
+    assert!(!String::from_utf8_lossy(&quiet.stderr).contains("perg: info:"));
+    assert!(stderr.contains("perg: info: compiled 1 pattern(s) in "), "{stderr}");
+    assert!(stderr.contains("perg: debug: 'ab': compiled by perg's engine to "), "{stderr}");
+    assert!(stderr.contains("l.png: skipped, extension not searched"), "{stderr}");
+    assert!(stderr.contains("perg: debug: worker 0: searched "), "{stderr}");
+}
+
+#[test]
+fn binary_files_only_say_that_they_match() {
+    let input = b"a\0b needle\nneedle\n";
+
+    assert_eq!(perg_stdin(&["-p", "needle", "-"], input), "Binary file <stdin> matches\n");
+    assert_eq!(perg_stdin(&["-p", "needle", "-c", "-"], input), "<stdin>:2\n");
+    assert_eq!(perg_stdin(&["-p", "needle", "-l", "-"], input), "<stdin>\n");
+    assert_eq!(perg_stdin(&["-p", "needle", "--vimgrep", "-"], input), "");
+    assert_eq!(
+        perg_stdin(&["-p", "needle", "-C", "0", "-a", "-"], input),
+        "<stdin>\n1 a\\x00b needle\n2 needle\n"
+    );
+    assert_eq!(perg_stdin(&["-p", "needle", "-I", "-"], input), "");
+    assert_eq!(
+        perg_stdin(
+            &["-p", "needle", "--null-data", "--vimgrep", "-"],
+            b"a\0b needle\0"
+        ),
+        "<stdin>:2:3:b needle\n"
+    );
+}
+
+#[test]
+fn max_results_stops_the_whole_search() {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..300 {
+        std::fs::write(dir.path().join(format!("{i:03}.txt")), "needle\nhay\nneedle\n").unwrap();
+    }
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_perg"))
+            .args(["-p", "needle", dir.path().to_str().unwrap(), "-g", "*.txt", "--sort", "path"])
+            .args(args)
+            .env("NO_COLOR", "1")
+            .output()
+            .unwrap()
+    };
+
+    let vimgrep = run(&["--max-results", "5", "--vimgrep", "--stats"]);
+    let stdout = String::from_utf8_lossy(&vimgrep.stdout);
+    assert_eq!(vimgrep.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&vimgrep.stderr), "-- result limit reached --\n");
+    let records: Vec<_> = stdout.lines().take_while(|line| !line.is_empty()).collect();
+    assert_eq!(records.len(), 5, "{stdout}");
+    assert!(records[4].ends_with("002.txt:1:1:needle"), "{stdout}");
+    // The files after the last record were never printed, or searched.
+    assert!(stdout.contains("\n2 files searched\n"), "{stdout}");
+
+    // Matches past the limit are left out, with the lines around them.
+    let grouped = run(&["--max-results", "3"]);
+    let stdout = String::from_utf8_lossy(&grouped.stdout);
+    assert!(stdout.ends_with("001.txt\n1 needle\n2 hay\n"), "{stdout}");
+    assert_eq!(stdout.matches("needle").count(), 3, "{stdout}");
+
+    let listed = run(&["--max-results", "2", "-l"]);
+    assert_eq!(String::from_utf8_lossy(&listed.stdout).lines().count(), 2);
+    let counted = run(&["--max-results", "2", "-c"]);
+    assert!(String::from_utf8_lossy(&counted.stdout).ends_with("001.txt:2\n"));
+}
+
+#[cfg(unix)]
+#[test]
+fn output_piped_into_head_ends_the_search_quietly() {
+    let dir = tempfile::tempdir().unwrap();
+    let lines: String = (0..20_000).map(|n| format!("needle {n}\n")).collect();
+    for i in 0..20 {
+        std::fs::write(dir.path().join(format!("{i}.txt")), &lines).unwrap();
+    }
+
+    for mode in [&["--vimgrep"][..], &["-C", "0"], &["--json", "--stats"]] {
+        let mut perg = Command::new(env!("CARGO_BIN_EXE_perg"))
+            .args(["-p", "needle", dir.path().to_str().unwrap(), "-g", "*.txt"])
+            .args(mode)
+            .env("NO_COLOR", "1")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to run perg");
+        let head = Command::new("head")
+            .args(["-n", "5"])
+            .stdin(Stdio::from(perg.stdout.take().unwrap()))
+            .output()
+            .unwrap();
+        let perg = perg.wait_with_output().unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&head.stdout).lines().count(), 5);
+        assert_eq!(perg.status.code(), Some(0), "{mode:?}");
+        assert_eq!(String::from_utf8_lossy(&perg.stderr), "", "{mode:?}");
+    }
+}
+
+#[test]
+fn timeout_abandons_a_file_and_finishes_the_search() {
+    let dir = tempfile::tempdir().unwrap();
+    let big: String = (0..100_000).map(|n| format!("needle {n}\n")).collect();
+    std::fs::write(dir.path().join("big.txt"), big).unwrap();
+    std::fs::write(dir.path().join("small.txt"), "needle\n").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_perg"))
+            .args([
+                "-p",
+                "needle",
+                dir.path().to_str().unwrap(),
+                "-g",
+                "*.txt",
+                "--vimgrep",
+                "--stats",
+            ])
+            .args(args)
+            .env("NO_COLOR", "1")
+            .output()
+            .unwrap()
+    };
+
+    // No time at all: the clock is only read every few lines, so short files still finish.
+    let timed_out = run(&["--timeout", "0ms"]);
+    let stdout = String::from_utf8_lossy(&timed_out.stdout);
+    let stderr = String::from_utf8_lossy(&timed_out.stderr);
+    assert_eq!(timed_out.status.code(), Some(0));
+    assert!(stderr.contains("big.txt: search timed out, results may be incomplete"), "{stderr}");
+    assert!(stdout.contains("small.txt:1:1:needle\n"), "{stdout}");
+    assert!(stdout.matches("big.txt:").count() < 100, "{stdout}");
+    assert!(
+        stdout.contains("\n2 files searched\n") && stdout.ends_with("\n1 files timed out\n"),
+        "{stdout}"
+    );
+
+    let silent = run(&["--timeout", "0", "-s"]);
+    assert_eq!(String::from_utf8_lossy(&silent.stderr), "");
+    assert!(String::from_utf8_lossy(&silent.stdout).ends_with("\n1 files timed out\n"));
+
+    let unlimited = run(&["--timeout", "1m"]);
+    assert!(String::from_utf8_lossy(&unlimited.stdout).ends_with("\n0 files timed out\n"));
+
+    let invalid = run(&["--timeout", "2h"]);
+    assert_eq!(invalid.status.code(), Some(2));
+    assert!(
+        String::from_utf8_lossy(&invalid.stderr).contains("unknown unit 'h'; expected ms, s or m")
+    );
+}
+
+#[test]
+fn line_range_matches_only_the_given_lines() {
+    let input = b"x1\nx2\nx3\nx4\nx5\nx6\n";
+
+    let stdout = perg_stdin(
+        &[
+            "-p",
+            "x",
+            "--line-range",
+            "2:3",
+            "--line-range",
+            "6:",
+            "--vimgrep",
+            "-",
+        ],
+        input,
+    );
+    assert_eq!(stdout, "<stdin>:2:1:x2\n<stdin>:3:1:x3\n<stdin>:6:1:x6\n");
+    let stdout = perg_stdin(&["-p", "x", "--line-range", ":2", "-C", "1", "-"], input);
+    assert_eq!(stdout, "<stdin>\n1 x1\n2 x2\n3 x3\n");
+
+    for (range, error) in [
+        ("3", "expected START:END"),
+        ("0:2", "lines count from 1"),
+        ("4:2", "ends before it starts"),
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_perg"))
+            .args(["-p", "x", "--line-range", range, "-"])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2));
+        assert!(String::from_utf8_lossy(&output.stderr).contains(error), "{range}");
+    }
+}
+
+#[test]
+fn each_pattern_is_highlighted_in_its_own_color() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("log.txt");
+    std::fs::write(&log, "user ok\nerror: user\n").unwrap();
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_perg"))
+            .args(["-e", "user", "-e", "error", "-C", "0"])
+            .args(args)
+            .arg(&log)
+            .env_remove("NO_COLOR")
+            .env("CLICOLOR_FORCE", "1")
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    let stdout = run(&[]);
+    assert!(
+        stdout.starts_with("pattern 1: \x1b[31muser\x1b[0m\npattern 2: \x1b[33merror\x1b[0m\n\n"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.ends_with(" \x1b[33merror\x1b[0m: \x1b[31muser\x1b[0m\n"),
+        "{stdout}"
+    );
+
+    let stdout = run(&["--colors", "2:bright-blue", "--colors", "1:green"]);
+    assert!(
+        stdout.ends_with(" \x1b[94merror\x1b[0m: \x1b[32muser\x1b[0m\n"),
+        "{stdout}"
+    );
+}
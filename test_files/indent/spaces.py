@@ -0,0 +1,7 @@
+class Walk:
+    def next(self):
+        files = self.files
+        if not files:
+            return None
+
+        return files
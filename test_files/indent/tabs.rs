@@ -0,0 +1,8 @@
+impl Walk {
+	fn next(&mut self) {
+		let files = self.files;
+		if files.is_empty() {
+			return None;
+		}
+	}
+}